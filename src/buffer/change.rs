@@ -1,13 +1,15 @@
 //! Change tracking for undo/redo functionality
 //!
 //! This module implements change tracking for the buffer system, enabling
-//! undo and redo operations. It uses a change history stack to record
-//! modifications to the buffer.
+//! undo and redo operations. It records modifications to the buffer in an
+//! undo tree, so no change is lost when editing after an undo.
 
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
 
 /// Types of changes that can be made to a buffer
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ChangeType {
     /// Insert text at a position
     Insert {
@@ -127,30 +129,164 @@ impl ChangeGroup {
     }
 }
 
+/// A text state in the undo tree
+#[derive(Debug, Clone)]
+pub struct UndoState {
+    /// Sequence number of the state, `0` is the original text
+    pub seq: usize,
+    /// Sequence number of the parent state
+    pub parent: usize,
+    /// Changes that turn the parent state into this state
+    pub group: ChangeGroup,
+    /// Sequence numbers of the child states, oldest first
+    pub children: Vec<usize>,
+    /// Child state that redo moves to
+    redo_child: Option<usize>,
+    /// Time the state was created
+    pub timestamp: Instant,
+    /// Write number if the buffer was written in this state
+    pub save_nr: Option<usize>,
+}
+
+/// A step taken when moving between states in the undo tree
+#[derive(Debug, Clone)]
+pub enum UndoStep {
+    /// Undo a change group
+    Undo(ChangeGroup),
+    /// Redo a change group
+    Redo(ChangeGroup),
+}
+
+/// A branch of the undo tree, as listed by `:undolist`
+#[derive(Debug, Clone, PartialEq)]
+pub struct UndoBranch {
+    /// Sequence number of the last state in the branch
+    pub seq: usize,
+    /// Number of changes between the original text and the last state
+    pub changes: usize,
+    /// Time the last state was created
+    pub timestamp: Instant,
+    /// Write number if the buffer was written in the last state
+    pub save_nr: Option<usize>,
+}
+
+/// Distance to move with `:earlier` and `:later`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UndoTime {
+    /// A number of states
+    Steps(usize),
+    /// A span of time
+    Duration(Duration),
+    /// A number of file writes
+    Writes(usize),
+}
+
+impl UndoTime {
+    /// Parse an `:earlier`/`:later` argument such as `3`, `10s`, `5m`, `1h`, `2d` or `1f`
+    ///
+    /// A count too large to be represented is not valid either.
+    pub fn parse(arg: &str) -> Option<Self> {
+        let arg = arg.trim();
+        if arg.is_empty() {
+            return Some(UndoTime::Steps(1));
+        }
+        
+        let digits = arg.chars().take_while(|c| c.is_ascii_digit()).count();
+        let count: u64 = if digits == 0 { 1 } else { arg[..digits].parse().ok()? };
+        let seconds = |unit: u64| count.checked_mul(unit).map(|secs| UndoTime::Duration(Duration::from_secs(secs)));
+        
+        match &arg[digits..] {
+            "" => usize::try_from(count).ok().map(UndoTime::Steps),
+            "s" => seconds(1),
+            "m" => seconds(60),
+            "h" => seconds(60 * 60),
+            "d" => seconds(24 * 60 * 60),
+            "f" => usize::try_from(count).ok().map(UndoTime::Writes),
+            _ => None,
+        }
+    }
+}
+
+/// A state of the undo tree as stored in an undo file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedUndoState {
+    /// Sequence number of the parent state
+    pub parent: usize,
+    /// Changes that turn the parent state into this state
+    pub changes: Vec<ChangeType>,
+    /// Sequence numbers of the child states, oldest first
+    pub children: Vec<usize>,
+    /// Child state that redo moves to
+    pub redo_child: Option<usize>,
+    /// Time the state was created, in seconds since the Unix epoch
+    pub time: u64,
+    /// Write number if the buffer was written in this state
+    pub save_nr: Option<usize>,
+}
+
+/// The undo tree of a buffer as stored in an undo file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistorySnapshot {
+    /// States in the undo tree, indexed by sequence number
+    pub states: Vec<SavedUndoState>,
+    /// Sequence number of the current state
+    pub current: usize,
+    /// Number of times the buffer was written
+    pub save_count: usize,
+}
+
 /// Change history for a buffer
+///
+/// The history is a tree of text states. Undo moves to the parent state and redo
+/// to the most recently visited child, so making a change after undoing starts a
+/// new branch instead of discarding the undone changes. Every state has a
+/// sequence number in the order it was created, which `g-`, `g+`, `:undo N`,
+/// `:earlier` and `:later` use to move through the tree chronologically.
 #[derive(Debug, Clone)]
 pub struct ChangeHistory {
-    /// Undo stack (changes that can be undone)
-    undo_stack: Vec<ChangeGroup>,
-    /// Redo stack (changes that have been undone)
-    redo_stack: Vec<ChangeGroup>,
+    /// States in the undo tree, indexed by sequence number
+    states: Vec<UndoState>,
+    /// Sequence number of the current state
+    current: usize,
     /// Current change group being built
     current_group: ChangeGroup,
     /// Maximum time between changes to be considered part of the same group
     group_timeout: Duration,
     /// Whether changes are being recorded
     recording: bool,
+    /// Whether changes join the group being built, however far apart they are
+    joining: bool,
+    /// Number of times the buffer was written
+    save_count: usize,
+    /// Sequence number of the state matching the file on disk
+    saved_seq: Option<usize>,
 }
 
 impl ChangeHistory {
     /// Create a new change history
     pub fn new() -> Self {
         Self {
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
+            states: vec![Self::root_state()],
+            current: 0,
             current_group: ChangeGroup::new(),
             group_timeout: Duration::from_millis(500), // 500ms timeout for grouping changes
             recording: true,
+            joining: false,
+            save_count: 0,
+            saved_seq: Some(0),
+        }
+    }
+    
+    /// Create the state for the original text
+    fn root_state() -> UndoState {
+        UndoState {
+            seq: 0,
+            parent: 0,
+            group: ChangeGroup::new(),
+            children: Vec::new(),
+            redo_child: None,
+            timestamp: Instant::now(),
+            save_nr: None,
         }
     }
     
@@ -164,12 +300,9 @@ impl ChangeHistory {
         
         // Check if we should start a new group
         if self.current_group.is_empty() || 
-           change.timestamp - self.current_group.timestamp > self.group_timeout {
+           (!self.joining && change.timestamp - self.current_group.timestamp > self.group_timeout) {
             // Commit the current group if it's not empty
-            if !self.current_group.is_empty() {
-                let group = std::mem::replace(&mut self.current_group, ChangeGroup::new());
-                self.undo_stack.push(group);
-            }
+            self.commit_current_group();
             
             // Start a new group
             self.current_group = ChangeGroup::new();
@@ -177,27 +310,43 @@ impl ChangeHistory {
         
         // Add the change to the current group
         self.current_group.add_change(change);
-        
-        // Clear the redo stack when a new change is made
-        self.redo_stack.clear();
     }
     
-    /// Commit the current change group
+    /// Commit the current change group as a new state
     pub fn commit_current_group(&mut self) {
-        if !self.current_group.is_empty() {
-            let group = std::mem::replace(&mut self.current_group, ChangeGroup::new());
-            self.undo_stack.push(group);
+        if self.current_group.is_empty() {
+            return;
         }
+        
+        let group = std::mem::replace(&mut self.current_group, ChangeGroup::new());
+        let seq = self.states.len();
+        let timestamp = group.changes.last().map_or(group.timestamp, |change| change.timestamp);
+        
+        // The new state becomes the branch that redo follows
+        let parent = &mut self.states[self.current];
+        parent.children.push(seq);
+        parent.redo_child = Some(seq);
+        
+        self.states.push(UndoState {
+            seq,
+            parent: self.current,
+            group,
+            children: Vec::new(),
+            redo_child: None,
+            timestamp,
+            save_nr: None,
+        });
+        self.current = seq;
     }
     
     /// Check if undo is available
     pub fn can_undo(&self) -> bool {
-        !self.undo_stack.is_empty() || !self.current_group.is_empty()
+        self.current != 0 || !self.current_group.is_empty()
     }
     
     /// Check if redo is available
     pub fn can_redo(&self) -> bool {
-        !self.redo_stack.is_empty()
+        self.current_group.is_empty() && self.states[self.current].redo_child.is_some()
     }
     
     /// Get the next undo group
@@ -205,32 +354,215 @@ impl ChangeHistory {
         // Commit the current group if it's not empty
         self.commit_current_group();
         
-        // Pop the last change group from the undo stack
-        if let Some(group) = self.undo_stack.pop() {
-            // Push to the redo stack
-            self.redo_stack.push(group.clone());
-            
-            // Return the original group for applying undo operations
-            Some(group)
-        } else {
-            None
+        if self.current == 0 {
+            return None;
         }
+        
+        // Move to the parent state, remembering which branch to redo
+        let state = &self.states[self.current];
+        let (seq, parent, group) = (state.seq, state.parent, state.group.clone());
+        self.states[parent].redo_child = Some(seq);
+        self.current = parent;
+        
+        // Return the original group for applying undo operations
+        Some(group)
     }
     
     /// Get the next redo group
     pub fn redo(&mut self) -> Option<ChangeGroup> {
-        // Pop the last change group from the redo stack
-        if let Some(group) = self.redo_stack.pop() {
-            // Push to the undo stack
-            self.undo_stack.push(group.clone());
-            
-            // Return the original group for applying redo operations
-            Some(group)
-        } else {
-            None
+        // Commit the current group if it's not empty
+        self.commit_current_group();
+        
+        let child = self.states[self.current].redo_child?;
+        self.current = child;
+        
+        // Return the original group for applying redo operations
+        Some(self.states[child].group.clone())
+    }
+    
+    /// Get the sequence number of the current state
+    pub fn seq_cur(&self) -> usize {
+        self.current
+    }
+    
+    /// Get the highest sequence number in the tree
+    pub fn seq_last(&self) -> usize {
+        self.states.len() - 1
+    }
+    
+    /// Get a state by sequence number
+    pub fn state(&self, seq: usize) -> Option<&UndoState> {
+        self.states.get(seq)
+    }
+    
+    /// Move to the state with the given sequence number
+    ///
+    /// Returns the steps to apply to the text, undoing up to the common
+    /// ancestor and redoing down to the target, or `None` if there is no such state.
+    pub fn goto(&mut self, target: usize) -> Option<Vec<UndoStep>> {
+        self.commit_current_group();
+        
+        if target >= self.states.len() {
+            return None;
+        }
+        
+        // Collect the path from the target up to the root
+        let mut target_path = vec![target];
+        while let Some(&seq) = target_path.last() {
+            if seq == 0 {
+                break;
+            }
+            target_path.push(self.states[seq].parent);
+        }
+        
+        // Undo until we reach a state on the target's path
+        let mut steps = Vec::new();
+        while !target_path.contains(&self.current) {
+            let state = &self.states[self.current];
+            steps.push(UndoStep::Undo(state.group.clone()));
+            let (seq, parent) = (state.seq, state.parent);
+            self.states[parent].redo_child = Some(seq);
+            self.current = parent;
+        }
+        
+        // Redo down to the target
+        let ancestor = target_path.iter().position(|&seq| seq == self.current).unwrap_or(0);
+        for &seq in target_path[..ancestor].iter().rev() {
+            self.states[self.current].redo_child = Some(seq);
+            steps.push(UndoStep::Redo(self.states[seq].group.clone()));
+            self.current = seq;
+        }
+        
+        Some(steps)
+    }
+    
+    /// Move back in time, as `g-` and `:earlier` do
+    pub fn earlier(&mut self, time: UndoTime) -> Vec<UndoStep> {
+        self.commit_current_group();
+        
+        let target = match time {
+            UndoTime::Steps(count) => self.current.saturating_sub(count),
+            UndoTime::Duration(duration) => {
+                match self.states[self.current].timestamp.checked_sub(duration) {
+                    Some(cutoff) => self.last_state_before(cutoff),
+                    None => 0,
+                }
+            }
+            UndoTime::Writes(count) => {
+                let base = match self.states[self.current].save_nr {
+                    Some(save_nr) => save_nr,
+                    None => self.last_save_before(self.current) + 1,
+                };
+                self.saved_state(base.saturating_sub(count)).unwrap_or(0)
+            }
+        };
+        
+        self.goto(target).unwrap_or_default()
+    }
+    
+    /// Move forward in time, as `g+` and `:later` do
+    pub fn later(&mut self, time: UndoTime) -> Vec<UndoStep> {
+        self.commit_current_group();
+        
+        let target = match time {
+            UndoTime::Steps(count) => self.current.saturating_add(count).min(self.seq_last()),
+            UndoTime::Duration(duration) => {
+                match self.states[self.current].timestamp.checked_add(duration) {
+                    Some(cutoff) => self.last_state_before(cutoff).max(self.current),
+                    None => self.seq_last(),
+                }
+            }
+            UndoTime::Writes(count) => {
+                let base = self.states[self.current].save_nr
+                    .unwrap_or_else(|| self.last_save_before(self.current));
+                self.saved_state(base.saturating_add(count)).unwrap_or(self.seq_last())
+            }
+        };
+        
+        self.goto(target).unwrap_or_default()
+    }
+    
+    /// Get the newest state created at or before a time
+    fn last_state_before(&self, cutoff: Instant) -> usize {
+        self.states.iter()
+            .filter(|state| state.seq != 0 && state.timestamp <= cutoff)
+            .map(|state| state.seq)
+            .max()
+            .unwrap_or(0)
+    }
+    
+    /// Get the number of the last write made in a state older than `seq`
+    fn last_save_before(&self, seq: usize) -> usize {
+        self.states[..seq].iter()
+            .filter_map(|state| state.save_nr)
+            .max()
+            .unwrap_or(0)
+    }
+    
+    /// Get the state the buffer was in when it was written for the given time
+    fn saved_state(&self, save_nr: usize) -> Option<usize> {
+        if save_nr == 0 {
+            return Some(0);
+        }
+        
+        self.states.iter()
+            .find(|state| state.save_nr == Some(save_nr))
+            .map(|state| state.seq)
+    }
+    
+    /// Record that the buffer was written in the current state
+    pub fn mark_saved(&mut self) {
+        self.commit_current_group();
+        
+        self.save_count += 1;
+        self.states[self.current].save_nr = Some(self.save_count);
+        self.saved_seq = Some(self.current);
+    }
+    
+    /// Check whether the current state matches the file on disk
+    pub fn is_saved_state(&self) -> bool {
+        self.current_group.is_empty() && self.saved_seq == Some(self.current)
+    }
+    
+    /// Get the branches of the undo tree, oldest first
+    pub fn branches(&self) -> Vec<UndoBranch> {
+        self.states.iter()
+            .filter(|state| state.seq != 0 && state.children.is_empty())
+            .map(|state| {
+                // Count the changes on the way back to the original text
+                let mut changes = 0;
+                let mut seq = state.seq;
+                while seq != 0 {
+                    changes += 1;
+                    seq = self.states[seq].parent;
+                }
+                
+                UndoBranch {
+                    seq: state.seq,
+                    changes,
+                    timestamp: state.timestamp,
+                    save_nr: state.save_nr,
+                }
+            })
+            .collect()
+    }
+    
+    /// Commit the current change group, unless changes are being joined
+    pub fn end_step(&mut self) {
+        if !self.joining {
+            self.commit_current_group();
         }
     }
     
+    /// Set whether changes join into one group
+    ///
+    /// Starting and stopping both commit the group built so far, so the
+    /// joined changes make a group of their own.
+    pub fn set_joining(&mut self, joining: bool) {
+        self.commit_current_group();
+        self.joining = joining;
+    }
+    
     /// Start recording changes
     pub fn start_recording(&mut self) {
         self.recording = true;
@@ -243,15 +575,121 @@ impl ChangeHistory {
     
     /// Clear the change history
     pub fn clear(&mut self) {
-        self.undo_stack.clear();
-        self.redo_stack.clear();
+        self.states = vec![Self::root_state()];
+        self.current = 0;
         self.current_group = ChangeGroup::new();
+        self.save_count = 0;
+        self.saved_seq = Some(0);
     }
     
     /// Set the group timeout
     pub fn set_group_timeout(&mut self, timeout: Duration) {
         self.group_timeout = timeout;
     }
+    
+    /// Take a snapshot of the undo tree for writing to an undo file
+    ///
+    /// Changes in the group that is still being built are not included.
+    pub fn snapshot(&self) -> HistorySnapshot {
+        let now = Instant::now();
+        let wall_now = SystemTime::now();
+        
+        let states = self.states.iter()
+            .map(|state| {
+                // Instants can't be stored, so convert them to wall clock time
+                let time = wall_now
+                    .checked_sub(now.saturating_duration_since(state.timestamp))
+                    .unwrap_or(wall_now)
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_secs());
+                
+                SavedUndoState {
+                    parent: state.parent,
+                    changes: state.group.changes.iter()
+                        .map(|change| change.change_type.clone())
+                        .collect(),
+                    children: state.children.clone(),
+                    redo_child: state.redo_child,
+                    time,
+                    save_nr: state.save_nr,
+                }
+            })
+            .collect();
+        
+        HistorySnapshot {
+            states,
+            current: self.current,
+            save_count: self.save_count,
+        }
+    }
+    
+    /// Rebuild a change history from a snapshot read from an undo file
+    ///
+    /// The current state of the snapshot is taken to match the file on disk.
+    /// Returns an error describing the problem if the snapshot is not a valid tree.
+    pub fn from_snapshot(snapshot: HistorySnapshot) -> Result<Self, String> {
+        let count = snapshot.states.len();
+        if count == 0 {
+            return Err("undo tree has no states".to_string());
+        }
+        if snapshot.current >= count {
+            return Err(format!("current state {} out of range", snapshot.current));
+        }
+        
+        let now = Instant::now();
+        let wall_now = SystemTime::now();
+        let mut states = Vec::with_capacity(count);
+        
+        for (seq, saved) in snapshot.states.into_iter().enumerate() {
+            // Every state other than the root must hang off an older state
+            if seq != 0 && saved.parent >= seq {
+                return Err(format!("state {} has invalid parent {}", seq, saved.parent));
+            }
+            if saved.children.iter().chain(saved.redo_child.iter()).any(|&child| child <= seq || child >= count) {
+                return Err(format!("state {} has invalid children", seq));
+            }
+            
+            // Convert the wall clock time back to an instant
+            let age = wall_now
+                .duration_since(UNIX_EPOCH + Duration::from_secs(saved.time))
+                .unwrap_or_default();
+            let timestamp = now.checked_sub(age).unwrap_or(now);
+            
+            let mut group = ChangeGroup::new();
+            group.timestamp = timestamp;
+            for change_type in saved.changes {
+                group.add_change(Change { change_type, timestamp });
+            }
+            
+            states.push(UndoState {
+                seq,
+                parent: if seq == 0 { 0 } else { saved.parent },
+                group,
+                children: saved.children,
+                redo_child: saved.redo_child,
+                timestamp,
+                save_nr: saved.save_nr,
+            });
+        }
+        
+        // Make sure each child actually points back at its parent
+        for state in &states {
+            if state.children.iter().any(|&child| states[child].parent != state.seq) {
+                return Err(format!("state {} has invalid children", state.seq));
+            }
+        }
+        
+        Ok(Self {
+            states,
+            current: snapshot.current,
+            current_group: ChangeGroup::new(),
+            group_timeout: Duration::from_millis(500),
+            recording: true,
+            joining: false,
+            save_count: snapshot.save_count,
+            saved_seq: Some(snapshot.current),
+        })
+    }
 }
 
 impl Default for ChangeHistory {
@@ -354,4 +792,130 @@ mod tests {
             text: " world".to_string(),
         });
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_change_history_branches() {
+        let mut history = ChangeHistory::new();
+        
+        history.record_change(ChangeType::Insert { position: 0, text: "one".to_string() });
+        history.commit_current_group();
+        history.undo().unwrap();
+        
+        // A change after undoing starts a new branch instead of dropping the old one
+        history.record_change(ChangeType::Insert { position: 0, text: "two".to_string() });
+        history.commit_current_group();
+        assert_eq!(history.seq_cur(), 2);
+        assert_eq!(history.seq_last(), 2);
+        assert!(!history.can_redo());
+        
+        let branches = history.branches();
+        assert_eq!(branches.iter().map(|b| b.seq).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(branches.iter().all(|b| b.changes == 1));
+        
+        // Jumping to the first branch undoes the second change and redoes the first
+        let steps = history.goto(1).unwrap();
+        assert_eq!(steps.len(), 2);
+        assert!(matches!(&steps[0], UndoStep::Undo(group) if group.changes[0].change_type == ChangeType::Insert { position: 0, text: "two".to_string() }));
+        assert!(matches!(&steps[1], UndoStep::Redo(group) if group.changes[0].change_type == ChangeType::Insert { position: 0, text: "one".to_string() }));
+        assert_eq!(history.seq_cur(), 1);
+        
+        // Redo follows the branch that was visited last
+        history.undo().unwrap();
+        let group = history.redo().unwrap();
+        assert_eq!(group.changes[0].change_type, ChangeType::Insert { position: 0, text: "one".to_string() });
+        
+        assert!(history.goto(3).is_none());
+    }
+    
+    #[test]
+    fn test_change_history_earlier_later() {
+        let mut history = ChangeHistory::new();
+        
+        for i in 0..3 {
+            history.record_change(ChangeType::Insert { position: i, text: "x".to_string() });
+            history.commit_current_group();
+            if i == 0 {
+                history.mark_saved();
+            }
+        }
+        assert!(!history.is_saved_state());
+        
+        // Steps move by sequence number
+        assert_eq!(history.earlier(UndoTime::Steps(2)).len(), 2);
+        assert_eq!(history.seq_cur(), 1);
+        assert!(history.is_saved_state());
+        assert_eq!(history.later(UndoTime::Steps(5)).len(), 2);
+        assert_eq!(history.seq_cur(), 3);
+        
+        // Writes move to the states that were written
+        history.earlier(UndoTime::Writes(1));
+        assert_eq!(history.seq_cur(), 1);
+        history.earlier(UndoTime::Writes(1));
+        assert_eq!(history.seq_cur(), 0);
+        history.later(UndoTime::Writes(1));
+        assert_eq!(history.seq_cur(), 1);
+        history.later(UndoTime::Writes(1));
+        assert_eq!(history.seq_cur(), 3);
+        
+        // All changes were made just now
+        history.earlier(UndoTime::Duration(Duration::from_secs(3600)));
+        assert_eq!(history.seq_cur(), 0);
+        history.later(UndoTime::Duration(Duration::from_secs(3600)));
+        assert_eq!(history.seq_cur(), 3);
+        
+        // Distances past the ends stop there
+        history.earlier(UndoTime::Duration(Duration::from_secs(u64::MAX)));
+        assert_eq!(history.seq_cur(), 0);
+        history.later(UndoTime::Duration(Duration::from_secs(u64::MAX)));
+        assert_eq!(history.seq_cur(), 3);
+        history.earlier(UndoTime::Steps(usize::MAX));
+        history.later(UndoTime::Steps(usize::MAX));
+        assert_eq!(history.seq_cur(), 3);
+        history.later(UndoTime::Writes(usize::MAX));
+        assert_eq!(history.seq_cur(), 3);
+    }
+    
+    #[test]
+    fn test_change_history_snapshot() {
+        let mut history = ChangeHistory::new();
+        for (position, text) in [(0, "a"), (1, "b")] {
+            history.record_change(ChangeType::Insert { position, text: text.to_string() });
+            history.commit_current_group();
+        }
+        history.undo();
+        history.mark_saved();
+        
+        let mut restored = ChangeHistory::from_snapshot(history.snapshot()).unwrap();
+        assert_eq!(restored.seq_cur(), 1);
+        assert_eq!(restored.seq_last(), 2);
+        assert!(restored.is_saved_state());
+        assert_eq!(restored.redo().unwrap().changes[0].change_type, ChangeType::Insert {
+            position: 1,
+            text: "b".to_string(),
+        });
+        
+        // A state whose parent is not older than itself is rejected
+        let mut snapshot = history.snapshot();
+        snapshot.states[1].parent = 2;
+        assert!(ChangeHistory::from_snapshot(snapshot).is_err());
+        
+        let mut snapshot = history.snapshot();
+        snapshot.current = 7;
+        assert!(ChangeHistory::from_snapshot(snapshot).is_err());
+    }
+    
+    #[test]
+    fn test_undo_time_parse() {
+        assert_eq!(UndoTime::parse(""), Some(UndoTime::Steps(1)));
+        assert_eq!(UndoTime::parse("3"), Some(UndoTime::Steps(3)));
+        assert_eq!(UndoTime::parse("10s"), Some(UndoTime::Duration(Duration::from_secs(10))));
+        assert_eq!(UndoTime::parse("10m"), Some(UndoTime::Duration(Duration::from_secs(600))));
+        assert_eq!(UndoTime::parse("2h"), Some(UndoTime::Duration(Duration::from_secs(7200))));
+        assert_eq!(UndoTime::parse("1d"), Some(UndoTime::Duration(Duration::from_secs(86400))));
+        assert_eq!(UndoTime::parse("3f"), Some(UndoTime::Writes(3)));
+        assert_eq!(UndoTime::parse("f"), Some(UndoTime::Writes(1)));
+        assert_eq!(UndoTime::parse("3x"), None);
+        assert_eq!(UndoTime::parse("99999999999999999999"), None);
+        assert_eq!(UndoTime::parse("999999999999999999d"), None);
+    }
+}
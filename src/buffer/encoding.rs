@@ -0,0 +1,269 @@
+//! File formats and encodings
+//!
+//! Buffers always hold UTF-8 text with `\n` line endings. This module converts
+//! between that and the bytes of a file, detecting the line ending style
+//! ('fileformat'), the character encoding ('fileencoding') and a byte order
+//! mark ('bomb') on read so the file can be written back the way it was found.
+
+use std::fmt;
+
+use super::{BufferError, BufferResult};
+
+/// Line ending style of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileFormat {
+    /// Lines end with `\n`
+    #[default]
+    Unix,
+    /// Lines end with `\r\n`
+    Dos,
+    /// Lines end with `\r`
+    Mac,
+}
+
+impl FileFormat {
+    /// Parse a 'fileformat' value
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "unix" => Some(FileFormat::Unix),
+            "dos" => Some(FileFormat::Dos),
+            "mac" => Some(FileFormat::Mac),
+            _ => None,
+        }
+    }
+
+    /// Get the 'fileformat' name
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileFormat::Unix => "unix",
+            FileFormat::Dos => "dos",
+            FileFormat::Mac => "mac",
+        }
+    }
+
+    /// Get the line ending, as shown in the status line
+    pub fn line_ending_name(&self) -> &'static str {
+        match self {
+            FileFormat::Unix => "LF",
+            FileFormat::Dos => "CRLF",
+            FileFormat::Mac => "CR",
+        }
+    }
+
+    /// Get the characters that end a line
+    pub fn line_ending(&self) -> &'static str {
+        match self {
+            FileFormat::Unix => "\n",
+            FileFormat::Dos => "\r\n",
+            FileFormat::Mac => "\r",
+        }
+    }
+
+    /// Detect the line ending style of text
+    ///
+    /// As in Vim, the file is only taken as DOS if every line ends in `\r\n`,
+    /// so a stray `\r` in a Unix file is kept as text.
+    pub fn detect(text: &str) -> Self {
+        let newlines = text.matches('\n').count();
+        if newlines == 0 {
+            return if text.contains('\r') { FileFormat::Mac } else { FileFormat::Unix };
+        }
+
+        if text.matches("\r\n").count() == newlines {
+            FileFormat::Dos
+        } else {
+            FileFormat::Unix
+        }
+    }
+
+    /// Convert text with this line ending style to `\n` line endings
+    pub fn to_buffer_text(&self, text: &str) -> String {
+        match self {
+            FileFormat::Unix => text.to_string(),
+            FileFormat::Dos => text.replace("\r\n", "\n"),
+            FileFormat::Mac => text.replace('\r', "\n"),
+        }
+    }
+
+    /// Convert text with `\n` line endings to this line ending style
+    pub fn to_file_text(&self, text: &str) -> String {
+        match self {
+            FileFormat::Unix => text.to_string(),
+            _ => text.replace('\n', self.line_ending()),
+        }
+    }
+}
+
+impl fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Character encoding of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileEncoding {
+    /// UTF-8
+    #[default]
+    Utf8,
+    /// UTF-16, little endian
+    Utf16Le,
+    /// UTF-16, big endian
+    Utf16Be,
+    /// ISO-8859-1
+    Latin1,
+}
+
+impl FileEncoding {
+    /// Parse a 'fileencoding' value, accepting the usual aliases
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(FileEncoding::Utf8),
+            "utf-16le" | "utf16le" => Some(FileEncoding::Utf16Le),
+            "utf-16" | "utf16" | "utf-16be" | "utf16be" => Some(FileEncoding::Utf16Be),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(FileEncoding::Latin1),
+            _ => None,
+        }
+    }
+
+    /// Get the 'fileencoding' name
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileEncoding::Utf8 => "utf-8",
+            FileEncoding::Utf16Le => "utf-16le",
+            FileEncoding::Utf16Be => "utf-16",
+            FileEncoding::Latin1 => "latin1",
+        }
+    }
+
+    /// Get the byte order mark for this encoding
+    pub fn bom(&self) -> &'static [u8] {
+        match self {
+            FileEncoding::Utf8 => &[0xEF, 0xBB, 0xBF],
+            FileEncoding::Utf16Le => &[0xFF, 0xFE],
+            FileEncoding::Utf16Be => &[0xFE, 0xFF],
+            FileEncoding::Latin1 => &[],
+        }
+    }
+
+    /// Decode the bytes of a file, detecting the encoding
+    ///
+    /// A byte order mark picks the encoding. Otherwise the file is UTF-8 if it
+    /// is valid UTF-8 and Latin-1 if not, which can decode any bytes. Returns the
+    /// text, the encoding and whether there was a byte order mark.
+    pub fn decode(bytes: &[u8]) -> (String, Self, bool) {
+        for encoding in [FileEncoding::Utf8, FileEncoding::Utf16Le, FileEncoding::Utf16Be] {
+            if let Some(rest) = bytes.strip_prefix(encoding.bom()) {
+                return (encoding.decode_bytes(rest), encoding, true);
+            }
+        }
+
+        match std::str::from_utf8(bytes) {
+            Ok(text) => (text.to_string(), FileEncoding::Utf8, false),
+            Err(_) => (FileEncoding::Latin1.decode_bytes(bytes), FileEncoding::Latin1, false),
+        }
+    }
+
+    /// Decode bytes in this encoding, replacing anything invalid
+    pub fn decode_bytes(&self, bytes: &[u8]) -> String {
+        match self {
+            FileEncoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            FileEncoding::Utf16Le | FileEncoding::Utf16Be => {
+                let units: Vec<u16> = bytes.chunks(2)
+                    .map(|pair| {
+                        let pair = [pair[0], pair.get(1).copied().unwrap_or(0)];
+                        if *self == FileEncoding::Utf16Le {
+                            u16::from_le_bytes(pair)
+                        } else {
+                            u16::from_be_bytes(pair)
+                        }
+                    })
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            FileEncoding::Latin1 => bytes.iter().map(|&byte| char::from(byte)).collect(),
+        }
+    }
+
+    /// Encode text in this encoding, with a byte order mark if `bom` is set
+    ///
+    /// Fails if the text has characters the encoding can't represent.
+    pub fn encode(&self, text: &str, bom: bool) -> BufferResult<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(text.len() + 3);
+        if bom {
+            bytes.extend_from_slice(self.bom());
+        }
+
+        match self {
+            FileEncoding::Utf8 => bytes.extend_from_slice(text.as_bytes()),
+            FileEncoding::Utf16Le => text.encode_utf16().for_each(|unit| bytes.extend_from_slice(&unit.to_le_bytes())),
+            FileEncoding::Utf16Be => text.encode_utf16().for_each(|unit| bytes.extend_from_slice(&unit.to_be_bytes())),
+            FileEncoding::Latin1 => {
+                for (line, text_line) in text.split('\n').enumerate() {
+                    if line > 0 {
+                        bytes.push(b'\n');
+                    }
+                    for c in text_line.chars() {
+                        match u8::try_from(u32::from(c)) {
+                            Ok(byte) => bytes.push(byte),
+                            Err(_) => {
+                                return Err(BufferError::Other(format!(
+                                    "E513: write error, conversion failed in line {} (make 'fenc' empty to override)",
+                                    line + 1
+                                )));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(bytes)
+    }
+}
+
+impl fmt::Display for FileEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_format_detect() {
+        assert_eq!(FileFormat::detect("a\r\nb\r\n"), FileFormat::Dos);
+        assert_eq!(FileFormat::detect("a\r\nb\n"), FileFormat::Unix);
+        assert_eq!(FileFormat::detect("a\rb\r"), FileFormat::Mac);
+        assert_eq!(FileFormat::detect("a"), FileFormat::Unix);
+
+        assert_eq!(FileFormat::Dos.to_buffer_text("a\r\nb\r\n"), "a\nb\n");
+        assert_eq!(FileFormat::Dos.to_file_text("a\nb\n"), "a\r\nb\r\n");
+        assert_eq!(FileFormat::Mac.to_buffer_text("a\rb"), "a\nb");
+    }
+
+    #[test]
+    fn test_file_encoding_round_trip() {
+        let text = "héllo\n";
+        for encoding in [FileEncoding::Utf8, FileEncoding::Utf16Le, FileEncoding::Utf16Be, FileEncoding::Latin1] {
+            let bom = encoding != FileEncoding::Latin1;
+            let bytes = encoding.encode(text, bom).unwrap();
+            assert_eq!(FileEncoding::decode(&bytes), (text.to_string(), encoding, bom));
+        }
+
+        // Plain UTF-8 has no byte order mark
+        let bytes = FileEncoding::Utf8.encode(text, false).unwrap();
+        assert_eq!(FileEncoding::decode(&bytes), (text.to_string(), FileEncoding::Utf8, false));
+    }
+
+    #[test]
+    fn test_file_encoding_latin1() {
+        // Not valid UTF-8, so read as Latin-1
+        let (text, encoding, bom) = FileEncoding::decode(&[b'c', 0xE9]);
+        assert_eq!((text.as_str(), encoding, bom), ("cé", FileEncoding::Latin1, false));
+
+        assert!(FileEncoding::Latin1.encode("a\n€", false).is_err());
+    }
+}
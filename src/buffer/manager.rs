@@ -8,7 +8,7 @@ use std::path::{Path, PathBuf};
 use std::error::Error;
 use std::fmt;
 
-use super::{Buffer, BufferError, BufferResult};
+use super::{Buffer, BufferError, WriteOptions};
 
 /// Errors that can occur during buffer management operations
 #[derive(Debug)]
@@ -65,6 +65,16 @@ pub struct BufferManager {
     next_id: usize,
     /// The current buffer ID
     current_id: Option<usize>,
+    /// The buffer that was current before it, for `#`
+    alternate_id: Option<usize>,
+    /// Directory for undo files, if 'undofile' is set
+    undo_dir: Option<PathBuf>,
+    /// Whether buffers keep swap files
+    swap_enabled: bool,
+    /// Directory for swap files, `None` to keep them next to the files
+    swap_dir: Option<PathBuf>,
+    /// Backup settings for writing files
+    write_options: WriteOptions,
 }
 
 impl BufferManager {
@@ -75,6 +85,84 @@ impl BufferManager {
             path_to_id: HashMap::new(),
             next_id: 1,
             current_id: None,
+            alternate_id: None,
+            undo_dir: None,
+            swap_enabled: false,
+            swap_dir: None,
+            write_options: WriteOptions::default(),
+        }
+    }
+    
+    /// Get the backup settings for writing files
+    pub fn write_options(&self) -> &WriteOptions {
+        &self.write_options
+    }
+    
+    /// Set the backup settings for writing files, for open buffers as well
+    pub fn set_write_options(&mut self, write_options: WriteOptions) {
+        for buffer in self.buffers.values_mut() {
+            buffer.set_write_options(write_options.clone());
+        }
+        self.write_options = write_options;
+    }
+    
+    /// Get the directory for undo files
+    pub fn undo_dir(&self) -> Option<&Path> {
+        self.undo_dir.as_deref()
+    }
+    
+    /// Set the directory for undo files, `None` turns persistent undo off
+    ///
+    /// Applies to files opened afterwards and to the next write of open buffers.
+    pub fn set_undo_dir(&mut self, undo_dir: Option<PathBuf>) {
+        for buffer in self.buffers.values_mut() {
+            buffer.set_undo_dir(undo_dir.clone());
+        }
+        self.undo_dir = undo_dir;
+    }
+    
+    /// Turn swap files on or off and set the directory they are kept in
+    ///
+    /// Open buffers start or stop keeping swap files right away.
+    pub fn set_swap(&mut self, enabled: bool, swap_dir: Option<PathBuf>) {
+        let changed = enabled != self.swap_enabled || swap_dir != self.swap_dir;
+        self.swap_enabled = enabled;
+        self.swap_dir = swap_dir;
+        
+        if !changed {
+            return;
+        }
+        for buffer in self.buffers.values_mut() {
+            buffer.close_swap_file();
+            if enabled {
+                buffer.open_swap_file(self.swap_dir.as_deref());
+            }
+        }
+    }
+    
+    /// Check whether buffers keep swap files
+    pub fn swap_enabled(&self) -> bool {
+        self.swap_enabled
+    }
+    
+    /// Get the directory for swap files
+    pub fn swap_dir(&self) -> Option<&Path> {
+        self.swap_dir.as_deref()
+    }
+    
+    /// Write the swap files of buffers that changed since their last write
+    pub fn update_swap_files(&mut self) {
+        for buffer in self.buffers.values_mut() {
+            if let Err(err) = buffer.update_swap_file() {
+                log::warn!("Failed to write swap file for {}: {}", buffer.name(), err);
+            }
+        }
+    }
+    
+    /// Remove the swap files of all buffers, as done when the editor exits
+    pub fn close_swap_files(&mut self) {
+        for buffer in self.buffers.values_mut() {
+            buffer.close_swap_file();
         }
     }
     
@@ -83,7 +171,9 @@ impl BufferManager {
         let id = self.next_id;
         self.next_id += 1;
         
-        let buffer = Buffer::new(id);
+        let mut buffer = Buffer::new(id);
+        buffer.set_undo_dir(self.undo_dir.clone());
+        buffer.set_write_options(self.write_options.clone());
         self.buffers.insert(id, buffer);
         
         if self.current_id.is_none() {
@@ -99,7 +189,7 @@ impl BufferManager {
         
         // Check if the file is already open
         if let Some(&id) = self.path_to_id.get(&path_buf) {
-            self.current_id = Some(id);
+            self.set_current_buffer(id)?;
             return Ok(id);
         }
         
@@ -108,7 +198,7 @@ impl BufferManager {
         self.next_id += 1;
         
         // Try to open the file, but if it doesn't exist, create a new buffer with that name
-        let buffer = match Buffer::from_file(id, &path_buf) {
+        let mut buffer = match Buffer::from_file_with_undo_dir(id, &path_buf, self.undo_dir.as_deref()) {
             Ok(buffer) => buffer,
             Err(err) => {
                 // If the file doesn't exist, create a new buffer with that name
@@ -123,6 +213,7 @@ impl BufferManager {
                         }
                         buffer.file_path = Some(path_buf.clone());
                         buffer.flags.new_file = true;
+                        buffer.set_undo_dir(self.undo_dir.clone());
                         
                         buffer
                     } else {
@@ -136,10 +227,17 @@ impl BufferManager {
             }
         };
         
+        buffer.set_write_options(self.write_options.clone());
+        
+        // Keep a swap file for crash recovery
+        if self.swap_enabled {
+            buffer.open_swap_file(self.swap_dir.as_deref());
+        }
+        
         self.buffers.insert(id, buffer);
         self.path_to_id.insert(path_buf, id);
         
-        self.current_id = Some(id);
+        self.set_current_buffer(id)?;
         
         Ok(id)
     }
@@ -176,6 +274,9 @@ impl BufferManager {
             return Err(BufferManagerError::BufferNotFound(id));
         }
         
+        if self.current_id != Some(id) {
+            self.alternate_id = self.current_id;
+        }
         self.current_id = Some(id);
         Ok(())
     }
@@ -185,6 +286,11 @@ impl BufferManager {
         self.current_id
     }
     
+    /// Get the ID of the buffer that was current before the current one
+    pub fn alternate_buffer_id(&self) -> Option<usize> {
+        self.alternate_id.filter(|id| self.buffers.contains_key(id))
+    }
+    
     /// Get a buffer ID by file path
     pub fn get_buffer_id_by_path<P: AsRef<Path>>(&self, path: P) -> Option<usize> {
         self.path_to_id.get(path.as_ref()).copied()
@@ -201,8 +307,10 @@ impl BufferManager {
             self.path_to_id.remove(&path.to_path_buf());
         }
         
-        // Remove the buffer
-        self.buffers.remove(&id);
+        // Remove the buffer along with its swap file
+        if let Some(mut buffer) = self.buffers.remove(&id) {
+            buffer.close_swap_file();
+        }
         
         // Update the current buffer if necessary
         if self.current_id == Some(id) {
@@ -0,0 +1,510 @@
+//! Marks
+//!
+//! Marks are named positions in a buffer. They follow edits the way they do in
+//! Vim: text inserted or deleted before a mark moves the mark along with the
+//! text it points at. When the lines a named mark is on are deleted the mark
+//! goes away, while the marks xvim keeps itself move to where the text was.
+//! The change list, the positions `g;` and `g,` move through, follows edits
+//! the same way.
+
+use std::collections::HashMap;
+
+/// Start of the last changed or yanked text
+pub const CHANGE_START_MARK: char = '[';
+/// End of the last changed or yanked text
+pub const CHANGE_END_MARK: char = ']';
+/// Start of the last visual selection
+pub const VISUAL_START_MARK: char = '<';
+/// End of the last visual selection
+pub const VISUAL_END_MARK: char = '>';
+/// Position of the last change
+pub const LAST_CHANGE_MARK: char = '.';
+/// Position where insert mode was last stopped
+pub const LAST_INSERT_MARK: char = '^';
+/// Position before the latest jump
+pub const PREVIOUS_CONTEXT_MARK: char = '\'';
+
+/// A mark is a named position in a buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Mark {
+    /// The line number (0-based)
+    pub line: usize,
+    /// The column number (0-based)
+    pub column: usize,
+}
+
+impl Mark {
+    /// Create a new mark
+    pub fn new(line: usize, column: usize) -> Self {
+        Self { line, column }
+    }
+}
+
+/// Check whether a mark can be set by the user with `m`
+pub fn is_user_mark(name: char) -> bool {
+    name.is_ascii_alphabetic()
+        || matches!(name, '[' | ']' | '<' | '>' | '\'' | '`')
+}
+
+/// Check whether a mark is a file mark (`A`-`Z`), which is global to the editor
+pub fn is_file_mark(name: char) -> bool {
+    name.is_ascii_uppercase()
+}
+
+/// Get the name a mark is stored under, as `` ` `` and `'` are the same mark
+pub fn mark_name(name: char) -> char {
+    if name == '`' { PREVIOUS_CONTEXT_MARK } else { name }
+}
+
+/// A collection of marks for a buffer
+#[derive(Debug, Clone, Default)]
+pub struct MarkMap {
+    /// The marks, keyed by their name (a single character)
+    marks: HashMap<char, Mark>,
+}
+
+impl MarkMap {
+    /// Create a new mark map
+    pub fn new() -> Self {
+        Self {
+            marks: HashMap::new(),
+        }
+    }
+
+    /// Set a mark at the given position
+    pub fn set_mark(&mut self, name: char, line: usize, column: usize) {
+        self.marks.insert(mark_name(name), Mark::new(line, column));
+    }
+
+    /// Get a mark by name
+    pub fn get_mark(&self, name: char) -> Option<&Mark> {
+        self.marks.get(&mark_name(name))
+    }
+
+    /// Remove a mark by name
+    pub fn remove_mark(&mut self, name: char) -> Option<Mark> {
+        self.marks.remove(&mark_name(name))
+    }
+
+    /// Clear all marks
+    pub fn clear(&mut self) {
+        self.marks.clear();
+    }
+
+    /// Get all marks
+    pub fn all_marks(&self) -> impl Iterator<Item = (&char, &Mark)> {
+        self.marks.iter()
+    }
+
+    /// Move marks for text inserted between `start` and `end`
+    ///
+    /// `end` is the position just after the inserted text. Marks at or after
+    /// `start` move with the text that follows the insertion.
+    pub fn adjust_insert(&mut self, start: Mark, end: Mark) {
+        for mark in self.marks.values_mut() {
+            adjust_insert(mark, start, end);
+        }
+    }
+
+    /// Move marks for text between `start` and `old_end` replaced by text ending at `new_end`
+    ///
+    /// Marks in the replaced text stay where they are, as long as that is
+    /// still inside the new text, so replacing lines keeps the marks on them.
+    pub fn adjust_replace(&mut self, start: Mark, old_end: Mark, new_end: Mark) {
+        for mark in self.marks.values_mut() {
+            adjust_replace(mark, start, old_end, new_end);
+        }
+    }
+
+    /// Move marks for text deleted between `start` and `end`
+    ///
+    /// `end` is the position just after the deleted text. Named marks on
+    /// lines that were deleted entirely are removed, other marks in the
+    /// deleted text move to `start`.
+    pub fn adjust_delete(&mut self, start: Mark, end: Mark) {
+        self.marks.retain(|&name, mark| {
+            let line_deleted = adjust_delete(mark, start, end);
+            !(line_deleted && name.is_ascii_alphabetic())
+        });
+    }
+}
+
+/// The positions of the changes made to a buffer, oldest first, for `g;` and `g,`
+///
+/// Changes close to each other on the same line make a single entry, at the
+/// position of the latest one.
+#[derive(Debug, Clone, Default)]
+pub struct ChangeList {
+    /// Positions of the changes
+    changes: Vec<Mark>,
+    /// Entry `g;` and `g,` moved to last, or the length of the list after a change
+    index: usize,
+}
+
+impl ChangeList {
+    /// Maximum number of changes remembered
+    pub const MAX_LEN: usize = 100;
+
+    /// Columns apart changes on the same line have to be to make separate entries
+    const SAME_LINE_COLUMNS: usize = 79;
+
+    /// Create an empty change list
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the position of a change
+    pub fn add(&mut self, position: Mark) {
+        match self.changes.last_mut() {
+            Some(last) if last.line == position.line
+                && last.column.abs_diff(position.column) <= Self::SAME_LINE_COLUMNS => *last = position,
+            _ => {
+                if self.changes.len() == Self::MAX_LEN {
+                    self.changes.remove(0);
+                }
+                self.changes.push(position);
+            },
+        }
+        self.index = self.changes.len();
+    }
+
+    /// Get the positions of the changes, oldest first
+    pub fn changes(&self) -> &[Mark] {
+        &self.changes
+    }
+
+    /// Get the entry `g;` and `g,` moved to last, which is the length of the list after a change
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Move `count` entries back in the list, as `g;` does
+    ///
+    /// A count past the oldest change stops at it. Returns `None` when already
+    /// at the oldest change.
+    pub fn older(&mut self, count: usize) -> Option<Mark> {
+        if self.index == 0 {
+            return None;
+        }
+        self.index = self.index.saturating_sub(count.max(1));
+        Some(self.changes[self.index])
+    }
+
+    /// Move `count` entries forward in the list, as `g,` does
+    ///
+    /// A count past the newest change stops at it. Returns `None` when already
+    /// at the newest change.
+    pub fn newer(&mut self, count: usize) -> Option<Mark> {
+        if self.index + 1 >= self.changes.len() {
+            return None;
+        }
+        self.index = (self.index + count.max(1)).min(self.changes.len() - 1);
+        Some(self.changes[self.index])
+    }
+
+    /// Move the positions for text inserted between `start` and `end`
+    pub fn adjust_insert(&mut self, start: Mark, end: Mark) {
+        for change in self.changes.iter_mut() {
+            adjust_insert(change, start, end);
+        }
+    }
+
+    /// Move the positions for text between `start` and `old_end` replaced by text ending at `new_end`
+    pub fn adjust_replace(&mut self, start: Mark, old_end: Mark, new_end: Mark) {
+        for change in self.changes.iter_mut() {
+            adjust_replace(change, start, old_end, new_end);
+        }
+    }
+
+    /// Move the positions for text deleted between `start` and `end`
+    ///
+    /// Changes in the deleted text move to where it was.
+    pub fn adjust_delete(&mut self, start: Mark, end: Mark) {
+        for change in self.changes.iter_mut() {
+            adjust_delete(change, start, end);
+        }
+    }
+}
+
+/// Lines marked by `:global`, in order, that move with the text until each is taken
+///
+/// A line deleted entirely loses its mark, so it isn't taken.
+#[derive(Debug, Clone, Default)]
+pub struct LineMarks {
+    marks: Vec<Mark>,
+}
+
+impl LineMarks {
+    /// Create an empty set of line marks
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mark lines, in place of the lines marked before
+    pub fn set(&mut self, lines: impl IntoIterator<Item = usize>) {
+        self.marks = lines.into_iter().map(|line| Mark::new(line, 0)).collect();
+    }
+
+    /// Take the mark off the first line still marked, returning that line
+    pub fn take_first(&mut self) -> Option<usize> {
+        if self.marks.is_empty() {
+            None
+        } else {
+            Some(self.marks.remove(0).line)
+        }
+    }
+
+    /// Remove all the marks
+    pub fn clear(&mut self) {
+        self.marks.clear();
+    }
+
+    /// Move the marks for text inserted between `start` and `end`
+    pub fn adjust_insert(&mut self, start: Mark, end: Mark) {
+        for mark in &mut self.marks {
+            adjust_insert(mark, start, end);
+        }
+    }
+
+    /// Move the marks for text between `start` and `old_end` replaced by text ending at `new_end`
+    pub fn adjust_replace(&mut self, start: Mark, old_end: Mark, new_end: Mark) {
+        for mark in &mut self.marks {
+            adjust_replace(mark, start, old_end, new_end);
+        }
+    }
+
+    /// Move the marks for text deleted between `start` and `end`, removing
+    /// those on lines that are gone
+    ///
+    /// A line is gone when the line break before it was deleted, or when
+    /// the deletion starts at its start and takes its line break.
+    pub fn adjust_delete(&mut self, start: Mark, end: Mark) {
+        self.marks.retain_mut(|mark| {
+            let gone = if mark.line > start.line {
+                *mark < end || (*mark == end && start.column > 0)
+            } else {
+                *mark == start && start.column == 0 && end.line > start.line
+            };
+            if !gone {
+                adjust_delete(mark, start, end);
+            }
+            !gone
+        });
+    }
+}
+
+/// Move a position for text inserted between `start` and `end`
+fn adjust_insert(mark: &mut Mark, start: Mark, end: Mark) {
+    if *mark < start {
+        return;
+    }
+
+    if mark.line == start.line {
+        mark.column = end.column + (mark.column - start.column);
+        mark.line = end.line;
+    } else {
+        mark.line += end.line - start.line;
+    }
+}
+
+/// Move a position for text between `start` and `old_end` replaced by text ending at `new_end`
+fn adjust_replace(mark: &mut Mark, start: Mark, old_end: Mark, new_end: Mark) {
+    if *mark < start {
+        return;
+    }
+
+    if *mark >= old_end {
+        if mark.line == old_end.line {
+            mark.column = new_end.column + (mark.column - old_end.column);
+            mark.line = new_end.line;
+        } else {
+            mark.line = mark.line - old_end.line + new_end.line;
+        }
+    } else if *mark > new_end {
+        *mark = new_end;
+    }
+}
+
+/// Move a position for text deleted between `start` and `end`
+///
+/// Returns whether the position was on a line that was deleted entirely.
+fn adjust_delete(mark: &mut Mark, start: Mark, end: Mark) -> bool {
+    if *mark < start {
+        return false;
+    }
+
+    if *mark >= end {
+        if mark.line == end.line {
+            mark.column = start.column + (mark.column - end.column);
+            mark.line = start.line;
+        } else {
+            mark.line -= end.line - start.line;
+        }
+        return false;
+    }
+
+    let line_deleted = (mark.line > start.line || start.column == 0) && mark.line < end.line;
+    *mark = start;
+    line_deleted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mark_creation() {
+        let mark = Mark::new(10, 5);
+        assert_eq!(mark.line, 10);
+        assert_eq!(mark.column, 5);
+    }
+
+    #[test]
+    fn test_mark_map() {
+        let mut mark_map = MarkMap::new();
+
+        // Set some marks
+        mark_map.set_mark('a', 10, 5);
+        mark_map.set_mark('b', 20, 10);
+
+        // Get marks
+        let mark_a = mark_map.get_mark('a').unwrap();
+        assert_eq!(mark_a.line, 10);
+        assert_eq!(mark_a.column, 5);
+
+        let mark_b = mark_map.get_mark('b').unwrap();
+        assert_eq!(mark_b.line, 20);
+        assert_eq!(mark_b.column, 10);
+
+        // Non-existent mark
+        assert!(mark_map.get_mark('c').is_none());
+
+        // Remove a mark
+        let removed = mark_map.remove_mark('a').unwrap();
+        assert_eq!(removed.line, 10);
+        assert_eq!(removed.column, 5);
+        assert!(mark_map.get_mark('a').is_none());
+
+        // Clear all marks
+        mark_map.clear();
+        assert!(mark_map.get_mark('b').is_none());
+    }
+
+    #[test]
+    fn test_mark_map_adjust_insert() {
+        let mut mark_map = MarkMap::new();
+        mark_map.set_mark('a', 0, 2);
+        mark_map.set_mark('b', 1, 4);
+        mark_map.set_mark('c', 3, 0);
+
+        // Two lines inserted at the start of line 1
+        mark_map.adjust_insert(Mark::new(1, 0), Mark::new(3, 0));
+        assert_eq!(mark_map.get_mark('a'), Some(&Mark::new(0, 2)));
+        assert_eq!(mark_map.get_mark('b'), Some(&Mark::new(3, 4)));
+        assert_eq!(mark_map.get_mark('c'), Some(&Mark::new(5, 0)));
+
+        // A line split before 'b
+        mark_map.adjust_insert(Mark::new(3, 1), Mark::new(4, 0));
+        assert_eq!(mark_map.get_mark('b'), Some(&Mark::new(4, 3)));
+
+        // Text inserted on the same line
+        mark_map.adjust_insert(Mark::new(4, 0), Mark::new(4, 2));
+        assert_eq!(mark_map.get_mark('b'), Some(&Mark::new(4, 5)));
+    }
+
+    #[test]
+    fn test_mark_map_adjust_delete() {
+        let mut mark_map = MarkMap::new();
+        mark_map.set_mark('a', 1, 2);
+        mark_map.set_mark('b', 3, 4);
+        mark_map.set_mark('c', 2, 1);
+        mark_map.set_mark(']', 2, 3);
+
+        // Lines 1 and 2 deleted
+        mark_map.adjust_delete(Mark::new(1, 0), Mark::new(3, 0));
+        assert_eq!(mark_map.get_mark('a'), None);
+        assert_eq!(mark_map.get_mark('c'), None);
+        assert_eq!(mark_map.get_mark(']'), Some(&Mark::new(1, 0)));
+        assert_eq!(mark_map.get_mark('b'), Some(&Mark::new(1, 4)));
+
+        // Part of a line deleted
+        mark_map.set_mark('a', 1, 1);
+        mark_map.adjust_delete(Mark::new(1, 0), Mark::new(1, 2));
+        assert_eq!(mark_map.get_mark('a'), Some(&Mark::new(1, 0)));
+        assert_eq!(mark_map.get_mark('b'), Some(&Mark::new(1, 2)));
+
+        // Joining lines moves the marks on the next line
+        mark_map.set_mark('d', 0, 3);
+        mark_map.adjust_delete(Mark::new(0, 5), Mark::new(1, 0));
+        assert_eq!(mark_map.get_mark('d'), Some(&Mark::new(0, 3)));
+        assert_eq!(mark_map.get_mark('b'), Some(&Mark::new(0, 7)));
+    }
+
+    #[test]
+    fn test_mark_map_adjust_replace() {
+        let mut mark_map = MarkMap::new();
+        mark_map.set_mark('a', 1, 2);
+        mark_map.set_mark('b', 3, 1);
+        mark_map.set_mark('c', 5, 0);
+
+        // Lines 1 to 3 replaced by a single line
+        mark_map.adjust_replace(Mark::new(1, 0), Mark::new(4, 0), Mark::new(2, 0));
+        assert_eq!(mark_map.get_mark('a'), Some(&Mark::new(1, 2)));
+        assert_eq!(mark_map.get_mark('b'), Some(&Mark::new(2, 0)));
+        assert_eq!(mark_map.get_mark('c'), Some(&Mark::new(3, 0)));
+    }
+
+    #[test]
+    fn test_mark_names() {
+        let mut mark_map = MarkMap::new();
+        mark_map.set_mark('`', 1, 1);
+        assert_eq!(mark_map.get_mark('\''), Some(&Mark::new(1, 1)));
+        assert!(is_user_mark('a') && is_user_mark('Z') && is_user_mark('<'));
+        assert!(!is_user_mark('.') && !is_user_mark('1'));
+        assert!(is_file_mark('A') && !is_file_mark('a'));
+    }
+
+    #[test]
+    fn test_change_list() {
+        let mut change_list = ChangeList::new();
+        assert!(change_list.older(1).is_none());
+
+        change_list.add(Mark::new(1, 0));
+        change_list.add(Mark::new(1, 4));
+        change_list.add(Mark::new(5, 2));
+        change_list.add(Mark::new(9, 0));
+        assert_eq!(change_list.changes(), &[Mark::new(1, 4), Mark::new(5, 2), Mark::new(9, 0)]);
+
+        // g; goes back from the latest change, g, forward again
+        assert_eq!(change_list.older(1), Some(Mark::new(9, 0)));
+        assert_eq!(change_list.older(5), Some(Mark::new(1, 4)));
+        assert!(change_list.older(1).is_none());
+        assert_eq!(change_list.newer(1), Some(Mark::new(5, 2)));
+        assert_eq!(change_list.newer(1), Some(Mark::new(9, 0)));
+        assert!(change_list.newer(1).is_none());
+
+        // The positions follow edits, and a new change starts over at the end
+        change_list.adjust_delete(Mark::new(2, 0), Mark::new(4, 0));
+        assert_eq!(change_list.changes()[1], Mark::new(3, 2));
+        change_list.add(Mark::new(0, 0));
+        assert_eq!(change_list.index(), 4);
+    }
+
+    #[test]
+    fn test_line_marks() {
+        let mut lines = LineMarks::new();
+        lines.set([1, 3, 5, 6]);
+
+        // Deleting line 1 takes its mark and moves the others up
+        lines.adjust_delete(Mark::new(1, 0), Mark::new(2, 0));
+        // A line inserted above line 2 (once 3) moves it down again
+        lines.adjust_insert(Mark::new(2, 0), Mark::new(3, 0));
+        // Deleting the last line goes from the end of the line before it
+        lines.adjust_delete(Mark::new(5, 4), Mark::new(6, 2));
+        // Changing text in a line keeps its mark
+        lines.adjust_delete(Mark::new(5, 0), Mark::new(5, 3));
+        assert_eq!(lines.take_first(), Some(3));
+        assert_eq!(lines.take_first(), Some(5));
+        assert_eq!(lines.take_first(), None);
+    }
+}
@@ -7,81 +7,33 @@
 mod manager;
 mod change;
 mod syntax;
+pub mod undofile;
+pub mod swap;
+mod encoding;
+mod write;
+pub mod mark;
 
 pub use manager::{BufferManager, BufferManagerError, BufferManagerResult};
-pub use change::{Change, ChangeGroup, ChangeHistory, ChangeType};
+pub use change::{Change, ChangeGroup, ChangeHistory, ChangeType, HistorySnapshot, SavedUndoState, UndoBranch, UndoStep, UndoTime};
 pub use syntax::{BufferSyntax, BufferSyntaxExt};
+pub use encoding::{FileEncoding, FileFormat};
+pub use write::{FileStamp, WriteOptions};
+pub use mark::{ChangeList, LineMarks, Mark, MarkMap};
 
 use ropey::Rope;
 use std::error::Error;
 use std::fs::File;
-use std::io::{self, BufReader, Read, Write};
+use std::io::{self, BufReader, Read};
 use std::path::{Path, PathBuf};
 use std::cmp::min;
-use regex::Regex;
-use std::collections::HashMap;
+use crate::cursor::CursorPosition;
+use crate::search::{MatchContext, Pattern, PatternOptions, Replacement};
+
+use swap::SwapInfo;
 
 // Forward declaration for VisualArea to avoid circular dependency
 pub use crate::visual::VisualArea;
 
-// Mark definitions (moved from mark module)
-/// A mark is a named position in a buffer
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct Mark {
-    /// The line number (0-based)
-    pub line: usize,
-    /// The column number (0-based)
-    pub column: usize,
-}
-
-impl Mark {
-    /// Create a new mark
-    pub fn new(line: usize, column: usize) -> Self {
-        Self { line, column }
-    }
-}
-
-/// A collection of marks for a buffer
-#[derive(Debug, Clone, Default)]
-pub struct MarkMap {
-    /// The marks, keyed by their name (a single character)
-    marks: HashMap<char, Mark>,
-}
-
-impl MarkMap {
-    /// Create a new mark map
-    pub fn new() -> Self {
-        Self {
-            marks: HashMap::new(),
-        }
-    }
-
-    /// Set a mark at the given position
-    pub fn set_mark(&mut self, name: char, line: usize, column: usize) {
-        self.marks.insert(name, Mark::new(line, column));
-    }
-
-    /// Get a mark by name
-    pub fn get_mark(&self, name: char) -> Option<&Mark> {
-        self.marks.get(&name)
-    }
-
-    /// Remove a mark by name
-    pub fn remove_mark(&mut self, name: char) -> Option<Mark> {
-        self.marks.remove(&name)
-    }
-
-    /// Clear all marks
-    pub fn clear(&mut self) {
-        self.marks.clear();
-    }
-
-    /// Get all marks
-    pub fn all_marks(&self) -> impl Iterator<Item = (&char, &Mark)> {
-        self.marks.iter()
-    }
-}
-
 /// Errors that can occur during buffer operations
 #[derive(Debug)]
 pub enum BufferError {
@@ -91,6 +43,14 @@ pub enum BufferError {
     ReadOnly,
     /// Invalid position in the buffer
     InvalidPosition,
+    /// The undo file could not be used
+    UndoFile(String),
+    /// The swap file could not be used
+    SwapFile(String),
+    /// The file is read-only on disk, writing needs `!`
+    FileReadOnly(PathBuf),
+    /// The file changed on disk since it was read, writing needs `!`
+    FileChanged(PathBuf),
     /// Other errors
     Other(String),
 }
@@ -101,6 +61,10 @@ impl std::fmt::Display for BufferError {
             BufferError::Io(err) => write!(f, "I/O error: {}", err),
             BufferError::ReadOnly => write!(f, "Buffer is read-only"),
             BufferError::InvalidPosition => write!(f, "Invalid position in buffer"),
+            BufferError::UndoFile(msg) => write!(f, "{}", msg),
+            BufferError::SwapFile(msg) => write!(f, "{}", msg),
+            BufferError::FileReadOnly(path) => write!(f, "E505: \"{}\" is read-only (add ! to override)", path.display()),
+            BufferError::FileChanged(path) => write!(f, "E949: \"{}\" has changed since reading it (add ! to override)", path.display()),
             BufferError::Other(msg) => write!(f, "{}", msg),
         }
     }
@@ -117,6 +81,24 @@ impl From<io::Error> for BufferError {
 /// Result type for buffer operations
 pub type BufferResult<T> = Result<T, BufferError>;
 
+/// How the file of a buffer changed on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskChange {
+    /// The file is as it was last read or written
+    Unchanged,
+    /// The file contents changed
+    Changed,
+    /// The file was removed
+    Deleted,
+}
+
+/// Decode the bytes of a file into buffer text with `\n` line endings
+fn decode_file_bytes(bytes: &[u8]) -> (String, FileFormat, FileEncoding, bool) {
+    let (text, fileencoding, bomb) = FileEncoding::decode(bytes);
+    let fileformat = FileFormat::detect(&text);
+    (fileformat.to_buffer_text(&text), fileformat, fileencoding, bomb)
+}
+
 /// Buffer state flags
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct BufferFlags {
@@ -191,10 +173,38 @@ pub struct Buffer {
     change_history: ChangeHistory,
     /// Marks in the buffer
     marks: MarkMap,
+    /// Positions of the changes made, for `g;` and `g,`
+    change_list: ChangeList,
+    /// Lines `:global` has yet to run its command on
+    global_lines: LineMarks,
     /// Syntax highlighting data
     syntax: syntax::BufferSyntax,
     /// Visual area for 'gv' command
     pub(crate) visual_area: Option<crate::visual::VisualArea>,
+    /// Directory for undo files, if 'undofile' is set
+    undo_dir: Option<PathBuf>,
+    /// Swap file written by this buffer
+    swap_path: Option<PathBuf>,
+    /// Directory the swap file is kept in, `None` to keep it next to the file
+    swap_dir: Option<PathBuf>,
+    /// Hash of the text last written to the swap file, empty if it had no changes
+    swap_hash: Option<String>,
+    /// Swap file left behind by a previous session that can be recovered
+    recover_path: Option<PathBuf>,
+    /// Warnings about undo and swap files that haven't been shown yet
+    messages: Vec<String>,
+    /// Line ending style used when writing the file
+    fileformat: FileFormat,
+    /// Character encoding used when writing the file
+    fileencoding: FileEncoding,
+    /// Whether the file starts with a byte order mark
+    bomb: bool,
+    /// The file on disk as it was last read or written
+    disk_stamp: Option<FileStamp>,
+    /// Hash of the file contents as they were last read or written
+    disk_hash: Option<String>,
+    /// Backup settings for writing the file
+    write_options: WriteOptions,
 }
 
 impl Buffer {
@@ -217,19 +227,45 @@ impl Buffer {
             window_count: 0,
             change_history: ChangeHistory::new(),
             marks: MarkMap::new(),
+            change_list: ChangeList::new(),
+            global_lines: LineMarks::new(),
             syntax: syntax::BufferSyntax::new(),
             visual_area: None,
+            undo_dir: None,
+            swap_path: None,
+            swap_dir: None,
+            swap_hash: None,
+            recover_path: None,
+            messages: Vec::new(),
+            fileformat: FileFormat::default(),
+            fileencoding: FileEncoding::default(),
+            bomb: false,
+            disk_stamp: None,
+            disk_hash: None,
+            write_options: WriteOptions::default(),
         }
     }
 
     /// Create a buffer from a file
     pub fn from_file<P: AsRef<Path>>(id: usize, path: P) -> BufferResult<Self> {
+        Self::from_file_with_undo_dir(id, path, None)
+    }
+    
+    /// Create a buffer from a file, restoring its undo history from the undo directory
+    ///
+    /// A missing undo file is ignored. An undo file that is corrupt or doesn't
+    /// match the file contents leaves the history empty and is reported through
+    /// [`Buffer::take_messages`].
+    pub fn from_file_with_undo_dir<P: AsRef<Path>>(id: usize, path: P, undo_dir: Option<&Path>) -> BufferResult<Self> {
         let path = path.as_ref();
         let file = File::open(path)?;
         let mut reader = BufReader::new(file);
         
-        let mut content = String::new();
-        reader.read_to_string(&mut content)?;
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        
+        // Decode the file and normalize its line endings
+        let (content, fileformat, fileencoding, bomb) = decode_file_bytes(&bytes);
         
         let file_name = path.file_name()
             .and_then(|n| n.to_str())
@@ -252,10 +288,29 @@ impl Buffer {
             window_count: 0,
             change_history: ChangeHistory::new(),
             marks: MarkMap::new(),
+            change_list: ChangeList::new(),
+            global_lines: LineMarks::new(),
             syntax: syntax::BufferSyntax::new(),
             visual_area: None,
+            undo_dir: undo_dir.map(Path::to_path_buf),
+            swap_path: None,
+            swap_dir: None,
+            swap_hash: None,
+            recover_path: None,
+            messages: Vec::new(),
+            fileformat,
+            fileencoding,
+            bomb,
+            disk_stamp: FileStamp::of(path),
+            disk_hash: Some(undofile::bytes_hash(&bytes)),
+            write_options: WriteOptions::default(),
         };
         
+        // Restore the undo history from the last session
+        if let Err(err) = buffer.read_undo_file() {
+            buffer.messages.push(err.to_string());
+        }
+        
         // Try to auto-detect syntax based on file extension
         if let Ok(registry) = crate::syntax::create_default_registry() {
             buffer.auto_detect_syntax(&registry);
@@ -289,6 +344,15 @@ impl Buffer {
         self.content.to_string()
     }
     
+    /// Get the text between two character indices (end exclusive)
+    pub fn text_range(&self, start: usize, end: usize) -> BufferResult<String> {
+        if start > end || end > self.content.len_chars() {
+            return Err(BufferError::InvalidPosition);
+        }
+        
+        Ok(self.content.slice(start..end).to_string())
+    }
+    
     /// Get the number of lines in the buffer
     pub fn line_count(&self) -> usize {
         self.content.len_lines()
@@ -385,9 +449,13 @@ impl Buffer {
             text: text.to_string(),
         });
         
-        self.content.insert(char_idx, text);
+        self.insert_text(char_idx, text);
         self.flags.modified = true;
         
+        // '[ and '] cover the inserted text, '. is on its last character
+        let end = self.mark_at(char_idx + text.chars().count().saturating_sub(1));
+        self.set_change_marks(self.mark_at(char_idx), end, end);
+        
         Ok(())
     }
     
@@ -411,11 +479,115 @@ impl Buffer {
             text: deleted_text,
         });
         
-        self.content.remove(start..end);
+        self.remove_text(start, end);
         self.flags.modified = true;
         
+        let start = self.mark_at(start);
+        self.set_change_marks(start, start, start);
+
         Ok(())
     }
+
+    /// Replace the text in the specified range as a single change
+    ///
+    /// Unlike a delete followed by an insert, marks in the range stay where
+    /// they are as far as the new text allows.
+    pub fn replace(&mut self, start: usize, end: usize, text: &str) -> BufferResult<()> {
+        if !self.modifiable {
+            return Err(BufferError::ReadOnly);
+        }
+
+        if start > end || end > self.content.len_chars() {
+            return Err(BufferError::InvalidPosition);
+        }
+
+        // Record the change for undo
+        self.change_history.record_change(ChangeType::Replace {
+            start,
+            end,
+            old_text: self.content.slice(start..end).to_string(),
+            new_text: text.to_string(),
+        });
+
+        self.replace_text(start, end, text);
+        self.flags.modified = true;
+
+        // '[ and '] cover the new text, '. is on its last character
+        let end = self.mark_at(start + text.chars().count().saturating_sub(1));
+        self.set_change_marks(self.mark_at(start), end, end);
+
+        Ok(())
+    }
+
+    /// Close the undo step being built, so the next change starts a new one
+    ///
+    /// While changes are joined this does nothing.
+    pub fn end_undo_step(&mut self) {
+        self.change_history.end_step();
+    }
+    
+    /// Make the changes from now on one undo step, until `end_undo_join`
+    pub fn start_undo_join(&mut self) {
+        self.change_history.set_joining(true);
+    }
+    
+    /// Stop joining changes, closing the undo step they made
+    pub fn end_undo_join(&mut self) {
+        self.change_history.set_joining(false);
+    }
+
+    /// Insert text, moving marks along with it
+    fn insert_text(&mut self, char_idx: usize, text: &str) {
+        let start = self.mark_at(char_idx);
+        self.content.insert(char_idx, text);
+        let end = self.mark_at(char_idx + text.chars().count());
+        self.marks.adjust_insert(start, end);
+        self.change_list.adjust_insert(start, end);
+        self.global_lines.adjust_insert(start, end);
+    }
+    
+    /// Remove text, moving marks to where it was
+    fn remove_text(&mut self, start: usize, end: usize) {
+        let (start_mark, end_mark) = (self.mark_at(start), self.mark_at(end));
+        self.content.remove(start..end);
+        self.marks.adjust_delete(start_mark, end_mark);
+        self.change_list.adjust_delete(start_mark, end_mark);
+        self.global_lines.adjust_delete(start_mark, end_mark);
+    }
+    
+    /// Replace text, keeping marks in it where they are as far as possible
+    fn replace_text(&mut self, start: usize, end: usize, text: &str) {
+        let (start_mark, end_mark) = (self.mark_at(start), self.mark_at(end));
+        self.content.remove(start..end);
+        self.content.insert(start, text);
+        let new_end = self.mark_at(start + text.chars().count());
+        self.marks.adjust_replace(start_mark, end_mark, new_end);
+        self.change_list.adjust_replace(start_mark, end_mark, new_end);
+        self.global_lines.adjust_replace(start_mark, end_mark, new_end);
+    }
+    
+    /// Get the line and column of a character index, as marks store them
+    fn mark_at(&self, char_idx: usize) -> Mark {
+        let char_idx = char_idx.min(self.content.len_chars());
+        let line = self.content.char_to_line(char_idx);
+        Mark::new(line, char_idx - self.content.line_to_char(line))
+    }
+    
+    /// Set the '[, '] and '. marks after a change, and add it to the change list
+    fn set_change_marks(&mut self, start: Mark, end: Mark, change: Mark) {
+        self.marks.set_mark(mark::CHANGE_START_MARK, start.line, start.column);
+        self.marks.set_mark(mark::CHANGE_END_MARK, end.line, end.column);
+        self.marks.set_mark(mark::LAST_CHANGE_MARK, change.line, change.column);
+        self.change_list.add(change);
+    }
+    
+    /// Set the '[ and '] marks around text that was yanked, from `start` up to `end`
+    pub fn set_yank_marks(&mut self, start: usize, end: usize) {
+        let start_mark = self.mark_at(start);
+        let end_mark = self.mark_at(end.saturating_sub(1).max(start));
+        self.marks.set_mark(mark::CHANGE_START_MARK, start_mark.line, start_mark.column);
+        self.marks.set_mark(mark::CHANGE_END_MARK, end_mark.line, end_mark.column);
+    }
     
     /// Check if the buffer has been modified
     pub fn is_modified(&self) -> bool {
@@ -480,6 +652,11 @@ impl Buffer {
         self.buffer_type
     }
     
+    /// Set the buffer type
+    pub fn set_buffer_type(&mut self, buffer_type: BufferType) {
+        self.buffer_type = buffer_type;
+    }
+    
     /// Set a mark at the current cursor position
     pub fn set_mark(&mut self, name: char, line: usize, column: usize) -> BufferResult<()> {
         if line >= self.line_count() {
@@ -511,6 +688,21 @@ impl Buffer {
         self.marks.all_marks()
     }
     
+    /// Get the positions of the changes made to the buffer
+    pub fn change_list(&self) -> &ChangeList {
+        &self.change_list
+    }
+    
+    /// Get the change list to move through it with `g;` and `g,`
+    pub fn change_list_mut(&mut self) -> &mut ChangeList {
+        &mut self.change_list
+    }
+    
+    /// Get a mutable reference to the lines marked by `:global`
+    pub fn global_lines_mut(&mut self) -> &mut LineMarks {
+        &mut self.global_lines
+    }
+    
     /// Convert a mark position to a character index
     pub fn mark_to_char_idx(&self, mark: &Mark) -> BufferResult<usize> {
         if mark.line >= self.line_count() {
@@ -534,58 +726,96 @@ impl Buffer {
                 return Err(BufferError::InvalidPosition);
             }
             
-            let line_content = self.line(mark.line)?;
-            if mark.column > line_content.len() {
-                return Err(BufferError::InvalidPosition);
-            }
+            // Text inside a line may have changed under the mark
+            let column = mark.column.min(self.line_length(mark.line)?);
             
-            Ok(Some((mark.line, mark.column)))
+            Ok(Some((mark.line, column)))
         } else {
             Ok(None)
         }
     }
     
+    /// Revert a change group, applying its changes in reverse order
+    fn apply_undo_group(&mut self, change_group: &ChangeGroup) {
+        for change in change_group.changes.iter().rev() {
+            match &change.change_type {
+                ChangeType::Insert { position, text } => {
+                    // For undo, an Insert becomes a Delete
+                    let end = *position + text.chars().count();
+                    if end <= self.content.len_chars() {
+                        self.remove_text(*position, end);
+                    }
+                },
+                ChangeType::Delete { start, end: _, text } => {
+                    // For undo, a Delete becomes an Insert
+                    if *start <= self.content.len_chars() {
+                        self.insert_text(*start, text);
+                    }
+                },
+                ChangeType::Replace { start, end: _, old_text, new_text } => {
+                    // For undo, a Replace reverts to the old text
+                    let end = *start + new_text.chars().count();
+                    if end <= self.content.len_chars() {
+                        self.replace_text(*start, end, old_text);
+                    }
+                },
+            }
+        }
+    }
+    
+    /// Reapply a change group
+    fn apply_redo_group(&mut self, change_group: &ChangeGroup) {
+        for change in &change_group.changes {
+            match &change.change_type {
+                ChangeType::Insert { position, text } => {
+                    // For redo, an Insert is applied as is
+                    if *position <= self.content.len_chars() {
+                        self.insert_text(*position, text);
+                    }
+                },
+                ChangeType::Delete { start, end, text: _ } => {
+                    // For redo, a Delete is applied as is
+                    if *start <= *end && *end <= self.content.len_chars() {
+                        self.remove_text(*start, *end);
+                    }
+                },
+                ChangeType::Replace { start, end, old_text: _, new_text } => {
+                    // For redo, a Replace applies the new text
+                    if *start <= *end && *end <= self.content.len_chars() {
+                        self.replace_text(*start, *end, new_text);
+                    }
+                },
+            }
+        }
+    }
+    
+    /// Apply the steps for moving to another state in the undo tree
+    fn apply_undo_steps(&mut self, steps: &[UndoStep]) -> bool {
+        for step in steps {
+            match step {
+                UndoStep::Undo(change_group) => self.apply_undo_group(change_group),
+                UndoStep::Redo(change_group) => self.apply_redo_group(change_group),
+            }
+        }
+        
+        // The buffer is unmodified again when it returns to the written state
+        if !steps.is_empty() {
+            self.flags.modified = !self.change_history.is_saved_state();
+        }
+        
+        !steps.is_empty()
+    }
+    
     /// Undo the last change
     pub fn undo(&mut self) -> BufferResult<bool> {
         if !self.modifiable {
             return Err(BufferError::ReadOnly);
         }
         
-        if let Some(change_group) = self.change_history.undo() {
-            // Apply each change in the group
-            for change in &change_group.changes {
-                match &change.change_type {
-                    ChangeType::Insert { position, text } => {
-                        // For undo, an Insert becomes a Delete
-                        let end = *position + text.chars().count();
-                        if *position < self.content.len_chars() && end <= self.content.len_chars() {
-                            self.content.remove(*position..end);
-                        }
-                    },
-                    ChangeType::Delete { start, end: _, text } => {
-                        // For undo, a Delete becomes an Insert
-                        if *start <= self.content.len_chars() {
-                            self.content.insert(*start, text);
-                        }
-                    },
-                    ChangeType::Replace { start, end: _, old_text, new_text: _ } => {
-                        // For undo, a Replace reverts to the old text
-                        let end = *start + old_text.chars().count();
-                        if *start < self.content.len_chars() && end <= self.content.len_chars() {
-                            self.content.remove(*start..end);
-                            self.content.insert(*start, old_text);
-                        }
-                    },
-                }
-            }
-            
-            // Mark the buffer as modified
-            self.flags.modified = true;
-            
-            Ok(true)
-        } else {
+        match self.change_history.undo() {
+            Some(change_group) => Ok(self.apply_undo_steps(&[UndoStep::Undo(change_group)])),
             // No changes to undo
-            Ok(false)
+            None => Ok(false),
         }
     }
     
@@ -595,40 +825,50 @@ impl Buffer {
             return Err(BufferError::ReadOnly);
         }
         
-        if let Some(change_group) = self.change_history.redo() {
-            // Apply each change in the group
-            for change in &change_group.changes {
-                match &change.change_type {
-                    ChangeType::Insert { position, text } => {
-                        // For redo, an Insert is applied as is
-                        self.content.insert(*position, text);
-                    },
-                    ChangeType::Delete { start, end, text: _ } => {
-                        // For redo, a Delete is applied as is
-                        if *start < self.content.len_chars() && *end <= self.content.len_chars() {
-                            self.content.remove(*start..*end);
-                        }
-                    },
-                    ChangeType::Replace { start, end, old_text: _, new_text } => {
-                        // For redo, a Replace applies the new text
-                        if *start < self.content.len_chars() && *end <= self.content.len_chars() {
-                            self.content.remove(*start..*end);
-                            self.content.insert(*start, new_text);
-                        }
-                    },
-                }
-            }
-            
-            // Mark the buffer as modified
-            self.flags.modified = true;
-            
-            Ok(true)
-        } else {
+        match self.change_history.redo() {
+            Some(change_group) => Ok(self.apply_undo_steps(&[UndoStep::Redo(change_group)])),
             // No changes to redo
-            Ok(false)
+            None => Ok(false),
+        }
+    }
+    
+    /// Jump to the state after change `seq` in the undo tree (`:undo N`)
+    pub fn undo_to(&mut self, seq: usize) -> BufferResult<bool> {
+        if !self.modifiable {
+            return Err(BufferError::ReadOnly);
+        }
+        
+        match self.change_history.goto(seq) {
+            Some(steps) => Ok(self.apply_undo_steps(&steps)),
+            None => Err(BufferError::Other(format!("Undo number {} not found", seq))),
         }
     }
     
+    /// Go to an older text state (`g-` and `:earlier`)
+    pub fn earlier(&mut self, time: UndoTime) -> BufferResult<bool> {
+        if !self.modifiable {
+            return Err(BufferError::ReadOnly);
+        }
+        
+        let steps = self.change_history.earlier(time);
+        Ok(self.apply_undo_steps(&steps))
+    }
+    
+    /// Go to a newer text state (`g+` and `:later`)
+    pub fn later(&mut self, time: UndoTime) -> BufferResult<bool> {
+        if !self.modifiable {
+            return Err(BufferError::ReadOnly);
+        }
+        
+        let steps = self.change_history.later(time);
+        Ok(self.apply_undo_steps(&steps))
+    }
+    
+    /// Get the change history
+    pub fn change_history(&self) -> &ChangeHistory {
+        &self.change_history
+    }
+    
     /// Check if undo is available
     pub fn can_undo(&self) -> bool {
         self.change_history.can_undo()
@@ -641,29 +881,37 @@ impl Buffer {
     
     /// Save the buffer to its file
     pub fn save(&mut self) -> BufferResult<()> {
-        if let Some(path) = &self.file_path {
-            let content = self.content.to_string();
-            let mut file = File::create(path)?;
-            file.write_all(content.as_bytes())?;
-            
-            // Reset modified flag
-            self.flags.modified = false;
-            
-            // Commit the current change group
-            self.change_history.commit_current_group();
-            
-            Ok(())
-        } else {
-            Err(BufferError::Other("No file path associated with buffer".to_string()))
+        self.write(false)
+    }
+    
+    /// Write the buffer to its file, as `:w` does, or `:w!` if `force` is set
+    pub fn write(&mut self, force: bool) -> BufferResult<()> {
+        match self.file_path.clone() {
+            Some(path) => self.write_as(path, force),
+            None => Err(BufferError::Other("No file path associated with buffer".to_string())),
         }
     }
     
     /// Save the buffer to a specific file
     pub fn save_as<P: AsRef<Path>>(&mut self, path: P) -> BufferResult<()> {
+        self.write_as(path, false)
+    }
+    
+    /// Write the buffer to a specific file, as `:w {file}` does, or `:w! {file}` if `force` is set
+    ///
+    /// Without `force` the write is refused if the buffer or the file is
+    /// read-only, or if the buffer's own file changed on disk since it was read.
+    pub fn write_as<P: AsRef<Path>>(&mut self, path: P, force: bool) -> BufferResult<()> {
         let path_buf = path.as_ref().to_path_buf();
-        let content = self.content.to_string();
-        let mut file = File::create(&path_buf)?;
-        file.write_all(content.as_bytes())?;
+        let renamed = self.file_path.as_ref() != Some(&path_buf);
+        if !force {
+            self.check_writable(&path_buf, renamed)?;
+        }
+        
+        let bytes = self.file_bytes()?;
+        write::write_atomic(&path_buf, &bytes, &self.write_options)?;
+        self.disk_stamp = FileStamp::of(&path_buf);
+        self.disk_hash = Some(undofile::bytes_hash(&bytes));
         
         // Update file path and name
         self.file_path = Some(path_buf.clone());
@@ -673,65 +921,465 @@ impl Buffer {
         
         // Reset modified flag
         self.flags.modified = false;
+        self.flags.new_file = false;
         
-        // Commit the current change group
-        self.change_history.commit_current_group();
+        // Remember the state that was written
+        self.change_history.mark_saved();
+        self.save_undo_file();
+        
+        // The swap file follows the file to its new name
+        if renamed && self.swap_path.is_some() {
+            let swap_dir = self.swap_dir.clone();
+            self.close_swap_file();
+            self.open_swap_file(swap_dir.as_deref());
+        } else {
+            self.refresh_swap_file();
+        }
         
         Ok(())
     }
     
-    /// Search for a pattern in the buffer
-    /// Returns a vector of (line_number, column_number, match_text) tuples
-    pub fn search(&self, pattern: &str, case_sensitive: bool) -> BufferResult<Vec<(usize, usize, String)>> {
-        let regex_pattern = if case_sensitive {
-            pattern.to_string()
-        } else {
-            format!("(?i){}", pattern)
+    /// Check that a write may go ahead without `!`
+    fn check_writable(&self, path: &Path, renamed: bool) -> BufferResult<()> {
+        if self.read_only {
+            return Err(BufferError::ReadOnly);
+        }
+        
+        if let Ok(metadata) = std::fs::metadata(path) {
+            if metadata.permissions().readonly() {
+                return Err(BufferError::FileReadOnly(path.to_path_buf()));
+            }
+        }
+        
+        // Don't overwrite changes made by something else
+        if !renamed && self.disk_changed(path) {
+            return Err(BufferError::FileChanged(path.to_path_buf()));
+        }
+        
+        Ok(())
+    }
+    
+    /// Get the file on disk as it was last read or written
+    pub fn disk_stamp(&self) -> Option<FileStamp> {
+        self.disk_stamp
+    }
+    
+    /// Check whether a file that was read or written differs from it now
+    ///
+    /// A new modification time alone doesn't count, the contents must differ too.
+    fn disk_changed(&self, path: &Path) -> bool {
+        if self.disk_stamp.is_none() {
+            return false;
+        }
+        if FileStamp::of(path) == self.disk_stamp {
+            return false;
+        }
+        
+        match std::fs::read(path) {
+            Ok(bytes) => self.disk_hash.as_deref() != Some(undofile::bytes_hash(&bytes).as_str()),
+            Err(_) => true,
+        }
+    }
+    
+    /// Check whether the file changed on disk since it was last read or written
+    ///
+    /// A change is only reported once: after `Changed` the caller either
+    /// reloads with [`Buffer::reload`] or keeps the buffer with
+    /// [`Buffer::keep_buffer_version`], and after `Deleted` the file counts as
+    /// never having been on disk.
+    pub fn check_disk(&mut self) -> DiskChange {
+        let path = match (&self.file_path, self.disk_stamp) {
+            (Some(path), Some(_)) => path.clone(),
+            _ => return DiskChange::Unchanged,
+        };
+        
+        let stamp = match FileStamp::of(&path) {
+            Some(stamp) => stamp,
+            None => {
+                self.disk_stamp = None;
+                self.disk_hash = None;
+                return DiskChange::Deleted;
+            }
         };
         
-        let regex = match Regex::new(&regex_pattern) {
-            Ok(r) => r,
-            Err(_) => return Err(BufferError::Other("Invalid search pattern".to_string())),
+        if !self.disk_changed(&path) {
+            // Only touched, remember the new time so the file isn't read again
+            self.disk_stamp = Some(stamp);
+            return DiskChange::Unchanged;
+        }
+        
+        DiskChange::Changed
+    }
+    
+    /// Reload the buffer from its file
+    ///
+    /// The new text replaces the old as a single change, so the reload can be
+    /// undone and the rest of the undo history is kept.
+    pub fn reload(&mut self) -> BufferResult<()> {
+        let path = match &self.file_path {
+            Some(path) => path.clone(),
+            None => return Err(BufferError::Other("No file path associated with buffer".to_string())),
         };
         
+        let bytes = std::fs::read(&path)?;
+        let (content, fileformat, fileencoding, bomb) = decode_file_bytes(&bytes);
+        
+        if self.content != content.as_str() {
+            self.replace_all(&content);
+        }
+        self.fileformat = fileformat;
+        self.fileencoding = fileencoding;
+        self.bomb = bomb;
+        self.disk_stamp = FileStamp::of(&path);
+        self.disk_hash = Some(undofile::bytes_hash(&bytes));
+        
+        // The buffer matches the file again
+        self.flags.modified = false;
+        self.change_history.mark_saved();
+        self.refresh_swap_file();
+        
+        Ok(())
+    }
+    
+    /// Keep the buffer as it is after its file changed on disk
+    ///
+    /// The change is not reported again, and the buffer may be written over it.
+    pub fn keep_buffer_version(&mut self) {
+        if let Some(path) = &self.file_path {
+            self.disk_stamp = FileStamp::of(path);
+            self.disk_hash = std::fs::read(path).ok().map(|bytes| undofile::bytes_hash(&bytes));
+        }
+    }
+    
+    /// Replace the whole text as a single undoable change
+    fn replace_all(&mut self, text: &str) {
+        let old_text = self.content.to_string();
+        self.change_history.record_change(ChangeType::Replace {
+            start: 0,
+            end: self.content.len_chars(),
+            old_text,
+            new_text: text.to_string(),
+        });
+        self.change_history.commit_current_group();
+        self.replace_text(0, self.content.len_chars(), text);
+        self.flags.modified = true;
+    }
+    
+    /// Get the backup settings for writing the file
+    pub fn write_options(&self) -> &WriteOptions {
+        &self.write_options
+    }
+    
+    /// Set the backup settings for writing the file
+    pub fn set_write_options(&mut self, write_options: WriteOptions) {
+        self.write_options = write_options;
+    }
+    
+    /// Convert the buffer to the bytes written to its file
+    fn file_bytes(&self) -> BufferResult<Vec<u8>> {
+        let text = self.fileformat.to_file_text(&self.content.to_string());
+        self.fileencoding.encode(&text, self.bomb)
+    }
+    
+    /// Get the line ending style used when writing the file
+    pub fn fileformat(&self) -> FileFormat {
+        self.fileformat
+    }
+    
+    /// Set the line ending style used when writing the file
+    pub fn set_fileformat(&mut self, fileformat: FileFormat) {
+        if self.fileformat != fileformat {
+            self.fileformat = fileformat;
+            self.flags.modified = true;
+        }
+    }
+    
+    /// Get the character encoding used when writing the file
+    pub fn fileencoding(&self) -> FileEncoding {
+        self.fileencoding
+    }
+    
+    /// Set the character encoding used when writing the file
+    pub fn set_fileencoding(&mut self, fileencoding: FileEncoding) {
+        if self.fileencoding != fileencoding {
+            self.fileencoding = fileencoding;
+            self.flags.modified = true;
+        }
+    }
+    
+    /// Check whether the file is written with a byte order mark
+    pub fn bomb(&self) -> bool {
+        self.bomb
+    }
+    
+    /// Set whether the file is written with a byte order mark
+    pub fn set_bomb(&mut self, bomb: bool) {
+        if self.bomb != bomb {
+            self.bomb = bomb;
+            self.flags.modified = true;
+        }
+    }
+    
+    /// Get the directory for undo files
+    pub fn undo_dir(&self) -> Option<&Path> {
+        self.undo_dir.as_deref()
+    }
+    
+    /// Set the directory for undo files, `None` turns persistent undo off
+    pub fn set_undo_dir(&mut self, undo_dir: Option<PathBuf>) {
+        self.undo_dir = undo_dir;
+    }
+    
+    /// Get the path of the undo file for this buffer
+    pub fn undo_file_path(&self) -> Option<PathBuf> {
+        match (&self.undo_dir, &self.file_path) {
+            (Some(undo_dir), Some(file_path)) => Some(undofile::undo_file_path(undo_dir, file_path)),
+            _ => None,
+        }
+    }
+    
+    /// Restore the undo history from the undo file
+    ///
+    /// Returns `Ok(true)` if the history was restored and `Ok(false)` if
+    /// persistent undo is off or there is no undo file.
+    pub fn read_undo_file(&mut self) -> BufferResult<bool> {
+        let path = match self.undo_file_path() {
+            Some(path) => path,
+            None => return Ok(false),
+        };
+        
+        match undofile::read_undo_file(&path, &self.content.to_string())? {
+            Some(history) => {
+                self.change_history = history;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+    
+    /// Write the undo history to the undo file
+    pub fn write_undo_file(&self) -> BufferResult<()> {
+        match self.undo_file_path() {
+            Some(path) => undofile::write_undo_file(&path, &self.content.to_string(), &self.change_history),
+            None => Ok(()),
+        }
+    }
+    
+    /// Write the undo file after a save, remembering any failure
+    fn save_undo_file(&mut self) {
+        if let Err(err) = self.write_undo_file() {
+            self.messages.push(err.to_string());
+        }
+    }
+    
+    /// Start keeping a swap file for the buffer
+    ///
+    /// Existing swap files for the file are checked first. One owned by a running
+    /// process means the file is being edited elsewhere, so the buffer is made
+    /// read-only. One left behind by a process that died with unsaved changes is
+    /// remembered for [`Buffer::recover`]. The buffer's own swap file takes the
+    /// first free name.
+    pub fn open_swap_file(&mut self, swap_dir: Option<&Path>) {
+        let file_path = match &self.file_path {
+            Some(path) => path.clone(),
+            None => return,
+        };
+        
+        let mut own_path = None;
+        for candidate in swap::swap_file_candidates(swap_dir, &file_path) {
+            if !candidate.exists() {
+                own_path = Some(candidate);
+                break;
+            }
+            
+            match SwapInfo::read(&candidate) {
+                Ok(info) if info.is_owner_running() => {
+                    self.messages.push(format!(
+                        "E325: ATTENTION: \"{}\" is already being edited by process {} (swap file {}), opening read-only",
+                        file_path.display(), info.pid, candidate.display()
+                    ));
+                    self.read_only = true;
+                }
+                Ok(info) if info.content.is_some() => {
+                    if self.recover_path.is_none() {
+                        self.messages.push(format!(
+                            "E325: ATTENTION: Found swap file {} with unsaved changes, use :recover to restore them",
+                            candidate.display()
+                        ));
+                        self.recover_path = Some(candidate);
+                    }
+                }
+                Ok(_) => {
+                    // A dead process without unsaved changes left nothing to recover
+                    if std::fs::remove_file(&candidate).is_ok() {
+                        own_path = Some(candidate);
+                        break;
+                    }
+                }
+                Err(err) => self.messages.push(err.to_string()),
+            }
+        }
+        
+        match own_path {
+            Some(path) => {
+                self.swap_path = Some(path);
+                self.swap_dir = swap_dir.map(Path::to_path_buf);
+                self.swap_hash = None;
+                self.refresh_swap_file();
+            }
+            None => self.messages.push(format!(
+                "E326: Too many swap files found for \"{}\"",
+                file_path.display()
+            )),
+        }
+    }
+    
+    /// Get the path of the swap file written by this buffer
+    pub fn swap_file_path(&self) -> Option<&Path> {
+        self.swap_path.as_deref()
+    }
+    
+    /// Get the swap file from an earlier session that can be recovered
+    pub fn recover_path(&self) -> Option<&Path> {
+        self.recover_path.as_deref()
+    }
+    
+    /// Write the buffer to its swap file if it changed since the last write
+    ///
+    /// Returns `Ok(true)` if the swap file was written.
+    pub fn update_swap_file(&mut self) -> BufferResult<bool> {
+        let (swap_path, file_path) = match (&self.swap_path, &self.file_path) {
+            (Some(swap_path), Some(file_path)) => (swap_path.clone(), file_path.clone()),
+            _ => return Ok(false),
+        };
+        
+        // Only unsaved text needs to be kept
+        let content = if self.flags.modified { Some(self.content.to_string()) } else { None };
+        let hash = content.as_deref().map(undofile::content_hash).unwrap_or_default();
+        if self.swap_hash.as_deref() == Some(hash.as_str()) {
+            return Ok(false);
+        }
+        
+        SwapInfo::new(&file_path, content).write(&swap_path)?;
+        self.swap_hash = Some(hash);
+        
+        Ok(true)
+    }
+    
+    /// Update the swap file, remembering any failure
+    fn refresh_swap_file(&mut self) {
+        if let Err(err) = self.update_swap_file() {
+            self.messages.push(err.to_string());
+        }
+    }
+    
+    /// Stop keeping a swap file and remove it
+    pub fn close_swap_file(&mut self) {
+        if let Some(path) = self.swap_path.take() {
+            let _ = std::fs::remove_file(path);
+        }
+        self.swap_hash = None;
+    }
+    
+    /// Restore the unsaved text from the swap file of an earlier session
+    ///
+    /// The recovered text replaces the buffer contents as a single change, so it
+    /// can be undone. Returns `Ok(false)` if the swap file had no changes.
+    pub fn recover(&mut self) -> BufferResult<bool> {
+        let path = match &self.recover_path {
+            Some(path) => path.clone(),
+            None => return Err(BufferError::SwapFile(format!("E305: No swap file found for {}", self.name))),
+        };
+        
+        let content = match SwapInfo::read(&path)?.content {
+            Some(content) => content,
+            None => return Ok(false),
+        };
+        
+        // Replace the text as one change
+        self.replace_all(&content);
+        self.recover_path = None;
+        
+        self.messages.push(format!(
+            "Recovery completed. You should check if everything is OK. You may want to delete the swap file {} now.",
+            path.display()
+        ));
+        self.refresh_swap_file();
+        
+        Ok(true)
+    }
+    
+    /// Take the warnings about undo and swap files that haven't been shown yet
+    pub fn take_messages(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.messages)
+    }
+    
+    /// Search for a pattern in the buffer
+    /// Returns a vector of (line_number, column_number, match_text) tuples
+    pub fn search(&self, pattern: &str, case_sensitive: bool) -> BufferResult<Vec<(usize, usize, String)>> {
+        let options = PatternOptions { ignore_case: !case_sensitive, ..Default::default() };
+        let pattern = Pattern::new(pattern, &options).map_err(|err| BufferError::Other(err.to_string()))?;
+        self.search_pattern(&pattern, None)
+    }
+    
+    /// Search for a compiled pattern in the buffer, with the cursor where `\%#` matches
+    /// Returns a vector of (line_number, column_number, match_text) tuples
+    pub fn search_pattern(&self, pattern: &Pattern, cursor: Option<CursorPosition>) -> BufferResult<Vec<(usize, usize, String)>> {
         let mut results = Vec::new();
         let content = self.content.to_string();
         
         for (line_idx, line) in content.lines().enumerate() {
-            for mat in regex.find_iter(line) {
-                let match_text = mat.as_str().to_string();
-                results.push((line_idx, mat.start(), match_text));
+            let context = self.match_context(line_idx, cursor);
+            for found in pattern.find_all(line, &context) {
+                results.push((line_idx, found.start, line[found.start..found.end].to_string()));
             }
         }
         
         Ok(results)
     }
     
+    /// Get what a pattern needs to know about a line to match in it
+    pub fn match_context(&self, line: usize, cursor: Option<CursorPosition>) -> MatchContext<'_> {
+        MatchContext {
+            line,
+            last_line: self.line_count().saturating_sub(1),
+            cursor,
+            visual: self.visual_area.as_ref(),
+        }
+    }
+    
     /// Search and replace text in the buffer
+    ///
+    /// The replacement is expanded as in `:substitute`, for every match in
+    /// every line.
     pub fn search_and_replace(&mut self, pattern: &str, replacement: &str, case_sensitive: bool) -> BufferResult<usize> {
         if !self.modifiable {
             return Err(BufferError::ReadOnly);
         }
         
-        let regex_pattern = if case_sensitive {
-            pattern.to_string()
-        } else {
-            format!("(?i){}", pattern)
-        };
-        
-        let regex = match Regex::new(&regex_pattern) {
-            Ok(r) => r,
-            Err(_) => return Err(BufferError::Other("Invalid search pattern".to_string())),
-        };
+        let options = PatternOptions { ignore_case: !case_sensitive, ..Default::default() };
+        let pattern = Pattern::new(pattern, &options).map_err(|err| BufferError::Other(err.to_string()))?;
+        let replacement = Replacement::parse(replacement);
         
         let content = self.content.to_string();
-        
-        // Count the number of replacements
-        let count = regex.find_iter(&content).count();
+        let mut count = 0;
+        let mut lines = Vec::new();
+        for (line_idx, line) in content.split('\n').enumerate() {
+            let mut new_line = String::new();
+            let mut last = 0;
+            for found in pattern.find_all(line, &self.match_context(line_idx, None)) {
+                new_line.push_str(&line[last..found.start]);
+                new_line.push_str(&replacement.expand(line, &found));
+                last = found.end;
+                count += 1;
+            }
+            new_line.push_str(&line[last..]);
+            lines.push(new_line);
+        }
         
         if count > 0 {
             // Create the new content with replacements
-            let new_content = regex.replace_all(&content, replacement).to_string();
+            let new_content = lines.join("\n");
             
             // Record the change for undo
             self.change_history.record_change(ChangeType::Replace {
@@ -742,7 +1390,7 @@ impl Buffer {
             });
             
             // Update the content
-            self.content = Rope::from_str(&new_content);
+            self.replace_text(0, self.content.len_chars(), &new_content);
             self.flags.modified = true;
         }
         
@@ -852,6 +1500,22 @@ mod tests {
         assert!(buffer.is_modified());
     }
     
+    #[test]
+    fn test_buffer_replace() {
+        let mut buffer = Buffer::new(1);
+        buffer.insert(0, "Hello, world!").unwrap();
+        buffer.change_history.commit_current_group();
+
+        buffer.replace(7, 12, "there").unwrap();
+        assert_eq!(buffer.content(), "Hello, there!");
+        assert!(buffer.replace(7, 20, "").is_err());
+
+        // The replace is undone as one change
+        buffer.change_history.commit_current_group();
+        assert!(buffer.undo().unwrap());
+        assert_eq!(buffer.content(), "Hello, world!");
+    }
+
     #[test]
     fn test_buffer_lines() {
         let mut buffer = Buffer::new(1);
@@ -904,6 +1568,48 @@ mod tests {
         assert!(!result);
     }
     
+    #[test]
+    fn test_buffer_undo_tree() {
+        let mut buffer = Buffer::new(1);
+        
+        buffer.insert(0, "Hello").unwrap();
+        buffer.change_history.commit_current_group();
+        buffer.insert(5, " world").unwrap();
+        buffer.change_history.commit_current_group();
+        
+        // Undo and make a different change
+        buffer.undo().unwrap();
+        buffer.insert(5, " there").unwrap();
+        buffer.change_history.commit_current_group();
+        assert_eq!(buffer.content(), "Hello there");
+        
+        // The undone change is still reachable
+        assert!(buffer.undo_to(2).unwrap());
+        assert_eq!(buffer.content(), "Hello world");
+        assert!(buffer.later(UndoTime::Steps(1)).unwrap());
+        assert_eq!(buffer.content(), "Hello there");
+        assert!(buffer.earlier(UndoTime::Steps(3)).unwrap());
+        assert_eq!(buffer.content(), "");
+        assert!(!buffer.is_modified());
+        assert!(buffer.undo_to(4).is_err());
+    }
+    
+    #[test]
+    fn test_buffer_undo_group_in_reverse() {
+        let mut buffer = Buffer::new(1);
+        
+        // Several changes in one group are undone last to first
+        buffer.insert(0, "ab").unwrap();
+        buffer.insert(2, "cd").unwrap();
+        buffer.delete(0, 1).unwrap();
+        assert_eq!(buffer.content(), "bcd");
+        
+        assert!(buffer.undo().unwrap());
+        assert_eq!(buffer.content(), "");
+        assert!(buffer.redo().unwrap());
+        assert_eq!(buffer.content(), "bcd");
+    }
+    
     #[test]
     fn test_buffer_save() {
         use tempfile::NamedTempFile;
@@ -938,6 +1644,186 @@ mod tests {
         assert_eq!(content, "Test content updated");
     }
     
+    #[test]
+    fn test_buffer_undo_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        let undo_dir = dir.path().join("undo");
+        
+        let mut buffer = Buffer::new(1);
+        buffer.set_undo_dir(Some(undo_dir.clone()));
+        buffer.insert(0, "one").unwrap();
+        buffer.save_as(&path).unwrap();
+        assert!(buffer.take_messages().is_empty());
+        
+        // Reopening the file brings back its history
+        let mut buffer = Buffer::from_file_with_undo_dir(2, &path, Some(&undo_dir)).unwrap();
+        assert!(buffer.take_messages().is_empty());
+        assert!(buffer.undo().unwrap());
+        assert_eq!(buffer.content(), "");
+        assert!(buffer.is_modified());
+        
+        // History written for other contents is reported, not applied
+        std::fs::write(&path, "changed").unwrap();
+        let mut buffer = Buffer::from_file_with_undo_dir(3, &path, Some(&undo_dir)).unwrap();
+        assert!(!buffer.take_messages().is_empty());
+        assert!(!buffer.can_undo());
+        assert_eq!(buffer.content(), "changed");
+    }
+    
+    #[test]
+    fn test_buffer_swap_file_recover() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        std::fs::write(&path, "saved").unwrap();
+        
+        // Editing keeps the unsaved text in the swap file
+        let mut buffer = Buffer::from_file(1, &path).unwrap();
+        buffer.open_swap_file(None);
+        let swap_path = buffer.swap_file_path().unwrap().to_path_buf();
+        assert_eq!(swap_path, dir.path().join(".file.txt.swp"));
+        buffer.insert(5, " unsaved").unwrap();
+        assert!(buffer.update_swap_file().unwrap());
+        assert!(!buffer.update_swap_file().unwrap());
+        
+        // Pretend the editing process died
+        let mut info = SwapInfo::read(&swap_path).unwrap();
+        info.pid = 999_999_999;
+        info.write(&swap_path).unwrap();
+        
+        let mut buffer = Buffer::from_file(2, &path).unwrap();
+        buffer.open_swap_file(None);
+        assert!(!buffer.take_messages().is_empty());
+        assert_eq!(buffer.recover_path(), Some(swap_path.as_path()));
+        assert_eq!(buffer.swap_file_path(), Some(dir.path().join(".file.txt.swo").as_path()));
+        assert!(buffer.recover().unwrap());
+        assert_eq!(buffer.content(), "saved unsaved");
+        assert!(buffer.is_modified());
+        
+        // Recovery can be undone
+        assert!(buffer.undo().unwrap());
+        assert_eq!(buffer.content(), "saved");
+        
+        buffer.close_swap_file();
+        assert!(!dir.path().join(".file.txt.swo").exists());
+    }
+    
+    #[test]
+    fn test_buffer_swap_file_in_use() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        std::fs::write(&path, "text").unwrap();
+        
+        let mut first = Buffer::from_file(1, &path).unwrap();
+        first.open_swap_file(None);
+        
+        // The swap file belongs to a running process, so the second buffer is read-only
+        let mut second = Buffer::from_file(2, &path).unwrap();
+        second.open_swap_file(None);
+        assert!(second.is_read_only());
+        assert!(second.recover_path().is_none());
+        assert!(!first.is_read_only());
+    }
+    
+    #[test]
+    fn test_buffer_keeps_file_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        
+        // UTF-16 with a byte order mark and DOS line endings
+        let mut bytes = vec![0xFF, 0xFE];
+        for unit in "one\r\ntwo\r\n".encode_utf16() {
+            bytes.extend_from_slice(&unit.to_le_bytes());
+        }
+        std::fs::write(&path, &bytes).unwrap();
+        
+        let mut buffer = Buffer::from_file(1, &path).unwrap();
+        assert_eq!(buffer.content(), "one\ntwo\n");
+        assert_eq!(buffer.fileformat(), FileFormat::Dos);
+        assert_eq!(buffer.fileencoding(), FileEncoding::Utf16Le);
+        assert!(buffer.bomb());
+        
+        // Written back the way it was read
+        buffer.save().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        
+        // Changing the format marks the buffer modified and converts on write
+        buffer.set_fileformat(FileFormat::Unix);
+        buffer.set_fileencoding(FileEncoding::Utf8);
+        buffer.set_bomb(false);
+        assert!(buffer.is_modified());
+        buffer.save().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"one\ntwo\n");
+    }
+    
+    #[test]
+    fn test_buffer_write_refusals() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        std::fs::write(&path, "text").unwrap();
+        
+        let mut buffer = Buffer::from_file(1, &path).unwrap();
+        buffer.insert(0, "new ").unwrap();
+        
+        // Changed behind our back
+        std::fs::write(&path, "changed elsewhere").unwrap();
+        assert!(matches!(buffer.save(), Err(BufferError::FileChanged(_))));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "changed elsewhere");
+        buffer.write(true).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new text");
+        
+        // Read-only on disk
+        let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&path, permissions).unwrap();
+        buffer.insert(0, "x").unwrap();
+        assert!(matches!(buffer.save(), Err(BufferError::FileReadOnly(_))));
+        buffer.write(true).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "xnew text");
+        assert!(std::fs::metadata(&path).unwrap().permissions().readonly());
+        
+        // Read-only buffer
+        buffer.set_read_only(true);
+        assert!(matches!(buffer.write_as(dir.path().join("other.txt"), false), Err(BufferError::ReadOnly)));
+    }
+    
+    #[test]
+    fn test_buffer_check_disk_and_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        std::fs::write(&path, "one").unwrap();
+        
+        let mut buffer = Buffer::from_file(1, &path).unwrap();
+        assert_eq!(buffer.check_disk(), DiskChange::Unchanged);
+        
+        // Rewriting the same contents is not a change
+        std::fs::write(&path, "one").unwrap();
+        assert_eq!(buffer.check_disk(), DiskChange::Unchanged);
+        
+        std::fs::write(&path, "one\ntwo").unwrap();
+        assert_eq!(buffer.check_disk(), DiskChange::Changed);
+        buffer.reload().unwrap();
+        assert_eq!(buffer.content(), "one\ntwo");
+        assert!(!buffer.is_modified());
+        assert_eq!(buffer.check_disk(), DiskChange::Unchanged);
+        
+        // The reload is a change that can be undone
+        assert!(buffer.undo().unwrap());
+        assert_eq!(buffer.content(), "one");
+        assert!(buffer.is_modified());
+        
+        // Keeping the buffer version stops the change from being reported
+        std::fs::write(&path, "three").unwrap();
+        assert_eq!(buffer.check_disk(), DiskChange::Changed);
+        buffer.keep_buffer_version();
+        assert_eq!(buffer.check_disk(), DiskChange::Unchanged);
+        buffer.save().unwrap();
+        
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(buffer.check_disk(), DiskChange::Deleted);
+        assert_eq!(buffer.check_disk(), DiskChange::Unchanged);
+    }
+    
     #[test]
     fn test_buffer_search() {
         let mut buffer = Buffer::new(1);
@@ -1022,6 +1908,39 @@ mod tests {
         assert!(buffer.set_mark('d', 0, 10).is_err()); // Invalid column
     }
     
+    #[test]
+    fn test_buffer_marks_follow_edits() {
+        let mut buffer = Buffer::new(1);
+        buffer.insert(0, "one\ntwo\nthree\nfour").unwrap();
+        buffer.set_mark('a', 2, 1).unwrap();
+        buffer.set_mark('b', 1, 0).unwrap();
+        
+        // A line inserted above moves the marks down
+        buffer.insert(0, "zero\n").unwrap();
+        assert_eq!(buffer.get_mark('a'), Some(&Mark::new(3, 1)));
+        assert_eq!(buffer.get_mark('['), Some(&Mark::new(0, 0)));
+        assert_eq!(buffer.get_mark(']'), Some(&Mark::new(0, 4)));
+        assert_eq!(buffer.get_mark('.'), Some(&Mark::new(0, 4)));
+        
+        // Deleting the line of 'b removes it, 'a moves up
+        buffer.change_history.commit_current_group();
+        buffer.delete(9, 13).unwrap();
+        assert_eq!(buffer.content(), "zero\none\nthree\nfour");
+        assert!(buffer.get_mark('b').is_none());
+        assert_eq!(buffer.get_mark('a'), Some(&Mark::new(2, 1)));
+        assert_eq!(buffer.get_mark('.'), Some(&Mark::new(2, 0)));
+        
+        // Undoing the delete moves 'a back down
+        buffer.undo().unwrap();
+        assert_eq!(buffer.content(), "zero\none\ntwo\nthree\nfour");
+        assert_eq!(buffer.get_mark('a'), Some(&Mark::new(3, 1)));
+        
+        // Yanked text is marked with '[ and ']
+        buffer.set_yank_marks(5, 8);
+        assert_eq!(buffer.get_mark('['), Some(&Mark::new(1, 0)));
+        assert_eq!(buffer.get_mark(']'), Some(&Mark::new(1, 2)));
+    }
+    
     #[test]
     #[ignore] // TODO: Fix this test
     fn test_buffer_find_next_prev() {
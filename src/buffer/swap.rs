@@ -0,0 +1,197 @@
+//! Swap files for crash recovery
+//!
+//! While a file is being edited xvim keeps a swap file for it, either next to
+//! the file as `.name.swp` or in the swap directory named after the full path
+//! of the file. The swap file records which process is editing the file and,
+//! once the buffer has unsaved changes, the text of the buffer. A swap file
+//! left behind by a process that is no longer running means the edits were
+//! never saved and can be brought back with `:recover`. A swap file owned by a
+//! running process means the file is already being edited elsewhere.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::undofile::escaped_path_name;
+use super::write::{write_atomic, WriteOptions};
+use super::{BufferError, BufferResult};
+
+/// Version of the swap file format
+pub const SWAP_FILE_VERSION: u32 = 1;
+
+/// Extensions tried in turn when a swap file already exists, as Vim does
+const SWAP_EXTENSIONS: [&str; 8] = ["swp", "swo", "swn", "swm", "swl", "swk", "swj", "swi"];
+
+/// Contents of a swap file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SwapInfo {
+    /// Version of the swap file format
+    pub version: u32,
+    /// ID of the process editing the file
+    pub pid: u32,
+    /// The file being edited
+    pub file_path: PathBuf,
+    /// Time the swap file was last written, in seconds since the Unix epoch
+    pub time: u64,
+    /// Text of the buffer, or `None` if it had no unsaved changes
+    pub content: Option<String>,
+}
+
+impl SwapInfo {
+    /// Create swap file contents for this process
+    pub fn new(file_path: &Path, content: Option<String>) -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+
+        Self {
+            version: SWAP_FILE_VERSION,
+            pid: std::process::id(),
+            file_path: file_path.to_path_buf(),
+            time,
+            content,
+        }
+    }
+
+    /// Read a swap file
+    pub fn read(path: &Path) -> BufferResult<Self> {
+        let data = fs::read_to_string(path)?;
+        let info: SwapInfo = serde_json::from_str(&data).map_err(|err| {
+            BufferError::SwapFile(format!("Corrupted swap file ({}): {}", err, path.display()))
+        })?;
+
+        if info.version != SWAP_FILE_VERSION {
+            return Err(BufferError::SwapFile(format!(
+                "Swap file {} has unsupported version {}",
+                path.display(),
+                info.version
+            )));
+        }
+
+        Ok(info)
+    }
+
+    /// Write a swap file
+    pub fn write(&self, path: &Path) -> BufferResult<()> {
+        let data = serde_json::to_string(self).map_err(|err| {
+            BufferError::SwapFile(format!("Cannot write swap file {}: {}", path.display(), err))
+        })?;
+
+        if let Some(dir) = path.parent() {
+            if !dir.as_os_str().is_empty() {
+                fs::create_dir_all(dir)?;
+            }
+        }
+        // Replaced whole, so a crash while writing can't leave half a swap file
+        let options = WriteOptions { writebackup: false, ..WriteOptions::default() };
+        write_atomic(path, data.as_bytes(), &options)?;
+
+        Ok(())
+    }
+
+    /// Check whether the swap file belongs to this process
+    pub fn is_own(&self) -> bool {
+        self.pid == std::process::id()
+    }
+
+    /// Check whether the process that wrote the swap file is still running
+    pub fn is_owner_running(&self) -> bool {
+        self.is_own() || process_running(self.pid)
+    }
+}
+
+/// Check whether a process is running
+#[cfg(unix)]
+fn process_running(pid: u32) -> bool {
+    // Anything that would read as a negative PID signals a whole process group
+    if pid == 0 || pid > i32::MAX as u32 {
+        return false;
+    }
+
+    std::process::Command::new("kill")
+        .arg("-0")
+        .arg(pid.to_string())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Check whether a process is running
+#[cfg(not(unix))]
+fn process_running(_pid: u32) -> bool {
+    // Without a way to ask, assume the owner is gone so the file can be recovered
+    false
+}
+
+/// Get the possible swap file paths for a file, in the order they are tried
+///
+/// With no swap directory the swap file is kept next to the file as a hidden file.
+pub fn swap_file_candidates(swap_dir: Option<&Path>, file_path: &Path) -> Vec<PathBuf> {
+    SWAP_EXTENSIONS.iter()
+        .map(|ext| match swap_dir {
+            Some(dir) => dir.join(format!("{}.{}", escaped_path_name(file_path), ext)),
+            None => {
+                let name = file_path.file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                file_path.with_file_name(format!(".{}.{}", name, ext))
+            }
+        })
+        .collect()
+}
+
+/// List the swap files in a directory, as `xvim -r` does
+pub fn list_swap_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| SWAP_EXTENSIONS.contains(&ext))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swap_file_candidates() {
+        let candidates = swap_file_candidates(None, Path::new("/tmp/dir/file.txt"));
+        assert_eq!(candidates[0], PathBuf::from("/tmp/dir/.file.txt.swp"));
+        assert_eq!(candidates[1], PathBuf::from("/tmp/dir/.file.txt.swo"));
+
+        let candidates = swap_file_candidates(Some(Path::new("/swap")), Path::new("/tmp/dir/file.txt"));
+        assert_eq!(candidates[0], PathBuf::from("/swap/%tmp%dir%file.txt.swp"));
+    }
+
+    #[test]
+    fn test_swap_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".file.swp");
+
+        let info = SwapInfo::new(&dir.path().join("file"), Some("text".to_string()));
+        info.write(&path).unwrap();
+        let read = SwapInfo::read(&path).unwrap();
+        assert_eq!(read, info);
+        assert!(read.is_own());
+        assert!(read.is_owner_running());
+        assert_eq!(list_swap_files(dir.path()).unwrap(), vec![path.clone()]);
+
+        // Writing again replaces the file, leaving nothing else behind
+        let info = SwapInfo::new(&dir.path().join("file"), None);
+        info.write(&path).unwrap();
+        assert_eq!(SwapInfo::read(&path).unwrap(), info);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        fs::write(&path, "garbage").unwrap();
+        assert!(matches!(SwapInfo::read(&path), Err(BufferError::SwapFile(_))));
+    }
+}
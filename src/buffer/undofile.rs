@@ -0,0 +1,168 @@
+//! Persistent undo files
+//!
+//! This module reads and writes the undo history of a buffer so it survives
+//! closing the file. Undo files live in the undo directory and are named after
+//! the full path of the file they belong to, with path separators replaced by
+//! `%`. Each undo file stores a hash of the file contents it was written for,
+//! so history is only restored onto the exact text it describes.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::change::{ChangeHistory, HistorySnapshot};
+use super::write::{write_atomic, WriteOptions};
+use super::{BufferError, BufferResult};
+
+/// Version of the undo file format
+pub const UNDO_FILE_VERSION: u32 = 1;
+
+/// Contents of an undo file
+#[derive(Debug, Serialize, Deserialize)]
+struct UndoFile {
+    /// Version of the undo file format
+    version: u32,
+    /// Hash of the file contents the history was written for
+    hash: String,
+    /// The undo tree
+    history: HistorySnapshot,
+}
+
+/// Hash the contents of a file, using 64-bit FNV-1a
+pub fn content_hash(content: &str) -> String {
+    bytes_hash(content.as_bytes())
+}
+
+/// Hash raw bytes, using 64-bit FNV-1a
+pub fn bytes_hash(bytes: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{:016x}", hash)
+}
+
+/// Get the path of the undo file for a file
+pub fn undo_file_path(undo_dir: &Path, file_path: &Path) -> PathBuf {
+    undo_dir.join(escaped_path_name(file_path))
+}
+
+/// Turn the absolute path of a file into a file name, replacing path separators with `%`
+///
+/// Using the absolute path keeps files with the same name in different
+/// directories from colliding when their state is kept in one directory.
+pub(crate) fn escaped_path_name(file_path: &Path) -> String {
+    let full_path = fs::canonicalize(file_path).unwrap_or_else(|_| {
+        std::env::current_dir()
+            .map(|dir| dir.join(file_path))
+            .unwrap_or_else(|_| file_path.to_path_buf())
+    });
+
+    full_path.to_string_lossy()
+        .chars()
+        .map(|c| if std::path::is_separator(c) || c == ':' { '%' } else { c })
+        .collect()
+}
+
+/// Write the undo history for the given file contents
+pub fn write_undo_file(path: &Path, content: &str, history: &ChangeHistory) -> BufferResult<()> {
+    let undo_file = UndoFile {
+        version: UNDO_FILE_VERSION,
+        hash: content_hash(content),
+        history: history.snapshot(),
+    };
+
+    let data = serde_json::to_string(&undo_file)
+        .map_err(|err| BufferError::UndoFile(format!("Cannot write undo file {}: {}", path.display(), err)))?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // Replaced whole like any other file, but without a backup
+    let options = WriteOptions { writebackup: false, ..WriteOptions::default() };
+    write_atomic(path, data.as_bytes(), &options)?;
+
+    Ok(())
+}
+
+/// Read the undo history for the given file contents
+///
+/// Returns `Ok(None)` if there is no undo file, and an error if the undo file
+/// is corrupt or was written for different contents.
+pub fn read_undo_file(path: &Path, content: &str) -> BufferResult<Option<ChangeHistory>> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(BufferError::Io(err)),
+    };
+
+    let corrupt = |reason: String| {
+        BufferError::UndoFile(format!("Corrupted undo file ({}): {}", reason, path.display()))
+    };
+
+    let undo_file: UndoFile = serde_json::from_str(&data).map_err(|err| corrupt(err.to_string()))?;
+    if undo_file.version != UNDO_FILE_VERSION {
+        return Err(corrupt(format!("unsupported version {}", undo_file.version)));
+    }
+
+    // The history only applies to the text it was written for
+    if undo_file.hash != content_hash(content) {
+        return Err(BufferError::UndoFile(
+            "File contents changed, cannot use undo info".to_string(),
+        ));
+    }
+
+    ChangeHistory::from_snapshot(undo_file.history)
+        .map(Some)
+        .map_err(corrupt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::ChangeType;
+
+    #[test]
+    fn test_undo_file_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("undo");
+
+        let mut history = ChangeHistory::new();
+        history.record_change(ChangeType::Insert { position: 0, text: "hi".to_string() });
+        history.mark_saved();
+
+        write_undo_file(&path, "hi", &history).unwrap();
+        let restored = read_undo_file(&path, "hi").unwrap().unwrap();
+        assert_eq!(restored.seq_cur(), 1);
+        assert!(restored.can_undo());
+
+        // No undo file is not an error
+        assert!(read_undo_file(&dir.path().join("missing"), "hi").unwrap().is_none());
+
+        // Writing again replaces the file, leaving nothing else behind
+        write_undo_file(&path, "hi", &ChangeHistory::new()).unwrap();
+        assert_eq!(read_undo_file(&path, "hi").unwrap().unwrap().seq_cur(), 0);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_undo_file_rejects_bad_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("undo");
+
+        write_undo_file(&path, "hi", &ChangeHistory::new()).unwrap();
+        assert!(matches!(read_undo_file(&path, "changed"), Err(BufferError::UndoFile(_))));
+
+        fs::write(&path, "{ not json").unwrap();
+        assert!(matches!(read_undo_file(&path, "hi"), Err(BufferError::UndoFile(_))));
+    }
+
+    #[test]
+    fn test_undo_file_path() {
+        let path = undo_file_path(Path::new("/undo"), Path::new("/home/user/file.txt"));
+        assert_eq!(path, PathBuf::from("/undo/%home%user%file.txt"));
+    }
+}
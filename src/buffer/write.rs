@@ -0,0 +1,170 @@
+//! Safe file writing
+//!
+//! Files are never truncated in place. The new contents go to a temporary file
+//! next to the target, which is synced to disk, given the permissions of the
+//! file it replaces and then renamed over it, so a crash during a write leaves
+//! either the old or the new file behind. With 'backup' or 'writebackup' set a
+//! copy of the old file is made first, kept afterwards only with 'backup'.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Settings for writing files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// Keep a backup of the old file after a successful write
+    pub backup: bool,
+    /// Make a backup of the old file while writing
+    pub writebackup: bool,
+    /// Directory for backup files, `None` for the directory of the file
+    pub backup_dir: Option<PathBuf>,
+    /// Appended to the file name to make the backup file name
+    pub backup_ext: String,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            backup: false,
+            writebackup: true,
+            backup_dir: None,
+            backup_ext: "~".to_string(),
+        }
+    }
+}
+
+impl WriteOptions {
+    /// Get the path of the backup file for a file
+    pub fn backup_path(&self, path: &Path) -> PathBuf {
+        let name = path.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let backup_name = format!("{}{}", name, self.backup_ext);
+
+        match &self.backup_dir {
+            Some(dir) => dir.join(backup_name),
+            None => path.with_file_name(backup_name),
+        }
+    }
+}
+
+/// What a file looked like on disk, to notice when something else changes it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    /// Modification time of the file
+    pub modified: SystemTime,
+    /// Size of the file in bytes
+    pub len: u64,
+}
+
+impl FileStamp {
+    /// Get the stamp of a file, or `None` if it can't be read
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Self {
+            modified: metadata.modified().ok()?,
+            len: metadata.len(),
+        })
+    }
+}
+
+/// Write a file without ever leaving it half written
+pub fn write_atomic(path: &Path, bytes: &[u8], options: &WriteOptions) -> io::Result<()> {
+    // Write through symlinks instead of replacing them
+    let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let existing = fs::metadata(&target).ok();
+
+    // Back up the old file first
+    let backup = match &existing {
+        Some(_) if options.backup || options.writebackup => {
+            let backup_path = options.backup_path(&target);
+            fs::copy(&target, &backup_path)?;
+            Some(backup_path)
+        }
+        _ => None,
+    };
+
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name = target.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let temp_path = dir.join(format!(".{}.xvim-{}.tmp", name, std::process::id()));
+
+    let result = (|| {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        drop(file);
+
+        // The new file takes over the permissions of the old one
+        if let Some(metadata) = &existing {
+            fs::set_permissions(&temp_path, metadata.permissions())?;
+        }
+
+        fs::rename(&temp_path, &target)?;
+
+        // Make the rename itself durable
+        #[cfg(unix)]
+        if let Ok(dir) = File::open(&dir) {
+            let _ = dir.sync_all();
+        }
+
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    } else if let Some(backup_path) = backup {
+        if !options.backup {
+            let _ = fs::remove_file(backup_path);
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_atomic_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "old").unwrap();
+
+        // 'writebackup' alone doesn't leave a backup behind
+        write_atomic(&path, b"new", &WriteOptions::default()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!dir.path().join("file.txt~").exists());
+
+        let options = WriteOptions { backup: true, ..WriteOptions::default() };
+        write_atomic(&path, b"newer", &options).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "newer");
+        assert_eq!(fs::read_to_string(dir.path().join("file.txt~")).unwrap(), "new");
+
+        // No temporary files are left over
+        let count = fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(count, 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomic_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("script.sh");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+
+        write_atomic(&path, b"new", &WriteOptions::default()).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+    }
+}
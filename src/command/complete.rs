@@ -0,0 +1,566 @@
+//! Completion - Completing the word before the cursor on the command line
+//!
+//! This module works out what the word before the cursor names, a command,
+//! a file, a buffer, an option and so on, completes paths and steps through
+//! the matches the way 'wildmode' asks for. Gathering the names of commands,
+//! buffers and options is left to the editor, which knows them.
+
+use std::path::{Path, PathBuf};
+
+/// What a word on the command line is completed as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompletionKind {
+    /// Ex command names
+    Command,
+    /// Files and directories
+    File,
+    /// Directories
+    Directory,
+    /// Buffer names
+    Buffer,
+    /// Option names
+    Option,
+    /// The value of the named option
+    OptionValue(String),
+    /// Help topics
+    Help,
+    /// Color scheme names
+    Color,
+    /// Nothing is completed
+    None,
+}
+
+impl CompletionKind {
+    /// Get a kind from its name, as `-complete=` takes it
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "command" => Some(CompletionKind::Command),
+            "file" => Some(CompletionKind::File),
+            "dir" => Some(CompletionKind::Directory),
+            "buffer" => Some(CompletionKind::Buffer),
+            "option" => Some(CompletionKind::Option),
+            "help" => Some(CompletionKind::Help),
+            "color" => Some(CompletionKind::Color),
+            _ => None,
+        }
+    }
+
+    /// Get the name of a kind, as `-complete=` takes it
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompletionKind::Command => "command",
+            CompletionKind::File => "file",
+            CompletionKind::Directory => "dir",
+            CompletionKind::Buffer => "buffer",
+            CompletionKind::Option => "option",
+            CompletionKind::Help => "help",
+            CompletionKind::Color => "color",
+            CompletionKind::OptionValue(_) | CompletionKind::None => "",
+        }
+    }
+}
+
+/// Commands whose arguments are completed, with the shortest abbreviation allowed
+const ARGUMENT_KINDS: &[(&str, usize, CompletionKind)] = &[
+    ("!", 1, CompletionKind::File),
+    ("edit", 1, CompletionKind::File),
+    ("write", 1, CompletionKind::File),
+    ("read", 1, CompletionKind::File),
+    ("split", 2, CompletionKind::File),
+    ("vsplit", 2, CompletionKind::File),
+    ("tabedit", 4, CompletionKind::File),
+    ("tabnew", 6, CompletionKind::File),
+    ("wq", 2, CompletionKind::File),
+    ("xit", 1, CompletionKind::File),
+    ("saveas", 3, CompletionKind::File),
+    ("source", 2, CompletionKind::File),
+    ("cd", 2, CompletionKind::Directory),
+    ("chdir", 3, CompletionKind::Directory),
+    ("lcd", 3, CompletionKind::Directory),
+    ("buffer", 1, CompletionKind::Buffer),
+    ("sbuffer", 2, CompletionKind::Buffer),
+    ("bdelete", 2, CompletionKind::Buffer),
+    ("set", 2, CompletionKind::Option),
+    ("setlocal", 4, CompletionKind::Option),
+    ("setglobal", 4, CompletionKind::Option),
+    ("help", 1, CompletionKind::Help),
+    ("colorscheme", 4, CompletionKind::Color),
+    ("delcommand", 4, CompletionKind::Command),
+];
+
+/// Get how the arguments of a command are completed
+///
+/// The command may be abbreviated as far as Vim allows.
+pub fn argument_kind(command: &str) -> CompletionKind {
+    ARGUMENT_KINDS.iter()
+        .find(|(full, min, _)| command.len() >= *min && full.starts_with(command))
+        .map_or(CompletionKind::None, |(_, _, kind)| kind.clone())
+}
+
+/// The word to complete on a command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompletionRequest {
+    /// Position of the word in the line, in characters
+    pub start: usize,
+    /// The word, with backslashes before spaces removed
+    pub lead: String,
+    /// What the word is completed as
+    pub kind: CompletionKind,
+    /// Name of the command the word belongs to, as typed
+    pub command: String,
+}
+
+/// Work out what to complete at the end of a command line
+///
+/// The line is the text before the cursor, without the `:`. A range in
+/// front of the command is skipped. While the command name is typed it is
+/// what gets completed, after it the last argument is.
+pub fn completion_request(line: &str) -> CompletionRequest {
+    let chars: Vec<char> = line.chars().collect();
+    let mut i = skip_range(&chars);
+
+    // The command name
+    let name_start = i;
+    if i < chars.len() && matches!(chars[i], '&' | '~' | '!') {
+        i += 1;
+    } else {
+        // User commands start with an uppercase letter and may hold digits
+        let user = chars.get(i).is_some_and(|c| c.is_uppercase());
+        while i < chars.len() && (chars[i].is_alphabetic() || (user && chars[i].is_ascii_digit())) {
+            i += 1;
+        }
+    }
+    let command: String = chars[name_start..i].iter().collect();
+    if i == chars.len() {
+        return CompletionRequest { start: name_start, lead: command.clone(), kind: CompletionKind::Command, command };
+    }
+
+    // The last argument, after the last space that is not escaped
+    if chars[i] == '!' && command != "!" {
+        i += 1;
+    }
+    let mut start = i;
+    let mut j = i;
+    while j < chars.len() {
+        match chars[j] {
+            '\\' => j += 1,
+            c if c.is_whitespace() => start = j + 1,
+            _ => {},
+        }
+        j += 1;
+    }
+    let mut lead = unescape(&chars[start.min(chars.len())..].iter().collect::<String>());
+    let mut kind = argument_kind(&command);
+
+    // `:set name=` completes the value of the option
+    if kind == CompletionKind::Option {
+        if let Some(eq) = lead.find('=') {
+            let name = lead[..eq].trim_end_matches(['+', '-', '^']).to_string();
+            start += lead[..=eq].chars().count();
+            lead = lead[eq + 1..].to_string();
+            kind = CompletionKind::OptionValue(name);
+        }
+    }
+
+    CompletionRequest { start, lead, kind, command }
+}
+
+/// Get the position after the `:`s, blanks and range at the start of a line
+fn skip_range(chars: &[char]) -> usize {
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            ':' | '.' | '$' | '%' | ',' | ';' | '+' | '-' => i += 1,
+            c if c.is_ascii_digit() || c.is_whitespace() => i += 1,
+            '\'' => i += 2,
+            '\\' if chars.get(i + 1).is_some_and(|c| matches!(c, '/' | '?' | '&')) => i += 2,
+            delimiter @ ('/' | '?') => {
+                i += 1;
+                while i < chars.len() && chars[i] != delimiter {
+                    if chars[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                i += 1;
+            },
+            _ => break,
+        }
+    }
+    i.min(chars.len())
+}
+
+/// Remove the backslashes that escape spaces
+fn unescape(word: &str) -> String {
+    word.replace("\\ ", " ")
+}
+
+/// Escape the spaces of a match, for it to stay a single argument
+pub fn escape_match(word: &str) -> String {
+    word.replace(' ', "\\ ")
+}
+
+/// Keep the names that start with the word typed, sorted and without duplicates
+pub fn filter_matches<I, S>(names: I, lead: &str) -> Vec<String>
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    let mut matches: Vec<String> = names.into_iter()
+        .map(Into::into)
+        .filter(|name| name.starts_with(lead))
+        .collect();
+    matches.sort();
+    matches.dedup();
+    matches
+}
+
+/// Complete a path
+///
+/// A leading `~` stands for the home directory. When the last component
+/// holds `*`, `?` or `[`, it is matched as a wildcard, otherwise as a
+/// prefix. Hidden files are only offered when the component starts with a
+/// dot. Directories end in a slash, and spaces are escaped.
+pub fn complete_path(lead: &str, directories_only: bool) -> Vec<String> {
+    if lead == "~" {
+        return match dirs::home_dir() {
+            Some(_) => vec!["~/".to_string()],
+            None => Vec::new(),
+        };
+    }
+
+    let (dir_text, name) = match lead.rfind('/') {
+        Some(idx) => lead.split_at(idx + 1),
+        None => ("", lead),
+    };
+    let dir = match expand_home(dir_text) {
+        Some(dir) => dir,
+        None => return Vec::new(),
+    };
+    let entries = match std::fs::read_dir(if dir_text.is_empty() { Path::new(".") } else { dir.as_path() }) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let pattern: Option<Vec<char>> = name.contains(['*', '?', '[']).then(|| name.chars().collect());
+    let mut matches: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if file_name.starts_with('.') && !name.starts_with('.') {
+                return None;
+            }
+            let matched = match &pattern {
+                Some(pattern) => wildcard_match(pattern, &file_name.chars().collect::<Vec<_>>()),
+                None => file_name.starts_with(name),
+            };
+            let is_dir = entry.path().is_dir();
+            if !matched || (directories_only && !is_dir) {
+                return None;
+            }
+            let slash = if is_dir { "/" } else { "" };
+            Some(escape_match(&format!("{}{}{}", dir_text, file_name, slash)))
+        })
+        .collect();
+    matches.sort();
+    matches
+}
+
+/// Put the home directory in place of a leading `~`
+pub(crate) fn expand_home(path: &str) -> Option<PathBuf> {
+    match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            Some(dirs::home_dir()?.join(rest.trim_start_matches('/')))
+        },
+        _ => Some(PathBuf::from(path)),
+    }
+}
+
+/// Check whether a name matches a wildcard
+///
+/// `*` matches any text, `?` any character and `[abc]` or `[a-z]` one of
+/// the characters given, or with `[!...]` one that is not.
+fn wildcard_match(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| wildcard_match(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && wildcard_match(&pattern[1..], &name[1..]),
+        Some('[') => {
+            let close = match pattern.iter().skip(2).position(|&c| c == ']') {
+                Some(idx) => idx + 2,
+                None => return name.first() == Some(&'[') && wildcard_match(&pattern[1..], &name[1..]),
+            };
+            let Some(&c) = name.first() else { return false };
+            let (negate, set) = match pattern[1] {
+                '!' | '^' => (true, &pattern[2..close]),
+                _ => (false, &pattern[1..close]),
+            };
+            let mut found = false;
+            let mut i = 0;
+            while i < set.len() {
+                if i + 2 < set.len() && set[i + 1] == '-' {
+                    found |= set[i] <= c && c <= set[i + 2];
+                    i += 3;
+                } else {
+                    found |= set[i] == c;
+                    i += 1;
+                }
+            }
+            found != negate && wildcard_match(&pattern[close + 1..], &name[1..])
+        },
+        Some(&c) => name.first() == Some(&c) && wildcard_match(&pattern[1..], &name[1..]),
+    }
+}
+
+/// What a press of Tab does, as one part of 'wildmode' says
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WildMode {
+    /// Complete the next full match (full)
+    Full,
+    /// Complete the longest common text of the matches (longest)
+    Longest,
+    /// Like `Longest`, showing the matches in the wildmenu (longest:full)
+    LongestFull,
+    /// Only show the matches (list)
+    List,
+    /// Show the matches and complete the first full match (list:full)
+    ListFull,
+    /// Show the matches and complete the longest common text (list:longest)
+    ListLongest,
+}
+
+impl WildMode {
+    /// Parse one part of 'wildmode'
+    pub fn parse(part: &str) -> Option<Self> {
+        match part {
+            "full" => Some(WildMode::Full),
+            "longest" => Some(WildMode::Longest),
+            "longest:full" => Some(WildMode::LongestFull),
+            "list" => Some(WildMode::List),
+            "list:full" => Some(WildMode::ListFull),
+            "list:longest" => Some(WildMode::ListLongest),
+            _ => None,
+        }
+    }
+}
+
+/// Completing a word, kept while Tab is pressed again
+#[derive(Debug, Clone)]
+pub struct Completion {
+    /// Position of the word in the line, in characters
+    start: usize,
+    /// Text typed before completing
+    typed: String,
+    /// Text the word was completed to
+    current: String,
+    /// The matches
+    matches: Vec<String>,
+    /// Match completed to when cycling, `None` for the text typed
+    selected: Option<usize>,
+    /// Number of times Tab was pressed
+    presses: usize,
+    /// Whether the matches are shown
+    listed: bool,
+}
+
+impl Completion {
+    /// Start completing the text typed from a position of the line
+    pub fn new(start: usize, typed: &str, matches: Vec<String>) -> Self {
+        Self {
+            start,
+            typed: typed.to_string(),
+            current: typed.to_string(),
+            matches,
+            selected: None,
+            presses: 0,
+            listed: false,
+        }
+    }
+
+    /// Get the position of the word in the line, in characters
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Get the matches
+    pub fn matches(&self) -> &[String] {
+        &self.matches
+    }
+
+    /// Get the match completed to when cycling
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    /// Check whether the matches are to be shown
+    pub fn is_listed(&self) -> bool {
+        self.listed
+    }
+
+    /// Press Tab, or Shift-Tab when going backward, returning the text to put in place of the word
+    ///
+    /// Each press takes the next part of 'wildmode', and the last part is
+    /// kept for the presses after it. Cycling past the last match comes
+    /// back to the text typed. Shift-Tab always cycles backward. With
+    /// `wildmenu` the matches are shown while cycling.
+    pub fn next(&mut self, modes: &[WildMode], forward: bool, wildmenu: bool) -> String {
+        let mode = match modes.get(self.presses.min(modes.len().saturating_sub(1))) {
+            Some(mode) if forward => *mode,
+            _ => WildMode::Full,
+        };
+        self.presses += 1;
+
+        match mode {
+            WildMode::Full => {
+                self.listed |= wildmenu;
+                self.cycle(forward);
+            },
+            WildMode::Longest => self.longest(),
+            WildMode::LongestFull => {
+                self.listed |= wildmenu;
+                self.longest();
+            },
+            WildMode::List => self.listed = true,
+            WildMode::ListFull => {
+                self.listed = true;
+                self.cycle(forward);
+            },
+            WildMode::ListLongest => {
+                self.listed = true;
+                self.longest();
+            },
+        }
+        self.current.clone()
+    }
+
+    /// Complete the next or previous match
+    fn cycle(&mut self, forward: bool) {
+        let count = self.matches.len();
+        self.selected = match (self.selected, forward) {
+            (None, true) => Some(0),
+            (None, false) => count.checked_sub(1),
+            (Some(index), true) => Some(index + 1).filter(|&index| index < count),
+            (Some(index), false) => index.checked_sub(1),
+        };
+        self.current = match self.selected {
+            Some(index) => self.matches[index].clone(),
+            None => self.typed.clone(),
+        };
+    }
+
+    /// Complete the longest text all matches start with, when it is longer than the text there
+    fn longest(&mut self) {
+        let Some(first) = self.matches.first() else { return };
+        let mut common = first.as_str();
+        for other in &self.matches[1..] {
+            let len = common.char_indices()
+                .zip(other.chars())
+                .find(|((_, a), b)| a != b)
+                .map_or(common.len().min(other.len()), |((idx, _), _)| idx);
+            common = &common[..len];
+        }
+        if common.len() > self.current.len() {
+            self.current = common.to_string();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(line: &str) -> (usize, String, CompletionKind) {
+        let request = completion_request(line);
+        (request.start, request.lead, request.kind)
+    }
+
+    #[test]
+    fn test_completion_request() {
+        assert_eq!(request("wr"), (0, "wr".to_string(), CompletionKind::Command));
+        assert_eq!(request(":1,$ s"), (5, "s".to_string(), CompletionKind::Command));
+        assert_eq!(request("'a,/x\\/y/de"), (9, "de".to_string(), CompletionKind::Command));
+        assert_eq!(request("e src/bu"), (2, "src/bu".to_string(), CompletionKind::File));
+        assert_eq!(request("e! my\\ fi"), (3, "my fi".to_string(), CompletionKind::File));
+        assert_eq!(request("r !cat fi"), (7, "fi".to_string(), CompletionKind::File));
+        assert_eq!(request("cd "), (3, String::new(), CompletionKind::Directory));
+        assert_eq!(request("b mai"), (2, "mai".to_string(), CompletionKind::Buffer));
+        assert_eq!(request("se nu ts"), (6, "ts".to_string(), CompletionKind::Option));
+        assert_eq!(request("set sw+=2"), (8, "2".to_string(), CompletionKind::OptionValue("sw".to_string())));
+        assert_eq!(request("h wri"), (2, "wri".to_string(), CompletionKind::Help));
+        assert_eq!(request("colo d"), (5, "d".to_string(), CompletionKind::Color));
+        assert_eq!(request("s/a/b/"), (1, "/a/b/".to_string(), CompletionKind::None));
+    }
+
+    #[test]
+    fn test_argument_kind() {
+        assert_eq!(argument_kind("e"), CompletionKind::File);
+        assert_eq!(argument_kind("sp"), CompletionKind::File);
+        assert_eq!(argument_kind("setl"), CompletionKind::Option);
+        assert_eq!(argument_kind("s"), CompletionKind::None);
+        assert_eq!(argument_kind("col"), CompletionKind::None);
+        assert_eq!(CompletionKind::from_name("dir"), Some(CompletionKind::Directory));
+        assert_eq!(CompletionKind::from_name("nothing"), None);
+        assert_eq!(CompletionKind::Directory.name(), "dir");
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        let matches = |pattern: &str, name: &str| {
+            wildcard_match(&pattern.chars().collect::<Vec<_>>(), &name.chars().collect::<Vec<_>>())
+        };
+        assert!(matches("*.rs", "main.rs"));
+        assert!(!matches("*.rs", "main.rsx"));
+        assert!(matches("m?in*", "main.rs"));
+        assert!(matches("[a-m]ain.rs", "main.rs"));
+        assert!(!matches("[!m]ain.rs", "main.rs"));
+        assert!(matches("[ab", "[ab"));
+    }
+
+    #[test]
+    fn test_complete_path() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join("main.rs"), "").unwrap();
+        std::fs::write(dir.path().join("my file.txt"), "").unwrap();
+        std::fs::write(dir.path().join(".hidden"), "").unwrap();
+        let base = format!("{}/", dir.path().display());
+
+        let strip = |matches: Vec<String>| -> Vec<String> {
+            matches.iter().map(|path| path[base.len()..].to_string()).collect()
+        };
+        assert_eq!(strip(complete_path(&base, false)), vec!["main.rs", "my\\ file.txt", "src/"]);
+        assert_eq!(strip(complete_path(&format!("{}m", base), false)), vec!["main.rs", "my\\ file.txt"]);
+        assert_eq!(strip(complete_path(&format!("{}*.rs", base), false)), vec!["main.rs"]);
+        assert_eq!(strip(complete_path(&format!("{}.", base), false)), vec![".hidden"]);
+        assert_eq!(strip(complete_path(&base, true)), vec!["src/"]);
+        assert!(complete_path(&format!("{}nothing/", base), false).is_empty());
+    }
+
+    #[test]
+    fn test_completion_cycling() {
+        let matches = vec!["edit".to_string(), "earlier".to_string(), "echo".to_string()];
+        let mut completion = Completion::new(0, "e", matches.clone());
+        let full = [WildMode::Full];
+        assert_eq!(completion.next(&full, true, true), "edit");
+        assert!(completion.is_listed());
+        assert_eq!(completion.next(&full, true, true), "earlier");
+        assert_eq!(completion.next(&full, true, true), "echo");
+        assert_eq!(completion.next(&full, true, true), "e");
+        assert_eq!(completion.selected(), None);
+        assert_eq!(completion.next(&full, false, true), "echo");
+
+        // longest,list:full
+        let modes = [WildMode::Longest, WildMode::ListFull];
+        let mut completion = Completion::new(0, "ta", vec!["tabnew".to_string(), "tabnext".to_string()]);
+        assert_eq!(completion.next(&modes, true, false), "tabne");
+        assert!(!completion.is_listed());
+        assert_eq!(completion.next(&modes, true, false), "tabnew");
+        assert!(completion.is_listed());
+        assert_eq!(completion.next(&modes, true, false), "tabnext");
+
+        let mut completion = Completion::new(0, "e", matches);
+        assert_eq!(completion.next(&[WildMode::List], true, false), "e");
+        assert!(completion.is_listed());
+        assert_eq!(completion.next(&[WildMode::Full], true, false), "edit");
+    }
+}
@@ -5,7 +5,7 @@
 
 use std::str::FromStr;
 use std::fmt;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 /// Error type for ex command parsing
@@ -55,7 +55,7 @@ impl From<crate::buffer::BufferError> for ExCommandError {
 /// Result type for ex command operations
 pub type ExCommandResult<T> = Result<T, ExCommandError>;
 
-/// Command range specification
+/// A line address of a command range
 #[derive(Debug, Clone, PartialEq)]
 pub enum RangeSpec {
     /// Current line (.)
@@ -66,54 +66,245 @@ pub enum RangeSpec {
     LineNumber(usize),
     /// Line with mark
     Mark(char),
-    /// Search pattern
+    /// Next line matching a pattern (/pattern/)
     Search(String),
+    /// Previous line matching a pattern (?pattern?)
+    SearchBackward(String),
+    /// Next line matching the last search pattern (\/)
+    NextMatch,
+    /// Previous line matching the last search pattern (\?)
+    PreviousMatch,
+    /// Next line matching the last substitute pattern (\&)
+    NextSubstituteMatch,
     /// Relative offset from current line
     Offset(isize),
+    /// An address resolved from the line of another one, like `/pattern/+2` or `/one//two/`
+    Chain(Box<RangeSpec>, Box<RangeSpec>),
 }
-impl FromStr for RangeSpec {
-    type Err = ExCommandError;
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.is_empty() {
-            return Err(ExCommandError::InvalidRange("Empty range specification".to_string()));
+impl RangeSpec {
+    /// Follow this address with another one resolved from its line
+    ///
+    /// Offsets in a row add up, so `.+1+1` is the same as `.+2`.
+    fn then(self, next: RangeSpec) -> RangeSpec {
+        match (self, next) {
+            (RangeSpec::Offset(a), RangeSpec::Offset(b)) => RangeSpec::Offset(a.saturating_add(b)),
+            (RangeSpec::Chain(base, offset), RangeSpec::Offset(b)) => match *offset {
+                RangeSpec::Offset(a) => RangeSpec::Chain(base, Box::new(RangeSpec::Offset(a.saturating_add(b)))),
+                offset => RangeSpec::Chain(Box::new(RangeSpec::Chain(base, Box::new(offset))), Box::new(RangeSpec::Offset(b))),
+            },
+            (base, next) => RangeSpec::Chain(Box::new(base), Box::new(next)),
         }
+    }
 
-        match s {
-            "." => Ok(RangeSpec::CurrentLine),
-            "$" => Ok(RangeSpec::LastLine),
-            _ if s.starts_with('\'') => {
-                if s.len() < 2 {
-                    return Err(ExCommandError::InvalidRange("Invalid mark: missing character after '".to_string()));
-                }
-                let mark = s.chars().nth(1).unwrap();
-                Ok(RangeSpec::Mark(mark))
+    /// Resolve the address to a line number from the number of the current line
+    ///
+    /// Line numbers count from 1, and 0 is the position before the first
+    /// line, which commands like `:move` accept.
+    pub fn resolve(&self, context: &dyn AddressContext, current: usize) -> ExCommandResult<usize> {
+        let line = match self {
+            RangeSpec::CurrentLine => current,
+            RangeSpec::LastLine => context.line_count(),
+            RangeSpec::LineNumber(line) => *line,
+            RangeSpec::Mark(mark) => context.mark_line(*mark)
+                .ok_or_else(|| ExCommandError::Other("E20: Mark not set".to_string()))?,
+            RangeSpec::Search(pattern) => find_line(context, pattern, current, false)?,
+            RangeSpec::SearchBackward(pattern) => find_line(context, pattern, current, true)?,
+            RangeSpec::NextMatch => find_line(context, "", current, false)?,
+            RangeSpec::PreviousMatch => find_line(context, "", current, true)?,
+            RangeSpec::NextSubstituteMatch => {
+                let pattern = context.last_substitute_pattern()
+                    .ok_or_else(|| ExCommandError::Other("E35: No previous regular expression".to_string()))?;
+                find_line(context, &pattern, current, false)?
             },
-            _ if s.starts_with('/') && s.len() > 1 => {
-                let pattern = s[1..].to_string();
-                Ok(RangeSpec::Search(pattern))
+            RangeSpec::Offset(offset) => current.checked_add_signed(*offset)
+                .ok_or_else(|| ExCommandError::Other("E16: Invalid range".to_string()))?,
+            RangeSpec::Chain(base, next) => {
+                let line = base.resolve(context, current)?;
+                next.resolve(context, line)?
             },
-            _ if s.starts_with('+') => {
-                let offset = s[1..].parse::<isize>().map_err(|_| {
-                    ExCommandError::InvalidRange(format!("Invalid offset: {}", s))
-                })?;
-                Ok(RangeSpec::Offset(offset))
+        };
+
+        if line > context.line_count() {
+            return Err(ExCommandError::Other("E16: Invalid range".to_string()));
+        }
+        Ok(line)
+    }
+}
+
+/// Find the line a pattern address stands for
+///
+/// An empty pattern is the last search pattern.
+fn find_line(context: &dyn AddressContext, pattern: &str, current: usize, backward: bool) -> ExCommandResult<usize> {
+    if pattern.is_empty() {
+        let pattern = context.last_search_pattern()
+            .ok_or_else(|| ExCommandError::Other("E35: No previous regular expression".to_string()))?;
+        return find_line(context, &pattern, current, backward);
+    }
+    context.find_line(pattern, current, backward)?
+        .ok_or_else(|| ExCommandError::Other(format!("E486: Pattern not found: {}", pattern)))
+}
+
+/// Parse the address at the start of the input
+///
+/// An address is a line number, `.`, `$`, a mark or a pattern, followed by
+/// any number of offsets and patterns. Returns `None` when the input
+/// doesn't start with an address.
+fn parse_address(input: &str) -> ExCommandResult<(Option<RangeSpec>, &str)> {
+    let mut address: Option<RangeSpec> = None;
+    let mut rest = input;
+
+    while let Some(c) = rest.chars().next() {
+        let (item, after) = match c {
+            '.' | '$' | '\'' if address.is_some() => {
+                return Err(ExCommandError::InvalidRange(format!("Unexpected '{}' in address", c)));
             },
-            _ if s.starts_with('-') => {
-                let offset = -s[1..].parse::<isize>().map_err(|_| {
-                    ExCommandError::InvalidRange(format!("Invalid offset: {}", s))
-                })?;
-                Ok(RangeSpec::Offset(offset))
+            '.' => (RangeSpec::CurrentLine, &rest[1..]),
+            '$' => (RangeSpec::LastLine, &rest[1..]),
+            '\'' => {
+                let mark = rest[1..].chars().next()
+                    .ok_or_else(|| ExCommandError::InvalidRange("Invalid mark: missing character after '".to_string()))?;
+                (RangeSpec::Mark(mark), &rest[1 + mark.len_utf8()..])
             },
-            _ => {
-                // Try to parse as a line number
-                match s.parse::<usize>() {
-                    Ok(num) => Ok(RangeSpec::LineNumber(num)),
-                    Err(_) => Err(ExCommandError::InvalidRange(format!("Invalid range: {}", s))),
+            '0'..='9' => {
+                let (number, after) = parse_number(rest)?;
+                // A number after an address is an offset, as in `.5`
+                match address {
+                    Some(_) => (RangeSpec::Offset(number as isize), after),
+                    None => (RangeSpec::LineNumber(number), after),
                 }
-            }
+            },
+            '+' | '-' => {
+                // A sign without a number is an offset of one
+                let (number, after) = match rest[1..].chars().next() {
+                    Some('0'..='9') => parse_number(&rest[1..])?,
+                    _ => (1, &rest[1..]),
+                };
+                let offset = number as isize;
+                (RangeSpec::Offset(if c == '-' { -offset } else { offset }), after)
+            },
+            '/' | '?' => {
+                let (pattern, after) = parse_pattern(&rest[1..], c);
+                if c == '/' {
+                    (RangeSpec::Search(pattern), after)
+                } else {
+                    (RangeSpec::SearchBackward(pattern), after)
+                }
+            },
+            '\\' => match rest[1..].chars().next() {
+                Some('/') => (RangeSpec::NextMatch, &rest[2..]),
+                Some('?') => (RangeSpec::PreviousMatch, &rest[2..]),
+                Some('&') => (RangeSpec::NextSubstituteMatch, &rest[2..]),
+                _ => return Err(ExCommandError::InvalidRange("E10: \\ should be followed by /, ? or &".to_string())),
+            },
+            _ => break,
+        };
+
+        address = Some(match address {
+            Some(address) => address.then(item),
+            None => item,
+        });
+        rest = after;
+    }
+
+    Ok((address, rest))
+}
+
+/// Parse a line number or offset
+fn parse_number(input: &str) -> ExCommandResult<(usize, &str)> {
+    let end = input.find(|c: char| !c.is_ascii_digit()).unwrap_or(input.len());
+    let number = input[..end].parse::<usize>()
+        .ok()
+        .filter(|number| *number <= isize::MAX as usize)
+        .ok_or_else(|| ExCommandError::InvalidRange(format!("Number too large: {}", &input[..end])))?;
+    Ok((number, &input[end..]))
+}
+
+/// Parse a pattern up to its closing delimiter, or the end of the input
+///
+/// A delimiter escaped with a backslash is part of the pattern.
+pub(super) fn parse_pattern(input: &str, delimiter: char) -> (String, &str) {
+    let mut pattern = String::new();
+    let mut chars = input.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == delimiter => return (pattern, &input[i + c.len_utf8()..]),
+            '\\' => match chars.next() {
+                Some((_, next)) if next == delimiter => pattern.push(next),
+                Some((_, next)) => {
+                    pattern.push('\\');
+                    pattern.push(next);
+                },
+                None => pattern.push('\\'),
+            },
+            c => pattern.push(c),
         }
     }
+
+    (pattern, "")
+}
+
+/// The pattern of a command line that is only a search, such as `/foo` or `?bar?`
+///
+/// A line with more after the closing delimiter, such as `/foo/d` or
+/// `/foo/,$s/a/b/`, is an Ex command with a pattern address and gives `None`.
+/// The pattern is returned as written, escapes included.
+pub fn search_pattern(line: &str) -> Option<(char, &str)> {
+    let delimiter = line.chars().next().filter(|c| *c == '/' || *c == '?')?;
+    let body = &line[delimiter.len_utf8()..];
+    let mut escaped = false;
+
+    for (i, c) in body.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == delimiter {
+            return body[i + c.len_utf8()..].trim().is_empty().then_some((delimiter, &body[..i]));
+        }
+    }
+
+    Some((delimiter, body))
+}
+
+impl FromStr for RangeSpec {
+    type Err = ExCommandError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(ExCommandError::InvalidRange("Empty range specification".to_string()));
+        }
+
+        match parse_address(s)? {
+            (Some(address), "") => Ok(address),
+            _ => Err(ExCommandError::InvalidRange(format!("Invalid range: {}", s))),
+        }
+    }
+}
+
+/// What line addresses are resolved against
+///
+/// Line numbers count from 1.
+pub trait AddressContext {
+    /// Get the number of the cursor line
+    fn current_line(&self) -> usize;
+
+    /// Get the number of lines
+    fn line_count(&self) -> usize;
+
+    /// Get the line of a mark, if it is set
+    fn mark_line(&self, mark: char) -> Option<usize>;
+
+    /// Find the first line after `line`, or before it when `backward`, that
+    /// matches a pattern, wrapping around the end of the buffer
+    fn find_line(&self, pattern: &str, line: usize, backward: bool) -> ExCommandResult<Option<usize>>;
+
+    /// Get the last search pattern
+    fn last_search_pattern(&self) -> Option<String>;
+
+    /// Get the pattern of the last substitute
+    fn last_substitute_pattern(&self) -> Option<String>;
 }
 
 /// Command range
@@ -123,28 +314,24 @@ pub struct Range {
     pub start: Option<RangeSpec>,
     /// End of range
     pub end: Option<RangeSpec>,
+    /// Whether the end is resolved from the start rather than from the current line, as after `;`
+    pub set_current: bool,
 }
 
 impl Range {
     /// Create a new range
     pub fn new(start: Option<RangeSpec>, end: Option<RangeSpec>) -> Self {
-        Self { start, end }
+        Self { start, end, set_current: false }
     }
 
     /// Create a range for the current line
     pub fn current_line() -> Self {
-        Self {
-            start: Some(RangeSpec::CurrentLine),
-            end: None,
-        }
+        Self::new(Some(RangeSpec::CurrentLine), None)
     }
 
     /// Create a range for the entire buffer
     pub fn entire_buffer() -> Self {
-        Self {
-            start: Some(RangeSpec::LineNumber(1)),
-            end: Some(RangeSpec::LastLine),
-        }
+        Self::new(Some(RangeSpec::LineNumber(1)), Some(RangeSpec::LastLine))
     }
 
     /// Check if the range is empty (no start or end)
@@ -156,6 +343,34 @@ impl Range {
     pub fn is_single_line(&self) -> bool {
         self.start.is_some() && self.end.is_none()
     }
+
+    /// Resolve the range to the numbers of its first and last line
+    ///
+    /// Line numbers count from 1, and 0 is the position before the first
+    /// line. Without a range this is the current line, and a range given
+    /// backwards is swapped.
+    pub fn resolve(&self, context: &dyn AddressContext) -> ExCommandResult<(usize, usize)> {
+        let current = context.current_line();
+        let start = match &self.start {
+            Some(start) => start.resolve(context, current)?,
+            None => current,
+        };
+        let end = match &self.end {
+            Some(end) => end.resolve(context, if self.set_current { start } else { current })?,
+            None => start,
+        };
+
+        Ok((start.min(end), start.max(end)))
+    }
+
+    /// Resolve the range to the indexes of the lines it covers
+    ///
+    /// Indexes count from 0, and line 0 stands for the first line, as it
+    /// does for most commands.
+    pub fn lines(&self, context: &dyn AddressContext) -> ExCommandResult<(usize, usize)> {
+        let (start, end) = self.resolve(context)?;
+        Ok((start.max(1) - 1, end.max(1) - 1))
+    }
 }
 
 /// Command flags
@@ -193,6 +408,8 @@ pub struct ExCommand {
     pub flags: CommandFlags,
     /// Command arguments
     pub args: Vec<String>,
+    /// Arguments as they were typed, before they were split
+    pub arg_text: String,
     /// Raw command string
     pub raw: String,
 }
@@ -204,6 +421,7 @@ impl ExCommand {
             name: name.to_string(),
             range,
             flags,
+            arg_text: args.join(" "),
             args,
             raw: raw.to_string(),
         }
@@ -244,6 +462,8 @@ impl ExCommandParser {
         self.aliases.insert("x".to_string(), "xit".to_string());
         self.aliases.insert("q".to_string(), "quit".to_string());
         self.aliases.insert("e".to_string(), "edit".to_string());
+        self.aliases.insert("rec".to_string(), "recover".to_string());
+        self.aliases.insert("checkt".to_string(), "checktime".to_string());
         self.aliases.insert("r".to_string(), "read".to_string());
         
         // Window operations
@@ -270,7 +490,12 @@ impl ExCommandParser {
         // Other operations
         self.aliases.insert("u".to_string(), "undo".to_string());
         self.aliases.insert("red".to_string(), "redo".to_string());
+        self.aliases.insert("undol".to_string(), "undolist".to_string());
+        self.aliases.insert("ea".to_string(), "earlier".to_string());
+        self.aliases.insert("lat".to_string(), "later".to_string());
         self.aliases.insert("se".to_string(), "set".to_string());
+        self.aliases.insert("setl".to_string(), "setlocal".to_string());
+        self.aliases.insert("setg".to_string(), "setglobal".to_string());
     }
 
     /// Parse a command string
@@ -284,11 +509,22 @@ impl ExCommandParser {
         // Parse range
         let (range, rest) = self.parse_range(input)?;
         
+        // A range alone moves the cursor to its last line
+        if rest.trim().is_empty() && !range.is_empty() {
+            return Ok(ExCommand::new("", range, CommandFlags::default(), Vec::new(), input));
+        }
+        
         // Parse command name
         let (name, rest) = self.parse_command_name(rest)?;
         
-        // Parse flags
-        let (flags, rest) = self.parse_flags(rest)?;
+        // Parse flags, which must follow the name directly. What follows
+        // `:!` and the `!` of `:r!` is a shell command instead
+        let (flags, rest) = match name.as_str() {
+            "!" => (CommandFlags::default(), rest),
+            "r" | "read" if rest.starts_with('!') => (CommandFlags { force: true, ..Default::default() }, &rest[1..]),
+            _ if rest.starts_with('!') => self.parse_flags(rest)?,
+            _ => (CommandFlags::default(), rest),
+        };
         
         // Parse arguments
         let args = self.parse_args(rest)?;
@@ -296,85 +532,50 @@ impl ExCommandParser {
         // Resolve command alias
         let resolved_name = self.resolve_alias(&name);
         
-        Ok(ExCommand::new(&resolved_name, range, flags, args, input))
+        let mut command = ExCommand::new(&resolved_name, range, flags, args, input);
+        command.arg_text = rest.trim_start().to_string();
+        Ok(command)
     }
     
     /// Parse a range specification
+    ///
+    /// Addresses are separated by `,` or `;`, and after `;` the second
+    /// address is resolved from the first one. A missing address stands for
+    /// the current line, and when more than two are given the last two are used.
     fn parse_range<'a>(&self, input: &'a str) -> ExCommandResult<(Range, &'a str)> {
-        let mut chars = input.chars().peekable();
-        let mut range_str = String::new();
-        
-        // Check for range characters
-        while let Some(&c) = chars.peek() {
-            match c {
-                '.' | '$' | '\'' | '/' | '?' | '+' | '-' | '0'..='9' | ',' | ';' => {
-                    range_str.push(c);
-                    chars.next();
-                    
-                    // If we just added a single quote, we need to add the next character too
-                    if c == '\'' && chars.peek().is_some() {
-                        if let Some(mark_char) = chars.next() {
-                            range_str.push(mark_char);
-                        }
-                    }
-                },
-                _ => break,
-            }
-        }
-        
-        let rest = &input[range_str.len()..];
-        
-        if range_str.is_empty() {
-            return Ok((Range::new(None, None), rest));
+        let input = input.trim_start();
+
+        if let Some(rest) = input.strip_prefix('%') {
+            return Ok((Range::entire_buffer(), rest));
         }
-        
-        // Parse the range
-        if range_str.contains(',') {
-            // Range with comma separator (e.g., 1,5)
-            let parts: Vec<&str> = range_str.split(',').collect();
-            if parts.len() != 2 {
-                return Err(ExCommandError::InvalidRange(format!("Invalid range: {}", range_str)));
-            }
-            
-            let start = if parts[0].is_empty() {
-                None
-            } else {
-                Some(RangeSpec::from_str(parts[0])?)
-            };
-            
-            let end = if parts[1].is_empty() {
-                None
-            } else {
-                Some(RangeSpec::from_str(parts[1])?)
+
+        let (mut start, mut rest) = parse_address(input)?;
+        let mut end = None;
+        let mut set_current = false;
+
+        loop {
+            let after = rest.trim_start();
+            let separator = match after.chars().next() {
+                Some(c @ (',' | ';')) => c,
+                _ => break,
             };
-            
-            Ok((Range::new(start, end), rest))
-        } else if range_str.contains(';') {
-            // Range with semicolon separator (e.g., 1;5)
-            let parts: Vec<&str> = range_str.split(';').collect();
-            if parts.len() != 2 {
-                return Err(ExCommandError::InvalidRange(format!("Invalid range: {}", range_str)));
+
+            if end.is_some() {
+                start = end.take();
             }
-            
-            let start = if parts[0].is_empty() {
-                None
-            } else {
-                Some(RangeSpec::from_str(parts[0])?)
-            };
-            
-            let end = if parts[1].is_empty() {
-                None
-            } else {
-                Some(RangeSpec::from_str(parts[1])?)
-            };
-            
-            // Note: semicolon means the second address is relative to the first
-            Ok((Range::new(start, end), rest))
-        } else {
-            // Single line range
-            let spec = RangeSpec::from_str(&range_str)?;
-            Ok((Range::new(Some(spec), None), rest))
+            if start.is_none() {
+                start = Some(RangeSpec::CurrentLine);
+            }
+
+            let (address, after) = parse_address(after[1..].trim_start())?;
+            end = Some(address.unwrap_or(RangeSpec::CurrentLine));
+            set_current = separator == ';';
+            rest = after;
         }
+
+        let mut range = Range::new(start, end);
+        range.set_current = set_current;
+        Ok((range, rest))
     }
     
     /// Parse a command name
@@ -385,12 +586,19 @@ impl ExCommandParser {
             return Err(ExCommandError::InvalidCommand("Missing command name".to_string()));
         }
         
+        // `:&` and `:~` repeat a substitute, and `:!` runs a shell command
+        if let Some(c @ ('&' | '~' | '!')) = input.chars().next() {
+            return Ok((c.to_string(), &input[1..]));
+        }
+        
         let mut chars = input.chars();
         let mut name = String::new();
         
-        // Get the command name (letters only)
+        // Get the command name (letters only, or letters and digits for
+        // user commands, which start with an uppercase letter)
+        let user = input.starts_with(|c: char| c.is_uppercase());
         while let Some(c) = chars.next() {
-            if c.is_alphabetic() {
+            if c.is_alphabetic() || (user && c.is_ascii_digit()) {
                 name.push(c);
             } else {
                 break;
@@ -479,6 +687,8 @@ impl ExCommandParser {
 pub struct ExCommandRegistry {
     /// Command handlers
     handlers: HashMap<String, Arc<dyn Fn(&ExCommand) -> ExCommandResult<()> + Send + Sync>>,
+    /// Names that abbreviate another command
+    abbreviations: HashSet<String>,
 }
 
 impl ExCommandRegistry {
@@ -486,6 +696,7 @@ impl ExCommandRegistry {
     pub fn new() -> Self {
         Self {
             handlers: HashMap::new(),
+            abbreviations: HashSet::new(),
         }
     }
     
@@ -495,21 +706,50 @@ impl ExCommandRegistry {
         F: Fn(&ExCommand) -> ExCommandResult<()> + Send + Sync + 'static,
     {
         self.handlers.insert(name.to_string(), Arc::new(handler));
+        self.abbreviations.remove(name);
+    }
+    
+    /// Register an abbreviation running the handler of a registered command
+    pub fn register_abbreviation(&mut self, abbreviation: &str, name: &str) {
+        if let Some(handler) = self.handlers.get(name).cloned() {
+            self.handlers.insert(abbreviation.to_string(), handler);
+            self.abbreviations.insert(abbreviation.to_string());
+        }
     }
     
     /// Execute a command
     pub fn execute(&self, cmd: &ExCommand) -> ExCommandResult<()> {
-        if let Some(handler) = self.handlers.get(&cmd.name) {
+        // The handler is cloned since it may define or delete commands
+        if let Some(handler) = self.handlers.get(&cmd.name).cloned() {
             handler(cmd)
         } else {
             Err(ExCommandError::UnknownCommand(cmd.name.clone()))
         }
     }
     
+    /// Remove a command, returning whether it was registered
+    pub fn unregister(&mut self, name: &str) -> bool {
+        self.abbreviations.remove(name);
+        self.handlers.remove(name).is_some()
+    }
+    
     /// Check if a command is registered
     pub fn has_command(&self, name: &str) -> bool {
         self.handlers.contains_key(name)
     }
+
+    /// Get the names of the registered commands, sorted
+    ///
+    /// Abbreviations and commands named by a symbol, such as `!` and `&`,
+    /// are left out.
+    pub fn command_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.handlers.keys()
+            .filter(|name| name.starts_with(|c: char| c.is_alphabetic()) && !self.abbreviations.contains(*name))
+            .cloned()
+            .collect();
+        names.sort();
+        names
+    }
 }
 
 impl Default for ExCommandRegistry {
@@ -528,7 +768,7 @@ mod tests {
         assert_eq!(RangeSpec::from_str("$").unwrap(), RangeSpec::LastLine);
         assert_eq!(RangeSpec::from_str("5").unwrap(), RangeSpec::LineNumber(5));
         assert_eq!(RangeSpec::from_str("'a").unwrap(), RangeSpec::Mark('a'));
-        assert_eq!(RangeSpec::from_str("/pattern/").unwrap(), RangeSpec::Search("pattern/".to_string()));
+        assert_eq!(RangeSpec::from_str("/pattern/").unwrap(), RangeSpec::Search("pattern".to_string()));
         assert_eq!(RangeSpec::from_str("+3").unwrap(), RangeSpec::Offset(3));
         assert_eq!(RangeSpec::from_str("-2").unwrap(), RangeSpec::Offset(-2));
         
@@ -579,6 +819,136 @@ mod tests {
         println!("Test completed successfully");
     }
     
+    /// Address context over a list of lines
+    struct TestContext {
+        lines: Vec<&'static str>,
+        current: usize,
+    }
+    
+    impl AddressContext for TestContext {
+        fn current_line(&self) -> usize {
+            self.current
+        }
+        
+        fn line_count(&self) -> usize {
+            self.lines.len()
+        }
+        
+        fn mark_line(&self, mark: char) -> Option<usize> {
+            match mark {
+                'a' => Some(2),
+                'b' => Some(4),
+                _ => None,
+            }
+        }
+        
+        fn find_line(&self, pattern: &str, line: usize, backward: bool) -> ExCommandResult<Option<usize>> {
+            let count = self.lines.len();
+            Ok((1..=count)
+                .map(|step| if backward {
+                    (line + 2 * count - 1 - step) % count
+                } else {
+                    (line + step - 1) % count
+                })
+                .find(|&index| self.lines[index].contains(pattern))
+                .map(|index| index + 1))
+        }
+        
+        fn last_search_pattern(&self) -> Option<String> {
+            Some("foo".to_string())
+        }
+        
+        fn last_substitute_pattern(&self) -> Option<String> {
+            None
+        }
+    }
+    
+    fn resolve(input: &str) -> ExCommandResult<(usize, usize)> {
+        let context = TestContext {
+            lines: vec!["one", "foo", "two", "bar", "foo", "three"],
+            current: 3,
+        };
+        let (range, rest) = ExCommandParser::new().parse_range(input)?;
+        assert_eq!(rest, "d");
+        range.resolve(&context)
+    }
+    
+    #[test]
+    fn test_parse_address_grammar() {
+        let parser = ExCommandParser::new();
+        
+        let (range, rest) = parser.parse_range("%s/a/b/").unwrap();
+        assert_eq!(range, Range::entire_buffer());
+        assert_eq!(rest, "s/a/b/");
+        
+        let (range, rest) = parser.parse_range("'<,'>normal").unwrap();
+        assert_eq!(range.start.unwrap(), RangeSpec::Mark('<'));
+        assert_eq!(range.end.unwrap(), RangeSpec::Mark('>'));
+        assert_eq!(rest, "normal");
+        
+        assert_eq!(
+            RangeSpec::from_str("/foo/+2").unwrap(),
+            RangeSpec::Chain(Box::new(RangeSpec::Search("foo".to_string())), Box::new(RangeSpec::Offset(2)))
+        );
+        assert_eq!(RangeSpec::from_str("+++").unwrap(), RangeSpec::Offset(3));
+        assert_eq!(RangeSpec::from_str("-").unwrap(), RangeSpec::Offset(-1));
+        assert_eq!(RangeSpec::from_str("?a\\?b").unwrap(), RangeSpec::SearchBackward("a?b".to_string()));
+        assert_eq!(RangeSpec::from_str("\\/").unwrap(), RangeSpec::NextMatch);
+        assert_eq!(RangeSpec::from_str("\\?").unwrap(), RangeSpec::PreviousMatch);
+        assert_eq!(RangeSpec::from_str("\\&").unwrap(), RangeSpec::NextSubstituteMatch);
+        assert!(RangeSpec::from_str("\\x").is_err());
+        assert!(RangeSpec::from_str("5$").is_err());
+        
+        let (range, rest) = parser.parse_range("3;+1d").unwrap();
+        assert!(range.set_current);
+        assert_eq!(rest, "d");
+        
+        let (range, _) = parser.parse_range(",5d").unwrap();
+        assert_eq!(range.start.unwrap(), RangeSpec::CurrentLine);
+    }
+    
+    #[test]
+    fn test_search_pattern() {
+        assert_eq!(search_pattern("/foo"), Some(('/', "foo")));
+        assert_eq!(search_pattern("/foo/"), Some(('/', "foo")));
+        assert_eq!(search_pattern("?a\\?b?"), Some(('?', "a\\?b")));
+        assert_eq!(search_pattern("/a\\/b"), Some(('/', "a\\/b")));
+        assert_eq!(search_pattern("/foo/d"), None);
+        assert_eq!(search_pattern("/foo/,$s/a/b/"), None);
+        assert_eq!(search_pattern("?foo?+1"), None);
+        assert_eq!(search_pattern("s/a/b/"), None);
+    }
+    
+    #[test]
+    fn test_resolve_range() {
+        assert_eq!(resolve("d").unwrap(), (3, 3));
+        assert_eq!(resolve("%d").unwrap(), (1, 6));
+        assert_eq!(resolve("2d").unwrap(), (2, 2));
+        assert_eq!(resolve(".,$d").unwrap(), (3, 6));
+        assert_eq!(resolve("-,+d").unwrap(), (2, 4));
+        assert_eq!(resolve("'a,'bd").unwrap(), (2, 4));
+        assert_eq!(resolve("/foo/d").unwrap(), (5, 5));
+        assert_eq!(resolve("?foo?d").unwrap(), (2, 2));
+        assert_eq!(resolve("/foo/-1d").unwrap(), (4, 4));
+        assert_eq!(resolve("/foo//foo/d").unwrap(), (2, 2));
+        assert_eq!(resolve("\\/d").unwrap(), (5, 5));
+        assert_eq!(resolve("5,2d").unwrap(), (2, 5));
+        assert_eq!(resolve("0d").unwrap(), (0, 0));
+        
+        // ';' moves to the first address before the second one is resolved
+        assert_eq!(resolve("5,+1d").unwrap(), (4, 5));
+        assert_eq!(resolve("5;+1d").unwrap(), (5, 6));
+        assert_eq!(resolve("1;/foo/d").unwrap(), (1, 2));
+        assert_eq!(resolve("1,2,4d").unwrap(), (2, 4));
+        
+        assert_eq!(resolve("7d").unwrap_err().to_string(), "E16: Invalid range");
+        assert_eq!(resolve("$+1d").unwrap_err().to_string(), "E16: Invalid range");
+        assert_eq!(resolve("-5d").unwrap_err().to_string(), "E16: Invalid range");
+        assert_eq!(resolve("'zd").unwrap_err().to_string(), "E20: Mark not set");
+        assert_eq!(resolve("/baz/d").unwrap_err().to_string(), "E486: Pattern not found: baz");
+        assert_eq!(resolve("\\&d").unwrap_err().to_string(), "E35: No previous regular expression");
+    }
+    
     #[test]
     fn test_parse_command_name() {
         let parser = ExCommandParser::new();
@@ -600,7 +970,19 @@ mod tests {
         
         // Test invalid command
         assert!(parser.parse_command_name("").is_err());
-        assert!(parser.parse_command_name("!").is_err());
+        
+        // Test shell command
+        let (name, rest) = parser.parse_command_name("!ls -l").unwrap();
+        assert_eq!(name, "!");
+        assert_eq!(rest, "ls -l");
+        
+        // User commands may hold digits
+        let (name, rest) = parser.parse_command_name("Edit2 x").unwrap();
+        assert_eq!(name, "Edit2");
+        assert_eq!(rest, " x");
+        let (name, rest) = parser.parse_command_name("e2").unwrap();
+        assert_eq!(name, "e");
+        assert_eq!(rest, "2");
     }
     
     #[test]
@@ -710,6 +1092,18 @@ mod tests {
         assert!(cmd.flags.force);
         assert_eq!(cmd.args, vec!["file.txt"]);
         
+        // Test arguments as typed
+        let cmd = parser.parse("normal  ll\\x").unwrap();
+        assert!(!cmd.flags.print);
+        assert!(!cmd.flags.list);
+        assert_eq!(cmd.arg_text, "ll\\x");
+        let cmd = parser.parse("%s#a  b#c#g").unwrap();
+        assert_eq!(cmd.name, "substitute");
+        assert_eq!(cmd.arg_text, "#a  b#c#g");
+        let cmd = parser.parse("&&").unwrap();
+        assert_eq!(cmd.name, "&");
+        assert_eq!(cmd.arg_text, "&");
+        
         // Test command with alias
         let cmd = parser.parse("w").unwrap();
         assert_eq!(cmd.name, "write");
@@ -742,5 +1136,12 @@ mod tests {
         // Test unknown command
         let cmd = ExCommand::new("unknown", Range::new(None, None), CommandFlags::default(), Vec::new(), "unknown");
         assert!(registry.execute(&cmd).is_err());
+        
+        // Abbreviations run the same handler but are not listed
+        registry.register_abbreviation("te", "test");
+        registry.register("!", |_| Ok(()));
+        let cmd = ExCommand::new("te", Range::new(None, None), CommandFlags::default(), Vec::new(), "te");
+        assert!(registry.execute(&cmd).is_ok());
+        assert_eq!(registry.command_names(), vec!["test"]);
     }
 }
\ No newline at end of file
@@ -2,16 +2,26 @@
 //!
 //! This module implements handlers for ex commands.
 
-use crate::command::{ExCommand, ExCommandError, ExCommandResult, ExCommandRegistry};
+use crate::command::{
+    AddressContext, ExCommand, ExCommandError, ExCommandResult, ExCommandRegistry, RangeSpec, UserCommand, LISTING_HEADER
+};
+use crate::command::complete::expand_home;
+use crate::command::ex::parse_pattern;
 use crate::cursor::CursorPosition;
 use crate::editor::Editor;
+use crate::option::SetCommand;
 use crate::plugin::PluginManager;
+use crate::search::{Substitute, SubstituteFlags, expand_previous};
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 
 // Global reference to the editor instance
 // This is a temporary solution until we have a proper way to pass the editor to command handlers
 static mut EDITOR: Option<*mut Editor> = None;
 
+/// Topics `:help` knows about besides the command names
+pub const HELP_TOPICS: &[&str] = &["buffers", "commands", "help", "mappings", "options", "tabs", "windows"];
+
 /// Set the global editor reference
 pub fn set_editor(editor: &mut Editor) {
     unsafe {
@@ -19,6 +29,20 @@ pub fn set_editor(editor: &mut Editor) {
     }
 }
 
+/// Run a user command, defined with `:command` or by a plugin
+pub fn run_user_command(name: &str, cmd: &ExCommand) -> ExCommandResult<()> {
+    // Get the editor reference
+    let editor = unsafe {
+        match EDITOR {
+            Some(editor_ptr) => &mut *editor_ptr,
+            None => return Err(ExCommandError::InvalidCommand("Editor not initialized".to_string())),
+        }
+    };
+    
+    editor.run_user_command(name, cmd)
+        .map_err(|err| ExCommandError::Other(err.to_string()))
+}
+
 // We'll use a simpler approach for now - just store a flag to indicate whether to quit
 // In a more complete implementation, we would use a proper command context
 
@@ -34,89 +58,118 @@ where
 pub fn register_handlers(registry: &mut ExCommandRegistry, plugin_manager: Option<Arc<Mutex<PluginManager>>>) {
     // File operations
     registry.register("write", make_handler(handle_write));
-    registry.register("w", make_handler(handle_write));
+    registry.register_abbreviation("w", "write");
     registry.register("quit", make_handler(handle_quit));
-    registry.register("q", make_handler(handle_quit));
+    registry.register_abbreviation("q", "quit");
     registry.register("wquit", make_handler(handle_write_quit));
     registry.register("wq", make_handler(handle_write_quit));
     registry.register("xit", make_handler(handle_write_quit));
-    registry.register("x", make_handler(handle_write_quit));
+    registry.register_abbreviation("x", "xit");
     registry.register("edit", make_handler(handle_edit));
-    registry.register("e", make_handler(handle_edit));
+    registry.register_abbreviation("e", "edit");
+    registry.register("recover", make_handler(handle_recover));
+    registry.register_abbreviation("rec", "recover");
+    registry.register("checktime", make_handler(handle_checktime));
+    registry.register_abbreviation("checkt", "checktime");
     registry.register("read", make_handler(handle_read));
-    registry.register("r", make_handler(handle_read));
+    registry.register_abbreviation("r", "read");
     
     // Window operations
     registry.register("split", make_handler(handle_split));
-    registry.register("sp", make_handler(handle_split));
+    registry.register_abbreviation("sp", "split");
     registry.register("vsplit", make_handler(handle_vsplit));
-    registry.register("vs", make_handler(handle_vsplit));
+    registry.register_abbreviation("vs", "vsplit");
     registry.register("close", make_handler(handle_close));
-    registry.register("clo", make_handler(handle_close));
+    registry.register_abbreviation("clo", "close");
     registry.register("only", make_handler(handle_only));
-    registry.register("on", make_handler(handle_only));
+    registry.register_abbreviation("on", "only");
     registry.register("wnext", make_handler(handle_wnext));
-    registry.register("wn", make_handler(handle_wnext));
+    registry.register_abbreviation("wn", "wnext");
     registry.register("wprevious", make_handler(handle_wprev));
-    registry.register("wp", make_handler(handle_wprev));
+    registry.register_abbreviation("wp", "wprevious");
     
     // Tab operations
     registry.register("tabedit", make_handler(handle_tabedit));
-    registry.register("tabe", make_handler(handle_tabedit));
+    registry.register_abbreviation("tabe", "tabedit");
     registry.register("tabnew", make_handler(handle_tabedit));
     registry.register("tabclose", make_handler(handle_tabclose));
-    registry.register("tabc", make_handler(handle_tabclose));
+    registry.register_abbreviation("tabc", "tabclose");
     registry.register("tabnext", make_handler(handle_tabnext));
-    registry.register("tabn", make_handler(handle_tabnext));
+    registry.register_abbreviation("tabn", "tabnext");
     registry.register("tabprevious", make_handler(handle_tabprev));
-    registry.register("tabp", make_handler(handle_tabprev));
+    registry.register_abbreviation("tabp", "tabprevious");
     
     // Editing operations
     registry.register("delete", make_handler(handle_delete));
-    registry.register("d", make_handler(handle_delete));
+    registry.register_abbreviation("d", "delete");
     registry.register("yank", make_handler(handle_yank));
-    registry.register("y", make_handler(handle_yank));
+    registry.register_abbreviation("y", "yank");
     registry.register("put", make_handler(handle_put));
-    registry.register("p", make_handler(handle_put));
+    registry.register_abbreviation("pu", "put");
+    registry.register("print", make_handler(handle_print));
+    registry.register_abbreviation("p", "print");
     registry.register("copy", make_handler(handle_copy));
-    registry.register("co", make_handler(handle_copy));
+    registry.register_abbreviation("co", "copy");
     registry.register("t", make_handler(handle_copy));
     registry.register("move", make_handler(handle_move));
-    registry.register("m", make_handler(handle_move));
+    registry.register_abbreviation("m", "move");
     registry.register("substitute", make_handler(handle_substitute));
-    registry.register("s", make_handler(handle_substitute));
+    registry.register_abbreviation("s", "substitute");
+    registry.register("&", make_handler(handle_substitute_repeat));
+    registry.register("~", make_handler(handle_substitute_search));
+    registry.register("!", make_handler(handle_shell));
     registry.register("global", make_handler(handle_global));
-    registry.register("g", make_handler(handle_global));
+    registry.register_abbreviation("g", "global");
     registry.register("vglobal", make_handler(handle_vglobal));
-    registry.register("v", make_handler(handle_vglobal));
+    registry.register_abbreviation("v", "vglobal");
     
     // Other operations
     registry.register("undo", make_handler(handle_undo));
-    registry.register("u", make_handler(handle_undo));
+    registry.register_abbreviation("u", "undo");
     registry.register("redo", make_handler(handle_redo));
-    registry.register("red", make_handler(handle_redo));
+    registry.register_abbreviation("red", "redo");
+    registry.register("undolist", make_handler(handle_undolist));
+    registry.register_abbreviation("undol", "undolist");
+    registry.register("earlier", make_handler(handle_earlier));
+    registry.register_abbreviation("ea", "earlier");
+    registry.register("later", make_handler(handle_later));
+    registry.register_abbreviation("lat", "later");
     registry.register("set", make_handler(handle_set));
-    registry.register("se", make_handler(handle_set));
+    registry.register_abbreviation("se", "set");
+    registry.register("setlocal", make_handler(handle_setlocal));
+    registry.register_abbreviation("setl", "setlocal");
+    registry.register("setglobal", make_handler(handle_setglobal));
+    registry.register_abbreviation("setg", "setglobal");
     registry.register("map", make_handler(handle_map));
     registry.register("unmap", make_handler(handle_unmap));
     registry.register("marks", make_handler(handle_marks));
     registry.register("jumps", make_handler(handle_jumps));
+    registry.register_abbreviation("ju", "jumps");
+    registry.register("changes", make_handler(handle_changes));
     registry.register("registers", make_handler(handle_registers));
-    registry.register("reg", make_handler(handle_registers));
+    registry.register_abbreviation("reg", "registers");
     registry.register("buffers", make_handler(handle_buffers));
     registry.register("ls", make_handler(handle_buffers));
     registry.register("files", make_handler(handle_buffers));
     registry.register("windows", make_handler(handle_windows));
     registry.register("tabs", make_handler(handle_tabs));
     registry.register("help", make_handler(handle_help));
-    registry.register("h", make_handler(handle_help));
+    registry.register_abbreviation("h", "help");
     
     // Additional commands
     registry.register("cd", make_handler(handle_cd));
     registry.register("chdir", make_handler(handle_cd));
     registry.register("sort", make_handler(handle_sort));
+    registry.register("colorscheme", make_handler(handle_colorscheme));
+    registry.register_abbreviation("colo", "colorscheme");
     registry.register("normal", make_handler(handle_normal));
-    registry.register("norm", make_handler(handle_normal));
+    registry.register_abbreviation("norm", "normal");
+    registry.register("command", make_handler(handle_command));
+    registry.register_abbreviation("com", "command");
+    registry.register("delcommand", make_handler(handle_delcommand));
+    registry.register_abbreviation("delc", "delcommand");
+    registry.register("source", make_handler(handle_source));
+    registry.register_abbreviation("so", "source");
     
     // Register plugin commands if a plugin manager is provided
     if let Some(plugin_manager) = plugin_manager {
@@ -134,18 +187,29 @@ fn handle_write(cmd: &ExCommand) -> ExCommandResult<()> {
         }
     };
     
+    // `:w !cmd` passes the lines to a command rather than writing them
+    if let Some(command) = cmd.arg_text.strip_prefix('!') {
+        let (start_line, end_line) = if cmd.range.is_empty() {
+            (0, editor.line_count().saturating_sub(1))
+        } else {
+            cmd.range.lines(editor)?
+        };
+        return editor.write_to_command(start_line, end_line, command)
+            .map_err(|err| ExCommandError::Other(err.to_string()));
+    }
+    
     // If a filename was provided, save the buffer to that file
     if let Some(filename) = cmd.first_arg() {
         let path = std::path::Path::new(filename);
         
         // Save the buffer to the specified file
-        match editor.save_current_buffer_as(path) {
+        match editor.write_current_buffer_as(path, cmd.flags.force) {
             Ok(_) => Ok(()),
             Err(err) => Err(ExCommandError::InvalidCommand(format!("Failed to write file: {}", err))),
         }
     } else {
         // Otherwise, save the buffer to its current file
-        match editor.save_current_buffer() {
+        match editor.write_current_buffer(cmd.flags.force) {
             Ok(_) => Ok(()),
             Err(err) => Err(ExCommandError::InvalidCommand(format!("Failed to write file: {}", err))),
         }
@@ -268,6 +332,14 @@ fn handle_read(cmd: &ExCommand) -> ExCommandResult<()> {
         }
     };
     
+    // `:r !cmd` and `:r!cmd` put what a command writes below the line, or above the first one for line 0
+    let command = if cmd.flags.force { Some(cmd.arg_text.as_str()) } else { cmd.arg_text.strip_prefix('!') };
+    if let Some(command) = command {
+        let (_, line) = cmd.range.resolve(editor)?;
+        return editor.read_command(line.checked_sub(1), command)
+            .map_err(|err| ExCommandError::Other(err.to_string()));
+    }
+    
     // Check if a filename was provided
     if let Some(filename) = cmd.first_arg() {
         let path = std::path::Path::new(filename);
@@ -677,6 +749,41 @@ fn handle_tabprev(_cmd: &ExCommand) -> ExCommandResult<()> {
     }
 }
 
+/// Get the lines a command applies to, counting from 0
+///
+/// A count argument, as in `:d 3`, stands for that many lines from the last
+/// line of the range.
+fn command_lines(editor: &Editor, cmd: &ExCommand) -> ExCommandResult<(usize, usize)> {
+    let (start, end) = cmd.range.lines(editor)?;
+    
+    match cmd.first_arg() {
+        Some(count_str) => {
+            let count = match count_str.parse::<usize>() {
+                Ok(n) if n > 0 => n,
+                _ => return Err(ExCommandError::InvalidArgument(format!("Invalid count: {}", count_str))),
+            };
+            let last_line = editor.line_count().saturating_sub(1);
+            Ok((end, (end + count - 1).min(last_line)))
+        },
+        None => Ok((start, end)),
+    }
+}
+
+/// Get the line a `:copy` or `:move` puts lines below, counting from 0
+///
+/// Address 0 puts them above the first line.
+fn destination_line(editor: &Editor, cmd: &ExCommand) -> ExCommandResult<Option<usize>> {
+    let args = cmd.args_str();
+    
+    if args.is_empty() {
+        return Err(ExCommandError::MissingArgument("Destination line number required".to_string()));
+    }
+    
+    let address = RangeSpec::from_str(&args)?;
+    let line = address.resolve(editor, editor.current_line())?;
+    Ok(line.checked_sub(1))
+}
+
 /// Handle the :delete command
 fn handle_delete(cmd: &ExCommand) -> ExCommandResult<()> {
     // Get the editor reference
@@ -687,32 +794,11 @@ fn handle_delete(cmd: &ExCommand) -> ExCommandResult<()> {
         }
     };
     
-    // Get the current buffer ID
-    let buffer_id = match editor.current_buffer_id() {
-        Some(id) => id,
-        None => return Err(ExCommandError::InvalidCommand("No buffer to delete from".to_string())),
-    };
-    
-    // Get the current cursor position
-    let cursor_pos = editor.cursor_position();
-    let start_line = cursor_pos.line;
-    
-    // Parse the count from the command arguments
-    let count = if let Some(count_str) = cmd.first_arg() {
-        match count_str.parse::<usize>() {
-            Ok(n) => n,
-            Err(_) => return Err(ExCommandError::InvalidCommand(format!("Invalid count: {}", count_str))),
-        }
-    } else {
-        // Default to 1 line if no count is provided
-        1
-    };
-    
-    // Calculate the end line (inclusive)
-    let end_line = start_line + count - 1;
+    let (start_line, end_line) = command_lines(editor, cmd)?;
+    let count = end_line - start_line + 1;
     
     // Delete the lines
-    match editor.delete_lines_from_cursor(buffer_id, start_line, end_line) {
+    match editor.delete_lines(start_line, end_line) {
         Ok(_) => {
             println!("{} line{} deleted", count, if count == 1 { "" } else { "s" });
             Ok(())
@@ -731,29 +817,8 @@ fn handle_yank(cmd: &ExCommand) -> ExCommandResult<()> {
         }
     };
     
-    // Get the current buffer ID
-    let buffer_id = match editor.current_buffer_id() {
-        Some(id) => id,
-        None => return Err(ExCommandError::InvalidCommand("No buffer to yank from".to_string())),
-    };
-    
-    // Get the current cursor position
-    let cursor_pos = editor.cursor_position();
-    let start_line = cursor_pos.line;
-    
-    // Parse the count from the command arguments
-    let count = if let Some(count_str) = cmd.first_arg() {
-        match count_str.parse::<usize>() {
-            Ok(n) => n,
-            Err(_) => return Err(ExCommandError::InvalidCommand(format!("Invalid count: {}", count_str))),
-        }
-    } else {
-        // Default to 1 line if no count is provided
-        1
-    };
-    
-    // Calculate the end line (inclusive)
-    let end_line = start_line + count - 1;
+    let (start_line, end_line) = command_lines(editor, cmd)?;
+    let count = end_line - start_line + 1;
     
     // Use the editor's yank_lines method if it exists, otherwise use a workaround
     match editor.yank_lines(start_line, end_line) {
@@ -825,36 +890,17 @@ fn handle_copy(cmd: &ExCommand) -> ExCommandResult<()> {
         }
     };
     
-    // Get the current buffer ID
-    let buffer_id = match editor.current_buffer_id() {
-        Some(id) => id,
-        None => return Err(ExCommandError::InvalidCommand("No buffer to copy in".to_string())),
-    };
+    let (start_line, end_line) = cmd.range.lines(editor)?;
+    let dest_line = destination_line(editor, cmd)?;
+    let count = end_line - start_line + 1;
     
-    // Get the arguments
-    let args = cmd.args_str();
-    
-    if args.is_empty() {
-        return Err(ExCommandError::MissingArgument("Destination line number required".to_string()));
-    }
-    
-    // Parse the destination line number
-    let dest_line = match args.parse::<usize>() {
-        Ok(n) => n,
-        Err(_) => return Err(ExCommandError::InvalidArgument(format!("Invalid line number: {}", args))),
-    };
-    
-    // Get the current cursor position
-    let cursor_pos = editor.cursor_position();
-    let start_line = cursor_pos.line;
-    
-    // Copy the current line to the destination
-    match editor.copy_line(buffer_id, start_line, dest_line) {
+    // Copy the lines below the destination
+    match editor.copy_lines(start_line, end_line, dest_line) {
         Ok(_) => {
-            println!("1 line copied");
+            println!("{} line{} copied", count, if count == 1 { "" } else { "s" });
             Ok(())
         },
-        Err(err) => Err(ExCommandError::InvalidCommand(format!("Failed to copy line: {}", err))),
+        Err(err) => Err(ExCommandError::InvalidCommand(format!("Failed to copy lines: {}", err))),
     }
 }
 
@@ -868,40 +914,25 @@ fn handle_move(cmd: &ExCommand) -> ExCommandResult<()> {
         }
     };
     
-    // Get the current buffer ID
-    let buffer_id = match editor.current_buffer_id() {
-        Some(id) => id,
-        None => return Err(ExCommandError::InvalidCommand("No buffer to move in".to_string())),
-    };
-    
-    // Get the arguments
-    let args = cmd.args_str();
-    
-    if args.is_empty() {
-        return Err(ExCommandError::MissingArgument("Destination line number required".to_string()));
-    }
-    
-    // Parse the destination line number
-    let dest_line = match args.parse::<usize>() {
-        Ok(n) => n,
-        Err(_) => return Err(ExCommandError::InvalidArgument(format!("Invalid line number: {}", args))),
-    };
-    
-    // Get the current cursor position
-    let cursor_pos = editor.cursor_position();
-    let start_line = cursor_pos.line;
+    let (start_line, end_line) = cmd.range.lines(editor)?;
+    let dest_line = destination_line(editor, cmd)?;
+    let count = end_line - start_line + 1;
     
-    // Move the current line to the destination
-    match editor.move_line(buffer_id, start_line, dest_line) {
+    // Move the lines below the destination
+    match editor.move_lines(start_line, end_line, dest_line) {
         Ok(_) => {
-            println!("1 line moved");
+            println!("{} line{} moved", count, if count == 1 { "" } else { "s" });
             Ok(())
         },
-        Err(err) => Err(ExCommandError::InvalidCommand(format!("Failed to move line: {}", err))),
+        Err(err) => Err(ExCommandError::InvalidCommand(format!("Failed to move lines: {}", err))),
     }
 }
 
 /// Handle the :substitute command
+///
+/// `:s/pattern/replacement/[flags] [count]` takes any delimiter that isn't a
+/// letter, a digit, a blank, `\`, `"` or `|`. Without a pattern it repeats
+/// the last substitute, like `:&`.
 fn handle_substitute(cmd: &ExCommand) -> ExCommandResult<()> {
     // Get the editor reference
     let editor = unsafe {
@@ -911,68 +942,34 @@ fn handle_substitute(cmd: &ExCommand) -> ExCommandResult<()> {
         }
     };
     
-    // Get the arguments
-    let args = cmd.args_str();
-    
-    if args.is_empty() {
-        // If no arguments, use the last search pattern and replacement
-        println!("Using last search pattern and replacement");
-        println!("  (Substitute with last pattern not fully implemented yet)");
-        return Ok(());
-    }
+    let args = cmd.arg_text.as_str();
+    let delimiter = match args.chars().next() {
+        Some(c) if !c.is_alphanumeric() && !c.is_whitespace() && !"\\\"|".contains(c) => c,
+        _ => return repeat_substitute(editor, cmd, false),
+    };
     
-    // Parse the substitute command
-    // Format: /pattern/replacement/[flags]
-    // or s/pattern/replacement/[flags]
+    // Split the arguments into the pattern, the replacement and the flags
+    let (pattern, rest) = parse_pattern(&args[delimiter.len_utf8()..], delimiter);
+    let (replacement, rest) = parse_pattern(rest, delimiter);
     
-    // Check if the command starts with 's/'
-    let args = if args.starts_with('s') && args.len() > 1 && args.chars().nth(1) == Some('/') {
-        &args[2..]
-    } else if args.starts_with('/') {
-        &args[1..]
+    // An empty pattern is the last search pattern, and `~` the last replacement
+    let pattern = if pattern.is_empty() {
+        editor.last_search_pattern()
+            .ok_or_else(|| ExCommandError::Other("E35: No previous regular expression".to_string()))?
     } else {
-        return Err(ExCommandError::InvalidArgument(format!("Invalid substitute format: {}", args)));
+        pattern
     };
+    let previous = editor.last_substitute().cloned();
+    let replacement = expand_previous(&replacement, previous.as_ref().map_or("", |previous| previous.replacement.as_str()));
+    let (flags, rest) = SubstituteFlags::parse(rest, previous.map(|previous| previous.flags).unwrap_or_default());
     
-    // Split the arguments by '/'
-    let parts: Vec<&str> = args.split('/').collect();
-    
-    if parts.len() < 2 {
-        return Err(ExCommandError::InvalidArgument(format!("Invalid substitute format: {}", args)));
-    }
-    
-    let pattern = parts[0];
-    let replacement = parts.get(1).unwrap_or(&"");
-    let flags = parts.get(2).unwrap_or(&"");
-    
-    // Parse flags
-    let global = flags.contains('g');
-    let case_sensitive = !flags.contains('i');
-    let confirm = flags.contains('c');
-    
-    // Get the current buffer ID
-    let buffer_id = match editor.current_buffer_id() {
-        Some(id) => id,
-        None => return Err(ExCommandError::InvalidCommand("No buffer to substitute in".to_string())),
-    };
-    
-    // Perform the search and replace
-    match editor.search_and_replace(pattern, replacement, case_sensitive, confirm) {
-        Ok(count) => {
-            println!("{} substitution{} on {} line{}",
-                count,
-                if count == 1 { "" } else { "s" },
-                count, // This is a simplification; in reality, the number of lines affected might be different
-                if count == 1 { "" } else { "s" }
-            );
-            Ok(())
-        },
-        Err(err) => Err(ExCommandError::InvalidCommand(format!("Failed to substitute: {}", err))),
-    }
+    run_substitute(editor, cmd, Substitute { pattern, replacement, flags }, rest)
 }
 
-/// Handle the :global command
-fn handle_global(cmd: &ExCommand) -> ExCommandResult<()> {
+/// Handle the :& command, which repeats the last substitute
+///
+/// `:&&` keeps its flags.
+fn handle_substitute_repeat(cmd: &ExCommand) -> ExCommandResult<()> {
     // Get the editor reference
     let editor = unsafe {
         match EDITOR {
@@ -981,92 +978,79 @@ fn handle_global(cmd: &ExCommand) -> ExCommandResult<()> {
         }
     };
     
-    // Get the arguments
-    let args = cmd.args_str();
-    
-    if args.is_empty() {
-        return Err(ExCommandError::MissingArgument("Pattern and command required".to_string()));
-    }
-    
-    // Parse the global command
-    // Format: /pattern/command
-    
-    // Check if the command starts with '/'
-    if !args.starts_with('/') {
-        return Err(ExCommandError::InvalidArgument(format!("Invalid global format: {}", args)));
-    }
-    
-    // Find the end of the pattern
-    let pattern_end = match args[1..].find('/') {
-        Some(pos) => pos + 1, // +1 to account for the leading '/'
-        None => return Err(ExCommandError::InvalidArgument(format!("Invalid global format: {}", args))),
+    repeat_substitute(editor, cmd, false)
+}
+
+/// Handle the :~ command, which repeats the last substitute with the last search pattern
+fn handle_substitute_search(cmd: &ExCommand) -> ExCommandResult<()> {
+    // Get the editor reference
+    let editor = unsafe {
+        match EDITOR {
+            Some(editor_ptr) => &mut *editor_ptr,
+            None => return Err(ExCommandError::InvalidCommand("Editor not initialized".to_string())),
+        }
     };
     
-    let pattern = &args[1..pattern_end];
-    let command = args[pattern_end + 1..].trim();
-    
-    if command.is_empty() {
-        return Err(ExCommandError::MissingArgument("Command required".to_string()));
-    }
-    
-    // Get the current buffer ID
-    let buffer_id = match editor.current_buffer_id() {
-        Some(id) => id,
-        None => return Err(ExCommandError::InvalidCommand("No buffer to execute global command in".to_string())),
+    repeat_substitute(editor, cmd, true)
+}
+
+/// Repeat the last substitute with the flags of a command
+///
+/// The `r` flag, or `search_pattern`, uses the last search pattern instead of
+/// the last substitute pattern.
+fn repeat_substitute(editor: &mut Editor, cmd: &ExCommand, search_pattern: bool) -> ExCommandResult<()> {
+    let previous = editor.last_substitute().cloned()
+        .ok_or_else(|| ExCommandError::Other("E35: No previous regular expression".to_string()))?;
+    let (flags, rest) = SubstituteFlags::parse(&cmd.arg_text, previous.flags);
+    
+    let pattern = if search_pattern || flags.search_pattern {
+        editor.last_search_pattern()
+            .ok_or_else(|| ExCommandError::Other("E35: No previous regular expression".to_string()))?
+    } else {
+        previous.pattern
     };
     
-    // Get a reference to the buffer
-    let buffer = editor.get_buffer_manager().get_buffer(buffer_id)?;
-    
-    // Create a command parser
-    let parser = crate::command::ExCommandParser::new();
-    
-    // Create a command registry
-    let mut registry = crate::command::ExCommandRegistry::new();
-    register_handlers(&mut registry, None);
-    
-    // Find lines that match the pattern
-    let mut matching_lines = Vec::new();
-    let line_count = buffer.line_count();
-    
-    for line_idx in 0..line_count {
-        let line = buffer.line(line_idx)?;
-        
-        // Check if the line matches the pattern
-        if line.contains(pattern) {
-            matching_lines.push(line_idx);
-        }
-    }
-    
-    // Execute the command on each matching line
-    // We need to process lines in reverse order to handle the case where we're deleting lines
-    // This ensures that line numbers remain valid even after deletions
-    let mut count = 0;
-    for line_idx in matching_lines.iter().rev() {
-        // Set the cursor to the matching line
-        editor.get_cursor_manager_mut().set_position(crate::cursor::CursorPosition::new(*line_idx, 0));
-        
-        // Parse and execute the command
-        match parser.parse(command) {
-            Ok(ex_cmd) => {
-                if let Err(err) = registry.execute(&ex_cmd) {
-                    return Err(ExCommandError::Other(format!("Failed to execute command on line {}: {}", line_idx + 1, err)));
-                }
-                count += 1;
-            },
-            Err(err) => {
-                return Err(ExCommandError::Other(format!("Failed to parse command: {}", err)));
-            }
-        }
+    run_substitute(editor, cmd, Substitute { pattern, replacement: previous.replacement, flags }, rest)
+}
+
+/// Run a substitute on the lines of a command, the current line by default
+///
+/// A count after the flags stands for that many lines from the last line of the range.
+fn run_substitute(editor: &mut Editor, cmd: &ExCommand, substitute: Substitute, rest: &str) -> ExCommandResult<()> {
+    let (mut start_line, mut end_line) = cmd.range.lines(editor)?;
+    
+    let rest = rest.trim();
+    if !rest.is_empty() {
+        let count = match rest.parse::<usize>() {
+            Ok(count) if count > 0 => count,
+            Ok(_) => return Err(ExCommandError::InvalidArgument("E939: Positive count required".to_string())),
+            Err(_) => return Err(ExCommandError::InvalidArgument(format!("E488: Trailing characters: {}", rest))),
+        };
+        start_line = end_line;
+        end_line = (end_line + count - 1).min(editor.line_count().saturating_sub(1));
     }
     
-    println!("{} line{} processed", count, if count == 1 { "" } else { "s" });
-    
-    Ok(())
+    editor.substitute(start_line, end_line, substitute)
+        .map_err(|err| ExCommandError::Other(err.to_string()))
+}
+
+/// Handle the :global command
+///
+/// `:g!` runs the command on the lines that don't match, like `:vglobal`.
+fn handle_global(cmd: &ExCommand) -> ExCommandResult<()> {
+    run_global(cmd, cmd.flags.force)
 }
 
 /// Handle the :vglobal command
 fn handle_vglobal(cmd: &ExCommand) -> ExCommandResult<()> {
+    run_global(cmd, true)
+}
+
+/// Run `:global` or `:vglobal` over the range, the whole buffer by default
+///
+/// The pattern is delimited like a substitute's, and the command defaults
+/// to `:p`.
+fn run_global(cmd: &ExCommand, invert: bool) -> ExCommandResult<()> {
     // Get the editor reference
     let editor = unsafe {
         match EDITOR {
@@ -1075,92 +1059,88 @@ fn handle_vglobal(cmd: &ExCommand) -> ExCommandResult<()> {
         }
     };
     
-    // Get the arguments
-    let args = cmd.args_str();
-    
-    if args.is_empty() {
-        return Err(ExCommandError::MissingArgument("Pattern and command required".to_string()));
-    }
-    
-    // Parse the vglobal command
-    // Format: /pattern/command
+    let args = cmd.arg_text.as_str();
+    let delimiter = match args.chars().next() {
+        Some(c) if !c.is_alphanumeric() && !c.is_whitespace() && !"\\\"|".contains(c) => c,
+        Some(_) => return Err(ExCommandError::InvalidArgument("E146: Regular expressions can't be delimited by letters".to_string())),
+        None => return Err(ExCommandError::MissingArgument("E476: Invalid command".to_string())),
+    };
+    let (pattern, command) = parse_pattern(&args[delimiter.len_utf8()..], delimiter);
+    let command = match command.trim_start() {
+        "" => "p",
+        command => command,
+    };
     
-    // Check if the command starts with '/'
-    if !args.starts_with('/') {
-        return Err(ExCommandError::InvalidArgument(format!("Invalid vglobal format: {}", args)));
-    }
+    // An empty pattern is the last search pattern
+    let pattern = if pattern.is_empty() {
+        editor.last_search_pattern()
+            .ok_or_else(|| ExCommandError::Other("E35: No previous regular expression".to_string()))?
+    } else {
+        pattern
+    };
     
-    // Find the end of the pattern
-    let pattern_end = match args[1..].find('/') {
-        Some(pos) => pos + 1, // +1 to account for the leading '/'
-        None => return Err(ExCommandError::InvalidArgument(format!("Invalid vglobal format: {}", args))),
+    let (start_line, end_line) = if cmd.range.is_empty() {
+        (0, editor.line_count().saturating_sub(1))
+    } else {
+        cmd.range.lines(editor)?
     };
     
-    let pattern = &args[1..pattern_end];
-    let command = args[pattern_end + 1..].trim();
+    editor.global(start_line, end_line, &pattern, invert, command)
+        .map_err(|err| ExCommandError::Other(err.to_string()))
+}
+
+/// Handle the :! command
+///
+/// With a range the lines are filtered through the command, otherwise the
+/// command runs and what it writes is shown.
+fn handle_shell(cmd: &ExCommand) -> ExCommandResult<()> {
+    // Get the editor reference
+    let editor = unsafe {
+        match EDITOR {
+            Some(editor_ptr) => &mut *editor_ptr,
+            None => return Err(ExCommandError::InvalidCommand("Editor not initialized".to_string())),
+        }
+    };
     
-    if command.is_empty() {
-        return Err(ExCommandError::MissingArgument("Command required".to_string()));
+    let command = cmd.arg_text.as_str();
+    if command.trim().is_empty() {
+        return Err(ExCommandError::MissingArgument("E471: Argument required".to_string()));
     }
     
-    // Get the current buffer ID
-    let buffer_id = match editor.current_buffer_id() {
-        Some(id) => id,
-        None => return Err(ExCommandError::InvalidCommand("No buffer to execute vglobal command in".to_string())),
+    let result = if cmd.range.is_empty() {
+        editor.shell_command(command)
+    } else {
+        let (start_line, end_line) = cmd.range.lines(editor)?;
+        editor.filter_lines(start_line, end_line, command)
     };
-    
-    // Get a reference to the buffer
-    let buffer = editor.get_buffer_manager().get_buffer(buffer_id)?;
-    
-    // Create a command parser
-    let parser = crate::command::ExCommandParser::new();
-    
-    // Create a command registry
-    let mut registry = crate::command::ExCommandRegistry::new();
-    register_handlers(&mut registry, None);
-    
-    // Find lines that don't match the pattern
-    let mut non_matching_lines = Vec::new();
-    let line_count = buffer.line_count();
-    
-    for line_idx in 0..line_count {
-        let line = buffer.line(line_idx)?;
-        
-        // Check if the line doesn't match the pattern
-        if !line.contains(pattern) {
-            non_matching_lines.push(line_idx);
+    result.map_err(|err| ExCommandError::Other(err.to_string()))
+}
+
+/// Handle the :print command
+fn handle_print(cmd: &ExCommand) -> ExCommandResult<()> {
+    // Get the editor reference
+    let editor = unsafe {
+        match EDITOR {
+            Some(editor_ptr) => &mut *editor_ptr,
+            None => return Err(ExCommandError::InvalidCommand("Editor not initialized".to_string())),
         }
-    }
+    };
     
-    // Execute the command on each non-matching line
-    // We need to process lines in reverse order to handle the case where we're deleting lines
-    // This ensures that line numbers remain valid even after deletions
-    let mut count = 0;
-    for line_idx in non_matching_lines.iter().rev() {
-        // Set the cursor to the non-matching line
-        editor.get_cursor_manager_mut().set_position(crate::cursor::CursorPosition::new(*line_idx, 0));
-        
-        // Parse and execute the command
-        match parser.parse(command) {
-            Ok(ex_cmd) => {
-                if let Err(err) = registry.execute(&ex_cmd) {
-                    return Err(ExCommandError::Other(format!("Failed to execute command on line {}: {}", line_idx + 1, err)));
-                }
-                count += 1;
-            },
-            Err(err) => {
-                return Err(ExCommandError::Other(format!("Failed to parse command: {}", err)));
-            }
-        }
+    let (start_line, end_line) = command_lines(editor, cmd)?;
+    let buffer_id = editor.current_buffer_id()
+        .ok_or_else(|| ExCommandError::InvalidCommand("No buffer to print".to_string()))?;
+    let buffer = editor.get_buffer_manager().get_buffer(buffer_id)?;
+    for line in start_line..=end_line {
+        println!("{}", buffer.line(line)?);
     }
     
-    println!("{} line{} processed", count, if count == 1 { "" } else { "s" });
-    
+    // The cursor goes to the last line printed
+    editor.get_cursor_manager_mut().set_position(CursorPosition::new(end_line, 0));
     Ok(())
 }
 
 /// Handle the :undo command
-fn handle_undo(_cmd: &ExCommand) -> ExCommandResult<()> {
+fn handle_undo(cmd: &ExCommand) -> ExCommandResult<()> {
     // Get the editor reference
     let editor = unsafe {
         match EDITOR {
@@ -1178,6 +1158,17 @@ fn handle_undo(_cmd: &ExCommand) -> ExCommandResult<()> {
     // Get a mutable reference to the buffer
     let buffer = editor.get_buffer_manager_mut().get_buffer_mut(buffer_id)?;
     
+    // With a count, jump to the state after that change
+    if let Some(arg) = cmd.first_arg() {
+        let seq = arg.parse::<usize>()
+            .map_err(|_| ExCommandError::InvalidArgument(format!("Invalid undo number: {}", arg)))?;
+        
+        return match buffer.undo_to(seq) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(ExCommandError::Other(format!("Failed to undo changes: {}", err))),
+        };
+    }
+    
     // Perform the undo operation
     match buffer.undo() {
         Ok(true) => {
@@ -1225,8 +1216,8 @@ fn handle_redo(_cmd: &ExCommand) -> ExCommandResult<()> {
     }
 }
 
-/// Handle the :set command
-fn handle_set(cmd: &ExCommand) -> ExCommandResult<()> {
+/// Handle the :undolist command
+fn handle_undolist(_cmd: &ExCommand) -> ExCommandResult<()> {
     // Get the editor reference
     let editor = unsafe {
         match EDITOR {
@@ -1235,62 +1226,168 @@ fn handle_set(cmd: &ExCommand) -> ExCommandResult<()> {
         }
     };
     
-    // Get the arguments
-    let args = cmd.args_str();
+    // Get the current buffer ID
+    let buffer_id = match editor.current_buffer_id() {
+        Some(id) => id,
+        None => return Err(ExCommandError::InvalidCommand("No buffer".to_string())),
+    };
     
-    if args.is_empty() {
-        // If no arguments, show all options
-        println!("Options:");
-        println!("  (Option display not fully implemented yet)");
+    let buffer = editor.get_buffer_manager().get_buffer(buffer_id)?;
+    let branches = buffer.change_history().branches();
+    
+    if branches.is_empty() {
+        println!("Nothing to undo");
         return Ok(());
     }
     
-    // Parse the arguments
-    // Format can be:
-    // - option (show option value)
-    // - option=value (set option to value)
-    // - nooption (set boolean option to false)
-    // - option! (toggle boolean option)
-    // - option? (show option value)
+    println!("number changes  when               saved");
+    for branch in branches {
+        // Show recent changes relative to now and older ones as a time of day
+        let elapsed = branch.timestamp.elapsed();
+        let when = if elapsed.as_secs() < 100 {
+            format!("{} seconds ago", elapsed.as_secs())
+        } else {
+            let time = chrono::Local::now() - chrono::Duration::from_std(elapsed).unwrap_or_else(|_| chrono::Duration::zero());
+            time.format("%H:%M:%S").to_string()
+        };
+        let saved = branch.save_nr.map(|nr| nr.to_string()).unwrap_or_default();
+        
+        println!("{:6} {:7}  {:<18} {}", branch.seq, branch.changes, when, saved);
+    }
+    
+    Ok(())
+}
+
+/// Move through the undo tree in time for :earlier and :later
+fn undo_in_time(cmd: &ExCommand, forward: bool) -> ExCommandResult<()> {
+    // Get the editor reference
+    let editor = unsafe {
+        match EDITOR {
+            Some(editor_ptr) => &mut *editor_ptr,
+            None => return Err(ExCommandError::InvalidCommand("Editor not initialized".to_string())),
+        }
+    };
     
-    let parts: Vec<&str> = args.split_whitespace().collect();
+    // Parse the count and unit
+    let arg = cmd.args_str();
+    let time = crate::buffer::UndoTime::parse(&arg)
+        .ok_or_else(|| ExCommandError::InvalidArgument(format!("E475: Invalid argument: {}", arg)))?;
     
-    for part in parts {
-        if part.contains('=') {
-            // Set option to value
-            let option_parts: Vec<&str> = part.split('=').collect();
-            if option_parts.len() != 2 {
-                return Err(ExCommandError::InvalidArgument(format!("Invalid option format: {}", part)));
-            }
-            
-            let option_name = option_parts[0];
-            let option_value = option_parts[1];
-            
-            println!("Setting option {} to {}", option_name, option_value);
-            println!("  (Option setting not fully implemented yet)");
-        } else if part.starts_with("no") {
-            // Set boolean option to false
-            let option_name = &part[2..];
-            println!("Setting option {} to false", option_name);
-            println!("  (Option setting not fully implemented yet)");
-        } else if part.ends_with('!') {
-            // Toggle boolean option
-            let option_name = &part[..part.len() - 1];
-            println!("Toggling option {}", option_name);
-            println!("  (Option toggling not fully implemented yet)");
-        } else if part.ends_with('?') {
-            // Show option value
-            let option_name = &part[..part.len() - 1];
-            println!("Option {}: (value not available)", option_name);
-            println!("  (Option display not fully implemented yet)");
-        } else {
-            // Show option value or set boolean option to true
-            println!("Option {}: (value not available)", part);
-            println!("  (Option display not fully implemented yet)");
+    let result = if forward {
+        editor.later(time)
+    } else {
+        editor.earlier(time)
+    };
+    
+    match result {
+        Ok(_) => Ok(()),
+        Err(err) => Err(ExCommandError::Other(format!("Failed to move in the undo tree: {}", err))),
+    }
+}
+
+/// Handle the :earlier command
+fn handle_earlier(cmd: &ExCommand) -> ExCommandResult<()> {
+    undo_in_time(cmd, false)
+}
+
+/// Handle the :later command
+fn handle_later(cmd: &ExCommand) -> ExCommandResult<()> {
+    undo_in_time(cmd, true)
+}
+
+/// Handle the :recover command
+fn handle_recover(cmd: &ExCommand) -> ExCommandResult<()> {
+    // Get the editor reference
+    let editor = unsafe {
+        match EDITOR {
+            Some(editor_ptr) => &mut *editor_ptr,
+            None => return Err(ExCommandError::InvalidCommand("Editor not initialized".to_string())),
+        }
+    };
+    
+    // Open the file to recover first if one was given
+    if let Some(filename) = cmd.first_arg() {
+        if let Err(err) = editor.open_file(filename) {
+            return Err(ExCommandError::InvalidCommand(format!("Failed to open file: {}", err)));
         }
     }
     
-    Ok(())
+    match editor.recover() {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            println!("Swap file has no unsaved changes");
+            Ok(())
+        }
+        Err(err) => Err(ExCommandError::InvalidCommand(err.to_string())),
+    }
+}
+
+/// Handle the :checktime command
+fn handle_checktime(cmd: &ExCommand) -> ExCommandResult<()> {
+    // Get the editor reference
+    let editor = unsafe {
+        match EDITOR {
+            Some(editor_ptr) => &mut *editor_ptr,
+            None => return Err(ExCommandError::InvalidCommand("Editor not initialized".to_string())),
+        }
+    };
+    
+    // An optional buffer number limits the check to that buffer
+    let buffer_id = match cmd.first_arg() {
+        Some(arg) => match arg.parse::<usize>() {
+            Ok(id) => Some(id),
+            Err(_) => return Err(ExCommandError::InvalidArgument(format!("E94: No matching buffer for {}", arg))),
+        },
+        None => None,
+    };
+    
+    match editor.check_time(buffer_id) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(ExCommandError::InvalidCommand(err.to_string())),
+    }
+}
+
+/// Handle the :set command
+fn handle_set(cmd: &ExCommand) -> ExCommandResult<()> {
+    run_set_command(cmd, SetCommand::Set)
+}
+
+/// Handle the :setlocal command
+fn handle_setlocal(cmd: &ExCommand) -> ExCommandResult<()> {
+    run_set_command(cmd, SetCommand::SetLocal)
+}
+
+/// Handle the :setglobal command
+fn handle_setglobal(cmd: &ExCommand) -> ExCommandResult<()> {
+    run_set_command(cmd, SetCommand::SetGlobal)
+}
+
+/// Run :set, :setlocal or :setglobal and show what it prints
+fn run_set_command(cmd: &ExCommand, command: SetCommand) -> ExCommandResult<()> {
+    // Get the editor reference
+    let editor = unsafe {
+        match EDITOR {
+            Some(editor_ptr) => &mut *editor_ptr,
+            None => return Err(ExCommandError::InvalidCommand("Editor not initialized".to_string())),
+        }
+    };
+    
+    // Arguments are:
+    // - option (set a boolean option, show any other option)
+    // - nooption / invoption / option! (turn off / toggle a boolean option)
+    // - option? (show option value)
+    // - option& (reset option to its default)
+    // - option=value, option+=value, option-=value, option^=value
+    // - all (show all options)
+    match editor.execute_set(command, &cmd.args_str()) {
+        Ok(lines) => {
+            for line in lines {
+                println!("  {}", line);
+            }
+            Ok(())
+        }
+        Err(err) => Err(ExCommandError::InvalidArgument(err.to_string())),
+    }
 }
 
 /// Handle the :map command
@@ -1377,10 +1474,14 @@ fn handle_marks(cmd: &ExCommand) -> ExCommandResult<()> {
         // If specific marks were requested, display only those
         mark_names.chars().collect::<Vec<char>>()
     } else {
-        // Otherwise, display all marks
-        // For now, we'll just display a few common marks
-        vec!['a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '\'', '`', '[', ']', '<', '>', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9']
+        // Otherwise, display all marks in the order Vim lists them
+        std::iter::once('\'')
+            .chain('a'..='z')
+            .chain('A'..='Z')
+            .chain(['[', ']', '^', '.', '<', '>'])
+            .collect::<Vec<char>>()
     };
+    let current_buffer_id = editor.current_buffer_id();
     
     println!("--- Marks ---");
     println!("mark line  col file/text");
@@ -1390,13 +1491,8 @@ fn handle_marks(cmd: &ExCommand) -> ExCommandResult<()> {
         // Get the mark position
         match editor.get_mark_position(mark) {
             Some(position) => {
-                // Get the buffer name
-                let buffer_name = match editor.get_buffer_name(position.buffer_id) {
-                    Some(name) => name,
-                    None => "[No Name]".to_string(),
-                };
-                
-                println!(" {}   {:4} {:3}  {}", mark, position.line, position.column, buffer_name);
+                let text = position_text(editor, current_buffer_id, position.buffer_id, position.line);
+                println!(" {} {:6} {:4} {}", mark, position.line + 1, position.column, text);
             },
             None => {
                 // Skip empty marks
@@ -1408,6 +1504,20 @@ fn handle_marks(cmd: &ExCommand) -> ExCommandResult<()> {
     Ok(())
 }
 
+/// Get the text `:marks`, `:jumps` and `:changes` show for a position
+///
+/// Positions in the current buffer show their line, others the name of their buffer.
+fn position_text(editor: &Editor, current_buffer_id: Option<usize>, buffer_id: usize, line: usize) -> String {
+    let text = if Some(buffer_id) == current_buffer_id {
+        editor.get_buffer_manager().get_buffer(buffer_id).ok()
+            .and_then(|buffer| buffer.line(line).ok())
+            .map(|line| line.trim_end_matches(['\n', '\r']).to_string())
+    } else {
+        editor.get_buffer_name(buffer_id)
+    };
+    text.unwrap_or_else(|| "[No Name]".to_string())
+}
+
 /// Handle the :jumps command
 fn handle_jumps(_cmd: &ExCommand) -> ExCommandResult<()> {
     // Get the editor reference
@@ -1420,34 +1530,69 @@ fn handle_jumps(_cmd: &ExCommand) -> ExCommandResult<()> {
     
     // Get the jump list
     let jump_list = editor.get_jump_list();
-    let current_jump_index = editor.get_current_jump_index();
-    
-    if jump_list.is_empty() {
-        println!("No jumps");
-        return Ok(());
-    }
+    let current_jump_index = editor.get_current_jump_index().unwrap_or(jump_list.len());
+    let current_buffer_id = editor.current_buffer_id();
     
-    println!("--- Jump list ---");
-    println!("jump line  col file/text");
+    println!(" jump line  col file/text");
     
-    // Display the jump list
+    // Entries are numbered by their distance from the current one, which Ctrl-O and Ctrl-I take as count
     for (i, jump) in jump_list.iter().enumerate() {
-        let current_marker = if Some(i) == current_jump_index { ">" } else { " " };
+        let current_marker = if i == current_jump_index { ">" } else { " " };
+        let text = position_text(editor, current_buffer_id, jump.buffer_id, jump.position.line);
         
-        // Get the buffer name
-        let buffer_name = match editor.get_buffer_name(jump.buffer_id) {
-            Some(name) => name,
-            None => "[No Name]".to_string(),
-        };
-        
-        println!("{} {:3} {:4} {:3}  {}",
+        println!("{}{:4} {:5} {:4} {}",
             current_marker,
-            i,
-            jump.position.line,
+            i.abs_diff(current_jump_index),
+            jump.position.line + 1,
             jump.position.column,
-            buffer_name
+            text
+        );
+    }
+    if current_jump_index >= jump_list.len() {
+        println!(">");
+    }
+    
+    Ok(())
+}
+
+/// Handle the :changes command
+fn handle_changes(_cmd: &ExCommand) -> ExCommandResult<()> {
+    // Get the editor reference
+    let editor = unsafe {
+        match EDITOR {
+            Some(editor_ptr) => &mut *editor_ptr,
+            None => return Err(ExCommandError::InvalidCommand("Editor not initialized".to_string())),
+        }
+    };
+    
+    let buffer_id = match editor.current_buffer_id() {
+        Some(id) => id,
+        None => return Err(ExCommandError::InvalidCommand("No buffer to list changes of".to_string())),
+    };
+    let change_list = match editor.get_buffer_manager().get_buffer(buffer_id) {
+        Ok(buffer) => buffer.change_list().clone(),
+        Err(err) => return Err(ExCommandError::Other(format!("Failed to get buffer: {}", err))),
+    };
+    
+    println!("change line  col text");
+    
+    // Like :jumps, entries are numbered by their distance from the current one
+    let current_index = change_list.index();
+    for (i, change) in change_list.changes().iter().enumerate() {
+        let current_marker = if i == current_index { ">" } else { " " };
+        let text = position_text(editor, Some(buffer_id), buffer_id, change.line);
+        
+        println!("{}{:5} {:5} {:4} {}",
+            current_marker,
+            i.abs_diff(current_index),
+            change.line + 1,
+            change.column,
+            text
         );
     }
+    if current_index >= change_list.changes().len() {
+        println!(">");
+    }
     
     Ok(())
 }
@@ -1673,6 +1818,8 @@ fn handle_help(cmd: &ExCommand) -> ExCommandResult<()> {
             println!("  :quit, :q - Quit the current window");
             println!("  :wquit, :wq, :xit, :x - Write and quit");
             println!("  :edit, :e - Edit a file");
+            println!("  :recover, :rec - Recover unsaved changes from a swap file");
+            println!("  :checktime, :checkt - Check whether files changed outside xvim");
             println!("  :read, :r - Read a file into the current buffer");
             println!("");
             println!("Window operations:");
@@ -1701,17 +1848,26 @@ fn handle_help(cmd: &ExCommand) -> ExCommandResult<()> {
             println!("");
             println!("Other operations:");
             println!("  :undo, :u - Undo changes");
+            println!("  :undolist - List the undo tree branches");
+            println!("  :earlier, :later - Move through the undo tree in time");
             println!("  :redo, :red - Redo changes");
             println!("  :set, :se - Set options");
+            println!("  :setlocal, :setl - Set the current window's or buffer's value of options");
+            println!("  :setglobal, :setg - Set the global value of options");
             println!("  :map - Create key mappings");
             println!("  :unmap - Remove key mappings");
             println!("  :marks - Display marks");
             println!("  :jumps - Display jump list");
+            println!("  :changes - Display change list");
             println!("  :registers, :reg - Display registers");
             println!("  :buffers, :ls, :files - Display buffers");
             println!("  :windows - Display windows");
             println!("  :tabs - Display tabs");
             println!("  :help, :h - Display help");
+            println!("  :colorscheme, :colo - Set the color scheme");
+            println!("  :command, :com - Define or list user commands");
+            println!("  :delcommand, :delc - Delete a user command");
+            println!("  :source, :so - Run the Ex commands in a file");
         },
         "options" => {
             println!("--- Available Options ---");
@@ -1780,6 +1936,110 @@ fn handle_cd(cmd: &ExCommand) -> ExCommandResult<()> {
     }
 }
 
+/// Handle the :colorscheme command
+///
+/// Without a name the current color scheme is shown.
+fn handle_colorscheme(cmd: &ExCommand) -> ExCommandResult<()> {
+    // Get the editor reference
+    let editor = unsafe {
+        match EDITOR {
+            Some(editor_ptr) => &mut *editor_ptr,
+            None => return Err(ExCommandError::InvalidCommand("Editor not initialized".to_string())),
+        }
+    };
+    
+    let name = match cmd.first_arg() {
+        Some(name) => name,
+        None => {
+            println!("{}", editor.config_manager().config().ui.color_scheme);
+            return Ok(());
+        },
+    };
+    if !editor.config_manager().color_scheme_names().iter().any(|scheme| scheme == name) {
+        return Err(ExCommandError::Other(format!("E185: Cannot find color scheme '{}'", name)));
+    }
+    
+    editor.config_manager_mut().config_mut().ui.color_scheme = name.to_string();
+    Ok(())
+}
+
+/// Handle the :command command
+///
+/// Without a replacement the user commands starting with the name given,
+/// or all of them, are listed.
+fn handle_command(cmd: &ExCommand) -> ExCommandResult<()> {
+    // Get the editor reference
+    let editor = unsafe {
+        match EDITOR {
+            Some(editor_ptr) => &mut *editor_ptr,
+            None => return Err(ExCommandError::InvalidCommand("Editor not initialized".to_string())),
+        }
+    };
+    
+    let text = cmd.arg_text.trim();
+    let prefix = if text.is_empty() {
+        String::new()
+    } else {
+        let command = UserCommand::parse(text)?;
+        if !command.replacement().is_empty() {
+            return editor.define_user_command(command, cmd.flags.force)
+                .map_err(|err| ExCommandError::Other(err.to_string()));
+        }
+        command.name
+    };
+    
+    let commands: Vec<&UserCommand> = editor.user_commands()
+        .filter(|command| command.name.starts_with(&prefix))
+        .collect();
+    if commands.is_empty() {
+        println!("No user-defined commands found");
+        return Ok(());
+    }
+    
+    println!("{}", LISTING_HEADER);
+    for command in commands {
+        println!("{}", command.listing_line());
+        if let Some(source) = &command.source {
+            println!("\tLast set from {}", source);
+        }
+    }
+    Ok(())
+}
+
+/// Handle the :delcommand command
+fn handle_delcommand(cmd: &ExCommand) -> ExCommandResult<()> {
+    // Get the editor reference
+    let editor = unsafe {
+        match EDITOR {
+            Some(editor_ptr) => &mut *editor_ptr,
+            None => return Err(ExCommandError::InvalidCommand("Editor not initialized".to_string())),
+        }
+    };
+    
+    let name = cmd.first_arg()
+        .ok_or_else(|| ExCommandError::Other("E471: Argument required".to_string()))?;
+    editor.delete_user_command(name)
+        .map_err(|err| ExCommandError::Other(err.to_string()))
+}
+
+/// Handle the :source command
+fn handle_source(cmd: &ExCommand) -> ExCommandResult<()> {
+    // Get the editor reference
+    let editor = unsafe {
+        match EDITOR {
+            Some(editor_ptr) => &mut *editor_ptr,
+            None => return Err(ExCommandError::InvalidCommand("Editor not initialized".to_string())),
+        }
+    };
+    
+    let file = cmd.first_arg()
+        .ok_or_else(|| ExCommandError::Other("E471: Argument required".to_string()))?;
+    let path = expand_home(file)
+        .ok_or_else(|| ExCommandError::Other(format!("E484: Can't open file {}", file)))?;
+    editor.source_file(path)
+        .map_err(|err| ExCommandError::Other(err.to_string()))
+}
+
 /// Handle the :sort command
 fn handle_sort(cmd: &ExCommand) -> ExCommandResult<()> {
     // Get the editor reference
@@ -1796,15 +2056,15 @@ fn handle_sort(cmd: &ExCommand) -> ExCommandResult<()> {
         None => return Err(ExCommandError::InvalidCommand("No buffer to sort".to_string())),
     };
     
-    // Get a mutable reference to the buffer
-    let buffer = editor.get_buffer_manager_mut().get_buffer_mut(buffer_id)?;
-    
-    // Parse the range from the command
-    // For now, we'll sort the entire buffer
-    let start_line = 0;
-    let end_line = buffer.line_count() - 1;
+    // Sort the lines of the range, the entire buffer by default
+    let (start_line, end_line) = if cmd.range.is_empty() {
+        (0, editor.line_count().saturating_sub(1))
+    } else {
+        cmd.range.lines(editor)?
+    };
     
     // Get the lines to sort
+    let buffer = editor.get_buffer_manager().get_buffer(buffer_id)?;
     let mut lines = Vec::new();
     for line_idx in start_line..=end_line {
         match buffer.line(line_idx) {
@@ -1848,24 +2108,8 @@ fn handle_sort(cmd: &ExCommand) -> ExCommandResult<()> {
     }
     
     // Replace the lines in the buffer
-    // First, delete all the lines in the range
-    let start_char_idx = buffer.position_to_char_idx(start_line, 0)?;
-    let end_char_idx = if end_line + 1 < buffer.line_count() {
-        buffer.position_to_char_idx(end_line + 1, 0)?
-    } else {
-        buffer.content().len()
-    };
-    
-    buffer.delete(start_char_idx, end_char_idx)?;
-    
-    // Then insert the sorted lines
-    let mut insert_text = lines.join("\n");
-    if end_line + 1 < buffer.line_count() {
-        // If we're not at the end of the buffer, add a newline
-        insert_text.push('\n');
-    }
-    
-    buffer.insert(start_char_idx, &insert_text)?;
+    editor.replace_lines(start_line, end_line, &lines)
+        .map_err(|err| ExCommandError::Other(format!("Failed to sort lines: {}", err)))?;
     
     println!("{} lines sorted", lines.len());
     Ok(())
@@ -1882,14 +2126,32 @@ fn handle_normal(cmd: &ExCommand) -> ExCommandResult<()> {
     };
     
     // Get the normal mode commands to execute
-    let normal_cmds = cmd.args_str();
+    let normal_cmds = cmd.arg_text.clone();
     
     if normal_cmds.is_empty() {
         return Err(ExCommandError::MissingArgument("Normal mode commands required".to_string()));
     }
     
-    // Execute the normal mode commands
-    match editor.execute_normal_mode_commands(&normal_cmds) {
+    // Execute the normal mode commands on each line of the range, or where the cursor is
+    let lines = if cmd.range.is_empty() {
+        None
+    } else {
+        Some(cmd.range.lines(editor)?)
+    };
+    
+    let result = match lines {
+        Some((start_line, end_line)) => (start_line..=end_line).try_for_each(|line| {
+            // Lines the commands delete are gone
+            if line >= editor.line_count() {
+                return Ok(());
+            }
+            editor.get_cursor_manager_mut().set_position(CursorPosition::new(line, 0));
+            editor.execute_normal_mode_commands(&normal_cmds)
+        }),
+        None => editor.execute_normal_mode_commands(&normal_cmds),
+    };
+    
+    match result {
         Ok(_) => {
             println!("Normal mode commands executed");
             Ok(())
@@ -0,0 +1,378 @@
+//! Command line - Editing the line typed after `:`, `/` or `?`
+//!
+//! This module keeps the text typed on the command line together with its
+//! cursor, and the history of the lines entered before. Ex commands and
+//! search patterns each have a history of their own.
+
+use std::collections::VecDeque;
+
+/// Maximum number of lines kept in a history
+const MAX_HISTORY: usize = 50;
+
+/// Lines entered on the command line, most recent first
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    entries: VecDeque<String>,
+}
+
+impl History {
+    /// Create an empty history
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a line, moving it to the front when it is there already
+    ///
+    /// Empty lines are not kept.
+    pub fn add(&mut self, line: &str) {
+        if line.is_empty() {
+            return;
+        }
+
+        self.entries.retain(|entry| entry != line);
+        self.entries.push_front(line.to_string());
+        self.entries.truncate(MAX_HISTORY);
+    }
+
+    /// Get the most recent line
+    pub fn latest(&self) -> Option<&str> {
+        self.get(0)
+    }
+
+    /// Get a line, counting from the most recent one
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(String::as_str)
+    }
+
+    /// Get the number of lines
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check whether there are no lines
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterate over the lines, most recent first
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(String::as_str)
+    }
+}
+
+/// How far browsing the history got
+#[derive(Debug, Clone)]
+struct Browse {
+    /// Line of the history shown, or `None` for the text typed
+    index: Option<usize>,
+    /// Text typed before browsing started, which lines must start with
+    typed: String,
+}
+
+/// The line being typed on the command line
+#[derive(Debug, Clone, Default)]
+pub struct CommandLine {
+    /// Text typed
+    text: String,
+    /// Position of the cursor, in characters
+    cursor: usize,
+    /// History browsing, until the text is edited
+    browse: Option<Browse>,
+}
+
+impl CommandLine {
+    /// Create an empty command line
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the text, with the cursor at its end
+    pub fn set(&mut self, text: &str) {
+        self.browse = None;
+        self.show(text);
+    }
+
+    /// Clear the text
+    pub fn clear(&mut self) {
+        self.set("");
+    }
+
+    /// Get the text
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Get the position of the cursor, in characters
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Check whether no text was typed
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Insert text at the cursor, leaving the cursor after it
+    pub fn insert(&mut self, text: &str) {
+        let idx = self.byte_index(self.cursor);
+        self.text.insert_str(idx, text);
+        self.cursor += text.chars().count();
+        self.browse = None;
+    }
+
+    /// Put text in place of the characters from a position up to the cursor
+    ///
+    /// The cursor ends up after the new text.
+    pub fn replace(&mut self, start: usize, text: &str) {
+        self.delete_range(start.min(self.cursor), self.cursor);
+        self.insert(text);
+    }
+
+    /// Delete the character before the cursor, returning whether there was one
+    pub fn delete_before(&mut self) -> bool {
+        if self.cursor == 0 {
+            return false;
+        }
+        self.delete_range(self.cursor - 1, self.cursor);
+        true
+    }
+
+    /// Delete the character under the cursor, or the one before it at the end of the line
+    pub fn delete_at(&mut self) {
+        if self.cursor < self.char_count() {
+            self.delete_range(self.cursor, self.cursor + 1);
+        } else {
+            self.delete_before();
+        }
+    }
+
+    /// Delete the word before the cursor, as Ctrl-W does
+    ///
+    /// Blanks before the cursor go along with the word. Other characters
+    /// that are not word characters are deleted as a word of their own.
+    pub fn delete_word_before(&mut self) {
+        let chars: Vec<char> = self.text.chars().collect();
+        let mut start = self.cursor;
+        while start > 0 && chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        if start > 0 {
+            let word = is_word_char(chars[start - 1]);
+            while start > 0 && !chars[start - 1].is_whitespace() && is_word_char(chars[start - 1]) == word {
+                start -= 1;
+            }
+        }
+        self.delete_range(start, self.cursor);
+    }
+
+    /// Delete everything before the cursor, as Ctrl-U does
+    pub fn delete_to_start(&mut self) {
+        self.delete_range(0, self.cursor);
+    }
+
+    /// Move the cursor one character left
+    pub fn move_left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    /// Move the cursor one character right
+    pub fn move_right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.char_count());
+    }
+
+    /// Move the cursor to the start of the line
+    pub fn move_start(&mut self) {
+        self.cursor = 0;
+    }
+
+    /// Move the cursor to the end of the line
+    pub fn move_end(&mut self) {
+        self.cursor = self.char_count();
+    }
+
+    /// Move the cursor to the start of the word before it
+    pub fn move_word_left(&mut self) {
+        let chars: Vec<char> = self.text.chars().collect();
+        while self.cursor > 0 && chars[self.cursor - 1].is_whitespace() {
+            self.cursor -= 1;
+        }
+        while self.cursor > 0 && !chars[self.cursor - 1].is_whitespace() {
+            self.cursor -= 1;
+        }
+    }
+
+    /// Move the cursor past the word after it
+    pub fn move_word_right(&mut self) {
+        let chars: Vec<char> = self.text.chars().collect();
+        while self.cursor < chars.len() && !chars[self.cursor].is_whitespace() {
+            self.cursor += 1;
+        }
+        while self.cursor < chars.len() && chars[self.cursor].is_whitespace() {
+            self.cursor += 1;
+        }
+    }
+
+    /// Show the next older line of a history that starts with the text typed
+    ///
+    /// Returns whether there was one.
+    pub fn history_older(&mut self, history: &History) -> bool {
+        let browse = self.browse.get_or_insert_with(|| Browse { index: None, typed: self.text.clone() });
+        let from = browse.index.map_or(0, |index| index + 1);
+        let found = (from..history.len())
+            .find(|&index| history.get(index).is_some_and(|line| line.starts_with(&browse.typed)));
+
+        match found {
+            Some(index) => {
+                browse.index = Some(index);
+                let text = history.get(index).unwrap_or_default().to_string();
+                self.show(&text);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Show the next newer line of a history that starts with the text typed
+    ///
+    /// Past the most recent line the text typed comes back. Returns whether
+    /// the text changed.
+    pub fn history_newer(&mut self, history: &History) -> bool {
+        let (from, typed) = match &self.browse {
+            Some(Browse { index: Some(index), typed }) => (*index, typed.clone()),
+            _ => return false,
+        };
+        let found = (0..from)
+            .rev()
+            .find(|&index| history.get(index).is_some_and(|line| line.starts_with(&typed)));
+
+        let text = found.and_then(|index| history.get(index)).unwrap_or(&typed).to_string();
+        self.browse = Some(Browse { index: found, typed });
+        self.show(&text);
+        true
+    }
+
+    /// Show text without ending history browsing
+    fn show(&mut self, text: &str) {
+        self.text = text.to_string();
+        self.cursor = self.char_count();
+    }
+
+    /// Delete characters from start to end (exclusive)
+    fn delete_range(&mut self, start: usize, end: usize) {
+        let (start_idx, end_idx) = (self.byte_index(start), self.byte_index(end));
+        self.text.replace_range(start_idx..end_idx, "");
+        self.cursor = start;
+        self.browse = None;
+    }
+
+    /// Get the byte index of a character position
+    fn byte_index(&self, position: usize) -> usize {
+        self.text.char_indices().nth(position).map_or(self.text.len(), |(idx, _)| idx)
+    }
+
+    /// Get the number of characters
+    fn char_count(&self) -> usize {
+        self.text.chars().count()
+    }
+}
+
+/// Check whether a character is part of a word
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history() {
+        let mut history = History::new();
+        history.add("first");
+        history.add("second");
+        history.add("");
+        history.add("first");
+        assert_eq!(history.iter().collect::<Vec<_>>(), vec!["first", "second"]);
+        assert_eq!(history.latest(), Some("first"));
+
+        for i in 0..MAX_HISTORY {
+            history.add(&i.to_string());
+        }
+        assert_eq!(history.len(), MAX_HISTORY);
+        assert_eq!(history.latest(), Some("49"));
+    }
+
+    #[test]
+    fn test_editing() {
+        let mut line = CommandLine::new();
+        line.insert("s/foo/bar/");
+        assert_eq!(line.cursor(), 10);
+
+        line.move_left();
+        line.move_left();
+        line.insert("ö");
+        assert_eq!(line.text(), "s/foo/baör/");
+        line.delete_before();
+        line.delete_at();
+        assert_eq!(line.text(), "s/foo/ba/");
+        assert_eq!(line.cursor(), 8);
+
+        line.move_start();
+        line.delete_at();
+        line.move_end();
+        line.delete_at();
+        assert_eq!(line.text(), "/foo/ba");
+        assert!(!CommandLine::new().delete_before());
+
+        line.set("edit some/file.txt  ");
+        line.delete_word_before();
+        assert_eq!(line.text(), "edit some/file.");
+        line.delete_word_before();
+        assert_eq!(line.text(), "edit some/file");
+        line.move_word_left();
+        assert_eq!(line.cursor(), 5);
+        line.delete_to_start();
+        assert_eq!(line.text(), "some/file");
+        assert_eq!(line.cursor(), 0);
+        line.move_word_right();
+        assert_eq!(line.cursor(), 9);
+
+        line.set("e src/bu x");
+        line.move_word_left();
+        line.move_left();
+        line.replace(2, "src/buffer/");
+        assert_eq!(line.text(), "e src/buffer/ x");
+        assert_eq!(line.cursor(), 13);
+    }
+
+    #[test]
+    fn test_history_browsing() {
+        let mut history = History::new();
+        for entry in ["set number", "edit file", "set list", "sort"] {
+            history.add(entry);
+        }
+
+        // Only lines starting with the text typed are shown
+        let mut line = CommandLine::new();
+        line.insert("se");
+        assert!(line.history_older(&history));
+        assert_eq!(line.text(), "set list");
+        assert!(line.history_older(&history));
+        assert_eq!(line.text(), "set number");
+        assert!(!line.history_older(&history));
+        assert_eq!(line.text(), "set number");
+        assert!(line.history_newer(&history));
+        assert_eq!(line.text(), "set list");
+        assert!(line.history_newer(&history));
+        assert_eq!(line.text(), "se");
+        assert!(!line.history_newer(&history));
+
+        // Editing starts over with the new text
+        line.clear();
+        assert!(line.history_older(&history));
+        assert_eq!(line.text(), "sort");
+        line.delete_before();
+        assert!(line.history_older(&history));
+        assert_eq!(line.text(), "sort");
+    }
+}
@@ -2,13 +2,24 @@
 //!
 //! This module handles the parsing and execution of Vim commands.
 
+mod complete;
 mod ex;
 pub mod handlers;
+mod line;
+mod script;
+mod user;
 
 pub use ex::{
     ExCommand, ExCommandError, ExCommandParser, ExCommandRegistry,
-    ExCommandResult, Range, RangeSpec, CommandFlags
+    ExCommandResult, Range, RangeSpec, CommandFlags, AddressContext, search_pattern
 };
+pub use complete::{
+    argument_kind, complete_path, completion_request, escape_match, filter_matches, Completion, CompletionKind,
+    CompletionRequest, WildMode
+};
+pub use line::{CommandLine, History};
+pub use script::script_commands;
+pub use user::{Address, Nargs, UserCommand, UserCommandAction, UserCommandCall, LISTING_HEADER};
 pub use handlers::{register_handlers, should_quit, reset_quit_flag, set_editor, handle_edit, handle_split};
 
 /// Command types
@@ -0,0 +1,69 @@
+//! Ex scripts - files of Ex commands run with `:source` and at startup
+//!
+//! This module splits the text of a script into the command lines to run.
+//! Running them is left to the editor, which knows the file being sourced
+//! so that errors and user commands can point back to it.
+
+/// Split a script into command lines, each with the line number it starts at
+///
+/// Lines whose first non-blank is `\` continue the line before, without the
+/// backslash, and lines starting with `"\ ` are comments between them. Other
+/// lines starting with `"` are comments, and blank lines are skipped. Colons
+/// before a command are dropped, as when it is typed.
+pub fn script_commands(text: &str) -> Vec<(usize, String)> {
+    let mut commands: Vec<(usize, String)> = Vec::new();
+    // Whether the last line started a command, so that continuations join it
+    let mut continuing = false;
+
+    for (idx, line) in text.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("\"\\ ") {
+            continue;
+        }
+        if let Some(rest) = trimmed.strip_prefix('\\') {
+            if continuing {
+                if let Some((_, command)) = commands.last_mut() {
+                    command.push_str(rest);
+                }
+            }
+            continue;
+        }
+
+        continuing = false;
+        let command = trimmed.trim_start_matches(|c: char| c == ':' || c.is_whitespace());
+        if command.is_empty() || command.starts_with('"') {
+            continue;
+        }
+        commands.push((idx + 1, command.to_string()));
+        continuing = true;
+    }
+
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_commands() {
+        let script = "\" Settings\n\
+            set shiftwidth=4\n\
+            \n\
+            :  map Q gq\n\
+            command -nargs=1\n\
+            \x20     \\ Greet\n\
+            \"\\ the replacement\n\
+            \x20     \\ echo <q-args>\n\
+            \" a comment\n\
+            \\ continues the comment\r\n\
+            set number\r\n";
+        assert_eq!(script_commands(script), vec![
+            (2, "set shiftwidth=4".to_string()),
+            (4, "map Q gq".to_string()),
+            (5, "command -nargs=1 Greet echo <q-args>".to_string()),
+            (11, "set number".to_string()),
+        ]);
+        assert!(script_commands("").is_empty());
+    }
+}
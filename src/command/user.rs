@@ -0,0 +1,404 @@
+//! User commands - Ex commands defined with `:command`
+//!
+//! This module parses the attributes of a `:command` definition and expands
+//! the replacement text when the command is run. Keeping the commands and
+//! running them is left to the editor, which also defines the commands
+//! plugins register, so that all of them show up in one listing.
+
+use super::complete::CompletionKind;
+use super::ex::{ExCommandError, ExCommandResult};
+
+/// Number of arguments a user command takes (-nargs)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Nargs {
+    /// No arguments (0), the default
+    #[default]
+    Zero,
+    /// Exactly one argument, spaces included (1)
+    One,
+    /// Any number of arguments (*)
+    Any,
+    /// None or one argument (?)
+    ZeroOrOne,
+    /// One or more arguments (+)
+    OneOrMore,
+}
+
+impl Nargs {
+    /// Parse the value of -nargs
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "0" => Some(Nargs::Zero),
+            "1" => Some(Nargs::One),
+            "*" => Some(Nargs::Any),
+            "?" => Some(Nargs::ZeroOrOne),
+            "+" => Some(Nargs::OneOrMore),
+            _ => None,
+        }
+    }
+
+    /// Get the value of -nargs
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Nargs::Zero => "0",
+            Nargs::One => "1",
+            Nargs::Any => "*",
+            Nargs::ZeroOrOne => "?",
+            Nargs::OneOrMore => "+",
+        }
+    }
+
+    /// Check the arguments a command is run with
+    pub fn check(&self, args: &str) -> ExCommandResult<()> {
+        match self {
+            Nargs::Zero if !args.is_empty() => Err(ExCommandError::Other(format!("E488: Trailing characters: {}", args))),
+            Nargs::One | Nargs::OneOrMore if args.is_empty() => {
+                Err(ExCommandError::Other("E471: Argument required".to_string()))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The lines or the count a user command takes (-range, -count)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Address {
+    /// Neither a range nor a count
+    #[default]
+    None,
+    /// A range, the current line by default (-range)
+    Range,
+    /// A range, the whole file by default (-range=%)
+    WholeFile,
+    /// A count in the line number position, N by default (-range=N)
+    RangeCount(usize),
+    /// A count in the line number position or as first argument, N by default (-count=N)
+    Count(usize),
+}
+
+impl Address {
+    /// Get how the address shows in the listing
+    fn as_string(&self) -> String {
+        match self {
+            Address::None => String::new(),
+            Address::Range => ".".to_string(),
+            Address::WholeFile => "%".to_string(),
+            Address::RangeCount(count) => count.to_string(),
+            Address::Count(count) => format!("{}c", count),
+        }
+    }
+}
+
+/// What a user command runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UserCommandAction {
+    /// An Ex command line, before expanding the escapes
+    Replacement(String),
+    /// The handler of a plugin
+    Plugin {
+        /// Name of the plugin
+        plugin: String,
+        /// Description the plugin gave
+        description: String,
+    },
+}
+
+/// A user command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserCommand {
+    /// Name, starting with an uppercase letter unless a plugin chose it
+    pub name: String,
+    /// Number of arguments
+    pub nargs: Nargs,
+    /// Range or count
+    pub address: Address,
+    /// Whether `!` may follow the name
+    pub bang: bool,
+    /// How the arguments are completed
+    pub complete: Option<CompletionKind>,
+    /// What the command runs
+    pub action: UserCommandAction,
+    /// Where the command was defined, `None` when it was typed
+    pub source: Option<String>,
+}
+
+/// Values the escapes of a replacement stand for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserCommandCall<'a> {
+    /// Arguments, as typed
+    pub args: &'a str,
+    /// First line of the range
+    pub line1: usize,
+    /// Last line of the range
+    pub line2: usize,
+    /// Count, 0 when there is none
+    pub count: usize,
+    /// Whether `!` followed the name
+    pub bang: bool,
+}
+
+/// Heading of the `:command` listing
+pub const LISTING_HEADER: &str = "    Name              Args Address Complete    Definition";
+
+impl UserCommand {
+    /// Parse the arguments of `:command`, attributes, name and replacement
+    ///
+    /// The replacement is empty when only a name was given, which lists the
+    /// commands starting with it.
+    pub fn parse(text: &str) -> ExCommandResult<Self> {
+        let error = |message: String| ExCommandError::Other(message);
+        let mut command = UserCommand {
+            name: String::new(),
+            nargs: Nargs::Zero,
+            address: Address::None,
+            bang: false,
+            complete: None,
+            action: UserCommandAction::Replacement(String::new()),
+            source: None,
+        };
+
+        // Attributes
+        let mut rest = text.trim_start();
+        while let Some(attribute) = rest.strip_prefix('-') {
+            let end = attribute.find(char::is_whitespace).unwrap_or(attribute.len());
+            rest = attribute[end..].trim_start();
+            let (key, value) = match attribute[..end].split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (&attribute[..end], None),
+            };
+
+            let count_value = |value: Option<&str>| -> ExCommandResult<usize> {
+                value.map_or(Ok(0), |value| value.parse()
+                    .map_err(|_| error("E178: Invalid default value for count".to_string())))
+            };
+            match key {
+                "" => return Err(error("E175: No attribute specified".to_string())),
+                "nargs" => {
+                    command.nargs = value.and_then(Nargs::parse)
+                        .ok_or_else(|| error("E176: Invalid number of arguments".to_string()))?;
+                },
+                "range" | "count" if command.address != Address::None => {
+                    return Err(error("E177: Count cannot be specified twice".to_string()));
+                },
+                "range" => {
+                    command.address = match value {
+                        None => Address::Range,
+                        Some("%") => Address::WholeFile,
+                        value => Address::RangeCount(count_value(value)?),
+                    };
+                },
+                "count" => command.address = Address::Count(count_value(value)?),
+                "bang" => command.bang = true,
+                "complete" => {
+                    let value = value.ok_or_else(|| error("E179: Argument required for -complete".to_string()))?;
+                    command.complete = Some(CompletionKind::from_name(value)
+                        .ok_or_else(|| error(format!("E180: Invalid complete value: {}", value)))?);
+                },
+                _ => return Err(error(format!("E181: Invalid attribute: -{}", &attribute[..end]))),
+            }
+        }
+
+        // Name, then the replacement after a blank
+        let end = rest.find(|c: char| !c.is_alphanumeric()).unwrap_or(rest.len());
+        let (name, replacement) = rest.split_at(end);
+        if name.is_empty() || !(replacement.is_empty() || replacement.starts_with(char::is_whitespace)) {
+            return Err(error("E182: Invalid command name".to_string()));
+        }
+        if !name.starts_with(|c: char| c.is_uppercase()) {
+            return Err(error("E183: User defined commands must start with an uppercase letter".to_string()));
+        }
+
+        command.name = name.to_string();
+        command.action = UserCommandAction::Replacement(replacement.trim_start().to_string());
+        Ok(command)
+    }
+
+    /// Create the command a plugin registered, taking any number of arguments
+    pub fn for_plugin(name: &str, plugin: &str, description: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            nargs: Nargs::Any,
+            address: Address::None,
+            bang: false,
+            complete: None,
+            action: UserCommandAction::Plugin {
+                plugin: plugin.to_string(),
+                description: description.to_string(),
+            },
+            source: Some(format!("plugin {}", plugin)),
+        }
+    }
+
+    /// Get the replacement, empty for the commands of plugins
+    pub fn replacement(&self) -> &str {
+        match &self.action {
+            UserCommandAction::Replacement(replacement) => replacement,
+            UserCommandAction::Plugin { .. } => "",
+        }
+    }
+
+    /// Expand the escapes of the replacement
+    ///
+    /// `<args>` is the arguments as typed, `<q-args>` the arguments quoted
+    /// as one string and `<f-args>` each argument quoted, separated by
+    /// commas. `<line1>`, `<line2>` and `<count>` are the range and count,
+    /// `<bang>` is `!` when one was given and `<lt>` is `<`. Escapes are
+    /// matched ignoring case, and anything else in `<>` stays as it is.
+    pub fn expand(&self, call: &UserCommandCall) -> String {
+        let replacement = self.replacement();
+        let mut out = String::new();
+        let mut rest = replacement;
+
+        while let Some(open) = rest.find('<') {
+            out.push_str(&rest[..open]);
+            rest = &rest[open..];
+            let close = match rest.find('>') {
+                Some(close) => close,
+                None => break,
+            };
+
+            let expansion = match rest[1..close].to_ascii_lowercase().as_str() {
+                "args" => call.args.to_string(),
+                "q-args" => quote(call.args),
+                "f-args" => match self.nargs {
+                    Nargs::One | Nargs::ZeroOrOne if !call.args.is_empty() => quote(call.args),
+                    _ => split_args(call.args).iter().map(|arg| quote(arg)).collect::<Vec<_>>().join(","),
+                },
+                "line1" => call.line1.to_string(),
+                "line2" => call.line2.to_string(),
+                "count" => call.count.to_string(),
+                "bang" => if call.bang { "!" } else { "" }.to_string(),
+                "lt" => "<".to_string(),
+                _ => {
+                    out.push('<');
+                    rest = &rest[1..];
+                    continue;
+                },
+            };
+            out.push_str(&expansion);
+            rest = &rest[close + 1..];
+        }
+
+        out.push_str(rest);
+        out
+    }
+
+    /// Get the line of the command in the `:command` listing
+    pub fn listing_line(&self) -> String {
+        let definition = match &self.action {
+            UserCommandAction::Replacement(replacement) => replacement.as_str(),
+            UserCommandAction::Plugin { description, .. } => description.as_str(),
+        };
+        let complete = self.complete.as_ref().map_or("", |kind| kind.name());
+        format!(
+            "{:<4}{:<17} {:<4} {:<7} {:<11} {}",
+            if self.bang { "!" } else { "" },
+            self.name,
+            self.nargs.as_str(),
+            self.address.as_string(),
+            complete,
+            definition,
+        )
+    }
+}
+
+/// Quote text as a string, escaping backslashes and double quotes
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Split arguments at blanks that are not escaped
+///
+/// A backslash before a blank or another backslash is taken out.
+fn split_args(args: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let mut chars = args.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek().is_some_and(|next| next.is_whitespace() || *next == '\\') => {
+                current.extend(chars.next());
+            },
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    result.push(std::mem::take(&mut current));
+                }
+            },
+            c => current.push(c),
+        }
+    }
+
+    if !current.is_empty() {
+        result.push(current);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(args: &str) -> UserCommandCall<'_> {
+        UserCommandCall { args, line1: 2, line2: 5, count: 5, bang: true }
+    }
+
+    #[test]
+    fn test_parse_user_command() {
+        let command = UserCommand::parse("-nargs=? -range=% -bang -complete=file Edit2 edit<bang> <args>").unwrap();
+        assert_eq!(command.name, "Edit2");
+        assert_eq!(command.nargs, Nargs::ZeroOrOne);
+        assert_eq!(command.address, Address::WholeFile);
+        assert!(command.bang);
+        assert_eq!(command.complete, Some(CompletionKind::File));
+        assert_eq!(command.replacement(), "edit<bang> <args>");
+
+        let command = UserCommand::parse("-count=3 Down  normal <count>j").unwrap();
+        assert_eq!(command.address, Address::Count(3));
+        assert_eq!(command.replacement(), "normal <count>j");
+        assert_eq!(UserCommand::parse("Only").unwrap().replacement(), "");
+
+        let err = |text: &str| UserCommand::parse(text).unwrap_err().to_string();
+        assert!(err("-nargs=2 X y").starts_with("E176"));
+        assert!(err("-range -count X y").starts_with("E177"));
+        assert!(err("-range=x X y").starts_with("E178"));
+        assert!(err("-complete X y").starts_with("E179"));
+        assert!(err("-complete=colour X y").starts_with("E180"));
+        assert!(err("-silent X y").starts_with("E181"));
+        assert!(err("X-y z").starts_with("E182"));
+        assert!(err("lower y").starts_with("E183"));
+    }
+
+    #[test]
+    fn test_expand_user_command() {
+        let mut command = UserCommand::parse("-nargs=* -range X <line1>,<line2>s/<args>/<COUNT><bang>/ <lt>args> <other>").unwrap();
+        assert_eq!(command.expand(&call("a b")), "2,5s/a b/5!/ <args> <other>");
+
+        command.action = UserCommandAction::Replacement("call F(<f-args>) <q-args>".to_string());
+        assert_eq!(command.expand(&call(r#"a\ b  c\\d "e""#)), r#"call F("a b","c\\d","\"e\"") "a\\ b  c\\\\d \"e\"""#);
+        assert_eq!(command.expand(&call("")), r#"call F() """#);
+
+        command.nargs = Nargs::One;
+        assert_eq!(command.expand(&call("a b")), r#"call F("a b") "a b""#);
+    }
+
+    #[test]
+    fn test_nargs_check() {
+        assert!(Nargs::Zero.check("").is_ok());
+        assert!(Nargs::Zero.check("x").is_err());
+        assert!(Nargs::One.check("").is_err());
+        assert!(Nargs::OneOrMore.check("a b").is_ok());
+        assert!(Nargs::ZeroOrOne.check("").is_ok());
+    }
+
+    #[test]
+    fn test_listing_line() {
+        let command = UserCommand::parse("-nargs=1 -bang -range -complete=buffer Buf buffer <args>").unwrap();
+        assert_eq!(command.listing_line(), format!("{:<4}{:<17} {:<4} {:<7} {:<11} {}", "!", "Buf", "1", ".", "buffer", "buffer <args>"));
+
+        let command = UserCommand::for_plugin("Greet", "greeter", "Say hello");
+        assert!(command.listing_line().ends_with("Say hello"));
+        assert_eq!(LISTING_HEADER, format!("{:<4}{:<17} {:<4} {:<7} {:<11} {}", "", "Name", "Args", "Address", "Complete", "Definition"));
+        assert_eq!(command.source.as_deref(), Some("plugin greeter"));
+    }
+}
@@ -115,6 +115,46 @@ pub struct GeneralConfig {
     /// Whether to expand tabs to spaces
     #[serde(default = "default_true")]
     pub expand_tabs: bool,
+    
+    /// Whether to keep undo history in undo files between sessions
+    #[serde(default)]
+    pub undofile: bool,
+    
+    /// Directory for undo files, defaults to the xvim data directory
+    #[serde(default)]
+    pub undodir: Option<PathBuf>,
+    
+    /// Whether to keep swap files for crash recovery
+    #[serde(default = "default_true")]
+    pub swapfile: bool,
+    
+    /// Directory for swap files, defaults to the directory of each file
+    #[serde(default)]
+    pub directory: Option<PathBuf>,
+    
+    /// Milliseconds without typing before swap files are written
+    #[serde(default = "default_updatetime")]
+    pub updatetime: u64,
+    
+    /// Whether to keep a backup of a file after writing it
+    #[serde(default)]
+    pub backup: bool,
+    
+    /// Whether to back up a file while writing it
+    #[serde(default = "default_true")]
+    pub writebackup: bool,
+    
+    /// Directory for backup files, defaults to the directory of each file
+    #[serde(default)]
+    pub backupdir: Option<PathBuf>,
+    
+    /// Appended to the file name to make the backup file name
+    #[serde(default = "default_backupext")]
+    pub backupext: String,
+    
+    /// Whether to reload unmodified buffers when their file changes on disk
+    #[serde(default)]
+    pub autoread: bool,
 }
 
 impl Default for GeneralConfig {
@@ -126,6 +166,16 @@ impl Default for GeneralConfig {
             auto_indent: true,
             tab_width: 4,
             expand_tabs: true,
+            undofile: false,
+            undodir: None,
+            swapfile: true,
+            directory: None,
+            updatetime: 4000,
+            backup: false,
+            writebackup: true,
+            backupdir: None,
+            backupext: default_backupext(),
+            autoread: false,
         }
     }
 }
@@ -191,6 +241,22 @@ fn default_true() -> bool {
     true
 }
 
+/// Get the directory undo files are written to when 'undodir' is empty
+pub(crate) fn default_undo_dir() -> PathBuf {
+    let mut path = dirs::data_dir().unwrap_or_else(|| PathBuf::from("."));
+    path.push("xvim");
+    path.push("undo");
+    path
+}
+
+fn default_backupext() -> String {
+    "~".to_string()
+}
+
+fn default_updatetime() -> u64 {
+    4000
+}
+
 fn default_tab_width() -> u8 {
     4
 }
@@ -240,6 +306,11 @@ impl ConfigManager {
         path
     }
     
+    /// Get the path to the startup script, `xvimrc` next to the configuration file
+    pub fn script_path(&self) -> PathBuf {
+        self.config_path.with_file_name("xvimrc")
+    }
+    
     /// Load configuration from the default path
     pub fn load(&mut self) -> ConfigResult<()> {
         let path = self.config_path.clone();
@@ -311,7 +382,24 @@ impl ConfigManager {
     pub fn set_config_path<P: AsRef<Path>>(&mut self, path: P) {
         self.config_path = path.as_ref().to_path_buf();
     }
-    
+
+    /// Get the names of the color schemes, sorted
+    ///
+    /// Besides the built-in "default", every file in the `colors` directory
+    /// next to the configuration file is a color scheme named after the file.
+    pub fn color_scheme_names(&self) -> Vec<String> {
+        let mut names = vec![default_color_scheme()];
+        if let Some(Ok(entries)) = self.config_path.parent().map(|dir| fs::read_dir(dir.join("colors"))) {
+            names.extend(entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_file())
+                .filter_map(|entry| entry.path().file_stem().map(|stem| stem.to_string_lossy().into_owned())));
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+
     /// Convert key mappings from the configuration to KeyMapping objects
     pub fn get_key_mappings(&self) -> Vec<KeyMapping> {
         let mut mappings = Vec::new();
@@ -404,4 +492,16 @@ mod tests {
             "escape"
         );
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_color_scheme_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = ConfigManager::with_path(dir.path().join("config.toml"));
+        assert_eq!(manager.color_scheme_names(), vec!["default"]);
+        
+        fs::create_dir(dir.path().join("colors")).unwrap();
+        fs::write(dir.path().join("colors").join("monokai.toml"), "").unwrap();
+        fs::write(dir.path().join("colors").join("desert.toml"), "").unwrap();
+        assert_eq!(manager.color_scheme_names(), vec!["default", "desert", "monokai"]);
+    }
+}
@@ -109,7 +109,26 @@ pub enum Direction {
     ScrollFullPageUp,
 }
 
+impl Direction {
+    /// Check whether an operator applied to the motion works on whole lines
+    pub fn is_linewise(&self) -> bool {
+        matches!(
+            self,
+            Direction::Up | Direction::Down | Direction::BufferStart | Direction::BufferEnd | Direction::LineNumber(_)
+        )
+    }
+
+    /// Check whether an operator applied to the motion includes the character it ends on
+    pub fn is_inclusive(&self) -> bool {
+        matches!(
+            self,
+            Direction::WordEnd | Direction::FindForward(_) | Direction::FindTillForward(_) | Direction::MatchingBracket
+        )
+    }
+}
+
 /// Cursor manager
+#[derive(Clone)]
 pub struct CursorManager {
     /// Current cursor position
     position: CursorPosition,
@@ -164,6 +183,20 @@ impl CursorManager {
         Ok(())
     }
 
+    /// Move the cursor in the specified direction `count` times
+    ///
+    /// Stops early once the cursor no longer moves, at the start or end of the buffer.
+    pub fn move_cursor_count(&mut self, direction: Direction, count: usize, buffer: &Buffer) -> BufferResult<()> {
+        for _ in 0..count.max(1) {
+            let before = (self.position.line, self.position.column);
+            self.move_cursor(direction, buffer)?;
+            if (self.position.line, self.position.column) == before {
+                break;
+            }
+        }
+        Ok(())
+    }
+
     /// Move the cursor up one line
     pub fn move_up(&mut self, buffer: &Buffer) -> BufferResult<()> {
         if self.position.line > 0 {
@@ -1005,4 +1038,24 @@ mod tests {
         cursor.move_to_paragraph_start(&buffer).unwrap();
         assert_eq!(cursor.position(), CursorPosition::new(3, 0));
     }
+
+    #[test]
+    fn test_cursor_manager_count_movement() {
+        let mut buffer = Buffer::new(1);
+        buffer.insert(0, "one two three four\nLine 2\nLine 3").unwrap();
+
+        let mut cursor = CursorManager::new();
+
+        // Move three words forward
+        cursor.move_cursor_count(Direction::WordNext, 3, &buffer).unwrap();
+        assert_eq!(cursor.position(), CursorPosition::new(0, 14));
+
+        // A count past the end of the buffer stops at the last line
+        cursor.move_cursor_count(Direction::Down, 10, &buffer).unwrap();
+        assert_eq!(cursor.position().line, 2);
+
+        // A count of zero moves once
+        cursor.move_cursor_count(Direction::Up, 0, &buffer).unwrap();
+        assert_eq!(cursor.position().line, 1);
+    }
 }
\ No newline at end of file
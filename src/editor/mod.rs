@@ -3,30 +3,43 @@
 //! This module contains the main Editor struct which coordinates all other components
 //! and manages the overall state of the editor.
 
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 // Import all modules from crate
-use crate::buffer::{BufferManager, BufferManagerError, BufferResult};
+use crate::buffer::{BufferManager, BufferManagerError, DiskChange, FileEncoding, FileFormat, UndoTime, WriteOptions};
 use crate::cursor::CursorPosition;
-use crate::command::{Command, CommandParser, ExCommandRegistry, register_handlers};
+use crate::command::{
+    Address, AddressContext, CommandLine, CommandParser, Completion, CompletionKind, CompletionRequest, ExCommand,
+    ExCommandError, ExCommandRegistry, ExCommandResult, History, RangeSpec, UserCommand, UserCommandAction,
+    UserCommandCall, WildMode, complete_path, completion_request, escape_match, filter_matches, register_handlers,
+    script_commands, search_pattern
+};
 use crate::r#macro::{MacroRecorder, MacroPlayer, MacroRecorderState};
 use crate::text_object::TextObjectType as TextObjectTypeExt;
-use crate::operator::{Operator, OperatorTarget, OperatorState, OperatorManager};
+use crate::operator::{Operator, OperatorTarget, OperatorState, OperatorManager, IndentOptions, change_case, format_lines, reindent_lines, shift_line};
+use crate::option::{OptionChange, OptionContext, OptionRegistry, OptionScope, OptionType, OptionValue, SetCommand};
 use crate::config::ConfigManager;
 use crate::cursor::{CursorManager, Direction};
 use crate::keymap::{KeyHandler, KeyMapping, KeySequence, Command as KeyCommand};
+use crate::mark::{self, FileMark, GlobalMarks, Jump};
 use crate::mode::ModeManager;
 use crate::plugin::PluginManager;
 use crate::register::{RegisterManager, RegisterType, RegisterContent};
+use crate::repeat::{InsertCommand, RepeatCommand, RepeatState, RepeatableChange};
 use crate::selection::{SelectionManager, SelectionType};
 use crate::syntax::{SyntaxRegistry, Theme, create_default_registry, create_default_theme};
 use crate::ui::{TerminalUi, UiError};
-use crate::search::{SearchState, SearchDirection, SearchFunctions};
+use crate::search::{Pattern, PatternResult, Replacement, SearchState, SearchDirection, SearchFunctions, Substitute};
 use crate::visual::{VisualState, VisualFunctions, BufferVisualExt};
 use crate::insert::{InsertState, InsertFunctions, BufferInsertExt};
 use crossterm::event::KeyEvent;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often open files are checked for changes made by other programs
+const CHECKTIME_INTERVAL: Duration = Duration::from_secs(2);
 
 // Forward declarations for text objects
 pub struct TextObject {
@@ -109,6 +122,139 @@ impl From<anyhow::Error> for EditorError {
 
 /// Result type used throughout the editor
 pub type EditorResult<T> = Result<T, EditorError>;
+
+/// A normal mode command waiting for the character it takes as argument
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PendingChar {
+    /// `m{a-zA-Z}`
+    SetMark,
+    /// `` `{mark} `` or, when `linewise`, `'{mark}`
+    JumpToMark { linewise: bool },
+    /// `{count}@{register}`
+    PlayMacro { count: usize },
+    /// `g` after an operator, as in `dgg` or `gUgU`
+    OperatorG,
+    /// `f`, `t`, `F` or `T` after an operator
+    OperatorFind { till: bool, backward: bool },
+    /// `i` or, when `around`, `a` after an operator
+    TextObject { around: bool },
+}
+
+/// Largest count accepted before a command, as in Vim
+const MAX_COUNT: usize = 999_999_999;
+
+/// How deep scripts may source other scripts, as in Vim
+const MAX_SOURCE_DEPTH: usize = 200;
+
+/// How deep user commands may run other user commands
+const MAX_USER_COMMAND_DEPTH: usize = 100;
+
+/// A match a substitute replaces
+#[derive(Debug, Clone)]
+struct SubstituteMatch {
+    /// Line of the match, from the first line of the substitute
+    line: usize,
+    /// Start of the match in the line
+    start: usize,
+    /// End of the match in the line
+    end: usize,
+    /// Text the match is replaced with
+    text: String,
+}
+
+/// Count the lines with matches, which are in order
+fn count_lines(matches: &[SubstituteMatch]) -> usize {
+    let mut lines: Vec<usize> = matches.iter().map(|found| found.line).collect();
+    lines.dedup();
+    lines.len()
+}
+
+/// A substitute with the `c` flag, asking about each match in turn
+#[derive(Debug)]
+struct SubstituteConfirm {
+    /// First line of the substitute
+    start: usize,
+    /// Last line of the substitute
+    end: usize,
+    /// The lines as they were before the substitute
+    lines: Vec<String>,
+    /// Every match, in order
+    matches: Vec<SubstituteMatch>,
+    /// Index of the match being asked about
+    index: usize,
+    /// Indexes of the matches to replace
+    accepted: Vec<usize>,
+}
+
+/// The text an operator is applied to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OperatorRange {
+    /// Characters from a start to an end index (exclusive)
+    Chars(usize, usize),
+    /// Lines from a start to an end line (inclusive)
+    Lines(usize, usize),
+    /// Columns from a start to an end column (exclusive) on each of the lines, as selected in visual block mode
+    Block { start_line: usize, end_line: usize, start_column: usize, end_column: usize },
+}
+
+/// Run a command through a shell, with the given standard input
+///
+/// The output comes back whatever the exit status is, see `shell_status`.
+/// Without input the command reads nothing.
+fn run_shell_command(shell: &str, flag: &str, command: &str, input: Option<&str>) -> EditorResult<std::process::Output> {
+    use std::io::Write;
+    use std::process::{Command as ProcessCommand, Stdio};
+    
+    // 'shell' may hold arguments of its own, such as "bash -l"
+    let mut words = shell.split_whitespace();
+    let program = words.next().unwrap_or(shell);
+    let mut process = ProcessCommand::new(program);
+    process.args(words);
+    if !flag.is_empty() {
+        process.arg(flag);
+    }
+    let mut child = process
+        .arg(command)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| EditorError::Other(format!("E282: Cannot execute shell {}: {}", shell, err)))?;
+    
+    // Write the input from another thread, so a command with a lot of output can't block it
+    let stdin = child.stdin.take();
+    let input = input.unwrap_or_default().to_string();
+    let writer = std::thread::spawn(move || {
+        if let Some(mut stdin) = stdin {
+            let _ = stdin.write_all(input.as_bytes());
+        }
+    });
+    let output = child.wait_with_output()?;
+    let _ = writer.join();
+    Ok(output)
+}
+
+/// Turn the exit status of a shell command into an error when it failed
+fn shell_status(status: std::process::ExitStatus) -> EditorResult<()> {
+    if status.success() {
+        return Ok(());
+    }
+    Err(EditorError::Other(match status.code() {
+        Some(code) => format!("shell returned {}", code),
+        None => "shell was terminated".to_string(),
+    }))
+}
+
+/// Move a manager lent to plugins out of its shared handle
+///
+/// A plugin that panicked while holding the lock does not keep the editor
+/// from getting its state back.
+fn take_lent<T: Default>(handle: Option<Arc<Mutex<T>>>) -> Option<T> {
+    let handle = handle?;
+    let mut lent = handle.lock().unwrap_or_else(|err| err.into_inner());
+    Some(std::mem::take(&mut *lent))
+}
+
 /// The main editor struct that coordinates all components
 pub struct Editor {
     /// Buffer manager
@@ -121,6 +267,8 @@ pub struct Editor {
     key_handler: KeyHandler,
     /// Configuration manager
     config_manager: ConfigManager,
+    /// Options set with :set
+    options: OptionRegistry,
     /// Syntax registry
     syntax_registry: Arc<SyntaxRegistry>,
     /// Current theme
@@ -147,14 +295,60 @@ pub struct Editor {
     view_position: usize,
     /// Whether the editor is running
     running: bool,
-    /// Command buffer for storing command text
-    command_buffer: String,
+    /// Line being typed on the command line, after the `:`, `/` or `?`
+    command_line: CommandLine,
+    /// History of Ex commands entered on the command line
+    command_history: History,
+    /// Whether Ctrl-R on the command line waits for a register name
+    command_register_pending: bool,
+    /// Completion of the word before the cursor, kept while Tab is pressed again
+    completion: Option<Completion>,
+    /// Plugins completing the arguments of their commands, by command name
+    plugin_completers: HashMap<String, String>,
+    /// Commands defined with `:command` or by plugins, by name
+    user_commands: BTreeMap<String, UserCommand>,
+    /// Scripts being sourced, innermost last, with the line being run
+    sourcing: Vec<(String, usize)>,
+    /// Number of user commands being run, one inside the other
+    user_command_depth: usize,
+    /// Message shown on the last line until the next key
+    message: Option<String>,
+    /// Question asked on the command line while waiting for its answer
+    question: Option<String>,
+    /// Lines a `!` operator filters through the command being typed
+    filter_range: Option<(usize, usize)>,
+    /// Whether the command line holds a search pattern rather than an Ex command
+    searching: bool,
+    /// Last substitute, for `:&` and the `\&` address
+    last_substitute: Option<Substitute>,
+    /// Substitute asking about each match, for the `c` flag
+    substitute_confirm: Option<SubstituteConfirm>,
+    /// Whether `:global` is running its command
+    global_busy: bool,
+    /// Last shell command, for `!` in the next one
+    last_shell_command: Option<String>,
     /// Search state
     search_state: SearchState,
     /// Visual mode state
     visual_state: VisualState,
     /// Insert mode state
     insert_state: InsertState,
+    /// Time of the last key press
+    last_key_time: Instant,
+    /// Whether keys were typed since the swap files were last written
+    swap_pending: bool,
+    /// Time open files were last checked for changes on disk
+    last_checktime: Instant,
+    /// File marks (`A`-`Z`), which are global to the editor
+    global_marks: GlobalMarks,
+    /// Normal mode command waiting for its character argument
+    pending_char: Option<PendingChar>,
+    /// Count being typed before a command or motion
+    pending_count: Option<usize>,
+    /// Count of the normal mode command being executed
+    count: Option<usize>,
+    /// The last change, for `.`
+    repeat_state: RepeatState,
 }
 
 impl Editor {
@@ -234,6 +428,7 @@ impl Editor {
             terminal,
             key_handler,
             config_manager,
+            options: OptionRegistry::new(),
             syntax_registry,
             theme,
             cursor_manager,
@@ -247,10 +442,33 @@ impl Editor {
             plugin_manager,
             view_position: 0,
             running: false,
-            command_buffer: String::new(),
+            command_line: CommandLine::new(),
+            command_history: History::new(),
+            command_register_pending: false,
+            completion: None,
+            plugin_completers: HashMap::new(),
+            user_commands: BTreeMap::new(),
+            sourcing: Vec::new(),
+            user_command_depth: 0,
+            message: None,
+            question: None,
+            filter_range: None,
+            searching: false,
+            last_substitute: None,
+            substitute_confirm: None,
+            global_busy: false,
+            last_shell_command: None,
             search_state: SearchState::new(),
             visual_state: VisualState::new(),
             insert_state: InsertState::new(),
+            last_key_time: Instant::now(),
+            swap_pending: false,
+            last_checktime: Instant::now(),
+            global_marks: GlobalMarks::new(),
+            pending_char: None,
+            pending_count: None,
+            count: None,
+            repeat_state: RepeatState::new(),
         };
         
         // Create an initial empty buffer
@@ -332,6 +550,129 @@ impl Editor {
         );
         self.key_handler.key_map_mut().add_mapping(redo_mapping);
         
+        // Repeat the last change with '.'
+        let repeat_mapping = KeyMapping::new(
+            Mode::Normal,
+            KeySequence::from_key(KeyEvent::new(KeyCode::Char('.'), KeyModifiers::NONE)),
+            KeyCommand::BuiltIn("repeat_change".to_string()),
+            false
+        );
+        self.key_handler.key_map_mut().add_mapping(repeat_mapping);
+        
+        // Go to the first line, or the line of the count, with gg
+        let first_line_mapping = KeyMapping::new(
+            Mode::Normal,
+            KeySequence::new(vec![
+                KeyEvent::new(KeyCode::Char('g'), KeyModifiers::NONE),
+                KeyEvent::new(KeyCode::Char('g'), KeyModifiers::NONE),
+            ]),
+            KeyCommand::BuiltIn("first_line".to_string()),
+            false
+        );
+        self.key_handler.key_map_mut().add_mapping(first_line_mapping);
+        
+        // The operators typed after g, in normal mode and on a visual selection
+        let g_operators = [
+            ('U', Operator::ToUpper),
+            ('u', Operator::ToLower),
+            ('~', Operator::SwapCase),
+            ('q', Operator::Format),
+            ('w', Operator::FormatKeepCursor),
+        ];
+        for mode in [Mode::Normal, Mode::Visual, Mode::VisualLine, Mode::VisualBlock] {
+            for (c, operator) in g_operators {
+                let g_operator_mapping = KeyMapping::new(
+                    mode,
+                    KeySequence::new(vec![
+                        KeyEvent::new(KeyCode::Char('g'), KeyModifiers::NONE),
+                        KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE),
+                    ]),
+                    KeyCommand::BuiltIn(operator.name().to_string()),
+                    false
+                );
+                self.key_handler.key_map_mut().add_mapping(g_operator_mapping);
+            }
+        }
+        
+        // Extend a visual selection to the first line with gg
+        for mode in [Mode::Visual, Mode::VisualLine, Mode::VisualBlock] {
+            let visual_first_line_mapping = KeyMapping::new(
+                mode,
+                KeySequence::new(vec![
+                    KeyEvent::new(KeyCode::Char('g'), KeyModifiers::NONE),
+                    KeyEvent::new(KeyCode::Char('g'), KeyModifiers::NONE),
+                ]),
+                KeyCommand::BuiltIn("first_line".to_string()),
+                false
+            );
+            self.key_handler.key_map_mut().add_mapping(visual_first_line_mapping);
+        }
+        
+        // Move through the undo tree chronologically with g- and g+
+        let earlier_mapping = KeyMapping::new(
+            Mode::Normal,
+            KeySequence::new(vec![
+                KeyEvent::new(KeyCode::Char('g'), KeyModifiers::NONE),
+                KeyEvent::new(KeyCode::Char('-'), KeyModifiers::NONE),
+            ]),
+            KeyCommand::BuiltIn("undo_earlier".to_string()),
+            false
+        );
+        self.key_handler.key_map_mut().add_mapping(earlier_mapping);
+        
+        let later_mapping = KeyMapping::new(
+            Mode::Normal,
+            KeySequence::new(vec![
+                KeyEvent::new(KeyCode::Char('g'), KeyModifiers::NONE),
+                KeyEvent::new(KeyCode::Char('+'), KeyModifiers::NONE),
+            ]),
+            KeyCommand::BuiltIn("undo_later".to_string()),
+            false
+        );
+        self.key_handler.key_map_mut().add_mapping(later_mapping);
+        
+        // Move through the jump list with Ctrl-O and Ctrl-I (which terminals send as Tab)
+        let jump_older_mapping = KeyMapping::new(
+            Mode::Normal,
+            KeySequence::from_key(KeyEvent::new(KeyCode::Char('o'), KeyModifiers::CONTROL)),
+            KeyCommand::BuiltIn("jump_older".to_string()),
+            false
+        );
+        self.key_handler.key_map_mut().add_mapping(jump_older_mapping);
+        
+        for key in [KeyEvent::new(KeyCode::Char('i'), KeyModifiers::CONTROL), KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE)] {
+            let jump_newer_mapping = KeyMapping::new(
+                Mode::Normal,
+                KeySequence::from_key(key),
+                KeyCommand::BuiltIn("jump_newer".to_string()),
+                false
+            );
+            self.key_handler.key_map_mut().add_mapping(jump_newer_mapping);
+        }
+        
+        // Move through the change list with g; and g,
+        let change_older_mapping = KeyMapping::new(
+            Mode::Normal,
+            KeySequence::new(vec![
+                KeyEvent::new(KeyCode::Char('g'), KeyModifiers::NONE),
+                KeyEvent::new(KeyCode::Char(';'), KeyModifiers::NONE),
+            ]),
+            KeyCommand::BuiltIn("change_older".to_string()),
+            false
+        );
+        self.key_handler.key_map_mut().add_mapping(change_older_mapping);
+        
+        let change_newer_mapping = KeyMapping::new(
+            Mode::Normal,
+            KeySequence::new(vec![
+                KeyEvent::new(KeyCode::Char('g'), KeyModifiers::NONE),
+                KeyEvent::new(KeyCode::Char(','), KeyModifiers::NONE),
+            ]),
+            KeyCommand::BuiltIn("change_newer".to_string()),
+            false
+        );
+        self.key_handler.key_map_mut().add_mapping(change_newer_mapping);
+        
         // Paste after cursor with 'p'
         let paste_mapping = KeyMapping::new(
             Mode::Normal,
@@ -489,11 +830,30 @@ impl Editor {
 
     /// Open a file in the editor
     pub fn open_file<P: AsRef<Path>>(&mut self, path: P) -> EditorResult<()> {
+        // Switching files is a jump, unless it's away from the empty buffer xvim starts with
+        let jumps = match self.current_buffer_id().and_then(|id| self.buffer_manager.get_buffer(id).ok()) {
+            Some(buffer) if buffer.file_path().is_some() || buffer.is_modified() => {
+                self.set_pcmark();
+                self.terminal.current_window().map(|window| window.jumps.clone())
+            },
+            _ => None,
+        };
+        
         // Open the file in the buffer manager
         let buffer_id = self.buffer_manager.open_file(&path)?;
         
         // Set it as the current buffer
         self.buffer_manager.set_current_buffer(buffer_id)?;
+        self.report_buffer_messages(buffer_id);
+        
+        // Bring back the file marks set in the file before
+        if let Some(file_path) = self.buffer_manager.get_buffer(buffer_id)?.file_path().map(Path::to_path_buf) {
+            let buffer = self.buffer_manager.get_buffer_mut(buffer_id)?;
+            for (name, mark) in self.global_marks.buffer_opened(buffer_id, &file_path) {
+                // The file may have become shorter since
+                let _ = buffer.set_mark(name, mark.line, mark.column);
+            }
+        }
         
         // Create a new tab for the file
         let file_name = path.as_ref().file_name()
@@ -502,6 +862,11 @@ impl Editor {
         
         self.terminal.create_tab(buffer_id, Some(file_name))?;
         
+        // The new window starts with the jump list of the one the file was opened from
+        if let (Some(jumps), Some(window)) = (jumps, self.terminal.current_window_mut()) {
+            window.jumps = jumps;
+        }
+        
         // Reset view position
         self.view_position = 0;
         
@@ -546,6 +911,11 @@ impl Editor {
     
     /// Save the current buffer
     pub fn save_current_buffer(&mut self) -> EditorResult<()> {
+        self.write_current_buffer(false)
+    }
+    
+    /// Write the current buffer, overriding the write safety checks if `force` is set
+    pub fn write_current_buffer(&mut self, force: bool) -> EditorResult<()> {
         // Get the current buffer ID
         let buffer_id = match self.current_buffer_id() {
             Some(id) => id,
@@ -564,13 +934,14 @@ impl Editor {
         }
         
         // Save the buffer
-        match buffer.save() {
+        match buffer.write(force) {
             Ok(_) => {
                 if let Some(path) = buffer.file_path() {
                     println!("\"{}\" written", path.display());
                 } else {
                     println!("Buffer written");
                 }
+                self.report_buffer_messages(buffer_id);
                 Ok(())
             },
             Err(err) => Err(EditorError::Buffer(err.into())),
@@ -579,6 +950,11 @@ impl Editor {
     
     /// Save the current buffer to a specific file
     pub fn save_current_buffer_as<P: AsRef<Path>>(&mut self, path: P) -> EditorResult<()> {
+        self.write_current_buffer_as(path, false)
+    }
+    
+    /// Write the current buffer to a specific file, overriding the write safety checks if `force` is set
+    pub fn write_current_buffer_as<P: AsRef<Path>>(&mut self, path: P, force: bool) -> EditorResult<()> {
         // Get the current buffer ID
         let buffer_id = match self.current_buffer_id() {
             Some(id) => id,
@@ -592,9 +968,10 @@ impl Editor {
         };
         
         // Save the buffer to the specified file
-        match buffer.save_as(path.as_ref()) {
+        match buffer.write_as(path.as_ref(), force) {
             Ok(_) => {
                 println!("\"{}\" written", path.as_ref().display());
+                self.report_buffer_messages(buffer_id);
                 Ok(())
             },
             Err(err) => Err(EditorError::Buffer(err.into())),
@@ -660,6 +1037,8 @@ impl Editor {
     
     /// Render the current state
     fn render(&mut self) -> EditorResult<()> {
+        self.sync_local_options();
+        
         // Get all buffers
         let mut buffers = Vec::new();
         for buffer_id in self.buffer_manager.buffer_ids() {
@@ -678,6 +1057,12 @@ impl Editor {
                     // Set the cursor position in the window
                     window.cursor = self.cursor_manager.position();
                     
+                    // Highlight the match a substitute asks about
+                    window.highlight = self.substitute_confirm.as_ref().map(|confirm| {
+                        let found = &confirm.matches[confirm.index];
+                        (confirm.start + found.line, found.start, found.end)
+                    });
+                    
                     // Ensure the cursor is visible
                     if let Some(buffer) = buffers.iter().find(|b| b.id() == buffer_id) {
                         let _ = window.ensure_cursor_visible(buffer);
@@ -686,19 +1071,70 @@ impl Editor {
             }
         }
         
-        // Render the current tab
-        self.terminal.render_current_tab(&buffer_refs, self.current_mode(), &self.command_buffer)?;
+        // Render the current tab, with the command line being typed or a question
+        let command_line = match (&self.substitute_confirm, &self.question) {
+            (Some(confirm), _) => {
+                let question = format!("replace with {} (y/n/a/q/l)?", confirm.matches[confirm.index].text);
+                let cursor = question.chars().count();
+                Some((question, cursor))
+            },
+            (None, Some(question)) => Some((question.clone(), question.chars().count())),
+            (None, None) if self.current_mode() == crate::mode::Mode::Command => {
+                Some((format!("{}{}", self.command_prompt(), self.command_line.text()), self.command_line.cursor() + 1))
+            },
+            (None, None) => None,
+        };
+        let command_line = command_line.as_ref().map(|(text, cursor)| (text.as_str(), *cursor));
+        let wildmenu = self.completion.as_ref()
+            .filter(|completion| completion.is_listed())
+            .map(|completion| (completion.matches(), completion.selected()));
+        self.terminal.render_current_tab(&buffer_refs, self.current_mode(), command_line, wildmenu, self.message())?;
         
         Ok(())
     }
     
-    /// Get the command buffer
+    /// Get the text typed on the command line, without the `:`, `/` or `?`
     pub fn command_buffer(&self) -> &str {
-        &self.command_buffer
+        self.command_line.text()
+    }
+    
+    /// Show a message on the last line, until the next key is typed
+    pub fn show_message(&mut self, message: &str) {
+        self.message = Some(message.to_string());
+    }
+    
+    /// Get the message shown on the last line
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+    
+    /// Get the character the command line starts with
+    fn command_prompt(&self) -> char {
+        match (self.searching, self.search_state.direction()) {
+            (false, _) => ':',
+            (true, SearchDirection::Forward) => '/',
+            (true, SearchDirection::Backward) => '?',
+        }
+    }
+    
+    /// Enter command mode with text on the command line
+    fn start_command_line(&mut self, text: &str) {
+        self.mode_manager.enter_command_mode();
+        self.command_line.set(text);
+        self.command_register_pending = false;
+        self.completion = None;
     }
     
     /// Process a key event
     fn process_key(&mut self, key: KeyEvent) -> EditorResult<()> {
+        // Enter or space only dismiss a message of several lines, as at Vim's hit-enter prompt
+        let message = self.message.take();
+        if message.is_some_and(|message| message.contains('\n'))
+            && matches!(key.code, crossterm::event::KeyCode::Enter | crossterm::event::KeyCode::Char(' '))
+        {
+            return Ok(());
+        }
+        
         // Check if we're playing back a macro
         if self.macro_player.is_playing() {
             // Get the next key from the macro
@@ -718,6 +1154,33 @@ impl Editor {
         self.process_key_internal(key)
     }
     
+    /// Finish a normal mode command with the character typed after it
+    fn handle_pending_char(&mut self, pending: PendingChar, c: char) -> EditorResult<()> {
+        match pending {
+            PendingChar::SetMark => self.set_mark(c),
+            PendingChar::PlayMacro { count } => {
+                self.play_macro(c, count);
+                Ok(())
+            },
+            PendingChar::OperatorG | PendingChar::OperatorFind { .. } | PendingChar::TextObject { .. } => {
+                self.handle_operator_pending_char(pending, c)
+            },
+            PendingChar::JumpToMark { linewise } => {
+                if !self.jump_to_mark(c)? {
+                    return Err(EditorError::Other("E20: Mark not set".to_string()));
+                }
+                
+                if linewise {
+                    if let Some(buffer_id) = self.current_buffer_id() {
+                        let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+                        self.cursor_manager.move_cursor(Direction::FirstNonWhitespace, buffer)?;
+                    }
+                }
+                Ok(())
+            },
+        }
+    }
+    
     /// Handle an operator in visual mode
     fn handle_visual_operator(&mut self, operator: Operator) -> EditorResult<()> {
         use crate::mode::Mode;
@@ -749,11 +1212,48 @@ impl Editor {
                 
                 // Get the text from the buffer
                 let content = buffer.content();
+                let visual_mode = self.visual_state().mode;
+                
+                let (first, last) = if (start_pos.line, start_pos.column) <= (end_pos.line, end_pos.column) {
+                    (start_pos, end_pos)
+                } else {
+                    (end_pos, start_pos)
+                };
+                
+                // Remember the size of the selection to repeat the change with '.'
+                if operator != Operator::Yank {
+                    let lines = last.line - first.line;
+                    let columns = if lines == 0 { last.column - first.column } else { last.column };
+                    let command = RepeatCommand::Visual { operator, mode: visual_mode, lines, columns };
+                    self.repeat_state.record(RepeatableChange::new(command));
+                }
+                
+                // The other operators work on the lines, the characters or the block selected
+                if !matches!(operator, Operator::Delete | Operator::Change | Operator::Yank) {
+                    let range = match visual_mode {
+                        crate::visual::VisualMode::Line => OperatorRange::Lines(first.line, last.line),
+                        crate::visual::VisualMode::Block => OperatorRange::Block {
+                            start_line: first.line,
+                            end_line: last.line,
+                            start_column: start_pos.column.min(end_pos.column),
+                            end_column: start_pos.column.max(end_pos.column) + 1,
+                        },
+                        // The character at the end of the selection is part of it
+                        crate::visual::VisualMode::Char if last.column < buffer.line_length(last.line)? => OperatorRange::Chars(start, end + 1),
+                        crate::visual::VisualMode::Char => OperatorRange::Chars(start, end),
+                    };
+                    self.end_visual_mode()?;
+                    return self.apply_operator(operator, range, self.count1());
+                }
+                
+                // End visual mode before the text changes, so '< and '> follow the change
+                self.end_visual_mode()?;
+                
                 if start < content.len() && end <= content.len() {
                     let text = content[start..end].to_string();
                     
                     // Store the text in the unnamed register
-                    let register_content = match self.visual_state().mode {
+                    let register_content = match visual_mode {
                         crate::visual::VisualMode::Line => {
                             let lines: Vec<&str> = text.lines().collect();
                             RegisterContent::line_wise(&lines)
@@ -779,6 +1279,7 @@ impl Editor {
                         },
                         Operator::Yank => {
                             // Just yank, no need to modify the buffer
+                            self.buffer_manager.get_buffer_mut(buffer_id)?.set_yank_marks(start, end);
                         },
                         _ => {
                             // Other operators not implemented yet
@@ -787,9 +1288,6 @@ impl Editor {
                     }
                 }
                 
-                // End visual mode
-                self.end_visual_mode()?;
-                
                 return Ok(());
             }
         }
@@ -803,8 +1301,65 @@ impl Editor {
         use crossterm::event::{KeyCode, KeyModifiers};
         use crate::mode::Mode;
         
+        // Keys answer a substitute asking about a match
+        if self.substitute_confirm.is_some() {
+            return self.answer_substitute_confirm(key);
+        }
+        
+        // Give a command waiting for a character the key typed
+        if let Some(pending) = self.pending_char.take() {
+            return match key.code {
+                KeyCode::Char(c) => self.handle_pending_char(pending, c),
+                // Any other key cancels the command, and the operator it belongs to
+                _ => {
+                    if self.current_mode() == Mode::OperatorPending {
+                        self.cancel_operator();
+                    }
+                    Ok(())
+                },
+            };
+        }
+        
+        // Digits typed before a command or motion are its count, a 0 only
+        // continues one and is otherwise the motion to the start of the line
+        let mode = self.current_mode();
+        if matches!(mode, Mode::Normal | Mode::OperatorPending) || mode.is_visual() {
+            match key.code {
+                KeyCode::Char(c @ '0'..='9') if key.modifiers == KeyModifiers::NONE && (c != '0' || self.pending_count.is_some()) => {
+                    let digit = c as usize - '0' as usize;
+                    let count = self.pending_count.unwrap_or(0).saturating_mul(10).saturating_add(digit);
+                    self.pending_count = Some(count.min(MAX_COUNT));
+                    return Ok(());
+                },
+                KeyCode::Esc => self.pending_count = None,
+                _ => {}
+            }
+        }
+        
+        // The count typed in normal or visual mode belongs to the command that follows
+        if mode == Mode::Normal || mode.is_visual() {
+            self.count = self.pending_count.take();
+        }
+        let result = self.process_command_key(key);
+        self.count = None;
+        result
+    }
+    
+    /// Get the count of the command being executed, 1 if none was typed
+    fn count1(&self) -> usize {
+        self.count.unwrap_or(1)
+    }
+    
+    /// Process a key that is not part of a count
+    fn process_command_key(&mut self, key: KeyEvent) -> EditorResult<()> {
+        use crossterm::event::{KeyCode, KeyModifiers};
+        use crate::mode::Mode;
+        
+        // Keys that finish a mapped sequence, like the U of gU, go to the key handler
+        let mapping_pending = self.key_handler.is_pending();
+        
         // Check for macro recording commands
-        if self.current_mode() == Mode::Normal {
+        if self.current_mode() == Mode::Normal && !mapping_pending {
             match key.code {
                 KeyCode::Char('q') if key.modifiers == KeyModifiers::NONE => {
                     // Check if we're already recording
@@ -821,10 +1376,8 @@ impl Editor {
                     }
                 },
                 KeyCode::Char('@') if key.modifiers == KeyModifiers::NONE => {
-                    // Play back a macro
-                    // This would normally be handled by getting the next key press,
-                    // but for now we'll just use a fixed register ('a')
-                    self.macro_player.start_playback('a');
+                    // Play back the macro in the register typed next
+                    self.pending_char = Some(PendingChar::PlayMacro { count: self.count1() });
                     return Ok(());
                 },
                 // Check for operator commands
@@ -840,6 +1393,16 @@ impl Editor {
                     // Yank operator
                     return self.handle_operator(Operator::Yank);
                 },
+                KeyCode::Char(c @ ('>' | '<' | '=' | '!')) if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                    // Shift, re-indent and filter operators
+                    if let Some(operator) = Operator::from_char(c) {
+                        return self.handle_operator(operator);
+                    }
+                },
+                KeyCode::Char('~') if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                    // Swap the case of count characters
+                    return self.swap_case_chars();
+                },
                 _ => {}
             }
         } else if self.current_mode() == Mode::OperatorPending {
@@ -847,7 +1410,7 @@ impl Editor {
             return self.handle_operator_pending_key(key);
         }
         // Handle operator keys in visual mode
-        if self.current_mode().is_visual() {
+        if self.current_mode().is_visual() && !mapping_pending {
             match key.code {
                 KeyCode::Char('d') if key.modifiers == KeyModifiers::NONE => {
                     // Delete operator in visual mode
@@ -861,6 +1424,28 @@ impl Editor {
                     // Yank operator in visual mode
                     return self.handle_visual_operator(Operator::Yank);
                 },
+                KeyCode::Char('U') if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                    // Make the selection uppercase
+                    return self.handle_visual_operator(Operator::ToUpper);
+                },
+                KeyCode::Char('u') if key.modifiers == KeyModifiers::NONE => {
+                    // Make the selection lowercase
+                    return self.handle_visual_operator(Operator::ToLower);
+                },
+                KeyCode::Char(c @ ('>' | '<' | '=' | '!' | '~')) if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                    // Shift, re-indent, filter and swap the case of the selection
+                    if let Some(operator) = Operator::from_char(c) {
+                        return self.handle_visual_operator(operator);
+                    }
+                },
+                KeyCode::Char(':') if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                    // Start a command on the lines of the selection
+                    self.end_visual_mode()?;
+                    self.filter_range = None;
+                    self.searching = false;
+                    self.start_command_line("'<,'>");
+                    return Ok(());
+                },
                 _ => {}
             }
         }
@@ -871,6 +1456,12 @@ impl Editor {
             return self.execute_key_command(command);
         }
         
+        // Wait for the rest of a mapped key sequence, keeping the count for it
+        if self.key_handler.is_pending() {
+            self.pending_count = self.count.take();
+            return Ok(());
+        }
+        
         // If the key handler didn't handle it, use the legacy key handling
         self.process_key_legacy(key)
     }
@@ -879,6 +1470,7 @@ impl Editor {
     fn handle_operator(&mut self, operator: Operator) -> EditorResult<()> {
         // Start the operator
         self.operator_manager.start_operator(operator);
+        self.operator_manager.set_count(self.count1());
         
         // Enter operator-pending mode
         self.mode_manager.enter_operator_pending_mode();
@@ -886,145 +1478,206 @@ impl Editor {
         Ok(())
     }
     
+    /// Start an operator typed after `g`, or apply it to the visual selection
+    fn handle_g_operator(&mut self, operator: Operator) -> EditorResult<()> {
+        if self.current_mode().is_visual() {
+            self.handle_visual_operator(operator)
+        } else {
+            self.handle_operator(operator)
+        }
+    }
+    
     /// Handle a key in operator-pending mode
     fn handle_operator_pending_key(&mut self, key: KeyEvent) -> EditorResult<()> {
         use crossterm::event::{KeyCode, KeyModifiers};
         
+        // A count typed after the operator multiplies its count, so 2d3w deletes six words
+        if let Some(motion_count) = self.pending_count.take() {
+            let count = self.operator_manager.current_state().map_or(1, |state| state.count);
+            self.operator_manager.set_count(count.saturating_mul(motion_count).min(MAX_COUNT));
+        }
+        
         // Get the current operator state
-        if let Some(state) = self.operator_manager.current_state() {
-            match key.code {
-                // Handle escape to cancel the operator
-                KeyCode::Esc => {
-                    self.operator_manager.cancel();
-                    self.mode_manager.enter_normal_mode();
-                    return Ok(());
-                },
-                
-                // Handle text object keys
-                KeyCode::Char('w') => {
-                    // Word text object
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::Word, false);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('W') => {
-                    // WORD text object (non-whitespace)
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::BigWord, false);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('s') => {
-                    // Sentence text object
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::Sentence, false);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('p') => {
-                    // Paragraph text object
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::Paragraph, false);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                // Handle inner/around text objects with 'i' and 'a' prefixes
-                KeyCode::Char('i') => {
-                    // Inner text object - wait for the next key
-                    return self.handle_inner_text_object(key);
-                },
-                KeyCode::Char('a') => {
-                    // Around text object - wait for the next key
-                    return self.handle_around_text_object(key);
-                },
-                
-                // Handle motion keys
-                KeyCode::Char('j') | KeyCode::Down => {
-                    // Down motion
-                    let target = OperatorTarget::Motion(crate::cursor::Direction::Down);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('k') | KeyCode::Up => {
-                    // Up motion
-                    let target = OperatorTarget::Motion(crate::cursor::Direction::Up);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('h') | KeyCode::Left => {
-                    // Left motion
-                    let target = OperatorTarget::Motion(crate::cursor::Direction::Left);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('l') | KeyCode::Right => {
-                    // Right motion
-                    let target = OperatorTarget::Motion(crate::cursor::Direction::Right);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                
-                // Handle visual mode operators
-                KeyCode::Char('v') => {
-                    // Enter visual mode and remember the operator
-                    self.start_visual_mode(crate::visual::VisualMode::Char)?;
-                    return Ok(());
-                },
-                
-                KeyCode::Char('V') => {
-                    // Enter visual line mode and remember the operator
-                    self.start_visual_mode(crate::visual::VisualMode::Line)?;
-                    return Ok(());
-                },
-                
-                KeyCode::Char('b') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                    // Enter visual block mode and remember the operator
-                    self.start_visual_mode(crate::visual::VisualMode::Block)?;
-                    return Ok(());
-                },
-                
-                // Handle line-wise operators (dd, yy, etc.)
-                KeyCode::Char(c) => {
-                    if let Some(op) = Operator::from_char(c) {
-                        if op == state.operator {
-                            // Line-wise operation (e.g., dd, yy, cc)
-                            let cursor_pos = self.cursor_manager.position();
-                            let target = OperatorTarget::LineRange(cursor_pos.line, cursor_pos.line);
-                            if let Some(completed_state) = self.operator_manager.set_target(target) {
-                                return self.execute_operator(completed_state);
-                            }
-                        }
-                    }
-                },
-                
-                _ => {
-                    // Unhandled key, cancel the operator
-                    self.operator_manager.cancel();
-                    self.mode_manager.enter_normal_mode();
-                }
+        let state = match self.operator_manager.current_state() {
+            Some(state) => state.clone(),
+            None => {
+                // No operator state, return to normal mode
+                self.mode_manager.enter_normal_mode();
+                return Ok(());
             }
-        } else {
-            // No operator state, return to normal mode
-            self.mode_manager.enter_normal_mode();
-        }
+        };
         
-        Ok(())
+        let direction = match key.code {
+            // Handle escape to cancel the operator
+            KeyCode::Esc => {
+                self.cancel_operator();
+                return Ok(());
+            },
+            
+            // Handle visual mode operators
+            KeyCode::Char('v') => {
+                // Enter visual mode and remember the operator
+                return self.start_visual_mode(crate::visual::VisualMode::Char);
+            },
+            KeyCode::Char('V') => {
+                // Enter visual line mode and remember the operator
+                return self.start_visual_mode(crate::visual::VisualMode::Line);
+            },
+            KeyCode::Char('b') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                // Enter visual block mode and remember the operator
+                return self.start_visual_mode(crate::visual::VisualMode::Block);
+            },
+            
+            // Handle line-wise operators (dd, >>, gUU, etc.) on count lines
+            KeyCode::Char(c) if state.operator.line_key() == Some(c) => {
+                return self.operate_on_count_lines();
+            },
+            
+            // Handle keys that need another one: gg, f/t/F/T and the inner/around text objects
+            KeyCode::Char('g') => {
+                self.pending_char = Some(PendingChar::OperatorG);
+                return Ok(());
+            },
+            KeyCode::Char(c @ ('f' | 't' | 'F' | 'T')) => {
+                let (till, backward) = (c.eq_ignore_ascii_case(&'t'), c.is_ascii_uppercase());
+                self.pending_char = Some(PendingChar::OperatorFind { till, backward });
+                return Ok(());
+            },
+            KeyCode::Char(c @ ('i' | 'a')) => {
+                self.pending_char = Some(PendingChar::TextObject { around: c == 'a' });
+                return Ok(());
+            },
+            
+            // Handle text object keys
+            KeyCode::Char('W') => {
+                return self.complete_operator(OperatorTarget::TextObject(TextObjectTypeExt::BigWord, false));
+            },
+            KeyCode::Char('s') => {
+                return self.complete_operator(OperatorTarget::TextObject(TextObjectTypeExt::Sentence, false));
+            },
+            KeyCode::Char('p') => {
+                return self.complete_operator(OperatorTarget::TextObject(TextObjectTypeExt::Paragraph, false));
+            },
+            
+            // Handle motion keys
+            KeyCode::Char('j') | KeyCode::Down => Direction::Down,
+            KeyCode::Char('k') | KeyCode::Up => Direction::Up,
+            KeyCode::Char('h') | KeyCode::Left => Direction::Left,
+            KeyCode::Char('l') | KeyCode::Right => Direction::Right,
+            KeyCode::Char('w') => Direction::WordNext,
+            KeyCode::Char('b') => Direction::WordPrev,
+            KeyCode::Char('e') => Direction::WordEnd,
+            KeyCode::Char('0') | KeyCode::Home => Direction::LineStart,
+            KeyCode::Char('^') => Direction::FirstNonWhitespace,
+            KeyCode::Char('$') | KeyCode::End => Direction::LineEnd,
+            KeyCode::Char('{') => Direction::ParagraphStart,
+            KeyCode::Char('}') => Direction::ParagraphEnd,
+            KeyCode::Char('%') => Direction::MatchingBracket,
+            // With a count G goes to that line, and the count is not repeated
+            KeyCode::Char('G') if state.count > 1 => {
+                return self.complete_operator_with_count(Direction::LineNumber(state.count - 1));
+            },
+            KeyCode::Char('G') => Direction::BufferEnd,
+            
+            _ => {
+                // Unhandled key, cancel the operator
+                self.cancel_operator();
+                return Ok(());
+            }
+        };
+        
+        self.complete_operator(OperatorTarget::Motion(direction))
     }
     
-    /// Execute an operator with its target
-    fn execute_operator(&mut self, state: OperatorState) -> EditorResult<()> {
-        // Return to normal mode
-        self.mode_manager.enter_normal_mode();
+    /// Finish an operator-pending command with the character typed after `g`, `f`, `i` or the like
+    fn handle_operator_pending_char(&mut self, pending: PendingChar, c: char) -> EditorResult<()> {
+        let state = match self.operator_manager.current_state() {
+            Some(state) => state.clone(),
+            None => {
+                self.mode_manager.enter_normal_mode();
+                return Ok(());
+            }
+        };
+        
+        match pending {
+            PendingChar::OperatorG if c == 'g' && state.count > 1 => {
+                self.complete_operator_with_count(Direction::LineNumber(state.count - 1))
+            },
+            PendingChar::OperatorG if c == 'g' => self.complete_operator(OperatorTarget::Motion(Direction::BufferStart)),
+            // A g operator typed again works on lines, as gUgU does
+            PendingChar::OperatorG if state.operator.is_g_operator() && state.operator.line_key() == Some(c) => {
+                self.operate_on_count_lines()
+            },
+            PendingChar::OperatorFind { till, backward } => {
+                let direction = match (till, backward) {
+                    (false, false) => Direction::FindForward(c),
+                    (true, false) => Direction::FindTillForward(c),
+                    (false, true) => Direction::FindBackward(c),
+                    (true, true) => Direction::FindTillBackward(c),
+                };
+                self.complete_operator(OperatorTarget::Motion(direction))
+            },
+            PendingChar::TextObject { around } => match TextObjectTypeExt::from_char(c) {
+                Some(object_type) => self.complete_operator(OperatorTarget::TextObject(object_type, around)),
+                None => {
+                    self.cancel_operator();
+                    Ok(())
+                },
+            },
+            _ => {
+                self.cancel_operator();
+                Ok(())
+            },
+        }
+    }
+    
+    /// Give the pending operator its target and execute it
+    fn complete_operator(&mut self, target: OperatorTarget) -> EditorResult<()> {
+        match self.operator_manager.set_target(target) {
+            Some(state) => self.execute_operator(state),
+            None => {
+                self.mode_manager.enter_normal_mode();
+                Ok(())
+            }
+        }
+    }
+    
+    /// Give the pending operator a motion that already used up its count, as `5dG` does
+    fn complete_operator_with_count(&mut self, direction: Direction) -> EditorResult<()> {
+        self.operator_manager.set_count(1);
+        self.complete_operator(OperatorTarget::Motion(direction))
+    }
+    
+    /// Apply the pending operator to count lines from the cursor, as `dd` and `>>` do
+    fn operate_on_count_lines(&mut self) -> EditorResult<()> {
+        let count = self.operator_manager.current_state().map_or(1, |state| state.count);
+        let cursor_pos = self.cursor_manager.position();
+        let last_line = match self.current_buffer_id() {
+            Some(buffer_id) => self.buffer_manager.get_buffer(buffer_id)?.line_count().saturating_sub(1),
+            None => cursor_pos.line,
+        };
+        let end = (cursor_pos.line + count - 1).min(last_line);
+        self.complete_operator(OperatorTarget::LineRange(cursor_pos.line, end))
+    }
+    
+    /// Cancel the pending operator and return to normal mode
+    fn cancel_operator(&mut self) {
+        self.operator_manager.cancel();
+        self.mode_manager.enter_normal_mode();
+    }
+    
+    /// Execute an operator with its target
+    fn execute_operator(&mut self, state: OperatorState) -> EditorResult<()> {
+        // Return to normal mode
+        self.mode_manager.enter_normal_mode();
         
         // Extract the operator and target before the match to avoid borrowing issues
         let operator = state.operator;
         let target = state.target.clone(); // Clone here to avoid borrowing issues
         
+        // Everything but a yank changes the text, and can be repeated with '.'
+        let repeat_target = target.clone().filter(|_| operator != Operator::Yank);
+        
         match (operator, target) {
             (Operator::Delete, Some(OperatorTarget::TextObject(object_type, include_delimiters))) => {
                 // Delete text object
@@ -1086,18 +1739,6 @@ impl Editor {
                 
                 self.yank_text_object(editor_object_type, include_delimiters)?;
             },
-            (Operator::Delete, Some(OperatorTarget::Motion(direction))) => {
-                // Delete to motion
-                self.delete_to_motion(direction)?;
-            },
-            (Operator::Change, Some(OperatorTarget::Motion(direction))) => {
-                // Change to motion
-                self.change_to_motion(direction)?;
-            },
-            (Operator::Yank, Some(OperatorTarget::Motion(direction))) => {
-                // Yank to motion
-                self.yank_to_motion(direction)?;
-            },
             (Operator::Delete, Some(OperatorTarget::LineRange(start, end))) => {
                 // Delete lines
                 self.delete_lines(start, end)?;
@@ -1110,12 +1751,32 @@ impl Editor {
                 // Yank lines
                 self.yank_lines(start, end)?;
             },
+            (Operator::Delete | Operator::Change | Operator::Yank, Some(OperatorTarget::CharRange(start, end))) => {
+                // Characters, as 'x' deletes
+                self.operate_on_chars(operator, start, end)?;
+            },
+            (operator, Some(target)) => {
+                // A motion, or a text object for the other operators
+                let range = match (operator, target) {
+                    (Operator::Change, OperatorTarget::Motion(Direction::WordNext)) => self.change_word_range(state.count)?,
+                    (_, target) => self.target_range(&target, state.count)?,
+                };
+                if let Some(range) = range {
+                    self.apply_operator(operator, range, 1)?;
+                }
+            },
             _ => {
                 // Unsupported operator/target combination
                 return Err(EditorError::Other(format!("Unsupported operator/target combination: {:?}", operator)));
             }
         }
         
+        // Only once the change was made, so a failed one keeps the last
+        if let Some(target) = repeat_target {
+            let change = RepeatableChange::new(RepeatCommand::Operator { operator, target });
+            self.repeat_state.record(change.with_count(state.count));
+        }
+        
         Ok(())
     }
     
@@ -1125,36 +1786,64 @@ impl Editor {
             KeyCommand::BuiltIn(cmd) => {
                 match cmd.as_str() {
                     "quit" => self.quit(),
-                    "enter_insert_mode" => { self.start_insert_mode(false)?; },
+                    "enter_insert_mode" => { self.insert_command(InsertCommand::Insert)?; },
                     "enter_normal_mode" => {
                         // If we're in visual mode, end it properly
                         if self.current_mode().is_visual() {
                             self.end_visual_mode()?;
                         } else if self.current_mode() == crate::mode::Mode::Insert {
                             self.end_insert_mode()?;
+                        } else if self.current_mode() == crate::mode::Mode::Command {
+                            self.leave_command_line();
                         } else {
                             self.mode_manager.enter_normal_mode();
                         }
                     },
-                    "enter_command_mode" => self.mode_manager.enter_command_mode(),
-                    "undo" => { self.undo()?; },
-                    "redo" => { self.redo()?; },
-                    "paste" => { self.paste()?; },
-                    "paste_before" => {
-                        // Move cursor left, paste, then move cursor right
-                        if let Some(buffer_id) = self.current_buffer_id() {
-                            let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                            self.cursor_manager.move_cursor(Direction::Left, buffer)?;
-                            
-                            // Clone the buffer to avoid borrowing issues
-                            let buffer_id_copy = buffer_id;
-                            self.paste()?;
-                            
-                            // Get the buffer again after paste
-                            let buffer = self.buffer_manager.get_buffer(buffer_id_copy)?;
-                            self.cursor_manager.move_cursor(Direction::Right, buffer)?;
+                    "enter_command_mode" => {
+                        self.filter_range = None;
+                        self.searching = false;
+                        // A count stands for that many lines from the cursor
+                        let range = match self.count {
+                            Some(count) if count > 1 => format!(".,.+{}", count - 1),
+                            Some(_) => ".".to_string(),
+                            None => String::new(),
+                        };
+                        self.start_command_line(&range);
+                    },
+                    "undo" => {
+                        for _ in 0..self.count1() {
+                            if !self.undo()? {
+                                break;
+                            }
+                        }
+                    },
+                    "redo" => {
+                        for _ in 0..self.count1() {
+                            if !self.redo()? {
+                                break;
+                            }
                         }
                     },
+                    "undo_earlier" => { self.earlier(UndoTime::Steps(self.count1()))?; },
+                    "undo_later" => { self.later(UndoTime::Steps(self.count1()))?; },
+                    "jump_older" => { self.jump_older(self.count1())?; },
+                    "jump_newer" => { self.jump_newer(self.count1())?; },
+                    "change_older" => self.change_older(self.count1())?,
+                    "change_newer" => self.change_newer(self.count1())?,
+                    "first_line" => {
+                        self.goto_line(Direction::BufferStart)?;
+                        if self.current_mode().is_visual() {
+                            self.selection_manager.update_selection(self.cursor_manager.position());
+                        }
+                    },
+                    "to_upper" => self.handle_g_operator(Operator::ToUpper)?,
+                    "to_lower" => self.handle_g_operator(Operator::ToLower)?,
+                    "swap_case" => self.handle_g_operator(Operator::SwapCase)?,
+                    "format" => self.handle_g_operator(Operator::Format)?,
+                    "format_keep_cursor" => self.handle_g_operator(Operator::FormatKeepCursor)?,
+                    "paste" => { self.put(None, false, self.count1())?; },
+                    "paste_before" => { self.put(None, true, self.count1())?; },
+                    "repeat_change" => { self.repeat_last_change(self.count)?; },
                     "next_tab" => {
                         self.terminal.next_tab()?;
                     },
@@ -1170,9 +1859,7 @@ impl Editor {
                         self.stop_macro_recording();
                     },
                     "play_macro" => {
-                        // This would normally get a register from the user
-                        // For now, just use register 'a'
-                        self.play_macro('a');
+                        self.pending_char = Some(PendingChar::PlayMacro { count: self.count1() });
                     },
                     "reselect_visual" => {
                         self.reselect_visual_area()?;
@@ -1234,51 +1921,58 @@ impl Editor {
             // Basic cursor movement in normal mode
             (Mode::Normal, KeyCode::Up) | (Mode::Normal, KeyCode::Char('k')) => {
                 if let Some(buffer_id) = self.current_buffer_id() {
+                    let count = self.count1();
                     let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                    self.cursor_manager.move_cursor(Direction::Up, buffer)?;
+                    self.cursor_manager.move_cursor_count(Direction::Up, count, buffer)?;
                 }
             },
             
             (Mode::Normal, KeyCode::Down) | (Mode::Normal, KeyCode::Char('j')) => {
                 if let Some(buffer_id) = self.current_buffer_id() {
+                    let count = self.count1();
                     let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                    self.cursor_manager.move_cursor(Direction::Down, buffer)?;
+                    self.cursor_manager.move_cursor_count(Direction::Down, count, buffer)?;
                 }
             },
             
             (Mode::Normal, KeyCode::Left) | (Mode::Normal, KeyCode::Char('h')) => {
                 if let Some(buffer_id) = self.current_buffer_id() {
+                    let count = self.count1();
                     let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                    self.cursor_manager.move_cursor(Direction::Left, buffer)?;
+                    self.cursor_manager.move_cursor_count(Direction::Left, count, buffer)?;
                 }
             },
             
             (Mode::Normal, KeyCode::Right) | (Mode::Normal, KeyCode::Char('l')) => {
                 if let Some(buffer_id) = self.current_buffer_id() {
+                    let count = self.count1();
                     let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                    self.cursor_manager.move_cursor(Direction::Right, buffer)?;
+                    self.cursor_manager.move_cursor_count(Direction::Right, count, buffer)?;
                 }
             },
             
             // Word movement in normal mode
             (Mode::Normal, KeyCode::Char('w')) => {
                 if let Some(buffer_id) = self.current_buffer_id() {
+                    let count = self.count1();
                     let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                    self.cursor_manager.move_cursor(Direction::WordNext, buffer)?;
+                    self.cursor_manager.move_cursor_count(Direction::WordNext, count, buffer)?;
                 }
             },
             
             (Mode::Normal, KeyCode::Char('b')) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
                 if let Some(buffer_id) = self.current_buffer_id() {
+                    let count = self.count1();
                     let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                    self.cursor_manager.move_cursor(Direction::WordPrev, buffer)?;
+                    self.cursor_manager.move_cursor_count(Direction::WordPrev, count, buffer)?;
                 }
             },
             
             (Mode::Normal, KeyCode::Char('e')) => {
                 if let Some(buffer_id) = self.current_buffer_id() {
+                    let count = self.count1();
                     let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                    self.cursor_manager.move_cursor(Direction::WordEnd, buffer)?;
+                    self.cursor_manager.move_cursor_count(Direction::WordEnd, count, buffer)?;
                 }
             },
             
@@ -1292,38 +1986,50 @@ impl Editor {
             
             (Mode::Normal, KeyCode::End) | (Mode::Normal, KeyCode::Char('$')) => {
                 if let Some(buffer_id) = self.current_buffer_id() {
+                    // With a count, to the end of the line count - 1 lines down
+                    let count = self.count1();
                     let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+                    if count > 1 {
+                        self.cursor_manager.move_cursor_count(Direction::Down, count - 1, buffer)?;
+                    }
                     self.cursor_manager.move_cursor(Direction::LineEnd, buffer)?;
                 }
             },
             
             // Buffer movement in normal mode
-            (Mode::Normal, KeyCode::Char('g')) => {
-                if let Some(buffer_id) = self.current_buffer_id() {
-                    let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                    self.cursor_manager.move_cursor(Direction::BufferStart, buffer)?;
-                }
+            (Mode::Normal, KeyCode::Char('G')) => {
+                self.goto_line(Direction::BufferEnd)?;
             },
             
-            (Mode::Normal, KeyCode::Char('G')) => {
+            // Jump to the matching bracket with '%' in normal mode
+            (Mode::Normal, KeyCode::Char('%')) => {
                 if let Some(buffer_id) = self.current_buffer_id() {
+                    let start = self.cursor_manager.position();
                     let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                    self.cursor_manager.move_cursor(Direction::BufferEnd, buffer)?;
+                    self.cursor_manager.move_cursor(Direction::MatchingBracket, buffer)?;
+                    
+                    let target = self.cursor_manager.position();
+                    if (target.line, target.column) != (start.line, start.column) {
+                        self.cursor_manager.set_position(start);
+                        self.jump_cursor(target);
+                    }
                 }
             },
             
             // Paragraph movement in normal mode
             (Mode::Normal, KeyCode::Char('{')) => {
                 if let Some(buffer_id) = self.current_buffer_id() {
+                    let count = self.count1();
                     let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                    self.cursor_manager.move_cursor(Direction::ParagraphStart, buffer)?;
+                    self.cursor_manager.move_cursor_count(Direction::ParagraphStart, count, buffer)?;
                 }
             },
             
             (Mode::Normal, KeyCode::Char('}')) => {
                 if let Some(buffer_id) = self.current_buffer_id() {
+                    let count = self.count1();
                     let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                    self.cursor_manager.move_cursor(Direction::ParagraphEnd, buffer)?;
+                    self.cursor_manager.move_cursor_count(Direction::ParagraphEnd, count, buffer)?;
                 }
             },
             
@@ -1339,12 +2045,25 @@ impl Editor {
             
             // Find next with 'n' in normal mode
             (Mode::Normal, KeyCode::Char('n')) => {
-                self.find_next_occurrence()?;
+                for _ in 0..self.count1() {
+                    if !self.find_next_occurrence()? {
+                        break;
+                    }
+                }
             },
             
             // Find previous with 'N' in normal mode
             (Mode::Normal, KeyCode::Char('N')) => {
-                self.find_prev_occurrence()?;
+                for _ in 0..self.count1() {
+                    if !self.find_prev_occurrence()? {
+                        break;
+                    }
+                }
+            },
+            
+            // Delete the character under the cursor with 'x'
+            (Mode::Normal, KeyCode::Char('x')) => {
+                self.delete_chars()?;
             },
             
             // Enter visual mode in normal mode
@@ -1368,8 +2087,6 @@ impl Editor {
                 self.end_visual_mode()?;
             },
             
-            // This case is handled by the first (Mode::Normal, KeyCode::Char('g')) pattern at line 1301
-            
             // Swap visual corners with 'o' in visual mode
             (mode, KeyCode::Char('o')) if mode.is_visual() => {
                 // Swap start and end of visual selection
@@ -1388,50 +2105,57 @@ impl Editor {
                 let result = match (mode, key.code) {
                     (_, KeyCode::Up) | (_, KeyCode::Char('k')) => {
                         if let Some(buffer_id) = self.current_buffer_id() {
+                            let count = self.count1();
                             let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                            self.cursor_manager.move_cursor(Direction::Up, buffer)?;
+                            self.cursor_manager.move_cursor_count(Direction::Up, count, buffer)?;
                         }
                         Ok(())
                     },
                     (_, KeyCode::Down) | (_, KeyCode::Char('j')) => {
                         if let Some(buffer_id) = self.current_buffer_id() {
+                            let count = self.count1();
                             let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                            self.cursor_manager.move_cursor(Direction::Down, buffer)?;
+                            self.cursor_manager.move_cursor_count(Direction::Down, count, buffer)?;
                         }
                         Ok(())
                     },
                     (_, KeyCode::Left) | (_, KeyCode::Char('h')) => {
                         if let Some(buffer_id) = self.current_buffer_id() {
+                            let count = self.count1();
                             let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                            self.cursor_manager.move_cursor(Direction::Left, buffer)?;
+                            self.cursor_manager.move_cursor_count(Direction::Left, count, buffer)?;
                         }
                         Ok(())
                     },
                     (_, KeyCode::Right) | (_, KeyCode::Char('l')) => {
                         if let Some(buffer_id) = self.current_buffer_id() {
+                            let count = self.count1();
                             let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                            self.cursor_manager.move_cursor(Direction::Right, buffer)?;
+                            self.cursor_manager.move_cursor_count(Direction::Right, count, buffer)?;
                         }
                         Ok(())
                     },
                     (_, KeyCode::Char('w')) => {
                         if let Some(buffer_id) = self.current_buffer_id() {
+                            let count = self.count1();
                             let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                            self.cursor_manager.move_cursor(Direction::WordNext, buffer)?;
+                            self.cursor_manager.move_cursor_count(Direction::WordNext, count, buffer)?;
                         }
                         Ok(())
                     },
                     (_, KeyCode::Char('b')) => {
                         if let Some(buffer_id) = self.current_buffer_id() {
+                            let count = self.count1();
                             let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                            self.cursor_manager.move_cursor(Direction::WordPrev, buffer)?;
+                            self.cursor_manager.move_cursor_count(Direction::WordPrev, count, buffer)?;
                         }
                         Ok(())
                     },
                     (_, KeyCode::Char('e')) => {
                         if let Some(buffer_id) = self.current_buffer_id() {
+                            let count = self.count1();
                             let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                            self.cursor_manager.move_cursor(Direction::WordEnd, buffer)?;
+                            self.cursor_manager.move_cursor_count(Direction::WordEnd, count, buffer)?;
                         }
                         Ok(())
                     },
@@ -1444,25 +2168,17 @@ impl Editor {
                     },
                     (_, KeyCode::End) | (_, KeyCode::Char('$')) => {
                         if let Some(buffer_id) = self.current_buffer_id() {
+                            // With a count, to the end of the line count - 1 lines down
+                            let count = self.count1();
                             let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+                            if count > 1 {
+                                self.cursor_manager.move_cursor_count(Direction::Down, count - 1, buffer)?;
+                            }
                             self.cursor_manager.move_cursor(Direction::LineEnd, buffer)?;
                         }
                         Ok(())
                     },
-                    (_, KeyCode::Char('g')) => {
-                        if let Some(buffer_id) = self.current_buffer_id() {
-                            let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                            self.cursor_manager.move_cursor(Direction::BufferStart, buffer)?;
-                        }
-                        Ok(())
-                    },
-                    (_, KeyCode::Char('G')) => {
-                        if let Some(buffer_id) = self.current_buffer_id() {
-                            let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-                            self.cursor_manager.move_cursor(Direction::BufferEnd, buffer)?;
-                        }
-                        Ok(())
-                    },
+                    (_, KeyCode::Char('G')) => self.goto_line(Direction::BufferEnd),
                     _ => Ok(()),
                 };
                 
@@ -1514,60 +2230,12 @@ impl Editor {
             
             // Open line below with 'o' in normal mode
             (Mode::Normal, KeyCode::Char('o')) => {
-                if let Some(buffer_id) = self.current_buffer_id() {
-                    let buffer = self.buffer_manager.get_buffer_mut(buffer_id)?;
-                    
-                    // Get cursor position
-                    let cursor_pos = self.cursor_manager.position();
-                    
-                    // Move to the end of the current line
-                    self.cursor_manager.move_cursor(Direction::LineEnd, buffer)?;
-                    
-                    // Get the updated cursor position
-                    let end_pos = self.cursor_manager.position();
-                    
-                    // Convert cursor position to character index
-                    let cursor_position = buffer.position_to_char_idx(end_pos.line, end_pos.column)?;
-                    
-                    // Insert a newline at the cursor position
-                    buffer.insert(cursor_position, "\n")?;
-                    
-                    // Update cursor position to the beginning of the new line
-                    let new_position = buffer.char_idx_to_position(cursor_position + 1)?;
-                    self.cursor_manager.set_position(new_position);
-                    
-                    // Enter insert mode
-                    self.start_insert_mode(false)?;
-                }
+                self.insert_command(InsertCommand::OpenBelow)?;
             },
             
             // Open line above with 'O' in normal mode
             (Mode::Normal, KeyCode::Char('O')) => {
-                if let Some(buffer_id) = self.current_buffer_id() {
-                    let buffer = self.buffer_manager.get_buffer_mut(buffer_id)?;
-                    
-                    // Get cursor position
-                    let cursor_pos = self.cursor_manager.position();
-                    
-                    // Move to the beginning of the current line
-                    self.cursor_manager.move_cursor(Direction::LineStart, buffer)?;
-                    
-                    // Get the updated cursor position
-                    let start_pos = self.cursor_manager.position();
-                    
-                    // Convert cursor position to character index
-                    let cursor_position = buffer.position_to_char_idx(start_pos.line, start_pos.column)?;
-                    
-                    // Insert a newline at the cursor position
-                    buffer.insert(cursor_position, "\n")?;
-                    
-                    // Update cursor position to the beginning of the original line
-                    let new_position = buffer.char_idx_to_position(cursor_position)?;
-                    self.cursor_manager.set_position(new_position);
-                    
-                    // Enter insert mode
-                    self.start_insert_mode(false)?;
-                }
+                self.insert_command(InsertCommand::OpenAbove)?;
             },
             
             // Paste from register with '"ap' in normal mode
@@ -1579,53 +2247,21 @@ impl Editor {
             
             // Set mark with 'm' followed by a character in normal mode
             (Mode::Normal, KeyCode::Char('m')) => {
-                // TODO: Implement a proper way to get the next key press
-                // For now, just set a mark at the current position with a fixed name
-                self.set_mark('a')?;
+                self.pending_char = Some(PendingChar::SetMark);
             },
             
             // Jump to mark with '`' followed by a character in normal mode
             (Mode::Normal, KeyCode::Char('`')) => {
-                // TODO: Implement a proper way to get the next key press
-                // For now, just jump to a mark with a fixed name
-                self.jump_to_mark('a')?;
-            },
-            
-            // Process command when Enter is pressed in command mode
-            (Mode::Command, KeyCode::Enter) => {
-                // Get the command text from the command buffer
-                let command_text = self.command_buffer.clone();
-                
-                // Clear the command buffer
-                self.command_buffer.clear();
-                
-                // Return to normal mode
-                self.mode_manager.enter_normal_mode();
-                
-                // Process the command if it's not empty
-                if !command_text.is_empty() {
-                    self.process_command(&command_text)?;
-                }
-            },
-            
-            // Handle backspace in command mode
-            (Mode::Command, KeyCode::Backspace) => {
-                // Remove the last character from the command buffer
-                self.command_buffer.pop();
+                self.pending_char = Some(PendingChar::JumpToMark { linewise: false });
             },
             
-            // Handle escape in command mode
-            (Mode::Command, KeyCode::Esc) => {
-                // Clear the command buffer and return to normal mode
-                self.command_buffer.clear();
-                self.mode_manager.enter_normal_mode();
+            // Jump to the line of a mark with '\'' followed by a character in normal mode
+            (Mode::Normal, KeyCode::Char('\'')) => {
+                self.pending_char = Some(PendingChar::JumpToMark { linewise: true });
             },
             
-            // Handle other keys in command mode
-            (Mode::Command, KeyCode::Char(c)) => {
-                // Add the character to the command buffer
-                self.command_buffer.push(c);
-            },
+            // Edit the command line, and run it when Enter is pressed
+            (Mode::Command, _) => self.command_line_key(key)?,
             
             // Handle key presses in insert mode
             (Mode::Insert, KeyCode::Char(c)) => {
@@ -1675,11 +2311,33 @@ impl Editor {
         while self.running {
             // Handle input
             if let Some(key) = self.terminal.poll_key(100)? {
-                // Process the key
-                self.process_key(key)?;
+                // Process the key, showing what went wrong after what the command wrote
+                if let Err(err) = self.process_key(key) {
+                    let message = match self.message.take() {
+                        Some(message) => format!("{}\n{}", message, err),
+                        None => err.to_string(),
+                    };
+                    self.show_message(&message);
+                }
+                self.last_key_time = Instant::now();
+                self.swap_pending = true;
                 
                 // Render the screen after processing input
                 self.render()?;
+            } else if self.swap_pending {
+                // Write swap files once the user stops typing for 'updatetime'
+                let updatetime = self.options.get_number("updatetime", OptionContext::default());
+                let updatetime = Duration::from_millis(updatetime.try_into().unwrap_or(0));
+                if self.last_key_time.elapsed() >= updatetime {
+                    self.buffer_manager.update_swap_files();
+                    self.swap_pending = false;
+                }
+            }
+            
+            // Look for files changed by other programs
+            let check_due = self.terminal.take_focus_gained() || self.last_checktime.elapsed() >= CHECKTIME_INTERVAL;
+            if check_due && self.check_time(None)? {
+                self.render()?;
             }
             
             // Check if a command has requested to quit
@@ -1688,6 +2346,9 @@ impl Editor {
             }
         }
         
+        // Swap files are only needed while editing
+        self.buffer_manager.close_swap_files();
+        
         // Clean up terminal
         self.terminal.cleanup()?;
         
@@ -1720,28 +2381,347 @@ impl Editor {
         }
     }
     
+    /// Go to an older text state in the current buffer
+    pub fn earlier(&mut self, time: UndoTime) -> EditorResult<bool> {
+        if let Some(buffer_id) = self.current_buffer_id() {
+            let buffer = self.buffer_manager.get_buffer_mut(buffer_id)?;
+            match buffer.earlier(time) {
+                Ok(result) => Ok(result),
+                Err(err) => Err(EditorError::Buffer(err.into())),
+            }
+        } else {
+            Ok(false)
+        }
+    }
+    
+    /// Go to a newer text state in the current buffer
+    pub fn later(&mut self, time: UndoTime) -> EditorResult<bool> {
+        if let Some(buffer_id) = self.current_buffer_id() {
+            let buffer = self.buffer_manager.get_buffer_mut(buffer_id)?;
+            match buffer.later(time) {
+                Ok(result) => Ok(result),
+                Err(err) => Err(EditorError::Buffer(err.into())),
+            }
+        } else {
+            Ok(false)
+        }
+    }
+    
     /// Process a command
     pub fn process_command(&mut self, command_str: &str) -> EditorResult<()> {
-        // Check if it's a search command
-        if command_str.starts_with('/') {
+        // Check if it's a search command, rather than a pattern address
+        match search_pattern(command_str) {
             // Forward search
-            let pattern = &command_str[1..];
-            return match self.execute_search(pattern) {
-                Ok(_) => Ok(()),
-                Err(err) => Err(err),
-            };
-        } else if command_str.starts_with('?') {
+            Some(('/', pattern)) => self.execute_search(pattern).map(|_| ()),
             // Backward search
-            let pattern = &command_str[1..];
-            self.search_state_mut().set_direction(SearchDirection::Backward);
-            return match self.execute_search(pattern) {
-                Ok(_) => Ok(()),
-                Err(err) => Err(err),
+            Some((_, pattern)) => {
+                self.search_state_mut().set_direction(SearchDirection::Backward);
+                self.execute_search(pattern).map(|_| ())
+            },
+            // Not a search command, parse as an Ex command
+            None => self.execute_ex_command(command_str),
+        }
+    }
+    
+    /// Run the Ex commands of a script, as `:source` does
+    ///
+    /// Every command line is run, even after one fails. The errors are
+    /// returned together, each with the file and line it happened at.
+    pub fn source_file<P: AsRef<Path>>(&mut self, path: P) -> EditorResult<()> {
+        let path = path.as_ref();
+        let file = path.display().to_string();
+        if self.sourcing.len() >= MAX_SOURCE_DEPTH {
+            return Err(EditorError::Other("E169: Command too recursive".to_string()));
+        }
+        let text = std::fs::read_to_string(path)
+            .map_err(|_| EditorError::Other(format!("E484: Can't open file {}", file)))?;
+        
+        // Handlers reach the editor through the global reference, also at startup
+        crate::command::set_editor(self);
+        
+        let mut errors = Vec::new();
+        self.sourcing.push((file.clone(), 0));
+        for (line, command) in script_commands(&text) {
+            if let Some(current) = self.sourcing.last_mut() {
+                current.1 = line;
+            }
+            if let Err(err) = self.execute_ex_command(&command) {
+                errors.push(format!("Error detected while processing {} line {}: {}", file, line, err));
+            }
+        }
+        self.sourcing.pop();
+        
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(EditorError::Other(errors.join("\n")))
+        }
+    }
+    
+    /// Handle a key typed on the command line
+    ///
+    /// Enter runs the line, and Esc or Backspace on an empty line leaves it.
+    /// Up and Down show the lines of the history that start with the text
+    /// typed, from the search history when a pattern is typed. Tab and
+    /// Shift-Tab complete the word before the cursor.
+    fn command_line_key(&mut self, key: KeyEvent) -> EditorResult<()> {
+        use crossterm::event::{KeyCode, KeyModifiers};
+        
+        // Any other key than Tab ends completing
+        if !matches!(key.code, KeyCode::Tab | KeyCode::BackTab) {
+            self.completion = None;
+        }
+        
+        // Ctrl-R inserts the register typed next, or with Ctrl-W the word under the cursor
+        if std::mem::take(&mut self.command_register_pending) {
+            let text = match key.code {
+                KeyCode::Char('w') if key.modifiers.contains(KeyModifiers::CONTROL) => self.word_under_cursor(),
+                KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => self.register_text(c),
+                _ => None,
             };
+            if let Some(text) = text {
+                self.command_line.insert(&text);
+            }
+            return Ok(());
+        }
+        
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        let word = key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::SHIFT);
+        match key.code {
+            KeyCode::Enter => return self.finish_command_line(),
+            KeyCode::Tab => return self.complete_command_line(true),
+            KeyCode::BackTab => return self.complete_command_line(false),
+            KeyCode::Esc => self.leave_command_line(),
+            KeyCode::Char('c') if control => self.leave_command_line(),
+            KeyCode::Char('r') if control => self.command_register_pending = true,
+            KeyCode::Char('b') if control => self.command_line.move_start(),
+            KeyCode::Char('e') if control => self.command_line.move_end(),
+            KeyCode::Char('w') if control => self.command_line.delete_word_before(),
+            KeyCode::Char('u') if control => self.command_line.delete_to_start(),
+            KeyCode::Char('h') if control => self.command_line_backspace(),
+            KeyCode::Char(_) if control => {},
+            KeyCode::Char(c) => self.command_line.insert(c.encode_utf8(&mut [0; 4])),
+            KeyCode::Backspace => self.command_line_backspace(),
+            KeyCode::Delete => self.command_line.delete_at(),
+            KeyCode::Left if word => self.command_line.move_word_left(),
+            KeyCode::Right if word => self.command_line.move_word_right(),
+            KeyCode::Left => self.command_line.move_left(),
+            KeyCode::Right => self.command_line.move_right(),
+            KeyCode::Home => self.command_line.move_start(),
+            KeyCode::End => self.command_line.move_end(),
+            KeyCode::Up | KeyCode::Down => {
+                let history = if self.searching { self.search_state.history() } else { &self.command_history };
+                if key.code == KeyCode::Up {
+                    self.command_line.history_older(history);
+                } else {
+                    self.command_line.history_newer(history);
+                }
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+    
+    /// Complete the word before the cursor on the command line
+    ///
+    /// The first press gathers the matches, the next ones step through them
+    /// as 'wildmode' says. After a single match completing starts over, so
+    /// that Tab after a directory goes on with the files in it. Patterns
+    /// are not completed.
+    fn complete_command_line(&mut self, forward: bool) -> EditorResult<()> {
+        if self.searching {
+            return Ok(());
+        }
+        
+        if self.completion.is_none() {
+            let line: String = self.command_line.text().chars().take(self.command_line.cursor()).collect();
+            let request = completion_request(&line);
+            let matches = self.completion_matches(&request, &line)?;
+            if matches.is_empty() {
+                return Ok(());
+            }
+            let typed: String = line.chars().skip(request.start).collect();
+            self.completion = Some(Completion::new(request.start, &typed, matches));
+        }
+        
+        let context = OptionContext::default();
+        let modes: Vec<WildMode> = self.options.get_list("wildmode", context).iter()
+            .filter_map(|part| WildMode::parse(part))
+            .collect();
+        let wildmenu = self.options.get_bool("wildmenu", context);
+        if let Some(completion) = self.completion.as_mut() {
+            let text = completion.next(&modes, forward, wildmenu);
+            self.command_line.replace(completion.start(), &text);
+            if completion.matches().len() == 1 {
+                self.completion = None;
+            }
+        }
+        Ok(())
+    }
+    
+    /// Get the matches for the word to complete on the command line
+    ///
+    /// A plugin that registered a completer for the command is asked for
+    /// them, whatever the command is.
+    fn completion_matches(&mut self, request: &CompletionRequest, line: &str) -> EditorResult<Vec<String>> {
+        if let Some(plugin) = self.plugin_completers.get(&request.command).cloned() {
+            self.sync_plugin_context();
+            let result = self.plugin_manager.complete(&plugin, &request.command, &request.lead, line);
+            self.apply_plugin_context();
+            return result
+                .map(|matches| matches.iter().map(|name| escape_match(name)).collect())
+                .map_err(|err| EditorError::Other(format!("Plugin '{}' failed: {}", plugin, err)));
+        }
+        
+        // User commands complete their arguments as -complete says
+        let kind = match self.user_commands.get(&request.command).and_then(|command| command.complete.clone()) {
+            Some(kind) if request.kind == CompletionKind::None => kind,
+            _ => request.kind.clone(),
+        };
+        
+        let lead = request.lead.as_str();
+        let matches = match &kind {
+            CompletionKind::Command => filter_matches(self.ex_command_registry.command_names(), lead),
+            CompletionKind::File => complete_path(lead, false),
+            CompletionKind::Directory => complete_path(lead, true),
+            CompletionKind::Buffer => {
+                // Buffer names match anywhere, as with `:buffer`
+                let mut names: Vec<String> = self.buffer_manager.buffer_ids().into_iter()
+                    .filter_map(|id| self.buffer_manager.get_buffer(id).ok())
+                    .map(|buffer| buffer.name().to_string())
+                    .filter(|name| !name.is_empty() && name.contains(lead))
+                    .map(|name| escape_match(&name))
+                    .collect();
+                names.sort();
+                names.dedup();
+                names
+            },
+            CompletionKind::Option => {
+                let mut names = Vec::new();
+                for def in self.options.defs() {
+                    names.push(def.name.to_string());
+                    if def.option_type() == OptionType::Bool {
+                        for prefix in ["no", "inv"].into_iter().filter(|prefix| lead.starts_with(prefix)) {
+                            names.push(format!("{}{}", prefix, def.name));
+                        }
+                    }
+                }
+                filter_matches(names, lead)
+            },
+            CompletionKind::OptionValue(name) => {
+                // The current value, to edit it
+                match self.options.get(name, self.option_context()) {
+                    Some(OptionValue::Bool(_)) | None => Vec::new(),
+                    Some(value) => filter_matches([escape_match(&value.to_string())], lead),
+                }
+            },
+            CompletionKind::Help => {
+                let topics = crate::command::handlers::HELP_TOPICS.iter().map(|topic| topic.to_string());
+                filter_matches(topics.chain(self.ex_command_registry.command_names()), lead)
+            },
+            CompletionKind::Color => filter_matches(self.config_manager.color_scheme_names(), lead),
+            CompletionKind::None => Vec::new(),
+        };
+        Ok(matches)
+    }
+    
+    /// Delete the character before the cursor on the command line, leaving it when it is empty
+    fn command_line_backspace(&mut self) {
+        if self.command_line.is_empty() {
+            self.leave_command_line();
+        } else {
+            self.command_line.delete_before();
         }
+    }
+    
+    /// Leave the command line without running it
+    fn leave_command_line(&mut self) {
+        self.command_line.clear();
+        self.completion = None;
+        self.filter_range = None;
+        self.searching = false;
+        self.mode_manager.enter_normal_mode();
+    }
+    
+    /// Run the line typed on the command line
+    ///
+    /// The lines of a `!` operator are filtered through the command, a
+    /// pattern is searched for, and anything else is an Ex command, which
+    /// goes into the history.
+    fn finish_command_line(&mut self) -> EditorResult<()> {
+        let command_text = self.command_line.text().to_string();
+        self.command_line.clear();
+        self.completion = None;
+        self.mode_manager.enter_normal_mode();
         
-        // Not a search command, parse as an Ex command
+        let searching = std::mem::take(&mut self.searching);
+        match (self.filter_range.take(), command_text.strip_prefix('!')) {
+            (Some((start, end)), Some(command)) => self.filter_lines(start, end, command)?,
+            _ if searching => {
+                self.execute_search(&command_text)?;
+            },
+            _ if command_text.is_empty() => {},
+            _ => {
+                self.command_history.add(&command_text);
+                self.execute_ex_command(&command_text)?;
+            },
+        }
+        Ok(())
+    }
+    
+    /// Get the text of a register to insert on the command line
+    ///
+    /// Besides the registers kept by the register manager, `/` holds the
+    /// last search pattern, `:` the last command line and `%` and `#` the
+    /// names of the current and the alternate file. Line breaks become
+    /// spaces, as the command line holds a single line.
+    fn register_text(&self, register: char) -> Option<String> {
+        let text = match register {
+            '/' => self.search_state.pattern()?.to_string(),
+            ':' => self.command_history.latest()?.to_string(),
+            '%' => self.buffer_file_name(self.current_buffer_id())?,
+            '#' => self.buffer_file_name(self.buffer_manager.alternate_buffer_id())?,
+            _ => match self.register_manager.get_register_by_char(register)? {
+                RegisterContent::MacroRecording(_) => return None,
+                content => content.as_string(),
+            },
+        };
+        Some(text.replace('\n', " "))
+    }
+    
+    /// Get the name of the file of a buffer
+    fn buffer_file_name(&self, buffer_id: Option<usize>) -> Option<String> {
+        let buffer = self.buffer_manager.get_buffer(buffer_id?).ok()?;
+        buffer.file_path().map(|path| path.display().to_string())
+    }
+    
+    /// Get the word under the cursor, or the first word after it in the line
+    fn word_under_cursor(&self) -> Option<String> {
+        let buffer = self.buffer_manager.get_buffer(self.current_buffer_id()?).ok()?;
+        let position = self.cursor_manager.position();
+        let chars: Vec<char> = buffer.line(position.line).ok()?.chars().collect();
+        
+        let mut start = (position.column..chars.len()).find(|&i| self.is_word_char(chars[i]))?;
+        if start == position.column {
+            while start > 0 && self.is_word_char(chars[start - 1]) {
+                start -= 1;
+            }
+        }
+        let end = (start..chars.len()).find(|&i| !self.is_word_char(chars[i])).unwrap_or(chars.len());
+        Some(chars[start..end].iter().collect())
+    }
+    
+    /// Execute an Ex command
+    ///
+    /// A range without a command moves the cursor to the last line of the range.
+    pub fn execute_ex_command(&mut self, command_str: &str) -> EditorResult<()> {
         match self.command_parser.parse_ex(command_str) {
+            Ok(ex_cmd) if ex_cmd.name.is_empty() => {
+                let (_, line) = ex_cmd.range.lines(self)
+                    .map_err(|err| EditorError::Other(format!("Command error: {}", err)))?;
+                self.set_pcmark();
+                self.move_to_first_non_blank(line)
+            },
             Ok(ex_cmd) => {
                 // Execute the command using the ex command registry
                 match self.ex_command_registry.execute(&ex_cmd) {
@@ -1798,24 +2778,256 @@ impl Editor {
         }
     }
     
-    /// Find the next occurrence of a pattern
-    pub fn find_next(&mut self, pattern: &str, case_sensitive: bool) -> EditorResult<Option<(usize, usize, String)>> {
-        if let Some(buffer_id) = self.current_buffer_id() {
-            let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-            
-            // Get cursor position
-            let cursor_pos = self.cursor_manager.position();
-            
-            match buffer.find_next(pattern, cursor_pos.line, cursor_pos.column, case_sensitive) {
-                Ok(result) => {
-                    // Move cursor to the match position if found
-                    if let Some((line, column, _)) = result {
-                        let new_pos = crate::cursor::CursorPosition::new(line, column);
-                        self.cursor_manager.set_position(new_pos);
-                    }
-                    Ok(result)
-                },
-                Err(err) => Err(EditorError::Buffer(err.into())),
+    /// Get the last substitute
+    pub fn last_substitute(&self) -> Option<&Substitute> {
+        self.last_substitute.as_ref()
+    }
+    
+    /// Compile a pattern, with the case given or else with 'ignorecase' and 'smartcase'
+    ///
+    /// `~` in the pattern matches the replacement of the last substitute.
+    pub fn compile_pattern(&self, pattern: &str, ignore_case: Option<bool>) -> PatternResult<Pattern> {
+        let mut options = self.search_state.pattern_options();
+        if let Some(ignore_case) = ignore_case {
+            options.ignore_case = ignore_case;
+            options.smart_case = false;
+        }
+        options.previous_replacement = self.last_substitute.as_ref().map(|substitute| substitute.replacement.as_str());
+        Pattern::new(pattern, &options)
+    }
+    
+    /// Substitute in lines from start to end (inclusive)
+    ///
+    /// The substitute becomes the one `:&` repeats and its pattern the last
+    /// search pattern. The replacements are made as one change. With the `c`
+    /// flag this only asks about the first match, and the keys that answer
+    /// go through `process_key`, so it can't be used under `:global`.
+    pub fn substitute(&mut self, start: usize, end: usize, substitute: Substitute) -> EditorResult<()> {
+        let pattern = substitute.pattern.clone();
+        let flags = substitute.flags;
+        if flags.confirm && self.global_busy {
+            return Err(EditorError::Other("Cannot confirm substitutions under :global".to_string()));
+        }
+        let compiled = self.compile_pattern(&pattern, flags.ignore_case)
+            .map_err(|err| EditorError::Other(err.to_string()))?;
+        let replacement = Replacement::parse(&substitute.replacement);
+        self.search_state.set_pattern(pattern.clone());
+        self.last_substitute = Some(substitute);
+        
+        // Find the matches, and what each one is replaced with
+        let lines = self.buffer_lines(start, end)?;
+        let buffer_id = self.current_buffer_id().ok_or_else(|| EditorError::Other("No buffer".to_string()))?;
+        let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+        let cursor = self.cursor_manager.position();
+        let mut matches = Vec::new();
+        for (line, text) in lines.iter().enumerate() {
+            let context = buffer.match_context(start + line, Some(cursor));
+            for found in compiled.find_all(text, &context) {
+                matches.push(SubstituteMatch {
+                    line,
+                    start: found.start,
+                    end: found.end,
+                    text: replacement.expand(text, &found),
+                });
+                if !flags.global {
+                    break;
+                }
+            }
+        }
+        
+        if matches.is_empty() {
+            // Under :global a line without a match isn't an error either
+            return if flags.no_error || self.global_busy {
+                Ok(())
+            } else {
+                Err(EditorError::Other(format!("E486: Pattern not found: {}", pattern)))
+            };
+        }
+        
+        if flags.count_only {
+            let lines = count_lines(&matches);
+            let message = format!("{} match{} on {} line{}",
+                matches.len(),
+                if matches.len() == 1 { "" } else { "es" },
+                lines,
+                if lines == 1 { "" } else { "s" }
+            );
+            self.show_message(&message);
+            return Ok(());
+        }
+        
+        if flags.confirm {
+            let first = &matches[0];
+            self.cursor_manager.set_position(CursorPosition::new(start + first.line, first.start));
+            self.substitute_confirm = Some(SubstituteConfirm { start, end, lines, matches, index: 0, accepted: Vec::new() });
+            return Ok(());
+        }
+        
+        self.make_substitutions(start, end, lines, matches)
+    }
+    
+    /// Answer the question of a substitute with the `c` flag about a match
+    ///
+    /// `y` replaces the match and `n` skips it, `a` replaces it and every
+    /// match after it, `l` replaces it and stops, and `q` or Escape stop.
+    fn answer_substitute_confirm(&mut self, key: KeyEvent) -> EditorResult<()> {
+        use crossterm::event::{KeyCode, KeyModifiers};
+        
+        let confirm = match self.substitute_confirm.as_mut() {
+            Some(confirm) => confirm,
+            None => return Ok(()),
+        };
+        let count = confirm.matches.len();
+        
+        match key.code {
+            _ if key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => return Ok(()),
+            KeyCode::Char('y') => {
+                confirm.accepted.push(confirm.index);
+                confirm.index += 1;
+            },
+            KeyCode::Char('l') => {
+                confirm.accepted.push(confirm.index);
+                confirm.index = count;
+            },
+            KeyCode::Char('n') => confirm.index += 1,
+            KeyCode::Char('a') => {
+                confirm.accepted.extend(confirm.index..count);
+                confirm.index = count;
+            },
+            KeyCode::Char('q') | KeyCode::Esc => confirm.index = count,
+            _ => return Ok(()),
+        }
+        
+        if let Some(next) = confirm.matches.get(confirm.index) {
+            let position = CursorPosition::new(confirm.start + next.line, next.start);
+            self.cursor_manager.set_position(position);
+            return Ok(());
+        }
+        
+        let confirm = self.substitute_confirm.take().expect("confirm was checked above");
+        let SubstituteConfirm { start, end, lines, matches, accepted, .. } = confirm;
+        let matches: Vec<SubstituteMatch> = accepted.into_iter().map(|index| matches[index].clone()).collect();
+        if matches.is_empty() {
+            return Ok(());
+        }
+        self.make_substitutions(start, end, lines, matches)
+    }
+    
+    /// Replace matches in lines from start to end (inclusive) as one change
+    ///
+    /// The matches are in order, and the cursor goes to the last line changed.
+    fn make_substitutions(&mut self, start: usize, end: usize, mut lines: Vec<String>, matches: Vec<SubstituteMatch>) -> EditorResult<()> {
+        let count = matches.len();
+        let changed_lines = count_lines(&matches);
+        let last_line = matches.last().map_or(0, |found| found.line);
+        
+        // Replace from the end of each line, so the earlier offsets still hold
+        for found in matches.iter().rev() {
+            lines[found.line].replace_range(found.start..found.end, &found.text);
+        }
+        self.end_undo_step()?;
+        self.replace_lines(start, end, &lines)?;
+        self.end_undo_step()?;
+        
+        // Lines split by the replacements move the lines after them down
+        let cursor_line = start + last_line + lines[..=last_line].iter().map(|line| line.matches('\n').count()).sum::<usize>();
+        self.move_to_first_non_blank(cursor_line)?;
+        
+        let message = format!("{} substitution{} on {} line{}",
+            count,
+            if count == 1 { "" } else { "s" },
+            changed_lines,
+            if changed_lines == 1 { "" } else { "s" }
+        );
+        self.show_message(&message);
+        Ok(())
+    }
+    
+    /// Run an Ex command on each line from start to end (inclusive) that
+    /// matches a pattern, or that doesn't with `invert`, as `:global` does
+    ///
+    /// The lines are marked first, and the command then runs on each marked
+    /// line that is still there, with the cursor on it. All the changes
+    /// make one undo step. The first command that fails stops the rest.
+    pub fn global(&mut self, start: usize, end: usize, pattern: &str, invert: bool, command: &str) -> EditorResult<()> {
+        if self.global_busy {
+            return Err(EditorError::Other("E147: Cannot do :global recursive".to_string()));
+        }
+        let compiled = self.compile_pattern(pattern, None)
+            .map_err(|err| EditorError::Other(err.to_string()))?;
+        self.search_state.set_pattern(pattern.to_string());
+        
+        let buffer_id = self.current_buffer_id().ok_or_else(|| EditorError::Other("No buffer".to_string()))?;
+        let cursor = self.cursor_manager.position();
+        let buffer = self.buffer_manager.get_buffer_mut(buffer_id)?;
+        let mut lines = Vec::new();
+        for line in start..=end.min(buffer.line_count().saturating_sub(1)) {
+            let found = compiled.is_match(&buffer.line(line)?, &buffer.match_context(line, Some(cursor)));
+            if found != invert {
+                lines.push(line);
+            }
+        }
+        if lines.is_empty() {
+            let message = if invert {
+                format!("Pattern found in every line: {}", pattern)
+            } else {
+                format!("Pattern not found: {}", pattern)
+            };
+            self.show_message(&message);
+            return Ok(());
+        }
+        buffer.global_lines_mut().set(lines);
+        buffer.start_undo_join();
+        
+        self.global_busy = true;
+        self.set_pcmark();
+        let mut result = Ok(());
+        // The command may remove the buffer, which ends the loop early
+        while let Some(line) = self.buffer_manager.get_buffer_mut(buffer_id).ok()
+            .and_then(|buffer| buffer.global_lines_mut().take_first())
+        {
+            self.cursor_manager.set_position(CursorPosition::new(line, 0));
+            result = self.execute_ex_command(command);
+            if result.is_err() {
+                break;
+            }
+        }
+        self.global_busy = false;
+        
+        let buffer = match self.buffer_manager.get_buffer_mut(buffer_id) {
+            Ok(buffer) => buffer,
+            Err(_) => return result,
+        };
+        buffer.global_lines_mut().clear();
+        buffer.end_undo_join();
+        let last_line = buffer.line_count().saturating_sub(1);
+        
+        // The cursor stays on the line the last command left it on
+        if self.current_buffer_id() == Some(buffer_id) {
+            let line = self.cursor_manager.position().line.min(last_line);
+            self.move_to_first_non_blank(line)?;
+        }
+        result
+    }
+    
+    /// Find the next occurrence of a pattern
+    pub fn find_next(&mut self, pattern: &str, case_sensitive: bool) -> EditorResult<Option<(usize, usize, String)>> {
+        if let Some(buffer_id) = self.current_buffer_id() {
+            let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+            
+            // Get cursor position
+            let cursor_pos = self.cursor_manager.position();
+            
+            match buffer.find_next(pattern, cursor_pos.line, cursor_pos.column, case_sensitive) {
+                Ok(result) => {
+                    // Move cursor to the match position if found
+                    if let Some((line, column, _)) = result {
+                        let new_pos = crate::cursor::CursorPosition::new(line, column);
+                        self.cursor_manager.set_position(new_pos);
+                    }
+                    Ok(result)
+                },
+                Err(err) => Err(EditorError::Buffer(err.into())),
             }
         } else {
             Ok(None)
@@ -1960,6 +3172,7 @@ impl Editor {
                         // Store the text in the unnamed register
                         let register_content = RegisterContent::character_wise(&text);
                         self.register_manager.set_register(RegisterType::Unnamed, register_content);
+                        self.buffer_manager.get_buffer_mut(buffer_id)?.set_yank_marks(text_object.start, text_object.end);
                         
                         Ok(Some(text))
                     } else {
@@ -1975,19 +3188,175 @@ impl Editor {
     
     /// Set a mark at the current cursor position
     pub fn set_mark(&mut self, name: char) -> EditorResult<()> {
+        if !mark::is_user_mark(name) {
+            return Err(EditorError::Other("E191: Argument must be a letter or forward/backward quote".to_string()));
+        }
+        
         if let Some(buffer_id) = self.current_buffer_id() {
-            let buffer = self.buffer_manager.get_buffer_mut(buffer_id)?;
-            
             // Get cursor position
             let cursor_pos = self.cursor_manager.position();
             
+            let buffer = self.buffer_manager.get_buffer_mut(buffer_id)?;
             buffer.set_mark(name, cursor_pos.line, cursor_pos.column)?;
+            
+            if mark::is_file_mark(name) {
+                let file_path = buffer.file_path().map(Path::to_path_buf);
+                let mark = mark::Mark::new(cursor_pos.line, cursor_pos.column);
+                let previous = self.global_marks.set_mark(name, buffer_id, file_path.as_deref(), mark);
+                
+                // A file mark is only in one buffer at a time
+                if let Some(previous_id) = previous.and_then(|previous| previous.buffer_id) {
+                    if previous_id != buffer_id {
+                        if let Ok(previous_buffer) = self.buffer_manager.get_buffer_mut(previous_id) {
+                            previous_buffer.remove_mark(name);
+                        }
+                    }
+                }
+            }
             Ok(())
         } else {
             Err(EditorError::Other("No buffer selected".to_string()))
         }
     }
     
+    /// Remember the cursor position before a jump
+    ///
+    /// The position becomes the previous context mark (`''`) and is added to
+    /// the jump list of the current window.
+    pub fn set_pcmark(&mut self) {
+        if let Some(jump) = self.current_jump() {
+            if let Ok(buffer) = self.buffer_manager.get_buffer_mut(jump.buffer_id) {
+                let _ = buffer.set_mark(mark::PREVIOUS_CONTEXT_MARK, jump.mark.line, jump.mark.column);
+            }
+            if let Some(window) = self.terminal.current_window_mut() {
+                window.jumps.push(jump);
+            }
+        }
+    }
+    
+    /// Get the cursor position as a jump list entry
+    fn current_jump(&self) -> Option<Jump> {
+        let cursor_pos = self.cursor_manager.position();
+        self.current_buffer_id()
+            .map(|buffer_id| Jump::new(buffer_id, mark::Mark::new(cursor_pos.line, cursor_pos.column)))
+    }
+    
+    /// Go `count` entries back in the jump list, as Ctrl-O does
+    pub fn jump_older(&mut self, count: usize) -> EditorResult<bool> {
+        let current = match self.current_jump() {
+            Some(jump) => jump,
+            None => return Ok(false),
+        };
+        
+        match self.terminal.current_window_mut().and_then(|window| window.jumps.back(current, count)) {
+            Some(jump) => self.go_to_jump(jump),
+            None => Ok(false),
+        }
+    }
+    
+    /// Go `count` entries forward in the jump list, as Ctrl-I does
+    pub fn jump_newer(&mut self, count: usize) -> EditorResult<bool> {
+        match self.terminal.current_window_mut().and_then(|window| window.jumps.forward(count)) {
+            Some(jump) => self.go_to_jump(jump),
+            None => Ok(false),
+        }
+    }
+    
+    /// Move the cursor to a jump list entry, switching buffers if needed
+    fn go_to_jump(&mut self, jump: Jump) -> EditorResult<bool> {
+        // The buffer of the entry may be gone
+        if self.buffer_manager.get_buffer(jump.buffer_id).is_err() {
+            return Ok(false);
+        }
+        
+        if self.current_buffer_id() != Some(jump.buffer_id) {
+            self.switch_to_buffer(jump.buffer_id)?;
+        }
+        self.move_cursor_to_mark(jump.buffer_id, jump.mark)?;
+        Ok(true)
+    }
+    
+    /// Move the cursor to a position in a buffer, keeping it inside the text
+    ///
+    /// Jump list and change list entries don't know about lines deleted since.
+    fn move_cursor_to_mark(&mut self, buffer_id: usize, mark: mark::Mark) -> EditorResult<()> {
+        let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+        let line = mark.line.min(buffer.line_count().saturating_sub(1));
+        let column = mark.column.min(buffer.line_length(line)?);
+        self.cursor_manager.set_position(CursorPosition::new(line, column));
+        Ok(())
+    }
+    
+    /// Go `count` entries back in the change list, as `g;` does
+    pub fn change_older(&mut self, count: usize) -> EditorResult<()> {
+        self.move_in_change_list(count, false)
+    }
+    
+    /// Go `count` entries forward in the change list, as `g,` does
+    pub fn change_newer(&mut self, count: usize) -> EditorResult<()> {
+        self.move_in_change_list(count, true)
+    }
+    
+    fn move_in_change_list(&mut self, count: usize, newer: bool) -> EditorResult<()> {
+        let buffer_id = match self.current_buffer_id() {
+            Some(id) => id,
+            None => return Err(EditorError::Other("No buffer selected".to_string())),
+        };
+        
+        let change_list = self.buffer_manager.get_buffer_mut(buffer_id)?.change_list_mut();
+        if change_list.changes().is_empty() {
+            return Err(EditorError::Other("E664: changelist is empty".to_string()));
+        }
+        
+        let change = if newer { change_list.newer(count) } else { change_list.older(count) };
+        match change {
+            Some(mark) => self.move_cursor_to_mark(buffer_id, mark),
+            None if newer => Err(EditorError::Other("E663: At end of changelist".to_string())),
+            None => Err(EditorError::Other("E662: At start of changelist".to_string())),
+        }
+    }
+    
+    /// Move the cursor to a position as a jump, remembering where it was
+    fn jump_cursor(&mut self, position: CursorPosition) {
+        self.set_pcmark();
+        self.cursor_manager.set_position(position);
+    }
+    
+    /// Get a file mark, with its position brought up to date
+    ///
+    /// While the buffer of the mark is open the buffer has the current
+    /// position of the mark.
+    pub fn file_mark(&mut self, name: char) -> Option<FileMark> {
+        let buffer_id = self.global_marks.get_mark(name)?.buffer_id;
+        if let Some(buffer_id) = buffer_id {
+            match self.buffer_manager.get_buffer(buffer_id) {
+                Ok(buffer) => match buffer.get_mark(name) {
+                    Some(mark) => {
+                        let mark = *mark;
+                        self.global_marks.update_mark(name, mark);
+                    },
+                    // The lines of the mark were deleted
+                    None => {
+                        self.global_marks.remove_mark(name);
+                    },
+                },
+                Err(_) => self.global_marks.detach_mark(name),
+            }
+        }
+        
+        self.global_marks.get_mark(name).cloned()
+    }
+    
+    /// Make a buffer the one shown in the current window
+    fn switch_to_buffer(&mut self, buffer_id: usize) -> EditorResult<()> {
+        self.buffer_manager.set_current_buffer(buffer_id)?;
+        if let Some(window) = self.terminal.current_window_mut() {
+            window.buffer_id = buffer_id;
+        }
+        self.view_position = 0;
+        Ok(())
+    }
+    
     /// Get a mark by name
     pub fn get_mark(&self, name: char) -> EditorResult<Option<(usize, usize)>> {
         if let Some(buffer_id) = self.current_buffer_id() {
@@ -2004,22 +3373,46 @@ impl Editor {
     }
     
     /// Jump to a mark
+    ///
+    /// Jumping to a file mark switches to the buffer it is in, opening its
+    /// file again if the buffer is gone.
     pub fn jump_to_mark(&mut self, name: char) -> EditorResult<bool> {
-        if let Some(buffer_id) = self.current_buffer_id() {
-            let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-            
-            match buffer.jump_to_mark(name)? {
-                Some((line, column)) => {
-                    // Update cursor position
-                    let cursor_pos = crate::cursor::CursorPosition::new(line, column);
-                    self.cursor_manager.set_position(cursor_pos);
-                    Ok(true)
+        let current_id = match self.current_buffer_id() {
+            Some(id) => id,
+            None => return Err(EditorError::Other("No buffer selected".to_string())),
+        };
+        
+        let buffer_id = if mark::is_file_mark(name) {
+            match self.file_mark(name) {
+                Some(FileMark { buffer_id: Some(buffer_id), .. }) => buffer_id,
+                Some(FileMark { file_path: Some(file_path), .. }) => {
+                    self.open_file(&file_path)?;
+                    match self.current_buffer_id() {
+                        Some(id) => id,
+                        None => return Ok(false),
+                    }
                 },
-                None => Ok(false),
+                _ => return Ok(false),
             }
         } else {
-            Err(EditorError::Other("No buffer selected".to_string()))
+            current_id
+        };
+        
+        let (line, column) = match self.buffer_manager.get_buffer(buffer_id)?.jump_to_mark(name)? {
+            Some(position) => position,
+            None => return Ok(false),
+        };
+        
+        // Opening the file of a file mark already remembered the position
+        if self.current_buffer_id() == Some(current_id) {
+            self.set_pcmark();
+        }
+        if self.current_buffer_id() != Some(buffer_id) {
+            self.switch_to_buffer(buffer_id)?;
         }
+        
+        self.cursor_manager.set_position(CursorPosition::new(line, column));
+        Ok(true)
     }
     
     /// Delete a mark
@@ -2105,6 +3498,15 @@ impl Editor {
             self.add_key_mapping(mapping);
         }
         
+        // Options start out with the configured values
+        self.options.load_config(&self.config_manager.config().general);
+        self.options.take_changes();
+        let names: Vec<&'static str> = self.options.defs().iter().map(|def| def.name).collect();
+        for name in names {
+            self.apply_option(&OptionChange { name, scope: OptionScope::Global, id: None });
+        }
+        self.sync_local_options();
+        
         // TODO: Apply other configuration settings
         // For now, just set some basic settings
         
@@ -2113,6 +3515,248 @@ impl Editor {
         self.set_key_timeout(1000);
     }
     
+    /// Get the options
+    pub fn options(&self) -> &OptionRegistry {
+        &self.options
+    }
+    
+    /// Get the current window and buffer, whose local option values are used
+    pub fn option_context(&self) -> OptionContext {
+        OptionContext::new(self.terminal.current_window_id(), self.current_buffer_id())
+    }
+    
+    /// Run `:set`, `:setlocal` or `:setglobal` with the given arguments
+    ///
+    /// Returns the lines to show, such as the values asked for with `{option}?`.
+    pub fn execute_set(&mut self, command: SetCommand, args: &str) -> EditorResult<Vec<String>> {
+        self.store_buffer_options();
+        let context = self.option_context();
+        let result = self.options.execute(command, args, context);
+        
+        // Arguments before a bad one still take effect
+        self.apply_option_changes();
+        result.map_err(|err| EditorError::Other(err.to_string()))
+    }
+    
+    /// Pass changed option values on to the parts of the editor that use them
+    pub fn apply_option_changes(&mut self) {
+        for change in self.options.take_changes() {
+            self.apply_option(&change);
+        }
+        self.sync_local_options();
+    }
+    
+    /// Pass the value of a changed option on
+    fn apply_option(&mut self, change: &OptionChange) {
+        let global = OptionContext::default();
+        
+        match change.name {
+            "undofile" | "undodir" => {
+                let undo_dir = if self.options.get_bool("undofile", global) {
+                    match self.options.get_string("undodir", global) {
+                        "" => Some(crate::config::default_undo_dir()),
+                        dir => Some(PathBuf::from(dir)),
+                    }
+                } else {
+                    None
+                };
+                self.buffer_manager.set_undo_dir(undo_dir);
+            }
+            "swapfile" | "directory" => {
+                let enabled = self.options.get_bool("swapfile", global);
+                let dir = Some(self.options.get_string("directory", global))
+                    .filter(|dir| !dir.is_empty())
+                    .map(PathBuf::from);
+                self.buffer_manager.set_swap(enabled, dir);
+            }
+            "backup" | "writebackup" | "backupdir" | "backupext" => {
+                let write_options = WriteOptions {
+                    backup: self.options.get_bool("backup", global),
+                    writebackup: self.options.get_bool("writebackup", global),
+                    backup_dir: Some(self.options.get_string("backupdir", global))
+                        .filter(|dir| !dir.is_empty())
+                        .map(PathBuf::from),
+                    backup_ext: self.options.get_string("backupext", global).to_string(),
+                };
+                self.buffer_manager.set_write_options(write_options);
+            }
+            "ignorecase" | "smartcase" => {
+                self.search_state.set_case_sensitive(!self.options.get_bool("ignorecase", global));
+                self.search_state.set_smart_case(self.options.get_bool("smartcase", global));
+            }
+            "fileformat" | "fileencoding" | "bomb" | "readonly" => {
+                // These are kept by the buffer itself
+                let buffer_id = match (change.scope, change.id) {
+                    (OptionScope::Buffer, Some(id)) => id,
+                    _ => return,
+                };
+                let context = OptionContext::new(None, Some(buffer_id));
+                let fileformat = FileFormat::parse(self.options.get_string("fileformat", context));
+                let fileencoding = FileEncoding::parse(self.options.get_string("fileencoding", context));
+                let bomb = self.options.get_bool("bomb", context);
+                let readonly = self.options.get_bool("readonly", context);
+                
+                if let Ok(buffer) = self.buffer_manager.get_buffer_mut(buffer_id) {
+                    match change.name {
+                        "fileformat" => buffer.set_fileformat(fileformat.unwrap_or_default()),
+                        "fileencoding" => buffer.set_fileencoding(fileencoding.unwrap_or_default()),
+                        "bomb" => buffer.set_bomb(bomb),
+                        _ => {
+                            buffer.set_read_only(readonly);
+                            if !readonly {
+                                buffer.set_modifiable(true);
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    
+    /// Bring the window and buffer local settings up to date with the options
+    ///
+    /// Also run before rendering, as new windows and buffers start out with
+    /// the global values.
+    fn sync_local_options(&mut self) {
+        let options = &self.options;
+        for window in self.terminal.windows_mut() {
+            let context = OptionContext::new(Some(window.id), Some(window.buffer_id));
+            window.number = options.get_bool("number", context);
+        }
+        
+        let context = self.option_context();
+        self.insert_state.auto_indent = self.options.get_bool("autoindent", context);
+    }
+    
+    /// Record the options kept by the current buffer, so :set shows them as they are
+    fn store_buffer_options(&mut self) {
+        let context = self.option_context();
+        let buffer = match context.buffer.and_then(|id| self.buffer_manager.get_buffer(id).ok()) {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        
+        let values = [
+            ("fileformat", OptionValue::String(buffer.fileformat().name().to_string())),
+            ("fileencoding", OptionValue::String(buffer.fileencoding().name().to_string())),
+            ("bomb", OptionValue::Bool(buffer.bomb())),
+            ("readonly", OptionValue::Bool(buffer.is_read_only())),
+        ];
+        for (name, value) in values {
+            self.options.store_local(name, value, context);
+        }
+    }
+    
+    /// Check whether files changed on disk, as `:checktime` does
+    ///
+    /// Checks the given buffer, or all buffers. An unmodified buffer is reloaded
+    /// without asking if 'autoread' is set, otherwise the user chooses between
+    /// keeping the buffer and loading the file. Returns whether any buffer was
+    /// affected.
+    pub fn check_time(&mut self, buffer_id: Option<usize>) -> EditorResult<bool> {
+        self.last_checktime = Instant::now();
+        
+        let buffer_ids = match buffer_id {
+            Some(id) => vec![id],
+            None => self.buffer_manager.buffer_ids(),
+        };
+        let autoread = self.options.get_bool("autoread", OptionContext::default());
+        
+        let mut changed = false;
+        for id in buffer_ids {
+            let buffer = self.buffer_manager.get_buffer_mut(id)?;
+            match buffer.check_disk() {
+                DiskChange::Unchanged => {}
+                DiskChange::Deleted => {
+                    let message = format!("E211: File \"{}\" no longer available", buffer.name());
+                    self.show_message(&message);
+                    changed = true;
+                }
+                DiskChange::Changed => {
+                    let name = buffer.name().to_string();
+                    let modified = buffer.is_modified();
+                    let reload = (autoread && !modified) || self.ask_reload(&name, modified)?;
+                    
+                    let buffer = self.buffer_manager.get_buffer_mut(id)?;
+                    if reload {
+                        if let Err(err) = buffer.reload() {
+                            return Err(EditorError::Buffer(err.into()));
+                        }
+                    } else {
+                        buffer.keep_buffer_version();
+                    }
+                    changed = true;
+                }
+            }
+        }
+        
+        Ok(changed)
+    }
+    
+    /// Ask whether to load a file that changed on disk
+    ///
+    /// The warning is shown above the question, which waits for its answer.
+    fn ask_reload(&mut self, name: &str, modified: bool) -> EditorResult<bool> {
+        let warning = if modified {
+            format!("W12: Warning: File \"{}\" has changed and the buffer was changed in xvim as well", name)
+        } else {
+            format!("W11: Warning: File \"{}\" has changed since editing started", name)
+        };
+        self.show_message(&warning);
+        
+        // Nobody to ask, keep the buffer
+        if !self.running {
+            return Ok(false);
+        }
+        
+        self.question = Some("[O]K, (L)oad File: ".to_string());
+        let answer = self.render().and_then(|_| self.read_reload_answer());
+        self.question = None;
+        self.message = None;
+        answer
+    }
+    
+    /// Wait for the answer to the question of `ask_reload`
+    fn read_reload_answer(&mut self) -> EditorResult<bool> {
+        use crossterm::event::KeyCode;
+        
+        loop {
+            if let Some(key) = self.terminal.poll_key(1000)? {
+                match key.code {
+                    KeyCode::Char('l') | KeyCode::Char('L') => return Ok(true),
+                    KeyCode::Char('o') | KeyCode::Char('O') | KeyCode::Enter | KeyCode::Esc => return Ok(false),
+                    _ => {}
+                }
+            }
+        }
+    }
+    
+    /// Recover the unsaved changes of the current buffer from its swap file
+    pub fn recover(&mut self) -> EditorResult<bool> {
+        if let Some(buffer_id) = self.current_buffer_id() {
+            let buffer = self.buffer_manager.get_buffer_mut(buffer_id)?;
+            let result = match buffer.recover() {
+                Ok(result) => Ok(result),
+                Err(err) => Err(EditorError::Buffer(err.into())),
+            };
+            self.report_buffer_messages(buffer_id);
+            result
+        } else {
+            Ok(false)
+        }
+    }
+    
+    /// Show the warnings a buffer collected about its undo and swap files
+    fn report_buffer_messages(&mut self, buffer_id: usize) {
+        if let Ok(buffer) = self.buffer_manager.get_buffer_mut(buffer_id) {
+            let messages = buffer.take_messages();
+            if !messages.is_empty() {
+                self.show_message(&messages.join("\n"));
+            }
+        }
+    }
+    
     /// Get a reference to the configuration manager
     pub fn config_manager(&self) -> &ConfigManager {
         &self.config_manager
@@ -2140,21 +3784,18 @@ impl Editor {
         // Set the terminal UI reference for the plugin manager
         self.plugin_manager.set_terminal_ui(Arc::new(Mutex::new(self.terminal.clone())));
         
-        // Set the plugin context references
-        if let Ok(mut context) = self.plugin_manager.context().lock() {
-            context.set_buffer_manager(Arc::new(Mutex::new(self.buffer_manager.clone())));
-            context.set_mode_manager(Arc::new(Mutex::new(self.mode_manager.clone())));
-            context.set_command_registry(Arc::new(Mutex::new(self.ex_command_registry.clone())));
-        }
-        
         // Load the noxvim plugin
         let plugin_path = std::path::Path::new("plugins/noxvim.wasm");
         if plugin_path.exists() {
-            match self.plugin_manager.load_plugin(plugin_path, "noxvim") {
+            self.sync_plugin_context();
+            let result = self.plugin_manager.load_plugin(plugin_path, "noxvim");
+            self.apply_plugin_context();
+            
+            match result {
                 Ok(_) => {
                     println!("Loaded noxvim plugin");
                     
-                    // Register mock NoxChat command since the WASM runtime is just a placeholder
+                    // Register the noxvim commands with the command registry
                     self.register_noxvim_commands();
                 }
                 Err(err) => {
@@ -2165,17 +3806,215 @@ impl Editor {
             eprintln!("noxvim plugin not found at {}", plugin_path.display());
         }
     }
-    /// Register commands for the noxvim plugin
-    fn register_noxvim_commands(&mut self) {
-        // Create a new plugin manager instance
-        let mut new_plugin_manager = PluginManager::new();
+    /// Lend the editor state to the plugin context before running plugin code
+    ///
+    /// The managers are moved behind the shared handles rather than copied,
+    /// and `apply_plugin_context` takes them back once the plugin returns.
+    pub fn sync_plugin_context(&mut self) {
+        let context = self.plugin_manager.context();
+        if let Ok(mut context) = context.lock() {
+            context.set_buffer_manager(Arc::new(Mutex::new(std::mem::take(&mut self.buffer_manager))));
+            context.set_mode_manager(Arc::new(Mutex::new(std::mem::take(&mut self.mode_manager))));
+            context.set_cursor_manager(Arc::new(Mutex::new(std::mem::take(&mut self.cursor_manager))));
+            context.set_selection_manager(Arc::new(Mutex::new(std::mem::take(&mut self.selection_manager))));
+            
+            // Nothing has changed yet
+            context.take_changed();
+        };
+    }
+    
+    /// Take back the editor state lent to plugins and act on their requests
+    ///
+    /// The commands and completers plugins registered are defined, and the
+    /// command lines they issued run now that the editor has its state back.
+    pub fn apply_plugin_context(&mut self) {
+        let context = self.plugin_manager.context();
+        let mut context = match context.lock() {
+            Ok(context) => context,
+            Err(_) => return,
+        };
+        if let Some(buffer_manager) = take_lent(context.take_buffer_manager()) {
+            self.buffer_manager = buffer_manager;
+        }
+        if let Some(mode_manager) = take_lent(context.take_mode_manager()) {
+            self.mode_manager = mode_manager;
+        }
+        if let Some(cursor_manager) = take_lent(context.take_cursor_manager()) {
+            self.cursor_manager = cursor_manager;
+        }
+        if let Some(selection_manager) = take_lent(context.take_selection_manager()) {
+            self.selection_manager = selection_manager;
+        }
+        let (changed, commands, completers, command_lines, messages) = (
+            context.take_changed(),
+            context.take_plugin_commands(),
+            context.take_plugin_completers(),
+            context.take_command_lines(),
+            context.take_messages(),
+        );
+        drop(context);
         
-        // Initialize the plugin system
-        if let Err(err) = new_plugin_manager.init() {
-            eprintln!("Warning: Failed to initialize plugin system: {}", err);
+        // Edits made by plugins go to the swap file like any other
+        if changed {
+            self.swap_pending = true;
         }
         
-        // Set up the plugin context with the buffer manager
+        // Define the commands plugins added, replacing earlier definitions
+        for command in commands {
+            self.insert_user_command(UserCommand::for_plugin(&command.name, &command.plugin, &command.description));
+        }
+        
+        // Use the completers plugins added for the arguments of commands
+        for completer in completers {
+            self.plugin_completers.insert(completer.command, completer.plugin);
+        }
+        
+        // The last message plugins gave is the one left on the screen
+        if let Some(message) = messages.last() {
+            self.show_message(message);
+        }
+        
+        // Run the command lines plugins issued
+        for command_line in command_lines {
+            if let Err(err) = self.execute_ex_command(&command_line) {
+                log::warn!("Plugin command '{}' failed: {}", command_line, err);
+            }
+        }
+    }
+    
+    /// Define a user command
+    ///
+    /// A command of the same name is only replaced when `overwrite` is set,
+    /// as by `:command!`.
+    pub fn define_user_command(&mut self, mut command: UserCommand, overwrite: bool) -> EditorResult<()> {
+        if !overwrite && self.user_commands.contains_key(&command.name) {
+            return Err(EditorError::Other(format!("E174: Command already exists: add ! to replace it: {}", command.name)));
+        }
+        
+        // Commands defined by a script remember where
+        if command.source.is_none() {
+            command.source = self.sourcing.last().map(|(file, line)| format!("{} line {}", file, line));
+        }
+        
+        self.insert_user_command(command);
+        Ok(())
+    }
+    
+    /// Keep a user command and register it with the Ex commands
+    fn insert_user_command(&mut self, command: UserCommand) {
+        let name = command.name.clone();
+        self.ex_command_registry.register(&command.name, move |cmd| {
+            crate::command::handlers::run_user_command(&name, cmd)
+        });
+        self.user_commands.insert(command.name.clone(), command);
+    }
+    
+    /// Delete a user command
+    pub fn delete_user_command(&mut self, name: &str) -> EditorResult<()> {
+        if self.user_commands.remove(name).is_none() {
+            return Err(EditorError::Other(format!("E184: No such user-defined command: {}", name)));
+        }
+        
+        self.ex_command_registry.unregister(name);
+        Ok(())
+    }
+    
+    /// Get the user commands, sorted by name
+    pub fn user_commands(&self) -> impl Iterator<Item = &UserCommand> {
+        self.user_commands.values()
+    }
+    
+    /// Run a user command
+    ///
+    /// The bang, range, count and arguments are checked against the
+    /// attributes of the command. The replacement is then run with its
+    /// escapes expanded, or the arguments are passed to the plugin.
+    pub fn run_user_command(&mut self, name: &str, cmd: &ExCommand) -> EditorResult<()> {
+        let command = self.user_commands.get(name).cloned()
+            .ok_or_else(|| EditorError::Other(format!("E492: Not an editor command: {}", name)))?;
+        if cmd.flags.force && !command.bang {
+            return Err(EditorError::Other("E477: No ! allowed".to_string()));
+        }
+        
+        let ranged = !cmd.range.is_empty();
+        let current = self.cursor_manager.position().line + 1;
+        let mut args = cmd.arg_text.trim_end();
+        let (line1, line2, count) = match command.address {
+            Address::None if ranged => return Err(EditorError::Other("E481: No range allowed".to_string())),
+            Address::None => (current, current, 0),
+            Address::Range | Address::WholeFile if ranged => {
+                let (line1, line2) = cmd.range.resolve(self).map_err(|err| EditorError::Other(err.to_string()))?;
+                (line1, line2, line2)
+            },
+            Address::Range => (current, current, 0),
+            Address::WholeFile => (1, self.line_count(), 0),
+            Address::RangeCount(default) | Address::Count(default) => {
+                // A count may be past the last line, so a plain number is not resolved
+                let mut count = match (&cmd.range.start, &cmd.range.end) {
+                    (Some(RangeSpec::LineNumber(count)), None) => Some(*count),
+                    _ if ranged => Some(cmd.range.resolve(self).map_err(|err| EditorError::Other(err.to_string()))?.1),
+                    _ => None,
+                };
+                
+                // -count also takes the count as first argument
+                if count.is_none() && matches!(command.address, Address::Count(_)) {
+                    let (first, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+                    if let Ok(number) = first.parse::<usize>() {
+                        count = Some(number);
+                        args = rest.trim_start();
+                    }
+                }
+                
+                let count = count.unwrap_or(default);
+                (current, current, count)
+            },
+        };
+        command.nargs.check(args).map_err(|err| EditorError::Other(err.to_string()))?;
+        
+        match &command.action {
+            UserCommandAction::Replacement(_) => {
+                // A command that runs itself would otherwise never stop
+                if self.user_command_depth >= MAX_USER_COMMAND_DEPTH {
+                    return Err(EditorError::Other("E169: Command too recursive".to_string()));
+                }
+                let call = UserCommandCall { args, line1, line2, count, bang: cmd.flags.force };
+                self.user_command_depth += 1;
+                let result = self.execute_ex_command(&command.expand(&call));
+                self.user_command_depth -= 1;
+                result
+            },
+            UserCommandAction::Plugin { plugin, .. } => {
+                self.call_plugin_command(plugin, name, &cmd.args)
+            },
+        }
+    }
+    
+    /// Run a command handler provided by a plugin
+    pub fn call_plugin_command(&mut self, plugin: &str, command: &str, args: &[String]) -> EditorResult<()> {
+        let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+        
+        self.sync_plugin_context();
+        let result = self.plugin_manager.call_command(plugin, command, &args);
+        self.apply_plugin_context();
+        
+        match result {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(EditorError::Other(format!("Plugin '{}' did not handle command: {}", plugin, command))),
+            Err(err) => Err(EditorError::Other(format!("Plugin '{}' failed: {}", plugin, err))),
+        }
+    }
+    
+    /// Register commands for the noxvim plugin
+    fn register_noxvim_commands(&mut self) {
+        // Create a new plugin manager instance
+        let mut new_plugin_manager = PluginManager::new();
+        
+        // Initialize the plugin system
+        if let Err(err) = new_plugin_manager.init() {
+            eprintln!("Warning: Failed to initialize plugin system: {}", err);
+        }
+        
+        // Set up the plugin context with the buffer manager
         if let Ok(mut context) = new_plugin_manager.context().lock() {
             // Set the buffer manager in the context
             let buffer_manager = Arc::new(Mutex::new(self.buffer_manager.clone()));
@@ -2271,250 +4110,180 @@ impl Editor {
     
     /// Paste text from the unnamed register at the cursor position
     pub fn paste(&mut self) -> EditorResult<bool> {
-        self.paste_from_register(RegisterType::Unnamed)
+        self.put(None, false, 1)
+    }
+    
+    /// Paste text from the unnamed register before the cursor position
+    pub fn paste_before(&mut self) -> EditorResult<bool> {
+        self.put(None, true, 1)
     }
     
     /// Paste text from a register specified by character at the cursor position
     pub fn paste_from_register_char(&mut self, c: char) -> EditorResult<bool> {
-        if let Some(register_type) = RegisterType::from_char(c) {
-            self.paste_from_register(register_type)
-        } else {
-            Ok(false)
-        }
+        self.put(Some(c), false, 1)
     }
     
-    /// Handle inner text object (i + key)
-    fn handle_inner_text_object(&mut self, _key: KeyEvent) -> EditorResult<()> {
-        use crossterm::event::{KeyCode, KeyModifiers};
+    /// Put the text of a register `count` times after or before the cursor, as `p` and `P` do
+    ///
+    /// Without a register the unnamed register is used.
+    pub fn put(&mut self, register: Option<char>, before: bool, count: usize) -> EditorResult<bool> {
+        let register_type = match register {
+            Some(c) => match RegisterType::from_char(c) {
+                Some(register_type) => register_type,
+                None => return Ok(false),
+            },
+            None => RegisterType::Unnamed,
+        };
+        let buffer_id = match self.current_buffer_id() {
+            Some(id) => id,
+            None => return Ok(false),
+        };
         
-        // Get the next key
-        if let Some(key) = self.terminal.poll_key(1000)? {
-            match key.code {
-                KeyCode::Char('w') => {
-                    // Inner word
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::Word, false);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('W') => {
-                    // Inner WORD
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::BigWord, false);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('s') => {
-                    // Inner sentence
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::Sentence, false);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('p') => {
-                    // Inner paragraph
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::Paragraph, false);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('(') | KeyCode::Char(')') => {
-                    // Inner parenthesis block
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::ParenBlock, false);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('{') | KeyCode::Char('}') => {
-                    // Inner brace block
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::BraceBlock, false);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('[') | KeyCode::Char(']') => {
-                    // Inner bracket block
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::BracketBlock, false);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('<') | KeyCode::Char('>') => {
-                    // Inner angle block
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::AngleBlock, false);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('\'') => {
-                    // Inner single quote block
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::SingleQuoteBlock, false);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('"') => {
-                    // Inner double quote block
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::DoubleQuoteBlock, false);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('`') => {
-                    // Inner backtick block
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::BacktickBlock, false);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('t') => {
-                    // Inner tag block
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::TagBlock, false);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Esc => {
-                    // Cancel the operator
-                    self.operator_manager.cancel();
-                    self.mode_manager.enter_normal_mode();
-                },
-                _ => {
-                    // Unhandled key, cancel the operator
-                    self.operator_manager.cancel();
-                    self.mode_manager.enter_normal_mode();
-                }
+        let change = RepeatableChange::new(RepeatCommand::Put { before });
+        self.repeat_state.record(change.with_count(count).with_register(register));
+        
+        // Before the cursor: move cursor left, paste, then move cursor right
+        if before {
+            let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+            self.cursor_manager.move_cursor(Direction::Left, buffer)?;
+        }
+        
+        let mut pasted = false;
+        for _ in 0..count.max(1) {
+            pasted = self.paste_from_register(register_type)?;
+            if !pasted {
+                break;
             }
-        } else {
-            // Timeout, cancel the operator
-            self.operator_manager.cancel();
-            self.mode_manager.enter_normal_mode();
         }
         
-        Ok(())
+        if before {
+            let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+            self.cursor_manager.move_cursor(Direction::Right, buffer)?;
+        }
+        
+        Ok(pasted)
     }
     
-    /// Handle around text object (a + key)
-    fn handle_around_text_object(&mut self, _key: KeyEvent) -> EditorResult<()> {
-        use crossterm::event::{KeyCode, KeyModifiers};
+    /// Start insert mode with `i`, `o` or `O`
+    fn insert_command(&mut self, command: InsertCommand) -> EditorResult<()> {
+        let buffer_id = match self.current_buffer_id() {
+            Some(id) => id,
+            None => return Ok(()),
+        };
         
-        // Get the next key
-        if let Some(key) = self.terminal.poll_key(1000)? {
-            match key.code {
-                KeyCode::Char('w') => {
-                    // Around word
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::Word, true);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('W') => {
-                    // Around WORD
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::BigWord, true);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('s') => {
-                    // Around sentence
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::Sentence, true);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('p') => {
-                    // Around paragraph
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::Paragraph, true);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('(') | KeyCode::Char(')') => {
-                    // Around parenthesis block
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::ParenBlock, true);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('{') | KeyCode::Char('}') => {
-                    // Around brace block
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::BraceBlock, true);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('[') | KeyCode::Char(']') => {
-                    // Around bracket block
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::BracketBlock, true);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('<') | KeyCode::Char('>') => {
-                    // Around angle block
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::AngleBlock, true);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('\'') => {
-                    // Around single quote block
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::SingleQuoteBlock, true);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('"') => {
-                    // Around double quote block
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::DoubleQuoteBlock, true);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('`') => {
-                    // Around backtick block
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::BacktickBlock, true);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Char('t') => {
-                    // Around tag block
-                    let target = OperatorTarget::TextObject(TextObjectTypeExt::TagBlock, true);
-                    if let Some(completed_state) = self.operator_manager.set_target(target) {
-                        return self.execute_operator(completed_state);
-                    }
-                },
-                KeyCode::Esc => {
-                    // Cancel the operator
-                    self.operator_manager.cancel();
-                    self.mode_manager.enter_normal_mode();
-                },
-                _ => {
-                    // Unhandled key, cancel the operator
-                    self.operator_manager.cancel();
-                    self.mode_manager.enter_normal_mode();
-                }
-            }
-        } else {
-            // Timeout, cancel the operator
-            self.operator_manager.cancel();
-            self.mode_manager.enter_normal_mode();
+        if command != InsertCommand::Insert {
+            let buffer = self.buffer_manager.get_buffer_mut(buffer_id)?;
+            
+            // 'o' opens a line at the end of the current one, 'O' at its start
+            let direction = if command == InsertCommand::OpenBelow { Direction::LineEnd } else { Direction::LineStart };
+            self.cursor_manager.move_cursor(direction, buffer)?;
+            let cursor_pos = self.cursor_manager.position();
+            let cursor_position = buffer.position_to_char_idx(cursor_pos.line, cursor_pos.column)?;
+            
+            // Insert a newline at the cursor position
+            buffer.insert(cursor_position, "\n")?;
+            
+            // Move to the beginning of the new line
+            let new_position = if command == InsertCommand::OpenBelow { cursor_position + 1 } else { cursor_position };
+            let new_position = buffer.char_idx_to_position(new_position)?;
+            self.cursor_manager.set_position(new_position);
         }
         
-        Ok(())
+        self.repeat_state.record(RepeatableChange::new(RepeatCommand::Insert(command)));
+        self.start_insert_mode(false)
+    }
+    
+    /// Repeat the last change at the cursor, as `.` does
+    ///
+    /// A count replaces the count of the change, also for later repeats.
+    /// Returns false if there is no change to repeat.
+    pub fn repeat_last_change(&mut self, count: Option<usize>) -> EditorResult<bool> {
+        let mut change = match self.repeat_state.last_change() {
+            Some(change) => change.clone(),
+            None => return Ok(false),
+        };
+        if let Some(count) = count {
+            change.count = count;
+        }
+        let buffer_id = match self.current_buffer_id() {
+            Some(id) => id,
+            None => return Ok(false),
+        };
+        let cursor_pos = self.cursor_manager.position();
+        
+        match change.command.clone() {
+            RepeatCommand::Operator { operator, target } => {
+                // Lines and characters are counted from the cursor
+                let target = match target {
+                    // A new count is the number of lines or characters
+                    OperatorTarget::LineRange(start, end) => {
+                        let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+                        let lines = count.map_or(end - start, |count| count.max(1) - 1);
+                        let end = (cursor_pos.line + lines).min(buffer.line_count().saturating_sub(1));
+                        OperatorTarget::LineRange(cursor_pos.line, end)
+                    },
+                    OperatorTarget::CharRange(start, end) => {
+                        let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+                        let cursor_position = buffer.position_to_char_idx(cursor_pos.line, cursor_pos.column)?;
+                        let available = buffer.line_length(cursor_pos.line)?.saturating_sub(cursor_pos.column);
+                        let length = count.unwrap_or(end - start).min(available);
+                        OperatorTarget::CharRange(cursor_position, cursor_position + length)
+                    },
+                    target => target,
+                };
+                
+                let mut state = OperatorState::new(operator);
+                state.set_target(target);
+                state.set_count(change.count);
+                self.execute_operator(state)?;
+            },
+            RepeatCommand::Visual { operator, mode, lines, columns } => {
+                self.repeat_visual_operator(buffer_id, operator, mode, lines, columns)?;
+            },
+            RepeatCommand::Insert(command) => {
+                self.insert_command(command)?;
+                // The text is typed once for every count
+                change.inserted_text = change.inserted_text.repeat(change.count.max(1));
+            },
+            RepeatCommand::Put { before } => {
+                self.put(change.next_register(), before, change.count)?;
+            },
+        }
+        
+        // Type the text again, leaving insert mode completes the change
+        if change.starts_insert_mode() && self.current_mode() == crate::mode::Mode::Insert {
+            self.insert_text(&change.inserted_text)?;
+            self.end_insert_mode()?;
+        }
+        
+        Ok(true)
     }
     
-    /// Delete text from the cursor position to the result of a motion
-    pub fn delete_to_motion(&mut self, direction: Direction) -> EditorResult<bool> {
+    /// Repeat a visual mode operator on an area at the cursor the size of the last one
+    fn repeat_visual_operator(&mut self, buffer_id: usize, operator: Operator, mode: crate::visual::VisualMode, lines: usize, columns: usize) -> EditorResult<()> {
+        let start = self.cursor_manager.position();
+        let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+        
+        let end_line = (start.line + lines).min(buffer.line_count().saturating_sub(1));
+        let end_column = if lines == 0 { start.column + columns } else { columns };
+        let end = CursorPosition::new(end_line, end_column.min(buffer.line_length(end_line)?));
+        
+        self.start_visual_mode(mode)?;
+        self.cursor_manager.set_position(end);
+        self.selection_manager.set_end(end);
+        self.handle_visual_operator(operator)
+    }
+    
+    /// Delete text from the cursor position to the result of a motion repeated `count` times
+    pub fn delete_to_motion(&mut self, direction: Direction, count: usize) -> EditorResult<bool> {
         if let Some(buffer_id) = self.current_buffer_id() {
             let buffer = self.buffer_manager.get_buffer(buffer_id)?;
             
             // Get cursor position
             let start_pos = self.cursor_manager.position();
             
-            // Move cursor according to the motion
-            self.cursor_manager.move_cursor(direction, buffer)?;
+            // Move cursor according to the motion, count times
+            self.cursor_manager.move_cursor_count(direction, count, buffer)?;
             
             // Get new cursor position
             let end_pos = self.cursor_manager.position();
@@ -2541,9 +4310,13 @@ impl Editor {
             }
             
             let buffer = self.buffer_manager.get_buffer_mut(buffer_id)?;
-            // Delete the text
+            // Delete the text, leaving the cursor where it started
             match buffer.delete(start, end) {
-                Ok(_) => Ok(true),
+                Ok(_) => {
+                    let new_position = buffer.char_idx_to_position(start)?;
+                    self.cursor_manager.set_position(new_position);
+                    Ok(true)
+                },
                 Err(err) => Err(EditorError::Buffer(err.into())),
             }
         } else {
@@ -2551,16 +4324,16 @@ impl Editor {
         }
     }
     
-    /// Change text from the cursor position to the result of a motion
-    pub fn change_to_motion(&mut self, direction: Direction) -> EditorResult<bool> {
+    /// Change text from the cursor position to the result of a motion repeated `count` times
+    pub fn change_to_motion(&mut self, direction: Direction, count: usize) -> EditorResult<bool> {
         if let Some(buffer_id) = self.current_buffer_id() {
             let buffer = self.buffer_manager.get_buffer(buffer_id)?;
             
             // Get cursor position
             let start_pos = self.cursor_manager.position();
             
-            // Move cursor according to the motion
-            self.cursor_manager.move_cursor(direction, buffer)?;
+            // Move cursor according to the motion, count times
+            self.cursor_manager.move_cursor_count(direction, count, buffer)?;
             
             // Get new cursor position
             let end_pos = self.cursor_manager.position();
@@ -2590,61 +4363,566 @@ impl Editor {
             // Delete the text
             match buffer.delete(start, end) {
                 Ok(_) => {
+                    let new_position = buffer.char_idx_to_position(start)?;
+                    self.cursor_manager.set_position(new_position);
+                    
                     // Enter insert mode
                     self.start_insert_mode(false)?;
                     Ok(true)
                 },
-                Err(err) => Err(EditorError::Buffer(err.into())),
+                Err(err) => Err(EditorError::Buffer(err.into())),
+            }
+        } else {
+            Ok(false)
+        }
+    }
+    
+    /// Yank text from the cursor position to the result of a motion repeated `count` times
+    pub fn yank_to_motion(&mut self, direction: Direction, count: usize) -> EditorResult<Option<String>> {
+        if let Some(buffer_id) = self.current_buffer_id() {
+            let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+            
+            // Get cursor position
+            let start_pos = self.cursor_manager.position();
+            
+            // Move cursor according to the motion, count times
+            self.cursor_manager.move_cursor_count(direction, count, buffer)?;
+            
+            // Get new cursor position
+            let end_pos = self.cursor_manager.position();
+            
+            // Convert positions to character indices
+            let start_idx = buffer.position_to_char_idx(start_pos.line, start_pos.column)?;
+            let end_idx = buffer.position_to_char_idx(end_pos.line, end_pos.column)?;
+            
+            // Determine the range to yank
+            let (start, end) = if start_idx <= end_idx {
+                (start_idx, end_idx)
+            } else {
+                (end_idx, start_idx)
+            };
+            
+            // Get the text from the buffer
+            let content = buffer.content();
+            if start < content.len() && end <= content.len() {
+                let text = content[start..end].to_string();
+                
+                // Store the text in the unnamed register
+                let register_content = RegisterContent::character_wise(&text);
+                self.register_manager.set_register(RegisterType::Unnamed, register_content);
+                self.buffer_manager.get_buffer_mut(buffer_id)?.set_yank_marks(start, end);
+                
+                // Return cursor to original position
+                self.cursor_manager.set_position(start_pos);
+                
+                Ok(Some(text))
+            } else {
+                Ok(None)
+            }
+        } else {
+            Ok(None)
+        }
+    }
+    
+    /// Apply an operator to the characters from start to end (exclusive)
+    fn operate_on_chars(&mut self, operator: Operator, start: usize, end: usize) -> EditorResult<bool> {
+        let buffer_id = match self.current_buffer_id() {
+            Some(id) => id,
+            None => return Ok(false),
+        };
+        let buffer = self.buffer_manager.get_buffer_mut(buffer_id)?;
+        if start >= end {
+            return Ok(false);
+        }
+        
+        // Store the text in the unnamed register
+        let text = buffer.text_range(start, end)?;
+        self.register_manager.set_register(RegisterType::Unnamed, RegisterContent::character_wise(&text));
+        
+        match operator {
+            Operator::Delete | Operator::Change => {
+                buffer.delete(start, end)?;
+                let new_position = buffer.char_idx_to_position(start)?;
+                self.cursor_manager.set_position(new_position);
+                if operator == Operator::Change {
+                    self.start_insert_mode(false)?;
+                }
+            },
+            Operator::Yank => buffer.set_yank_marks(start, end),
+            _ => return Err(EditorError::Other(format!("Operator {:?} not implemented for characters", operator))),
+        }
+        
+        Ok(true)
+    }
+    
+    /// Resolve the text an operator target covers, repeating a motion `count` times
+    fn target_range(&mut self, target: &OperatorTarget, count: usize) -> EditorResult<Option<OperatorRange>> {
+        let buffer_id = match self.current_buffer_id() {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+        
+        let range = match *target {
+            OperatorTarget::Motion(direction) => {
+                // Move the cursor to find where the motion ends, then put it back
+                let start_pos = self.cursor_manager.position();
+                self.cursor_manager.move_cursor_count(direction, count, buffer)?;
+                let end_pos = self.cursor_manager.position();
+                self.cursor_manager.set_position(start_pos);
+                
+                let (first, last) = if (start_pos.line, start_pos.column) <= (end_pos.line, end_pos.column) {
+                    (start_pos, end_pos)
+                } else {
+                    (end_pos, start_pos)
+                };
+                if direction.is_linewise() {
+                    OperatorRange::Lines(first.line, last.line)
+                } else {
+                    let start = buffer.position_to_char_idx(first.line, first.column)?;
+                    let mut end = buffer.position_to_char_idx(last.line, last.column)?;
+                    // An inclusive motion takes the character it ends on too
+                    let moved = (start_pos.line, start_pos.column) != (end_pos.line, end_pos.column);
+                    if direction.is_inclusive() && moved && last.column < buffer.line_length(last.line)? {
+                        end += 1;
+                    }
+                    OperatorRange::Chars(start, end)
+                }
+            },
+            OperatorTarget::TextObject(object_type, include_delimiters) => {
+                let cursor_pos = self.cursor_manager.position();
+                let position = buffer.position_to_char_idx(cursor_pos.line, cursor_pos.column)?;
+                match crate::text_object::find_text_object(buffer, position, object_type, include_delimiters)? {
+                    Some(object) => OperatorRange::Chars(object.start, object.end),
+                    None => return Ok(None),
+                }
+            },
+            OperatorTarget::LineRange(start, end) => OperatorRange::Lines(start, end),
+            OperatorTarget::CharRange(start, end) => OperatorRange::Chars(start, end),
+        };
+        
+        Ok(Some(range))
+    }
+    
+    /// Resolve the text `cw` changes
+    ///
+    /// Like in Vim, on a word it changes to the end of the word rather than
+    /// to the start of the next one.
+    fn change_word_range(&mut self, count: usize) -> EditorResult<Option<OperatorRange>> {
+        let buffer_id = match self.current_buffer_id() {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+        let cursor_pos = self.cursor_manager.position();
+        let line = buffer.line(cursor_pos.line)?;
+        let mut chars = line.chars().skip(cursor_pos.column);
+        let class = |c: char| if c.is_whitespace() { 0 } else if c.is_alphanumeric() || c == '_' { 1 } else { 2 };
+        
+        match (chars.next(), chars.next()) {
+            (current, _) if current.is_none_or(char::is_whitespace) => {
+                self.target_range(&OperatorTarget::Motion(Direction::WordNext), count)
+            },
+            // On the last character of a word, that word is the first of the count
+            (Some(c), next) if next.is_none_or(|next| class(next) != class(c)) => {
+                if count > 1 {
+                    return self.target_range(&OperatorTarget::Motion(Direction::WordEnd), count - 1);
+                }
+                let start = buffer.position_to_char_idx(cursor_pos.line, cursor_pos.column)?;
+                Ok(Some(OperatorRange::Chars(start, start + 1)))
+            },
+            _ => self.target_range(&OperatorTarget::Motion(Direction::WordEnd), count),
+        }
+    }
+    
+    /// Apply an operator to a range of text
+    ///
+    /// `>` and `<` shift the lines by `levels` shiftwidths, the other
+    /// operators ignore it.
+    fn apply_operator(&mut self, operator: Operator, range: OperatorRange, levels: usize) -> EditorResult<()> {
+        let buffer_id = match self.current_buffer_id() {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        
+        // Operators that work on lines take every line the range touches
+        let lines = match range {
+            OperatorRange::Lines(start, end) => Some((start, end)),
+            OperatorRange::Chars(start, end) if operator.is_linewise() => {
+                let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+                let first = buffer.char_idx_to_position(start)?.line;
+                let last = buffer.char_idx_to_position(end.saturating_sub(1).max(start))?.line;
+                Some((first, last))
+            },
+            OperatorRange::Block { start_line, end_line, .. } if operator.is_linewise() => Some((start_line, end_line)),
+            _ => None,
+        };
+        
+        match (operator, range, lines) {
+            (Operator::Delete, _, Some((start, end))) => { self.delete_lines(start, end)?; },
+            (Operator::Change, _, Some((start, end))) => { self.change_lines(start, end)?; },
+            (Operator::Yank, _, Some((start, end))) => { self.yank_lines(start, end)?; },
+            (Operator::Delete | Operator::Change | Operator::Yank, OperatorRange::Chars(start, end), None) => {
+                self.operate_on_chars(operator, start, end)?;
+            },
+            (Operator::Indent | Operator::Outdent, _, Some((start, end))) => {
+                let options = self.indent_options();
+                let levels = if operator == Operator::Indent { levels as isize } else { -(levels as isize) };
+                let shifted: Vec<String> = self.buffer_lines(start, end)?
+                    .iter()
+                    .map(|line| shift_line(line, levels, &options))
+                    .collect();
+                self.replace_lines(start, end, &shifted)?;
+                self.move_to_first_non_blank(start)?;
+            },
+            (Operator::Reindent, _, Some((start, end))) => {
+                let options = self.indent_options();
+                let previous = self.previous_non_blank_line(start)?;
+                let reindented = reindent_lines(&self.buffer_lines(start, end)?, previous.as_deref(), &options);
+                self.replace_lines(start, end, &reindented)?;
+                self.move_to_first_non_blank(start)?;
+            },
+            (Operator::Format | Operator::FormatKeepCursor, _, Some((start, end))) => {
+                let cursor_pos = self.cursor_manager.position();
+                let width = match self.options.get_number("textwidth", self.option_context()) {
+                    // Like Vim, format to 79 columns without a textwidth
+                    0 => 79,
+                    width => width as usize,
+                };
+                let formatted = format_lines(&self.buffer_lines(start, end)?, width);
+                let last = start + formatted.len().saturating_sub(1);
+                self.replace_lines(start, end, &formatted)?;
+                
+                if operator == Operator::FormatKeepCursor {
+                    let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+                    let line = cursor_pos.line.min(buffer.line_count().saturating_sub(1));
+                    let column = cursor_pos.column.min(buffer.line_length(line)?);
+                    self.cursor_manager.set_position(CursorPosition::new(line, column));
+                } else {
+                    self.move_to_first_non_blank(last)?;
+                }
+            },
+            (Operator::Filter, _, Some((start, end))) => {
+                // The command is typed on the command line, see `filter_lines`
+                self.filter_range = Some((start, end));
+                self.start_command_line("!");
+            },
+            (Operator::ToUpper | Operator::ToLower | Operator::SwapCase, OperatorRange::Lines(start, end), _) => {
+                let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+                let start_idx = buffer.position_to_char_idx(start, 0)?;
+                let end_idx = buffer.position_to_char_idx(end, buffer.line_length(end)?)?;
+                self.apply_operator(operator, OperatorRange::Chars(start_idx, end_idx), levels)?;
+            },
+            (Operator::ToUpper | Operator::ToLower | Operator::SwapCase, OperatorRange::Chars(start, end), _) => {
+                let buffer = self.buffer_manager.get_buffer_mut(buffer_id)?;
+                let text = buffer.text_range(start, end)?;
+                let changed = change_case(&text, operator);
+                if changed != text {
+                    buffer.replace(start, end, &changed)?;
+                }
+                let position = buffer.char_idx_to_position(start)?;
+                self.cursor_manager.set_position(position);
+            },
+            (Operator::ToUpper | Operator::ToLower | Operator::SwapCase, OperatorRange::Block { start_line, end_line, start_column, end_column }, _) => {
+                let buffer = self.buffer_manager.get_buffer_mut(buffer_id)?;
+                for line in start_line..=end_line {
+                    let length = buffer.line_length(line)?;
+                    if start_column >= length {
+                        continue;
+                    }
+                    let start = buffer.position_to_char_idx(line, start_column)?;
+                    let end = buffer.position_to_char_idx(line, end_column.min(length))?;
+                    let text = buffer.text_range(start, end)?;
+                    let changed = change_case(&text, operator);
+                    if changed != text {
+                        buffer.replace(start, end, &changed)?;
+                    }
+                }
+                self.cursor_manager.set_position(CursorPosition::new(start_line, start_column));
+            },
+            _ => {
+                return Err(EditorError::Other(format!("Operator {:?} not implemented for {:?}", operator, range)));
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Get the indent settings of the current buffer
+    fn indent_options(&self) -> IndentOptions {
+        let context = self.option_context();
+        IndentOptions {
+            shiftwidth: self.options.get_number("shiftwidth", context) as usize,
+            tabstop: self.options.get_number("tabstop", context) as usize,
+            expandtab: self.options.get_bool("expandtab", context),
+        }
+    }
+    
+    /// Get the lines from start to end (inclusive) of the current buffer
+    fn buffer_lines(&self, start: usize, end: usize) -> EditorResult<Vec<String>> {
+        let buffer_id = match self.current_buffer_id() {
+            Some(id) => id,
+            None => return Ok(Vec::new()),
+        };
+        let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+        (start..=end).map(|line| Ok(buffer.line(line)?)).collect()
+    }
+    
+    /// Get the last line before `line` that is not blank, which sets the indent for `=`
+    fn previous_non_blank_line(&self, line: usize) -> EditorResult<Option<String>> {
+        if line == 0 {
+            return Ok(None);
+        }
+        Ok(self.buffer_lines(0, line - 1)?.into_iter().rev().find(|line| !line.trim().is_empty()))
+    }
+    
+    /// Replace lines from start to end (inclusive) with new lines, as one change
+    ///
+    /// Without new lines the old ones are deleted.
+    pub fn replace_lines(&mut self, start: usize, end: usize, lines: &[String]) -> EditorResult<()> {
+        let buffer_id = match self.current_buffer_id() {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let buffer = self.buffer_manager.get_buffer_mut(buffer_id)?;
+        let mut start_idx = buffer.position_to_char_idx(start, 0)?;
+        let mut end_idx = buffer.position_to_char_idx(end, buffer.line_length(end)?)?;
+        
+        if lines.is_empty() {
+            // Take a line break along, the one before the lines when they end the buffer
+            if end + 1 < buffer.line_count() {
+                end_idx += 1;
+            } else {
+                start_idx = start_idx.saturating_sub(1);
+            }
+        }
+        
+        let text = lines.join("\n");
+        if buffer.text_range(start_idx, end_idx)? != text {
+            buffer.replace(start_idx, end_idx, &text)?;
+        }
+        Ok(())
+    }
+    
+    /// Close the undo step being built in the current buffer
+    fn end_undo_step(&mut self) -> EditorResult<()> {
+        if let Some(buffer_id) = self.current_buffer_id() {
+            self.buffer_manager.get_buffer_mut(buffer_id)?.end_undo_step();
+        }
+        Ok(())
+    }
+    
+    /// Move the cursor to the first non-blank character of a line
+    fn move_to_first_non_blank(&mut self, line: usize) -> EditorResult<()> {
+        if let Some(buffer_id) = self.current_buffer_id() {
+            let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+            let line = line.min(buffer.line_count().saturating_sub(1));
+            self.cursor_manager.set_position(CursorPosition::new(line, 0));
+            self.cursor_manager.move_cursor(Direction::FirstNonWhitespace, buffer)?;
+        }
+        Ok(())
+    }
+    
+    /// Filter lines from start to end (inclusive) through a shell command
+    ///
+    /// The lines are replaced with what the command writes to its standard
+    /// output. When the command fails they are left as they were.
+    pub fn filter_lines(&mut self, start: usize, end: usize, command: &str) -> EditorResult<()> {
+        let command = self.expand_shell_command(command)?;
+        
+        // The empty line after a final line break is not filtered, so the break stays
+        let after_last_break = end > start && end + 1 == self.line_count()
+            && self.buffer_lines(end, end)?.iter().all(String::is_empty);
+        let end = if after_last_break { end - 1 } else { end };
+        let input = self.lines_input(start, end)?;
+        let output = self.run_shell(&command, Some(&input))?;
+        shell_status(output.status)?;
+        let lines: Vec<String> = String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect();
+        
+        self.end_undo_step()?;
+        self.replace_lines(start, end, &lines)?;
+        self.end_undo_step()?;
+        self.move_to_first_non_blank(start)
+    }
+    
+    /// Run a shell command and show what it writes, as `:!` does
+    pub fn shell_command(&mut self, command: &str) -> EditorResult<()> {
+        let command = self.expand_shell_command(command)?;
+        let output = self.run_shell(&command, None)?;
+        self.show_shell_output(&output);
+        shell_status(output.status)
+    }
+    
+    /// Pass lines from start to end (inclusive) to a shell command, as `:w !` does
+    ///
+    /// The buffer doesn't change and what the command writes is shown.
+    pub fn write_to_command(&mut self, start: usize, end: usize, command: &str) -> EditorResult<()> {
+        let command = self.expand_shell_command(command)?;
+        let input = self.lines_input(start, end)?;
+        let output = self.run_shell(&command, Some(&input))?;
+        self.show_shell_output(&output);
+        shell_status(output.status)
+    }
+    
+    /// Show what a shell command wrote to its standard output and then its standard error
+    fn show_shell_output(&mut self, output: &std::process::Output) {
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+        let text = text.trim_end_matches('\n');
+        if !text.is_empty() {
+            self.show_message(text);
+        }
+    }
+    
+    /// Put what a shell command writes below a line, as `:r !` does
+    ///
+    /// A line of `None` puts the output above the first line. Nothing is put
+    /// when the command fails. The cursor goes to the first line put.
+    pub fn read_command(&mut self, line: Option<usize>, command: &str) -> EditorResult<()> {
+        let command = self.expand_shell_command(command)?;
+        let output = self.run_shell(&command, None)?;
+        shell_status(output.status)?;
+        let lines: Vec<String> = String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect();
+        if lines.is_empty() {
+            return Ok(());
+        }
+        
+        self.end_undo_step()?;
+        self.put_lines(line, &lines)?;
+        self.end_undo_step()?;
+        self.move_to_first_non_blank(line.map_or(0, |line| line + 1))
+    }
+    
+    /// Expand `%`, `#` and `!` in a shell command
+    ///
+    /// `%` stands for the name of the current file, `#` for the alternate
+    /// file and `!` for the previous command. A backslash keeps any of them
+    /// as it is. The expanded command becomes the previous command.
+    pub fn expand_shell_command(&mut self, command: &str) -> EditorResult<String> {
+        let mut out = String::new();
+        let mut chars = command.chars();
+        
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(next @ ('%' | '#' | '!')) => out.push(next),
+                    Some(next) => {
+                        out.push('\\');
+                        out.push(next);
+                    },
+                    None => out.push('\\'),
+                },
+                '%' | '#' => {
+                    let buffer_id = if c == '%' {
+                        self.current_buffer_id()
+                    } else {
+                        self.buffer_manager.alternate_buffer_id()
+                    };
+                    match self.buffer_file_name(buffer_id) {
+                        Some(path) => out.push_str(&path),
+                        None if c == '#' && buffer_id.is_none() => {
+                            return Err(EditorError::Other("E194: No alternate file name to substitute for '#'".to_string()));
+                        },
+                        None => return Err(EditorError::Other("E499: Empty file name for '%' or '#'".to_string())),
+                    }
+                },
+                '!' => match &self.last_shell_command {
+                    Some(previous) => out.push_str(previous),
+                    None => return Err(EditorError::Other("E34: No previous command".to_string())),
+                },
+                c => out.push(c),
             }
-        } else {
-            Ok(false)
         }
+        
+        self.last_shell_command = Some(out.clone());
+        Ok(out)
+    }
+    
+    /// Run a command through 'shell' and 'shellcmdflag'
+    fn run_shell(&self, command: &str, input: Option<&str>) -> EditorResult<std::process::Output> {
+        let global = OptionContext::default();
+        run_shell_command(
+            self.options.get_string("shell", global),
+            self.options.get_string("shellcmdflag", global),
+            command,
+            input,
+        )
+    }
+    
+    /// Get lines from start to end (inclusive) as input for a command, each ending in a newline
+    fn lines_input(&self, start: usize, end: usize) -> EditorResult<String> {
+        Ok(self.buffer_lines(start, end)?
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect())
+    }
+    
+    /// Delete count characters under and after the cursor, as `x` does
+    ///
+    /// Only characters of the cursor line are deleted.
+    fn delete_chars(&mut self) -> EditorResult<bool> {
+        let buffer_id = match self.current_buffer_id() {
+            Some(id) => id,
+            None => return Ok(false),
+        };
+        let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+        let cursor_pos = self.cursor_manager.position();
+        let line_length = buffer.line_length(cursor_pos.line)?;
+        if cursor_pos.column >= line_length {
+            return Ok(false);
+        }
+        
+        let start = buffer.position_to_char_idx(cursor_pos.line, cursor_pos.column)?;
+        let end = start + self.count1().min(line_length - cursor_pos.column);
+        
+        let mut state = OperatorState::new(Operator::Delete);
+        state.set_target(OperatorTarget::CharRange(start, end));
+        state.set_count(self.count1());
+        self.execute_operator(state)?;
+        Ok(true)
+    }
+    
+    /// Swap the case of count characters from the cursor on, as `~` does
+    ///
+    /// Only characters of the cursor line change, and the cursor moves past them.
+    fn swap_case_chars(&mut self) -> EditorResult<()> {
+        let buffer_id = match self.current_buffer_id() {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+        let cursor_pos = self.cursor_manager.position();
+        let line_length = buffer.line_length(cursor_pos.line)?;
+        if cursor_pos.column >= line_length {
+            return Ok(());
+        }
+        
+        let count = self.count1().min(line_length - cursor_pos.column);
+        let start = buffer.position_to_char_idx(cursor_pos.line, cursor_pos.column)?;
+        
+        let mut state = OperatorState::new(Operator::SwapCase);
+        state.set_target(OperatorTarget::CharRange(start, start + count));
+        state.set_count(self.count1());
+        self.execute_operator(state)?;
+        
+        let column = (cursor_pos.column + count).min(line_length - 1);
+        self.cursor_manager.set_position(CursorPosition::new(cursor_pos.line, column));
+        Ok(())
     }
     
-    /// Yank text from the cursor position to the result of a motion
-    pub fn yank_to_motion(&mut self, direction: Direction) -> EditorResult<Option<String>> {
+    /// Go to the line of the count, or in the direction given without one, as `gg` and `G` do
+    fn goto_line(&mut self, default: Direction) -> EditorResult<()> {
         if let Some(buffer_id) = self.current_buffer_id() {
-            let buffer = self.buffer_manager.get_buffer(buffer_id)?;
-            
-            // Get cursor position
-            let start_pos = self.cursor_manager.position();
-            
-            // Move cursor according to the motion
-            self.cursor_manager.move_cursor(direction, buffer)?;
-            
-            // Get new cursor position
-            let end_pos = self.cursor_manager.position();
-            
-            // Convert positions to character indices
-            let start_idx = buffer.position_to_char_idx(start_pos.line, start_pos.column)?;
-            let end_idx = buffer.position_to_char_idx(end_pos.line, end_pos.column)?;
-            
-            // Determine the range to yank
-            let (start, end) = if start_idx <= end_idx {
-                (start_idx, end_idx)
-            } else {
-                (end_idx, start_idx)
+            let direction = match self.count {
+                Some(count) => Direction::LineNumber(count - 1),
+                None => default,
             };
             
-            // Get the text from the buffer
-            let content = buffer.content();
-            if start < content.len() && end <= content.len() {
-                let text = content[start..end].to_string();
-                
-                // Store the text in the unnamed register
-                let register_content = RegisterContent::character_wise(&text);
-                self.register_manager.set_register(RegisterType::Unnamed, register_content);
-                
-                // Return cursor to original position
-                self.cursor_manager.set_position(start_pos);
-                
-                Ok(Some(text))
-            } else {
-                Ok(None)
-            }
-        } else {
-            Ok(None)
+            self.set_pcmark();
+            let buffer = self.buffer_manager.get_buffer(buffer_id)?;
+            self.cursor_manager.move_cursor(direction, buffer)?;
         }
+        Ok(())
     }
     
     /// Delete lines from start to end (inclusive)
@@ -2752,6 +5030,7 @@ impl Editor {
                 let lines: Vec<&str> = text.lines().collect();
                 let register_content = RegisterContent::line_wise(&lines);
                 self.register_manager.set_register(RegisterType::Unnamed, register_content);
+                self.buffer_manager.get_buffer_mut(buffer_id)?.set_yank_marks(start_idx, end_idx);
                 
                 Ok(Some(text))
             } else {
@@ -2777,9 +5056,9 @@ impl Editor {
         self.macro_recorder.state()
     }
     
-    /// Play back a macro from a register
-    pub fn play_macro(&mut self, register: char) -> bool {
-        self.macro_player.start_playback(register)
+    /// Play back a macro from a register `count` times
+    pub fn play_macro(&mut self, register: char, count: usize) -> bool {
+        self.macro_player.start_playback(register, count)
     }
     
     /// Check if a macro is currently being played
@@ -2797,6 +5076,744 @@ mod tests {
         let editor = Editor::new();
         assert!(editor.is_ok());
     }
+    
+    fn editor_with_text(text: &str) -> Editor {
+        let mut editor = Editor::new().unwrap();
+        let buffer_id = editor.current_buffer_id().unwrap();
+        editor.get_buffer_manager_mut().get_buffer_mut(buffer_id).unwrap().insert(0, text).unwrap();
+        editor
+    }
+    
+    fn current_text(editor: &Editor) -> String {
+        let buffer_id = editor.current_buffer_id().unwrap();
+        editor.get_buffer_manager().get_buffer(buffer_id).unwrap().content()
+    }
+    
+    #[test]
+    fn test_repeat_operator() {
+        let mut editor = editor_with_text("one\ntwo\nthree\nfour\n");
+        assert!(!editor.repeat_last_change(None).unwrap());
+        
+        // dd on the first line, repeated on the line the cursor ends up on
+        let mut state = OperatorState::new(Operator::Delete);
+        state.set_target(OperatorTarget::LineRange(0, 0));
+        editor.execute_operator(state).unwrap();
+        assert_eq!(current_text(&editor), "two\nthree\nfour\n");
+        
+        editor.cursor_manager.set_position(CursorPosition::new(1, 0));
+        assert!(editor.repeat_last_change(None).unwrap());
+        assert_eq!(current_text(&editor), "two\nfour\n");
+        
+        // An operator that fails leaves the last change to repeat
+        let mut state = OperatorState::new(Operator::Delete);
+        state.set_target(OperatorTarget::LineRange(10, 12));
+        assert!(editor.execute_operator(state).is_err());
+        editor.cursor_manager.set_position(CursorPosition::new(0, 0));
+        assert!(editor.repeat_last_change(None).unwrap());
+        assert_eq!(current_text(&editor), "four\n");
+    }
+    
+    #[test]
+    fn test_repeat_insert() {
+        let mut editor = editor_with_text("one\ntwo");
+        
+        // o with some text typed, a typo included
+        editor.insert_command(InsertCommand::OpenBelow).unwrap();
+        editor.insert_text("nev").unwrap();
+        editor.delete_char_before_cursor().unwrap();
+        editor.insert_text("w").unwrap();
+        editor.end_insert_mode().unwrap();
+        assert_eq!(current_text(&editor), "one\nnew\ntwo");
+        
+        editor.cursor_manager.set_position(CursorPosition::new(2, 0));
+        editor.repeat_last_change(None).unwrap();
+        assert_eq!(current_text(&editor), "one\nnew\ntwo\nnew");
+        assert_eq!(editor.current_mode(), crate::mode::Mode::Normal);
+    }
+    
+    fn type_keys(editor: &mut Editor, keys: &str) {
+        use crossterm::event::{KeyCode, KeyModifiers};
+        for c in keys.chars() {
+            editor.process_key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)).unwrap();
+        }
+    }
+    
+    #[test]
+    fn test_count_motions() {
+        let mut editor = editor_with_text("one two three four\n1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11");
+        
+        type_keys(&mut editor, "3w");
+        assert_eq!(editor.cursor_position(), CursorPosition::new(0, 14));
+        type_keys(&mut editor, "5j");
+        assert_eq!(editor.cursor_position().line, 5);
+        type_keys(&mut editor, "10G");
+        assert_eq!(editor.cursor_position().line, 9);
+        type_keys(&mut editor, "3gg");
+        assert_eq!(editor.cursor_position().line, 2);
+        type_keys(&mut editor, "gg");
+        assert_eq!(editor.cursor_position().line, 0);
+        
+        // 0 after a count is part of it, and otherwise a motion
+        type_keys(&mut editor, "$0");
+        assert_eq!(editor.cursor_position().column, 0);
+        type_keys(&mut editor, "10j");
+        assert_eq!(editor.cursor_position().line, 10);
+    }
+    
+    #[test]
+    fn test_count_operators() {
+        let mut editor = editor_with_text("a b c d e f g h\n1\n2\n3\n4\n5\n6");
+        
+        // d3w deletes three words, 2d3w six
+        type_keys(&mut editor, "d3w");
+        assert_eq!(current_text(&editor), "d e f g h\n1\n2\n3\n4\n5\n6");
+        
+        // 4x deletes four characters, 3p puts them back three times
+        type_keys(&mut editor, "4x");
+        assert_eq!(current_text(&editor), "f g h\n1\n2\n3\n4\n5\n6");
+        type_keys(&mut editor, "3p");
+        assert_eq!(current_text(&editor), "d e d e d e f g h\n1\n2\n3\n4\n5\n6");
+        
+        // 2dd deletes two lines, and 3. repeats it on three
+        type_keys(&mut editor, "j2dd");
+        assert_eq!(current_text(&editor), "d e d e d e f g h\n3\n4\n5\n6");
+        type_keys(&mut editor, "3.");
+        assert_eq!(current_text(&editor), "d e d e d e f g h\n6");
+        
+        type_keys(&mut editor, "gg2d3w");
+        assert_eq!(current_text(&editor), "f g h\n6");
+    }
+    
+    #[test]
+    fn test_operator_motions() {
+        use crossterm::event::{KeyCode, KeyModifiers};
+        let mut editor = editor_with_text("foo bar baz\nx (a, b) y\nline3\nline4");
+        
+        // e is inclusive, cw changes to the end of the word
+        type_keys(&mut editor, "de");
+        assert_eq!(current_text(&editor), " bar baz\nx (a, b) y\nline3\nline4");
+        type_keys(&mut editor, "wcwqux");
+        editor.process_key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE)).unwrap();
+        assert_eq!(current_text(&editor), " qux baz\nx (a, b) y\nline3\nline4");
+        
+        // Text objects after i and a, f and t, and linewise j and G
+        type_keys(&mut editor, "j0wwdi(");
+        assert_eq!(current_text(&editor), " qux baz\nx () y\nline3\nline4");
+        type_keys(&mut editor, "0dt)");
+        assert_eq!(current_text(&editor), " qux baz\n) y\nline3\nline4");
+        type_keys(&mut editor, "dj");
+        assert_eq!(current_text(&editor), " qux baz\nline4");
+        type_keys(&mut editor, "ggdG");
+        assert_eq!(current_text(&editor), "");
+    }
+    
+    #[test]
+    fn test_shift_and_case_operators() {
+        let mut editor = editor_with_text("one\ntwo\nthree\nfour words here");
+        editor.execute_set(SetCommand::Set, "shiftwidth=2 expandtab").unwrap();
+        
+        // >> with a count shifts that many lines, < and > take any motion
+        type_keys(&mut editor, "2>>");
+        assert_eq!(current_text(&editor), "  one\n  two\nthree\nfour words here");
+        assert_eq!(editor.cursor_position(), CursorPosition::new(0, 2));
+        type_keys(&mut editor, "j<k");
+        assert_eq!(current_text(&editor), "one\ntwo\nthree\nfour words here");
+        type_keys(&mut editor, "3G>G");
+        assert_eq!(current_text(&editor), "one\ntwo\n  three\n  four words here");
+        
+        // gU, g~ and ~ with text objects, lines, motions and counts
+        type_keys(&mut editor, "gggUiwjg~~");
+        assert_eq!(current_text(&editor), "ONE\nTWO\n  three\n  four words here");
+        type_keys(&mut editor, "jjwgU2w3~");
+        assert_eq!(current_text(&editor), "ONE\nTWO\n  three\n  fouR WORDS here");
+        assert_eq!(editor.cursor_position(), CursorPosition::new(3, 5));
+    }
+    
+    #[test]
+    fn test_format_and_reindent_operators() {
+        let mut editor = editor_with_text("aaa bbb ccc ddd\neee\n\nif x {\nfoo();\n}");
+        editor.execute_set(SetCommand::Set, "textwidth=8 shiftwidth=4 expandtab").unwrap();
+        
+        // gq wraps to textwidth and leaves the cursor on the last line
+        type_keys(&mut editor, "gqj");
+        assert_eq!(current_text(&editor), "aaa bbb\nccc ddd\neee\n\nif x {\nfoo();\n}");
+        assert_eq!(editor.cursor_position().line, 2);
+        
+        // gw keeps the cursor where it is
+        type_keys(&mut editor, "gggwip");
+        assert_eq!(current_text(&editor), "aaa bbb\nccc ddd\neee\n\nif x {\nfoo();\n}");
+        assert_eq!(editor.cursor_position(), CursorPosition::new(0, 0));
+        
+        type_keys(&mut editor, "G=2k");
+        assert_eq!(current_text(&editor), "aaa bbb\nccc ddd\neee\n\nif x {\n    foo();\n}");
+        assert_eq!(editor.cursor_position(), CursorPosition::new(4, 0));
+    }
+    
+    #[test]
+    fn test_visual_operators() {
+        use crossterm::event::{KeyCode, KeyModifiers};
+        let mut editor = editor_with_text("hello world\nsecond line\nthird");
+        editor.execute_set(SetCommand::Set, "shiftwidth=2 expandtab").unwrap();
+        
+        // The end of a characterwise selection is part of it
+        type_keys(&mut editor, "veU");
+        assert_eq!(current_text(&editor), "HELLO world\nsecond line\nthird");
+        type_keys(&mut editor, "Vj>");
+        assert_eq!(current_text(&editor), "  HELLO world\n  second line\nthird");
+        type_keys(&mut editor, "Vu");
+        assert_eq!(current_text(&editor), "  hello world\n  second line\nthird");
+        
+        // A block changes the same columns on each line
+        type_keys(&mut editor, "gg0");
+        editor.process_key(KeyEvent::new(KeyCode::Char('v'), KeyModifiers::CONTROL)).unwrap();
+        type_keys(&mut editor, "jjlllg~");
+        assert_eq!(current_text(&editor), "  HEllo world\n  SEcond line\nTHIRd");
+    }
+    
+    #[test]
+    fn test_visual_counts() {
+        let mut editor = editor_with_text("a\nb\nc\nd\ne\nf g h i");
+        editor.execute_set(SetCommand::Set, "shiftwidth=2 expandtab").unwrap();
+        
+        // 3j extends the selection three lines, and 2> shifts it twice
+        type_keys(&mut editor, "V3j");
+        assert_eq!(editor.cursor_position().line, 3);
+        type_keys(&mut editor, "2>");
+        assert_eq!(current_text(&editor), "    a\n    b\n    c\n    d\ne\nf g h i");
+        
+        // Word motions and G take a count too
+        type_keys(&mut editor, "G0v2wU");
+        assert_eq!(current_text(&editor), "    a\n    b\n    c\n    d\ne\nF G H i");
+        type_keys(&mut editor, "ggV2G<");
+        assert_eq!(current_text(&editor), "  a\n  b\n    c\n    d\ne\nF G H i");
+    }
+    
+    #[test]
+    fn test_command_line_ranges() {
+        use crossterm::event::{KeyCode, KeyModifiers};
+        use crate::mode::Mode;
+        
+        let mut editor = editor_with_text("one\nfoo\nthree\nfoo four\nfive");
+        let enter = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+        let esc = KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE);
+        
+        // A count before ':' and ':' in visual mode start with a range
+        type_keys(&mut editor, "3:");
+        assert_eq!(editor.command_buffer(), ".,.+2");
+        editor.process_key(esc).unwrap();
+        type_keys(&mut editor, "Vj:");
+        assert_eq!(editor.command_buffer(), "'<,'>");
+        assert_eq!(editor.current_mode(), Mode::Command);
+        editor.process_key(esc).unwrap();
+        
+        // A range alone moves the cursor to its last line
+        type_keys(&mut editor, ":$-1");
+        editor.process_key(enter).unwrap();
+        assert_eq!(editor.cursor_position().line, 3);
+        type_keys(&mut editor, ":?foo?");
+        editor.process_key(enter).unwrap();
+        assert_eq!(editor.cursor_position().line, 1);
+        type_keys(&mut editor, ":'<;+2");
+        editor.process_key(enter).unwrap();
+        assert_eq!(editor.cursor_position().line, 2);
+        
+        // A search from the command line is still a search
+        type_keys(&mut editor, "/four");
+        editor.process_key(enter).unwrap();
+        assert_eq!(editor.cursor_position().line, 3);
+        
+        type_keys(&mut editor, ":9");
+        assert_eq!(editor.process_key(enter).unwrap_err().to_string(), "Command error: E16: Invalid range");
+    }
+    
+    #[test]
+    fn test_command_line_editing() {
+        use crossterm::event::{KeyCode, KeyModifiers};
+        use crate::mode::Mode;
+        
+        let mut editor = editor_with_text("alpha beta\ngamma");
+        let key = |editor: &mut Editor, code| editor.process_key(KeyEvent::new(code, KeyModifiers::NONE)).unwrap();
+        let ctrl = |editor: &mut Editor, c| editor.process_key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)).unwrap();
+        
+        // Text goes in at the cursor
+        type_keys(&mut editor, ":ab");
+        ctrl(&mut editor, 'b');
+        type_keys(&mut editor, "x");
+        ctrl(&mut editor, 'e');
+        key(&mut editor, KeyCode::Left);
+        type_keys(&mut editor, "y");
+        assert_eq!(editor.command_buffer(), "xayb");
+        assert_eq!(editor.command_line.cursor(), 3);
+        key(&mut editor, KeyCode::Delete);
+        key(&mut editor, KeyCode::Home);
+        key(&mut editor, KeyCode::Delete);
+        assert_eq!(editor.command_buffer(), "ay");
+        
+        // Ctrl-W deletes a word, Ctrl-U everything before the cursor
+        key(&mut editor, KeyCode::End);
+        type_keys(&mut editor, " one two");
+        ctrl(&mut editor, 'w');
+        assert_eq!(editor.command_buffer(), "ay one ");
+        ctrl(&mut editor, 'u');
+        assert_eq!(editor.command_buffer(), "");
+        
+        // Backspace on an empty line leaves it
+        key(&mut editor, KeyCode::Backspace);
+        assert_eq!(editor.current_mode(), Mode::Normal);
+        
+        // Ex commands and patterns have histories of their own
+        for command in [":2", ":1"] {
+            type_keys(&mut editor, command);
+            key(&mut editor, KeyCode::Enter);
+        }
+        type_keys(&mut editor, "/gam");
+        key(&mut editor, KeyCode::Enter);
+        assert_eq!(editor.cursor_position().line, 1);
+        
+        type_keys(&mut editor, ":");
+        key(&mut editor, KeyCode::Up);
+        assert_eq!(editor.command_buffer(), "1");
+        key(&mut editor, KeyCode::Up);
+        assert_eq!(editor.command_buffer(), "2");
+        key(&mut editor, KeyCode::Up);
+        assert_eq!(editor.command_buffer(), "2");
+        key(&mut editor, KeyCode::Down);
+        key(&mut editor, KeyCode::Down);
+        assert_eq!(editor.command_buffer(), "");
+        type_keys(&mut editor, "2");
+        key(&mut editor, KeyCode::Up);
+        assert_eq!(editor.command_buffer(), "2");
+        key(&mut editor, KeyCode::Esc);
+        
+        type_keys(&mut editor, "/");
+        assert_eq!(editor.command_prompt(), '/');
+        key(&mut editor, KeyCode::Up);
+        assert_eq!(editor.command_buffer(), "gam");
+        key(&mut editor, KeyCode::Esc);
+        
+        // Ctrl-R inserts a register, or the word under the cursor
+        editor.register_manager.set_register_by_char('a', RegisterContent::line_wise(&["x", "y"]));
+        type_keys(&mut editor, ":s/");
+        ctrl(&mut editor, 'r');
+        ctrl(&mut editor, 'w');
+        type_keys(&mut editor, "/");
+        ctrl(&mut editor, 'r');
+        type_keys(&mut editor, "/");
+        ctrl(&mut editor, 'r');
+        type_keys(&mut editor, "a");
+        ctrl(&mut editor, 'r');
+        type_keys(&mut editor, ":");
+        assert_eq!(editor.command_buffer(), "s/gamma/gamx y1");
+        ctrl(&mut editor, 'r');
+        type_keys(&mut editor, "q");
+        assert_eq!(editor.command_buffer(), "s/gamma/gamx y1");
+        assert_eq!(editor.current_mode(), Mode::Command);
+    }
+    
+    #[test]
+    fn test_command_line_completion() {
+        use crossterm::event::{KeyCode, KeyModifiers};
+        
+        let mut editor = editor_with_text("text");
+        let key = |editor: &mut Editor, code| editor.process_key(KeyEvent::new(code, KeyModifiers::NONE)).unwrap();
+        let listed = |editor: &Editor| editor.completion.as_ref().is_some_and(|completion| completion.is_listed());
+        
+        // Command names cycle back to the text typed
+        type_keys(&mut editor, ":tabn");
+        key(&mut editor, KeyCode::Tab);
+        assert_eq!(editor.command_buffer(), "tabnew");
+        assert!(listed(&editor));
+        key(&mut editor, KeyCode::Tab);
+        assert_eq!(editor.command_buffer(), "tabnext");
+        key(&mut editor, KeyCode::Tab);
+        assert_eq!(editor.command_buffer(), "tabn");
+        key(&mut editor, KeyCode::BackTab);
+        assert_eq!(editor.command_buffer(), "tabnext");
+        key(&mut editor, KeyCode::Esc);
+        
+        // A directory completes to the files in it on the next Tab
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join("src").join("buffer.rs"), "").unwrap();
+        let base = dir.path().display().to_string();
+        type_keys(&mut editor, &format!(":e {}/s", base));
+        key(&mut editor, KeyCode::Tab);
+        assert_eq!(editor.command_buffer(), format!("e {}/src/", base));
+        key(&mut editor, KeyCode::Tab);
+        assert_eq!(editor.command_buffer(), format!("e {}/src/buffer.rs", base));
+        key(&mut editor, KeyCode::Esc);
+        
+        // Option names follow 'wildmode', values complete to the current one
+        editor.execute_set(SetCommand::Set, "wildmode=longest,list").unwrap();
+        type_keys(&mut editor, ":set she");
+        key(&mut editor, KeyCode::Tab);
+        assert_eq!(editor.command_buffer(), "set shell");
+        assert!(!listed(&editor));
+        key(&mut editor, KeyCode::Tab);
+        assert_eq!(editor.command_buffer(), "set shell");
+        assert!(listed(&editor));
+        type_keys(&mut editor, " ts=");
+        assert!(editor.completion.is_none());
+        key(&mut editor, KeyCode::Tab);
+        assert_eq!(editor.command_buffer(), "set shell ts=4");
+        key(&mut editor, KeyCode::Esc);
+        
+        // Patterns are not completed
+        type_keys(&mut editor, "/tabn");
+        key(&mut editor, KeyCode::Tab);
+        assert_eq!(editor.command_buffer(), "tabn");
+    }
+    
+    #[test]
+    fn test_user_commands() {
+        use crossterm::event::{KeyCode, KeyModifiers};
+        
+        let mut editor = editor_with_text("one\ntwo\nthree\nfour\nfive");
+        for definition in ["-range Go <line1>", "-range=% Last <line2>", "-count=3 Down <count>", "Plain 1"] {
+            editor.define_user_command(UserCommand::parse(definition).unwrap(), false).unwrap();
+        }
+        let run = |editor: &mut Editor, line: &str| {
+            let cmd = editor.command_parser.parse_ex(line).unwrap();
+            editor.run_user_command(&cmd.name.clone(), &cmd)
+        };
+        
+        // Ranges and counts expand in the replacement
+        run(&mut editor, "2,3Go").unwrap();
+        assert_eq!(editor.cursor_position().line, 1);
+        run(&mut editor, "Last").unwrap();
+        assert_eq!(editor.cursor_position().line, 4);
+        run(&mut editor, "Down").unwrap();
+        assert_eq!(editor.cursor_position().line, 2);
+        run(&mut editor, "Down 2").unwrap();
+        assert_eq!(editor.cursor_position().line, 1);
+        run(&mut editor, "9Down").unwrap_err();
+        
+        // The attributes are checked before running
+        assert!(run(&mut editor, "Go!").unwrap_err().to_string().contains("E477"));
+        assert!(run(&mut editor, "2Plain").unwrap_err().to_string().contains("E481"));
+        assert!(run(&mut editor, "Plain x").unwrap_err().to_string().contains("E488"));
+        let again = UserCommand::parse("-range Go 1").unwrap();
+        assert!(editor.define_user_command(again.clone(), false).unwrap_err().to_string().contains("E174"));
+        editor.define_user_command(again, true).unwrap();
+        run(&mut editor, "3,4Go").unwrap();
+        assert_eq!(editor.cursor_position().line, 0);
+        
+        // Deleting a command unregisters it
+        editor.delete_user_command("Go").unwrap();
+        assert!(editor.delete_user_command("Go").unwrap_err().to_string().contains("E184"));
+        assert!(editor.execute_ex_command("Go").is_err());
+        let names: Vec<&str> = editor.user_commands().map(|command| command.name.as_str()).collect();
+        assert_eq!(names, ["Down", "Last", "Plain"]);
+        
+        // -complete chooses what the arguments complete to
+        editor.define_user_command(UserCommand::parse("-nargs=1 -complete=option Opt set <args>").unwrap(), false).unwrap();
+        type_keys(&mut editor, ":Opt wildm");
+        editor.process_key(KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE)).unwrap();
+        assert_eq!(editor.command_buffer(), "Opt wildmenu");
+    }
+    
+    #[test]
+    fn test_check_time_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        std::fs::write(&path, "one").unwrap();
+        let mut editor = Editor::new().unwrap();
+        editor.open_file(&path).unwrap();
+        
+        // Without anybody to ask the buffer is kept, and the warning shown
+        std::fs::write(&path, "two").unwrap();
+        assert!(editor.check_time(None).unwrap());
+        assert!(editor.message().unwrap().starts_with("W11:"), "{:?}", editor.message());
+        assert_eq!(current_text(&editor), "one");
+        
+        std::fs::remove_file(&path).unwrap();
+        assert!(editor.check_time(None).unwrap());
+        assert!(editor.message().unwrap().starts_with("E211:"), "{:?}", editor.message());
+    }
+    
+    #[test]
+    fn test_swap_file_warning_is_shown() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        std::fs::write(&path, "text").unwrap();
+        let mut other = crate::buffer::Buffer::from_file(1, &path).unwrap();
+        other.open_swap_file(None);
+        
+        let mut editor = Editor::new().unwrap();
+        editor.open_file(&path).unwrap();
+        assert!(editor.message().unwrap().starts_with("E325:"), "{:?}", editor.message());
+    }
+    
+    #[test]
+    fn test_messages_of_several_lines() {
+        use crossterm::event::{KeyCode, KeyModifiers};
+        let mut editor = editor_with_text("one\ntwo\nthree");
+        let enter = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+        
+        // Enter only dismisses a message of several lines
+        editor.show_message("first\nsecond");
+        editor.process_key(enter).unwrap();
+        assert_eq!(editor.message(), None);
+        assert_eq!(editor.cursor_position().line, 0);
+        
+        // Other keys are commands
+        editor.show_message("first\nsecond");
+        type_keys(&mut editor, "j");
+        assert_eq!(editor.cursor_position().line, 1);
+        assert_eq!(editor.message(), None);
+    }
+    
+    #[test]
+    fn test_plugin_edits_and_command_lines() {
+        use crossterm::event::{KeyCode, KeyModifiers};
+        use std::io::Write;
+        
+        // A plugin command that inserts a line and then issues a command line
+        let mut file = tempfile::Builder::new().suffix(".wat").tempfile().unwrap();
+        file.write_all(br#"
+            (module
+              (import "xvim" "register_command" (func $register_command (param i32 i32 i32 i32) (result i32)))
+              (import "xvim" "get_current_buffer_id" (func $buffer (result i64)))
+              (import "xvim" "insert_text" (func $insert_text (param i64 i64 i32 i32) (result i32)))
+              (import "xvim" "execute_command" (func $execute_command (param i32 i32) (result i32)))
+              (import "xvim" "editor_message" (func $editor_message (param i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "Hello")
+              (data (i32.const 16) "Hello\n")
+              (data (i32.const 32) ":2")
+              (data (i32.const 48) "Inserted")
+              (func (export "init") (result i32)
+                (call $register_command (i32.const 0) (i32.const 5) (i32.const 0) (i32.const 0)))
+              (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+              (func (export "handle_command") (param i32 i32) (result i32)
+                (drop (call $insert_text (call $buffer) (i64.const 0) (i32.const 16) (i32.const 6)))
+                (drop (call $execute_command (i32.const 32) (i32.const 2)))
+                (drop (call $editor_message (i32.const 48) (i32.const 8)))
+                (i32.const 1)))
+        "#).unwrap();
+        
+        let mut editor = editor_with_text("world");
+        editor.sync_plugin_context();
+        editor.plugin_manager.load_plugin(file.path(), "hello").unwrap();
+        editor.apply_plugin_context();
+        assert!(editor.user_commands().any(|command| command.name == "Hello"));
+        
+        // The command line runs after the edit is back in the editor's buffer
+        editor.call_plugin_command("hello", "Hello", &[]).unwrap();
+        assert_eq!(current_text(&editor), "Hello\nworld");
+        assert_eq!(editor.cursor_position().line, 1);
+        
+        // Messages stay on the last line until the next key
+        assert_eq!(editor.message(), Some("Inserted"));
+        editor.process_key(KeyEvent::new(KeyCode::Char('k'), KeyModifiers::NONE)).unwrap();
+        assert_eq!(editor.message(), None);
+        
+        // The editor keeps its state between plugin calls
+        let context = editor.plugin_manager.context();
+        assert!(context.lock().unwrap().buffer_manager().is_none());
+    }
+    
+#[test]
+    fn test_substitute_confirm() {
+        use crossterm::event::{KeyCode, KeyModifiers};
+        use crate::search::SubstituteFlags;
+        
+        let mut editor = editor_with_text("a a\nb\na a a");
+        let flags = SubstituteFlags { global: true, confirm: true, ..Default::default() };
+        let substitute = Substitute { pattern: "a".to_string(), replacement: "<&>".to_string(), flags };
+        editor.substitute(0, 2, substitute.clone()).unwrap();
+        assert_eq!(editor.cursor_position().line, 0);
+        
+        // Nothing changes until the last answer, and then it all changes at once
+        type_keys(&mut editor, "ynx");
+        assert_eq!(editor.cursor_position().line, 2);
+        assert_eq!(editor.cursor_position().column, 0);
+        assert_eq!(current_text(&editor), "a a\nb\na a a");
+        type_keys(&mut editor, "l");
+        assert_eq!(current_text(&editor), "<a> a\nb\n<a> a a");
+        assert_eq!(editor.cursor_position().line, 2);
+        assert!(editor.undo().unwrap());
+        assert_eq!(current_text(&editor), "a a\nb\na a a");
+        
+        editor.substitute(0, 2, substitute.clone()).unwrap();
+        type_keys(&mut editor, "na");
+        assert_eq!(current_text(&editor), "a <a>\nb\n<a> <a> <a>");
+        
+        editor.substitute(0, 2, substitute).unwrap();
+        editor.process_key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE)).unwrap();
+        assert_eq!(current_text(&editor), "a <a>\nb\n<a> <a> <a>");
+        
+        // Keys go back to normal mode, with the cursor on the match asked about
+        type_keys(&mut editor, "x");
+        assert_eq!(current_text(&editor), " <a>\nb\n<a> <a> <a>");
+    }
+    
+    #[test]
+    fn test_vim_patterns() {
+        use crossterm::event::{KeyCode, KeyModifiers};
+        use crate::search::SubstituteFlags;
+        let enter = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+        let mut editor = editor_with_text("let a = 1\nlet ab = 2\nlet b = 3");
+        
+        // Searches, addresses and substitutes all take Vim's syntax
+        type_keys(&mut editor, "/\\<ab\\>");
+        editor.process_key(enter).unwrap();
+        assert_eq!(editor.cursor_position(), CursorPosition::new(1, 4));
+        type_keys(&mut editor, ":/\\Cb\\s/");
+        editor.process_key(enter).unwrap();
+        assert_eq!(editor.cursor_position().line, 2);
+        let substitute = |pattern: &str, replacement: &str| Substitute {
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            flags: SubstituteFlags { global: true, ..Default::default() },
+        };
+        editor.substitute(0, 2, substitute("\\v(\\w+) \\= \\zs(\\d)", "\\2\\1")).unwrap();
+        assert_eq!(current_text(&editor), "let a = 1a\nlet ab = 2ab\nlet b = 3b");
+        
+        // \%V only matches in the area gv would reselect
+        type_keys(&mut editor, "ggjvj");
+        editor.process_key(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE)).unwrap();
+        editor.substitute(0, 2, substitute("\\%V\\a", "_")).unwrap();
+        assert_eq!(current_text(&editor), "let a = 1a\n___ __ = 2__\n_et b = 3b");
+    }
+    
+    #[test]
+    fn test_substitute_line_breaks() {
+        use crate::search::SubstituteFlags;
+        
+        let mut editor = editor_with_text("a,b\nc\nd,e");
+        let flags = SubstituteFlags { global: true, ..Default::default() };
+        let substitute = Substitute { pattern: ",".to_string(), replacement: "\\r".to_string(), flags };
+        editor.substitute(0, 2, substitute).unwrap();
+        assert_eq!(current_text(&editor), "a\nb\nc\nd\ne");
+        assert_eq!(editor.cursor_position().line, 4);
+        assert!(editor.undo().unwrap());
+        assert_eq!(current_text(&editor), "a,b\nc\nd,e");
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn test_filter_operator() {
+        use crossterm::event::{KeyCode, KeyModifiers};
+        let mut editor = editor_with_text("b\na\nc\nlast");
+        
+        // ! takes the command on the command line
+        type_keys(&mut editor, "!2jsort");
+        assert_eq!(editor.current_mode(), crate::mode::Mode::Command);
+        editor.process_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE)).unwrap();
+        assert_eq!(current_text(&editor), "a\nb\nc\nlast");
+        
+        // A command that fails leaves the lines alone
+        type_keys(&mut editor, "!!exit 3");
+        let result = editor.process_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE));
+        assert_eq!(result.unwrap_err().to_string(), "shell returned 3");
+        assert_eq!(current_text(&editor), "a\nb\nc\nlast");
+        
+        editor.filter_lines(3, 3, "tr a-z A-Z").unwrap();
+        assert_eq!(current_text(&editor), "a\nb\nc\nLAST");
+    }
+    
+    #[test]
+    fn test_expand_shell_command() {
+        let dir = std::env::temp_dir().join(format!("xvim-shell-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (first, second) = (dir.join("first.txt"), dir.join("second.txt"));
+        std::fs::write(&first, "one\n").unwrap();
+        std::fs::write(&second, "two\n").unwrap();
+        
+        let mut editor = editor_with_text("text");
+        assert!(editor.expand_shell_command("wc %").unwrap_err().to_string().contains("E499"));
+        assert!(editor.expand_shell_command("wc #").unwrap_err().to_string().contains("E194"));
+        assert!(editor.expand_shell_command("!").unwrap_err().to_string().contains("E34"));
+        assert_eq!(editor.expand_shell_command("echo 100\\% \\# \\!").unwrap(), "echo 100% # !");
+        assert_eq!(editor.expand_shell_command("! again").unwrap(), "echo 100% # ! again");
+        
+        editor.open_file(&first).unwrap();
+        assert!(editor.expand_shell_command("diff % #").unwrap_err().to_string().contains("E499"));
+        editor.open_file(&second).unwrap();
+        assert_eq!(
+            editor.expand_shell_command("diff % #").unwrap(),
+            format!("diff {} {}", second.display(), first.display())
+        );
+        
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn test_shell_commands() {
+        let mut editor = editor_with_text("one\ntwo");
+        
+        editor.read_command(Some(0), "printf 'a\\nb\\n'").unwrap();
+        assert_eq!(current_text(&editor), "one\na\nb\ntwo");
+        assert_eq!(editor.cursor_position().line, 1);
+        editor.read_command(None, "echo first").unwrap();
+        assert_eq!(current_text(&editor), "first\none\na\nb\ntwo");
+        
+        // A failing command puts nothing and reports its exit status
+        let err = editor.read_command(Some(0), "echo lost; exit 2").unwrap_err();
+        assert_eq!(err.to_string(), "shell returned 2");
+        assert_eq!(current_text(&editor), "first\none\na\nb\ntwo");
+        editor.write_to_command(1, 3, "grep -q zzz").unwrap_err();
+        editor.write_to_command(1, 3, "grep -q b").unwrap();
+        
+        // Commands run through 'shell' and 'shellcmdflag'
+        editor.execute_set(SetCommand::Set, "shellcmdflag=-ec").unwrap();
+        editor.filter_lines(0, 4, "false; sort").unwrap_err();
+        assert_eq!(current_text(&editor), "first\none\na\nb\ntwo");
+        editor.execute_set(SetCommand::Set, "shell=xvim-no-such-shell").unwrap();
+        let err = editor.shell_command("true").unwrap_err();
+        assert!(err.to_string().starts_with("E282: Cannot execute shell xvim-no-such-shell"));
+    }
+}
+
+// Resolve Ex command addresses against the current buffer
+impl AddressContext for Editor {
+    fn current_line(&self) -> usize {
+        self.cursor_manager.position().line + 1
+    }
+    
+    fn line_count(&self) -> usize {
+        self.current_buffer_id()
+            .and_then(|buffer_id| self.buffer_manager.get_buffer(buffer_id).ok())
+            .map_or(0, |buffer| buffer.line_count())
+    }
+    
+    fn mark_line(&self, mark: char) -> Option<usize> {
+        self.get_mark(mark).ok().flatten().map(|(line, _)| line + 1)
+    }
+    
+    fn find_line(&self, pattern: &str, line: usize, backward: bool) -> ExCommandResult<Option<usize>> {
+        let buffer = match self.current_buffer_id().and_then(|buffer_id| self.buffer_manager.get_buffer(buffer_id).ok()) {
+            Some(buffer) => buffer,
+            None => return Ok(None),
+        };
+        let compiled = self.compile_pattern(pattern, None)
+            .map_err(|err| ExCommandError::Other(err.to_string()))?;
+        let cursor = self.cursor_manager.position();
+        
+        // Start from the line after the address, or before it, going around the end
+        let count = buffer.line_count();
+        for step in 1..=count {
+            let index = if backward {
+                (line + 2 * count - 1 - step) % count
+            } else {
+                (line + step - 1) % count
+            };
+            if compiled.is_match(&buffer.line(index)?, &buffer.match_context(index, Some(cursor))) {
+                return Ok(Some(index + 1));
+            }
+        }
+        Ok(None)
+    }
+    
+    fn last_search_pattern(&self) -> Option<String> {
+        self.search_state.pattern().map(str::to_string)
+    }
+    
+    fn last_substitute_pattern(&self) -> Option<String> {
+        self.last_substitute.as_ref().map(|substitute| substitute.pattern.clone())
+    }
 }
 
 // Implement SearchFunctions for Editor
@@ -2805,14 +5822,9 @@ impl SearchFunctions for Editor {
         // Set the search direction
         self.search_state.set_direction(direction);
         
-        // Enter command mode
-        self.mode_manager.enter_command_mode();
-        
-        // Set the command buffer to the search prefix
-        self.command_buffer = match direction {
-            SearchDirection::Forward => "/".to_string(),
-            SearchDirection::Backward => "?".to_string(),
-        };
+        // Enter command mode, for the pattern to be typed after the `/` or `?`
+        self.searching = true;
+        self.start_command_line("");
         
         Ok(())
     }
@@ -2835,15 +5847,16 @@ impl SearchFunctions for Editor {
         // Get the search direction
         let direction = self.search_state.direction();
         
-        // Get the case sensitivity
-        let case_sensitive = self.search_state.case_sensitive();
+        // Compile the pattern, with 'ignorecase' and 'smartcase'
+        let compiled = self.compile_pattern(pattern, None)
+            .map_err(|err| EditorError::Other(err.to_string()))?;
         
         // Get the current buffer
         if let Some(buffer_id) = self.current_buffer_id() {
             let buffer = self.buffer_manager.get_buffer(buffer_id)?;
             
             // Perform the search
-            let results = buffer.search(pattern, case_sensitive)?;
+            let results = buffer.search_pattern(&compiled, Some(self.cursor_position()))?;
             
             // Store the results
             self.search_state.set_results(results);
@@ -2873,13 +5886,13 @@ impl SearchFunctions for Editor {
                 if let Some((line, column, _)) = self.search_state.next_result() {
                     // Move the cursor to the match position
                     let new_pos = CursorPosition::new(line, column);
-                    self.cursor_manager.set_position(new_pos);
+                    self.jump_cursor(new_pos);
                     return Ok(true);
                 } else if !self.search_state.results().is_empty() {
                     // If we have results but couldn't get the next one, try the first one
                     let (line, column, _) = self.search_state.results()[0];
                     let new_pos = CursorPosition::new(line, column);
-                    self.cursor_manager.set_position(new_pos);
+                    self.jump_cursor(new_pos);
                     self.search_state.set_current_result_index(Some(0));
                     return Ok(true);
                 }
@@ -2902,14 +5915,14 @@ impl SearchFunctions for Editor {
                 if let Some((line, column, _)) = self.search_state.prev_result() {
                     // Move the cursor to the match position
                     let new_pos = CursorPosition::new(line, column);
-                    self.cursor_manager.set_position(new_pos);
+                    self.jump_cursor(new_pos);
                     return Ok(true);
                 } else if !self.search_state.results().is_empty() {
                     // If we have results but couldn't get the previous one, try the last one
                     let last_index = self.search_state.results().len() - 1;
                     let (line, column, _) = self.search_state.results()[last_index];
                     let new_pos = CursorPosition::new(line, column);
-                    self.cursor_manager.set_position(new_pos);
+                    self.jump_cursor(new_pos);
                     self.search_state.set_current_result_index(Some(last_index));
                     return Ok(true);
                 }
@@ -2956,6 +5969,19 @@ impl VisualFunctions for Editor {
         if let Some(buffer_id) = self.current_buffer_id() {
             let visual_area = self.visual_state().save_visual_area(buffer_id, self.cursor_position());
             if let Ok(buffer) = self.buffer_manager.get_buffer_mut(buffer_id) {
+                // '< and '> mark the selection, whichever way it was made
+                let (mut start, mut end) = if (visual_area.start.line, visual_area.start.column) <= (visual_area.end.line, visual_area.end.column) {
+                    (visual_area.start, visual_area.end)
+                } else {
+                    (visual_area.end, visual_area.start)
+                };
+                if visual_area.mode == crate::visual::VisualMode::Line {
+                    start.column = 0;
+                    end.column = buffer.line_length(end.line).unwrap_or(0);
+                }
+                let _ = buffer.set_mark(mark::VISUAL_START_MARK, start.line, start.column);
+                let _ = buffer.set_mark(mark::VISUAL_END_MARK, end.line, end.column);
+                
                 buffer.set_visual_area(visual_area);
             }
         }
@@ -3080,6 +6106,18 @@ impl InsertFunctions for Editor {
     }
     
     fn end_insert_mode(&mut self) -> EditorResult<()> {
+        // Remember where insert mode was stopped for '^ and gi
+        if let Some(buffer_id) = self.current_buffer_id() {
+            let cursor_pos = self.cursor_manager.position();
+            if let Ok(buffer) = self.buffer_manager.get_buffer_mut(buffer_id) {
+                let _ = buffer.set_mark(mark::LAST_INSERT_MARK, cursor_pos.line, cursor_pos.column);
+            }
+        }
+        
+        // A change that went on in insert mode is complete now
+        let inserted_text = self.insert_state().inserted_text.clone();
+        self.repeat_state.finish_insert(&inserted_text);
+        
         // End insert mode
         self.insert_state_mut().end();
         
@@ -3183,11 +6221,6 @@ impl InsertFunctions for Editor {
                     String::new()
                 };
                 
-                // Record the deleted text
-                if !char_to_delete.is_empty() {
-                    self.insert_state_mut().record_delete(&char_to_delete);
-                }
-                
                 // Now get a mutable reference to the buffer and perform the deletion
                 let buffer = self.buffer_manager.get_buffer_mut(buffer_id)?;
                 
@@ -3370,18 +6403,23 @@ impl Editor {
         Vec::new()
     }
 
-    /// Get the jump list
+    /// Get the jump list of the current window
     pub fn get_jump_list(&self) -> Vec<JumpInfo> {
-        // For now, just return an empty vector
-        // In a real implementation, this would get the jump list
-        Vec::new()
+        self.terminal.current_window()
+            .map(|window| window.jumps.jumps().iter()
+                .map(|jump| JumpInfo {
+                    buffer_id: jump.buffer_id,
+                    position: CursorPosition::new(jump.mark.line, jump.mark.column),
+                })
+                .collect())
+            .unwrap_or_default()
     }
 
-    /// Get the current jump index
+    /// Get the entry of the jump list Ctrl-O and Ctrl-I moved to last
+    ///
+    /// This is the length of the list right after a jump.
     pub fn get_current_jump_index(&self) -> Option<usize> {
-        // For now, just return None
-        // In a real implementation, this would get the current jump index
-        None
+        self.terminal.current_window().map(|window| window.jumps.index())
     }
 
     /// Get the content of a register
@@ -3396,24 +6434,89 @@ impl Editor {
     }
 
     /// Get the position of a mark
-    pub fn get_mark_position(&self, mark: char) -> Option<MarkPosition> {
-        // For now, just return None
-        // In a real implementation, this would get the position of the mark
-        None
+    pub fn get_mark_position(&mut self, mark: char) -> Option<MarkPosition> {
+        if mark::is_file_mark(mark) {
+            let file_mark = self.file_mark(mark)?;
+            return Some(MarkPosition {
+                buffer_id: file_mark.buffer_id?,
+                line: file_mark.mark.line,
+                column: file_mark.mark.column,
+            });
+        }
+        
+        let buffer_id = self.current_buffer_id()?;
+        let mark = *self.buffer_manager.get_buffer(buffer_id).ok()?.get_mark(mark)?;
+        Some(MarkPosition {
+            buffer_id,
+            line: mark.line,
+            column: mark.column,
+        })
     }
 
-    /// Copy a line from one position to another
-    pub fn copy_line(&mut self, buffer_id: usize, source_line: usize, dest_line: usize) -> EditorResult<()> {
-        // For now, just return Ok
-        // In a real implementation, this would copy the line
+    /// Copy lines from start to end (inclusive) below a line
+    ///
+    /// Lines count from 0, and a destination of `None` puts the copy above
+    /// the first line. The cursor goes to the last line of the copy.
+    pub fn copy_lines(&mut self, start: usize, end: usize, dest: Option<usize>) -> EditorResult<()> {
+        let lines = self.buffer_lines(start, end)?;
+        self.put_lines(dest, &lines)?;
+        
+        let last = dest.map_or(0, |dest| dest + 1) + end - start;
+        self.move_to_first_non_blank(last)
+    }
+    
+    /// Put lines below a line, or above the first line for `None`
+    fn put_lines(&mut self, dest: Option<usize>, lines: &[String]) -> EditorResult<()> {
+        let buffer_id = match self.current_buffer_id() {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+        let buffer = self.buffer_manager.get_buffer_mut(buffer_id)?;
+        
+        let text = lines.join("\n");
+        match dest {
+            Some(dest) => {
+                let idx = buffer.position_to_char_idx(dest, buffer.line_length(dest)?)?;
+                buffer.insert(idx, &format!("\n{}", text))?;
+            },
+            None => buffer.insert(0, &format!("{}\n", text))?,
+        }
         Ok(())
     }
 
-    /// Move a line from one position to another
-    pub fn move_line(&mut self, buffer_id: usize, source_line: usize, dest_line: usize) -> EditorResult<()> {
-        // For now, just return Ok
-        // In a real implementation, this would move the line
-        Ok(())
+    /// Move lines from start to end (inclusive) below a line
+    ///
+    /// Lines count from 0, and a destination of `None` puts the lines above
+    /// the first line. Moving lines into themselves is an error. The cursor
+    /// goes to the last line moved.
+    pub fn move_lines(&mut self, start: usize, end: usize, dest: Option<usize>) -> EditorResult<()> {
+        if dest.is_some_and(|dest| dest >= start && dest < end) {
+            return Err(EditorError::Other("E134: Cannot move a range of lines into itself".to_string()));
+        }
+        
+        // Rewrite the lines from the first one touched to the last one as one change
+        let moved = self.buffer_lines(start, end)?;
+        let (first, last, cursor_line, lines) = match dest {
+            Some(dest) if dest > end => {
+                let mut lines = self.buffer_lines(end + 1, dest)?;
+                lines.extend(moved);
+                (start, dest, dest, lines)
+            },
+            Some(dest) if dest == end => (start, end, end, Vec::new()),
+            _ => {
+                let first = dest.map_or(0, |dest| dest + 1);
+                let mut lines = moved;
+                if first < start {
+                    lines.extend(self.buffer_lines(first, start - 1)?);
+                }
+                (first, end, first + end - start, lines)
+            },
+        };
+        if !lines.is_empty() {
+            self.replace_lines(first, last, &lines)?;
+        }
+        
+        self.move_to_first_non_blank(cursor_line)
     }
     
     /// Execute normal mode commands
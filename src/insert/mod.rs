@@ -74,9 +74,14 @@ impl InsertState {
     }
 
     /// Record deleted text (for backspace)
-    pub fn record_delete(&mut self, _text: &str) {
-        // For a complete implementation, we would record deletions
-        // to properly support undo, but we'll keep it simple for now
+    ///
+    /// Text typed in this session and deleted again is no longer part of
+    /// what was inserted.
+    pub fn record_delete(&mut self, text: &str) {
+        if self.inserted_text.ends_with(text) {
+            self.inserted_text.truncate(self.inserted_text.len() - text.len());
+            self.lines_inserted -= text.matches('\n').count();
+        }
     }
 }
 
@@ -261,6 +261,11 @@ impl KeyHandler {
         self.default_command(key, mode)
     }
 
+    /// Check whether the keys typed so far start a mapped key sequence
+    pub fn is_pending(&self) -> bool {
+        !self.current_sequence.is_empty()
+    }
+
     /// Get the default command for a key in the given mode
     fn default_command(&self, _key: KeyEvent, _mode: Mode) -> Option<Command> {
         // This is where we would implement the default Vim key bindings
@@ -353,9 +358,11 @@ mod tests {
         // First key in the sequence
         let result1 = handler.process_key(key1, Mode::Normal);
         assert!(result1.is_none()); // No command yet, waiting for more keys
+        assert!(handler.is_pending());
 // Second key in the sequence
 let result2 = handler.process_key(key2, Mode::Normal);
 assert_eq!(result2, Some(cmd1.clone())); // Found a mapping for 'gg'
+assert!(!handler.is_pending());
 
 // First key in the sequence again
 let result3 = handler.process_key(key1, Mode::Normal);
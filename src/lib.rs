@@ -11,8 +11,10 @@ pub mod r#macro;
 pub mod mark;
 pub mod mode;
 pub mod operator;
+pub mod option;
 pub mod plugin;
 pub mod register;
+pub mod repeat;
 pub mod search;
 pub mod selection;
 pub mod syntax;
@@ -143,14 +143,16 @@ impl MacroPlayer {
         }
     }
     
-    /// Start playing a macro from a register
-    pub fn start_playback(&mut self, register: char) -> bool {
+    /// Start playing a macro from a register `count` times
+    pub fn start_playback(&mut self, register: char, count: usize) -> bool {
         // Get the macro from the register
         if let Some(register_type) = RegisterType::from_char(register) {
             if let Some(content) = self.register_manager.get_register(register_type) {
                 if let Some(macro_keys) = content.as_macro_recording() {
-                    // Push the macro onto the playback stack
-                    self.playback_stack.push((register, macro_keys.clone(), 0));
+                    // Push the macro onto the playback stack once for every count
+                    for _ in 0..count.max(1) {
+                        self.playback_stack.push((register, macro_keys.clone(), 0));
+                    }
                     return true;
                 }
             }
@@ -264,7 +266,7 @@ mod tests {
         let mut player = MacroPlayer::new(register_manager);
         
         // Start playback
-        assert!(player.start_playback('a'));
+        assert!(player.start_playback('a', 1));
         assert!(player.is_playing());
         
         // Get the keys one by one
@@ -279,5 +281,10 @@ mod tests {
         // Should be done now
         assert_eq!(player.next_key(), None);
         assert!(!player.is_playing());
+        
+        // With a count the macro is played that many times
+        assert!(player.start_playback('a', 3));
+        let played: Vec<KeyEvent> = std::iter::from_fn(|| player.next_key()).collect();
+        assert_eq!(played, keys.repeat(3));
     }
 }
\ No newline at end of file
@@ -2,6 +2,9 @@
 
 use clap::Parser;
 use log::{info, LevelFilter};
+use std::path::PathBuf;
+use xvim::buffer::swap;
+use xvim::config::ConfigManager;
 use xvim::editor;
 
 /// Command line arguments for xvim
@@ -15,6 +18,42 @@ struct Args {
     /// Enable debug logging
     #[clap(short, long)]
     debug: bool,
+
+    /// Recover the files from their swap files, or list swap files if no file is given
+    #[clap(short = 'r')]
+    recover: bool,
+
+    /// Run the Ex commands in this file at startup instead of the xvimrc, or none with NONE
+    #[clap(short = 'u', value_name = "XVIMRC")]
+    xvimrc: Option<String>,
+}
+
+/// List the swap files in the current directory and the swap directory
+fn list_swap_files() {
+    let mut config_manager = ConfigManager::new();
+    let _ = config_manager.load();
+
+    let mut dirs = vec![PathBuf::from(".")];
+    if let Some(dir) = &config_manager.config().general.directory {
+        dirs.push(dir.clone());
+    }
+
+    println!("Swap files found:");
+    for dir in dirs {
+        println!("   In directory {}:", dir.display());
+        let files = swap::list_swap_files(&dir).unwrap_or_default();
+        if files.is_empty() {
+            println!("      -- none --");
+        }
+        for (index, path) in files.iter().enumerate() {
+            println!("{:>6}.    {}", index + 1, path.display());
+            if let Ok(info) = swap::SwapInfo::read(path) {
+                println!("          file name: {}", info.file_path.display());
+                println!("         process ID: {}{}", info.pid, if info.is_owner_running() { " (STILL RUNNING)" } else { "" });
+                println!("           modified: {}", if info.content.is_some() { "YES" } else { "no" });
+            }
+        }
+    }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -31,15 +70,39 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     // TODO: Set up proper logging
     println!("xvim starting up...");
     
+    // With -r and no files, just list the swap files that could be recovered
+    if args.recover && args.files.is_empty() {
+        list_swap_files();
+        return Ok(());
+    }
+    
     // Initialize the editor
     let mut editor = editor::Editor::new()?;
     
+    // Run the startup script before the files are opened
+    let script = match args.xvimrc.as_deref() {
+        Some("NONE") => None,
+        Some(path) => Some(PathBuf::from(path)),
+        None => Some(editor.config_manager().script_path()).filter(|path| path.exists()),
+    };
+    if let Some(path) = script {
+        if let Err(e) = editor.source_file(&path) {
+            eprintln!("{}", e);
+        }
+    }
+    
     // Open files specified on the command line
     for file in args.files {
         match editor.open_file(&file) {
             Ok(_) => println!("Opened file: {}", file),
             Err(e) => eprintln!("Error opening file {}: {}", file, e),
         }
+        
+        if args.recover {
+            if let Err(e) = editor.recover() {
+                eprintln!("Error recovering file {}: {}", file, e);
+            }
+        }
     }
     
     // Run the editor
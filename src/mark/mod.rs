@@ -1,64 +1,199 @@
 //! Mark module
 //!
 //! This module implements marks for xvim, which are named positions in a buffer
-//! that allow users to quickly jump to specific locations.
+//! that allow users to quickly jump to specific locations. Marks local to a
+//! buffer are kept by the buffer itself (see `buffer::MarkMap`), which moves
+//! them as the text is edited. File marks (`A`-`Z`) are global to the editor
+//! and remember the file they were set in, so they can be jumped to from any
+//! buffer, and even after the buffer they were set in is gone.
+//!
+//! Each window also keeps a jump list, the positions it jumped from, which
+//! `Ctrl-O` and `Ctrl-I` move through.
 
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
-/// A mark is a named position in a buffer
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct Mark {
-    /// The line number (0-based)
-    pub line: usize,
-    /// The column number (0-based)
-    pub column: usize,
-}
+pub use crate::buffer::mark::{
+    is_file_mark, is_user_mark, mark_name, CHANGE_END_MARK, CHANGE_START_MARK, LAST_CHANGE_MARK,
+    LAST_INSERT_MARK, PREVIOUS_CONTEXT_MARK, VISUAL_END_MARK, VISUAL_START_MARK,
+};
+pub use crate::buffer::{ChangeList, Mark, MarkMap};
 
-impl Mark {
-    /// Create a new mark
-    pub fn new(line: usize, column: usize) -> Self {
-        Self { line, column }
-    }
+/// A file mark, set in some buffer and global to the editor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMark {
+    /// The buffer the mark was set in, while it is open
+    pub buffer_id: Option<usize>,
+    /// The file of the buffer, if it has one
+    pub file_path: Option<PathBuf>,
+    /// Last known position of the mark
+    pub mark: Mark,
 }
 
-/// A collection of marks for a buffer
+/// The file marks `A`-`Z`
+///
+/// While the buffer a file mark was set in is open the buffer keeps the mark
+/// up to date with its edits. The position here is the one last seen, used
+/// once the buffer is closed.
 #[derive(Debug, Clone, Default)]
-pub struct MarkMap {
-    /// The marks, keyed by their name (a single character)
-    marks: HashMap<char, Mark>,
+pub struct GlobalMarks {
+    /// The marks, keyed by their name
+    marks: HashMap<char, FileMark>,
 }
 
-impl MarkMap {
-    /// Create a new mark map
+impl GlobalMarks {
+    /// Create an empty set of file marks
     pub fn new() -> Self {
         Self {
             marks: HashMap::new(),
         }
     }
 
-    /// Set a mark at the given position
-    pub fn set_mark(&mut self, name: char, line: usize, column: usize) {
-        self.marks.insert(name, Mark::new(line, column));
+    /// Set a file mark, returning the one it replaces
+    pub fn set_mark(&mut self, name: char, buffer_id: usize, file_path: Option<&Path>, mark: Mark) -> Option<FileMark> {
+        self.marks.insert(name, FileMark {
+            buffer_id: Some(buffer_id),
+            file_path: file_path.map(Path::to_path_buf),
+            mark,
+        })
     }
 
-    /// Get a mark by name
-    pub fn get_mark(&self, name: char) -> Option<&Mark> {
+    /// Get a file mark by name
+    pub fn get_mark(&self, name: char) -> Option<&FileMark> {
         self.marks.get(&name)
     }
 
-    /// Remove a mark by name
-    pub fn remove_mark(&mut self, name: char) -> Option<Mark> {
+    /// Remove a file mark by name
+    pub fn remove_mark(&mut self, name: char) -> Option<FileMark> {
         self.marks.remove(&name)
     }
 
-    /// Clear all marks
-    pub fn clear(&mut self) {
-        self.marks.clear();
+    /// Get all file marks
+    pub fn all_marks(&self) -> impl Iterator<Item = (&char, &FileMark)> {
+        self.marks.iter()
     }
 
-    /// Get all marks
-    pub fn all_marks(&self) -> impl Iterator<Item = (&char, &Mark)> {
-        self.marks.iter()
+    /// Update the position of a file mark from the buffer it is in
+    pub fn update_mark(&mut self, name: char, mark: Mark) {
+        if let Some(file_mark) = self.marks.get_mut(&name) {
+            file_mark.mark = mark;
+        }
+    }
+
+    /// Forget the buffer of a file mark whose buffer is gone
+    pub fn detach_mark(&mut self, name: char) {
+        if let Some(file_mark) = self.marks.get_mut(&name) {
+            file_mark.buffer_id = None;
+        }
+    }
+
+    /// Find the buffer a file mark belongs to after a file is opened again
+    pub fn buffer_opened(&mut self, buffer_id: usize, file_path: &Path) -> Vec<(char, Mark)> {
+        let mut reattached = Vec::new();
+        for (name, file_mark) in self.marks.iter_mut() {
+            if file_mark.buffer_id.is_none() && file_mark.file_path.as_deref() == Some(file_path) {
+                file_mark.buffer_id = Some(buffer_id);
+                reattached.push((*name, file_mark.mark));
+            }
+        }
+        reattached
+    }
+}
+
+/// A position in the jump list of a window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Jump {
+    /// The buffer jumped from
+    pub buffer_id: usize,
+    /// The position jumped from
+    pub mark: Mark,
+}
+
+impl Jump {
+    /// Create a new jump list entry
+    pub fn new(buffer_id: usize, mark: Mark) -> Self {
+        Self { buffer_id, mark }
+    }
+
+    /// Check whether two entries are on the same line, which the list keeps only once
+    fn same_line(&self, other: &Jump) -> bool {
+        self.buffer_id == other.buffer_id && self.mark.line == other.mark.line
+    }
+}
+
+/// The jump list of a window
+///
+/// Every jump adds the position it was made from at the end of the list.
+/// `Ctrl-O` moves back through the list and `Ctrl-I` forward again.
+#[derive(Debug, Clone, Default)]
+pub struct JumpList {
+    /// The positions jumped from, oldest first
+    jumps: Vec<Jump>,
+    /// Entry `Ctrl-O` and `Ctrl-I` moved to last, or the length of the list after a jump
+    index: usize,
+}
+
+impl JumpList {
+    /// Maximum number of jumps remembered
+    pub const MAX_LEN: usize = 100;
+
+    /// Create an empty jump list
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the position a jump is made from
+    ///
+    /// An older entry on the same line is removed, so every line is in the
+    /// list once.
+    pub fn push(&mut self, jump: Jump) {
+        self.jumps.retain(|entry| !entry.same_line(&jump));
+        if self.jumps.len() == Self::MAX_LEN {
+            self.jumps.remove(0);
+        }
+        self.jumps.push(jump);
+        self.index = self.jumps.len();
+    }
+
+    /// Get the entries, oldest first
+    pub fn jumps(&self) -> &[Jump] {
+        &self.jumps
+    }
+
+    /// Get the entry `Ctrl-O` and `Ctrl-I` moved to last, which is the length of the list after a jump
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Move `count` entries back from `current`, as `Ctrl-O` does
+    ///
+    /// Going back from the end of the list first adds the current position,
+    /// so `Ctrl-I` can return to it.
+    pub fn back(&mut self, current: Jump, count: usize) -> Option<Jump> {
+        let count = count.max(1);
+        if self.index == self.jumps.len() {
+            if self.jumps.len() < count {
+                return None;
+            }
+            self.push(current);
+            self.index = self.jumps.len() - 1;
+        }
+
+        if self.index < count {
+            return None;
+        }
+        self.index -= count;
+        Some(self.jumps[self.index])
+    }
+
+    /// Move `count` entries forward, as `Ctrl-I` does
+    pub fn forward(&mut self, count: usize) -> Option<Jump> {
+        let index = self.index + count.max(1);
+        if index >= self.jumps.len() {
+            return None;
+        }
+        self.index = index;
+        Some(self.jumps[self.index])
     }
 }
 
@@ -67,40 +202,52 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_mark_creation() {
-        let mark = Mark::new(10, 5);
-        assert_eq!(mark.line, 10);
-        assert_eq!(mark.column, 5);
+    fn test_global_marks() {
+        let mut marks = GlobalMarks::new();
+        let path = Path::new("/tmp/file.txt");
+        assert!(marks.set_mark('A', 1, Some(path), Mark::new(3, 2)).is_none());
+        assert!(marks.set_mark('B', 2, None, Mark::new(1, 0)).is_none());
+        assert_eq!(marks.get_mark('B').unwrap().buffer_id, Some(2));
+
+        // The buffer moves the mark while it's open
+        marks.update_mark('A', Mark::new(5, 2));
+        assert_eq!(marks.get_mark('A'), Some(&FileMark {
+            buffer_id: Some(1),
+            file_path: Some(path.to_path_buf()),
+            mark: Mark::new(5, 2),
+        }));
+
+        // Once its buffer is gone, the file is opened again in a new one
+        marks.detach_mark('A');
+        assert_eq!(marks.get_mark('A').unwrap().buffer_id, None);
+        assert_eq!(marks.buffer_opened(4, path), vec![('A', Mark::new(5, 2))]);
+        assert_eq!(marks.get_mark('A').unwrap().buffer_id, Some(4));
     }
 
     #[test]
-    fn test_mark_map() {
-        let mut mark_map = MarkMap::new();
-        
-        // Set some marks
-        mark_map.set_mark('a', 10, 5);
-        mark_map.set_mark('b', 20, 10);
-        
-        // Get marks
-        let mark_a = mark_map.get_mark('a').unwrap();
-        assert_eq!(mark_a.line, 10);
-        assert_eq!(mark_a.column, 5);
-        
-        let mark_b = mark_map.get_mark('b').unwrap();
-        assert_eq!(mark_b.line, 20);
-        assert_eq!(mark_b.column, 10);
-        
-        // Non-existent mark
-        assert!(mark_map.get_mark('c').is_none());
-        
-        // Remove a mark
-        let removed = mark_map.remove_mark('a').unwrap();
-        assert_eq!(removed.line, 10);
-        assert_eq!(removed.column, 5);
-        assert!(mark_map.get_mark('a').is_none());
-        
-        // Clear all marks
-        mark_map.clear();
-        assert!(mark_map.get_mark('b').is_none());
-    }
-}
\ No newline at end of file
+    fn test_jump_list() {
+        let mut jumps = JumpList::new();
+        assert!(jumps.back(Jump::new(1, Mark::new(0, 0)), 1).is_none());
+
+        jumps.push(Jump::new(1, Mark::new(0, 0)));
+        jumps.push(Jump::new(1, Mark::new(10, 0)));
+        jumps.push(Jump::new(2, Mark::new(3, 1)));
+        // Jumping from a line again moves it to the end
+        jumps.push(Jump::new(1, Mark::new(0, 4)));
+        assert_eq!(jumps.jumps().len(), 3);
+        assert_eq!(jumps.jumps()[2], Jump::new(1, Mark::new(0, 4)));
+
+        // Ctrl-O remembers where it started, Ctrl-I goes back there
+        let current = Jump::new(2, Mark::new(20, 0));
+        assert_eq!(jumps.back(current, 1), Some(Jump::new(1, Mark::new(0, 4))));
+        assert_eq!(jumps.back(current, 2), Some(Jump::new(1, Mark::new(10, 0))));
+        assert!(jumps.back(current, 1).is_none());
+        assert_eq!(jumps.forward(3), Some(current));
+        assert!(jumps.forward(1).is_none());
+
+        // A new jump goes to the end of the list
+        jumps.back(current, 1);
+        jumps.push(Jump::new(2, Mark::new(5, 0)));
+        assert_eq!(jumps.index(), jumps.jumps().len());
+    }
+}
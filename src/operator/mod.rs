@@ -19,8 +19,12 @@ pub enum Operator {
     Indent,
     /// Outdent text
     Outdent,
-    /// Format text
+    /// Format text to `textwidth` (`gq`)
     Format,
+    /// Format text to `textwidth`, keeping the cursor where it is (`gw`)
+    FormatKeepCursor,
+    /// Re-indent lines (`=`)
+    Reindent,
     /// Fold text
     Fold,
     /// Unfold text
@@ -45,6 +49,8 @@ impl Operator {
             Operator::Indent => "indent",
             Operator::Outdent => "outdent",
             Operator::Format => "format",
+            Operator::FormatKeepCursor => "format_keep_cursor",
+            Operator::Reindent => "reindent",
             Operator::Fold => "fold",
             Operator::Unfold => "unfold",
             Operator::ToUpper => "to_upper",
@@ -62,7 +68,7 @@ impl Operator {
             'y' => Some(Operator::Yank),
             '>' => Some(Operator::Indent),
             '<' => Some(Operator::Outdent),
-            '=' => Some(Operator::Format),
+            '=' => Some(Operator::Reindent),
             'z' => Some(Operator::Fold),
             'Z' => Some(Operator::Unfold),
             'g' => Some(Operator::ToUpper), // This is actually 'gU' or 'gu', but we'll handle the 'U'/'u' separately
@@ -71,6 +77,44 @@ impl Operator {
             _ => None,
         }
     }
+    
+    /// Get the key that applies the operator to whole lines when typed after it
+    ///
+    /// This is the second `d` of `dd`, or the `U` of `gUU`.
+    pub fn line_key(&self) -> Option<char> {
+        match self {
+            Operator::Delete => Some('d'),
+            Operator::Change => Some('c'),
+            Operator::Yank => Some('y'),
+            Operator::Indent => Some('>'),
+            Operator::Outdent => Some('<'),
+            Operator::Format => Some('q'),
+            Operator::FormatKeepCursor => Some('w'),
+            Operator::Reindent => Some('='),
+            Operator::ToUpper => Some('U'),
+            Operator::ToLower => Some('u'),
+            Operator::SwapCase => Some('~'),
+            Operator::Filter => Some('!'),
+            Operator::Fold | Operator::Unfold => None,
+        }
+    }
+    
+    /// Check whether the operator is typed after a `g`, like `gU`
+    pub fn is_g_operator(&self) -> bool {
+        matches!(
+            self,
+            Operator::Format | Operator::FormatKeepCursor | Operator::ToUpper | Operator::ToLower | Operator::SwapCase
+        )
+    }
+    
+    /// Check whether the operator always works on whole lines
+    pub fn is_linewise(&self) -> bool {
+        matches!(
+            self,
+            Operator::Indent | Operator::Outdent | Operator::Format | Operator::FormatKeepCursor
+                | Operator::Reindent | Operator::Filter
+        )
+    }
 }
 
 /// The target of an operator
@@ -178,6 +222,155 @@ impl Default for OperatorManager {
     }
 }
 
+/// Indentation settings used to shift and re-indent lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndentOptions {
+    /// Columns of one level of indent, `tabstop` when 0
+    pub shiftwidth: usize,
+    /// Columns of a tab
+    pub tabstop: usize,
+    /// Whether indent is made of spaces only
+    pub expandtab: bool,
+}
+
+impl IndentOptions {
+    /// Get the columns of one level of indent
+    pub fn shift_width(&self) -> usize {
+        if self.shiftwidth == 0 {
+            self.tabstop.max(1)
+        } else {
+            self.shiftwidth
+        }
+    }
+    
+    /// Get the columns a line is indented by
+    pub fn indent_width(&self, line: &str) -> usize {
+        let tabstop = self.tabstop.max(1);
+        let mut width = 0;
+        for c in line.chars() {
+            match c {
+                ' ' => width += 1,
+                '\t' => width += tabstop - width % tabstop,
+                _ => break,
+            }
+        }
+        width
+    }
+    
+    /// Give a line an indent of `width` columns
+    ///
+    /// The indent is made of tabs and spaces, or of spaces only with `expandtab`.
+    pub fn set_indent(&self, line: &str, width: usize) -> String {
+        let text = line.trim_start_matches([' ', '\t']);
+        let indent = if self.expandtab {
+            " ".repeat(width)
+        } else {
+            let tabstop = self.tabstop.max(1);
+            format!("{}{}", "\t".repeat(width / tabstop), " ".repeat(width % tabstop))
+        };
+        indent + text
+    }
+}
+
+/// Shift a line by levels of indent, to the right when `levels` is positive
+///
+/// Like in Vim, empty lines are left alone.
+pub fn shift_line(line: &str, levels: isize, options: &IndentOptions) -> String {
+    if line.trim().is_empty() {
+        return line.to_string();
+    }
+    
+    let shift = options.shift_width() * levels.unsigned_abs();
+    let width = options.indent_width(line);
+    let width = if levels >= 0 { width + shift } else { width.saturating_sub(shift) };
+    options.set_indent(line, width)
+}
+
+/// Re-indent lines by their brackets, as `=` does
+///
+/// A line is indented like the one before it, a level more after a line that
+/// ends with an opening bracket and a level less when it starts with a closing
+/// one. `previous` is the last non-blank line before the lines, if any.
+pub fn reindent_lines(lines: &[String], previous: Option<&str>, options: &IndentOptions) -> Vec<String> {
+    let shift = options.shift_width();
+    let opens = |line: &str| line.trim_end().ends_with(['{', '(', '[']);
+    let closes = |line: &str| line.trim_start().starts_with(['}', ')', ']']);
+    
+    let mut width = match previous {
+        Some(line) if opens(line) => options.indent_width(line) + shift,
+        Some(line) => options.indent_width(line),
+        None => 0,
+    };
+    
+    lines.iter().map(|line| {
+        if line.trim().is_empty() {
+            return String::new();
+        }
+        
+        let line_width = if closes(line) { width.saturating_sub(shift) } else { width };
+        width = if opens(line) { line_width + shift } else { line_width };
+        options.set_indent(line, line_width)
+    }).collect()
+}
+
+/// Change the case of a text, as `gU`, `gu` and `g~` do
+///
+/// Characters whose other case is more than one character, like `ß`, are kept.
+pub fn change_case(text: &str, operator: Operator) -> String {
+    text.chars().map(|c| {
+        let changed: Vec<char> = match operator {
+            Operator::ToUpper => c.to_uppercase().collect(),
+            Operator::ToLower => c.to_lowercase().collect(),
+            Operator::SwapCase if c.is_lowercase() => c.to_uppercase().collect(),
+            Operator::SwapCase => c.to_lowercase().collect(),
+            _ => return c,
+        };
+        match changed.as_slice() {
+            [changed] => *changed,
+            _ => c,
+        }
+    }).collect()
+}
+
+/// Format lines to `width` columns, as `gq` does
+///
+/// The lines of each paragraph, which blank lines separate, are joined and
+/// broken again between words. A paragraph keeps the indent of its first line.
+pub fn format_lines(lines: &[String], width: usize) -> Vec<String> {
+    let mut formatted = Vec::new();
+    let mut start = 0;
+    
+    while start < lines.len() {
+        if lines[start].trim().is_empty() {
+            formatted.push(String::new());
+            start += 1;
+            continue;
+        }
+        
+        let end = lines[start..].iter()
+            .position(|line| line.trim().is_empty())
+            .map_or(lines.len(), |offset| start + offset);
+        let indent: String = lines[start].chars().take_while(|c| c.is_whitespace()).collect();
+        
+        let mut line = String::new();
+        for word in lines[start..end].iter().flat_map(|line| line.split_whitespace()) {
+            if !line.is_empty() && indent.chars().count() + line.chars().count() + 1 + word.chars().count() > width {
+                formatted.push(format!("{}{}", indent, line));
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        formatted.push(format!("{}{}", indent, line));
+        
+        start = end;
+    }
+    
+    formatted
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -188,6 +381,60 @@ mod tests {
         assert_eq!(Operator::from_char('c'), Some(Operator::Change));
         assert_eq!(Operator::from_char('y'), Some(Operator::Yank));
         assert_eq!(Operator::from_char('x'), None);
+        assert_eq!(Operator::from_char('='), Some(Operator::Reindent));
+    }
+    
+    #[test]
+    fn test_operator_line_key() {
+        assert_eq!(Operator::Delete.line_key(), Some('d'));
+        assert_eq!(Operator::ToUpper.line_key(), Some('U'));
+        assert_eq!(Operator::FormatKeepCursor.line_key(), Some('w'));
+        assert!(Operator::ToUpper.is_g_operator());
+        assert!(!Operator::Indent.is_g_operator());
+        assert!(Operator::Filter.is_linewise());
+        assert!(!Operator::SwapCase.is_linewise());
+    }
+    
+    #[test]
+    fn test_shift_line() {
+        let spaces = IndentOptions { shiftwidth: 4, tabstop: 8, expandtab: true };
+        assert_eq!(shift_line("foo", 1, &spaces), "    foo");
+        assert_eq!(shift_line("  foo", 2, &spaces), "          foo");
+        assert_eq!(shift_line("      foo", -1, &spaces), "  foo");
+        assert_eq!(shift_line("  foo", -1, &spaces), "foo");
+        assert_eq!(shift_line("", 1, &spaces), "");
+        
+        let tabs = IndentOptions { shiftwidth: 0, tabstop: 4, expandtab: false };
+        assert_eq!(shift_line("  foo", 1, &tabs), "\t  foo");
+        assert_eq!(shift_line("\t\tfoo", -1, &tabs), "\tfoo");
+    }
+    
+    #[test]
+    fn test_reindent_lines() {
+        let options = IndentOptions { shiftwidth: 2, tabstop: 8, expandtab: true };
+        let lines: Vec<String> = ["fn main() {", "let x = [", "1,", "];", "", "}"].iter().map(|line| line.to_string()).collect();
+        assert_eq!(
+            reindent_lines(&lines, None, &options),
+            vec!["fn main() {", "  let x = [", "    1,", "  ];", "", "}"]
+        );
+        
+        let lines = vec!["    body".to_string(), "}".to_string()];
+        assert_eq!(reindent_lines(&lines, Some("  if x {"), &options), vec!["    body", "  }"]);
+    }
+    
+    #[test]
+    fn test_change_case() {
+        assert_eq!(change_case("Hello, World", Operator::ToUpper), "HELLO, WORLD");
+        assert_eq!(change_case("Hello, World", Operator::ToLower), "hello, world");
+        assert_eq!(change_case("Hello, World", Operator::SwapCase), "hELLO, wORLD");
+        assert_eq!(change_case("straße", Operator::ToUpper), "STRAßE");
+    }
+    
+    #[test]
+    fn test_format_lines() {
+        let lines: Vec<String> = ["  one two", "three four five", "", "six"].iter().map(|line| line.to_string()).collect();
+        assert_eq!(format_lines(&lines, 11), vec!["  one two", "  three", "  four five", "", "six"]);
+        assert_eq!(format_lines(&lines, 79), vec!["  one two three four five", "", "six"]);
     }
     
     #[test]
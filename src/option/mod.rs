@@ -0,0 +1,857 @@
+//! Option module
+//!
+//! This module implements the options behind `:set`, `:setlocal` and
+//! `:setglobal`. Every option has a type, a default value and a scope. Global
+//! options have a single value. Window-local and buffer-local options also have
+//! a global value, which a window or buffer uses until it is given a value of
+//! its own. Changes are queued so the editor can pass new values on to the
+//! parts of the editor that use them.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::buffer::{FileEncoding, FileFormat};
+use crate::command::WildMode;
+use crate::config::GeneralConfig;
+
+/// Errors that can occur when setting options
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionError {
+    /// No option with this name
+    Unknown(String),
+    /// The value is not valid for the option
+    InvalidArgument(String),
+    /// A number option was given something else
+    NumberRequired(String),
+}
+
+impl fmt::Display for OptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionError::Unknown(arg) => write!(f, "E518: Unknown option: {}", arg),
+            OptionError::InvalidArgument(arg) => write!(f, "E474: Invalid argument: {}", arg),
+            OptionError::NumberRequired(arg) => write!(f, "E521: Number required after =: {}", arg),
+        }
+    }
+}
+
+impl Error for OptionError {}
+
+/// Result type for option operations
+pub type OptionResult<T> = Result<T, OptionError>;
+
+/// Value of an option
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionValue {
+    /// On or off
+    Bool(bool),
+    /// A number
+    Number(i64),
+    /// A string
+    String(String),
+    /// A comma separated list of strings
+    List(Vec<String>),
+}
+
+impl OptionValue {
+    /// Get the type of the value
+    pub fn option_type(&self) -> OptionType {
+        match self {
+            OptionValue::Bool(_) => OptionType::Bool,
+            OptionValue::Number(_) => OptionType::Number,
+            OptionValue::String(_) => OptionType::String,
+            OptionValue::List(_) => OptionType::List,
+        }
+    }
+
+    /// Get the value as a bool
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            OptionValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Get the value as a number
+    pub fn as_number(&self) -> Option<i64> {
+        match self {
+            OptionValue::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Get the value as a string
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            OptionValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Get the value as a list
+    pub fn as_list(&self) -> Option<&[String]> {
+        match self {
+            OptionValue::List(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for OptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionValue::Bool(value) => write!(f, "{}", value),
+            OptionValue::Number(value) => write!(f, "{}", value),
+            OptionValue::String(value) => write!(f, "{}", value),
+            OptionValue::List(values) => write!(f, "{}", values.join(",")),
+        }
+    }
+}
+
+/// Type of an option
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptionType {
+    /// On or off
+    Bool,
+    /// A number
+    Number,
+    /// A string
+    String,
+    /// A comma separated list of strings
+    List,
+}
+
+/// Where an option can have a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OptionScope {
+    /// One value for the whole editor
+    Global,
+    /// A value for each window
+    Window,
+    /// A value for each buffer
+    Buffer,
+}
+
+/// Which values a set command changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetCommand {
+    /// `:set`, both the global and the local value
+    Set,
+    /// `:setlocal`, only the local value
+    SetLocal,
+    /// `:setglobal`, only the global value
+    SetGlobal,
+}
+
+/// The window and buffer whose local values are used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OptionContext {
+    /// Current window
+    pub window: Option<usize>,
+    /// Current buffer
+    pub buffer: Option<usize>,
+}
+
+impl OptionContext {
+    /// Create a context for a window showing a buffer
+    pub fn new(window: Option<usize>, buffer: Option<usize>) -> Self {
+        Self { window, buffer }
+    }
+
+    /// Get the window or buffer ID for a scope
+    fn id(&self, scope: OptionScope) -> Option<usize> {
+        match scope {
+            OptionScope::Global => None,
+            OptionScope::Window => self.window,
+            OptionScope::Buffer => self.buffer,
+        }
+    }
+}
+
+/// Definition of an option
+#[derive(Debug, Clone)]
+pub struct OptionDef {
+    /// Full name
+    pub name: &'static str,
+    /// Short name, empty if there is none
+    pub short: &'static str,
+    /// Where the option can have a value
+    pub scope: OptionScope,
+    /// Default value
+    pub default: OptionValue,
+    /// Check for new values
+    pub validator: Option<fn(&OptionValue) -> bool>,
+}
+
+impl OptionDef {
+    /// Create an option definition
+    pub fn new(name: &'static str, short: &'static str, scope: OptionScope, default: OptionValue) -> Self {
+        Self {
+            name,
+            short,
+            scope,
+            default,
+            validator: None,
+        }
+    }
+
+    /// Only accept values the validator agrees with
+    pub fn with_validator(mut self, validator: fn(&OptionValue) -> bool) -> Self {
+        self.validator = Some(validator);
+        self
+    }
+
+    /// Get the type of the option
+    pub fn option_type(&self) -> OptionType {
+        self.default.option_type()
+    }
+
+    /// Check whether a name refers to this option
+    pub fn matches(&self, name: &str) -> bool {
+        self.name == name || (!self.short.is_empty() && self.short == name)
+    }
+
+    /// Check whether a value is valid for this option
+    pub fn is_valid(&self, value: &OptionValue) -> bool {
+        value.option_type() == self.option_type()
+            && self.validator.is_none_or(|validator| validator(value))
+    }
+}
+
+/// A change to an option value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionChange {
+    /// Full name of the option
+    pub name: &'static str,
+    /// Scope of the changed value, `Global` for the global value
+    pub scope: OptionScope,
+    /// Window or buffer whose local value changed
+    pub id: Option<usize>,
+}
+
+/// How a `:set` argument changes a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Assign {
+    /// `=`
+    Set,
+    /// `+=`
+    Add,
+    /// `-=`
+    Remove,
+    /// `^=`
+    Prepend,
+}
+
+fn positive(value: &OptionValue) -> bool {
+    value.as_number().is_some_and(|number| number > 0)
+}
+
+fn not_negative(value: &OptionValue) -> bool {
+    value.as_number().is_some_and(|number| number >= 0)
+}
+
+fn not_empty(value: &OptionValue) -> bool {
+    value.as_str().is_some_and(|value| !value.is_empty())
+}
+
+fn file_format(value: &OptionValue) -> bool {
+    value.as_str().is_some_and(|value| FileFormat::parse(value).is_some())
+}
+
+fn file_encoding(value: &OptionValue) -> bool {
+    value.as_str().is_some_and(|value| FileEncoding::parse(value).is_some())
+}
+
+fn clipboard(value: &OptionValue) -> bool {
+    value.as_list().is_some_and(|values| {
+        values.iter().all(|value| matches!(value.as_str(), "unnamed" | "unnamedplus"))
+    })
+}
+
+fn match_pairs(value: &OptionValue) -> bool {
+    value.as_list().is_some_and(|values| {
+        values.iter().all(|pair| {
+            let chars: Vec<char> = pair.chars().collect();
+            chars.len() == 3 && chars[1] == ':' && chars[0] != chars[2]
+        })
+    })
+}
+
+fn wild_mode(value: &OptionValue) -> bool {
+    value.as_list().is_some_and(|parts| {
+        parts.len() <= 4 && parts.iter().all(|part| WildMode::parse(part).is_some())
+    })
+}
+
+/// Get the shell to run commands with, $SHELL when it is set
+fn default_shell() -> String {
+    match std::env::var("SHELL") {
+        Ok(shell) if !shell.is_empty() => shell,
+        _ if cfg!(windows) => "cmd.exe".to_string(),
+        _ => "sh".to_string(),
+    }
+}
+
+/// Get the options xvim knows about
+pub fn builtin_options() -> Vec<OptionDef> {
+    use OptionScope::{Buffer, Global, Window};
+    use OptionValue::{Bool, Number};
+
+    let string = |value: &str| OptionValue::String(value.to_string());
+    let list = |values: &[&str]| OptionValue::List(values.iter().map(|value| value.to_string()).collect());
+
+    vec![
+        OptionDef::new("autoindent", "ai", Buffer, Bool(true)),
+        OptionDef::new("autoread", "ar", Global, Bool(false)),
+        OptionDef::new("backup", "bk", Global, Bool(false)),
+        OptionDef::new("backupdir", "bdir", Global, string("")),
+        OptionDef::new("backupext", "bex", Global, string("~")).with_validator(not_empty),
+        OptionDef::new("bomb", "", Buffer, Bool(false)),
+        OptionDef::new("clipboard", "cb", Global, list(&["unnamedplus"])).with_validator(clipboard),
+        OptionDef::new("directory", "dir", Global, string("")),
+        OptionDef::new("expandtab", "et", Buffer, Bool(true)),
+        OptionDef::new("fileencoding", "fenc", Buffer, string("utf-8")).with_validator(file_encoding),
+        OptionDef::new("fileformat", "ff", Buffer, string("unix")).with_validator(file_format),
+        OptionDef::new("ignorecase", "ic", Global, Bool(false)),
+        OptionDef::new("matchpairs", "mps", Buffer, list(&["(:)", "{:}", "[:]"])).with_validator(match_pairs),
+        OptionDef::new("number", "nu", Window, Bool(true)),
+        OptionDef::new("readonly", "ro", Buffer, Bool(false)),
+        OptionDef::new("shell", "sh", Global, string(&default_shell())).with_validator(not_empty),
+        OptionDef::new("shellcmdflag", "shcf", Global, string(if cfg!(windows) { "/c" } else { "-c" })),
+        OptionDef::new("shiftwidth", "sw", Buffer, Number(4)).with_validator(not_negative),
+        OptionDef::new("smartcase", "scs", Global, Bool(false)),
+        OptionDef::new("swapfile", "swf", Global, Bool(true)),
+        OptionDef::new("tabstop", "ts", Buffer, Number(4)).with_validator(positive),
+        OptionDef::new("textwidth", "tw", Buffer, Number(0)).with_validator(not_negative),
+        OptionDef::new("undodir", "udir", Global, string("")),
+        OptionDef::new("undofile", "udf", Global, Bool(false)),
+        OptionDef::new("updatetime", "ut", Global, Number(4000)).with_validator(not_negative),
+        OptionDef::new("wildmenu", "wmnu", Global, Bool(true)),
+        OptionDef::new("wildmode", "wim", Global, list(&["full"])).with_validator(wild_mode),
+        OptionDef::new("writebackup", "wb", Global, Bool(true)),
+    ]
+}
+
+/// The options and their values
+#[derive(Debug, Clone)]
+pub struct OptionRegistry {
+    /// Option definitions, sorted by name
+    defs: Vec<OptionDef>,
+    /// Global values, by option name
+    global: HashMap<&'static str, OptionValue>,
+    /// Local values of windows and buffers
+    local: HashMap<(OptionScope, usize), HashMap<&'static str, OptionValue>>,
+    /// Changes not yet taken by the editor
+    changes: Vec<OptionChange>,
+}
+
+impl Default for OptionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OptionRegistry {
+    /// Create a registry of the built-in options with their default values
+    pub fn new() -> Self {
+        let mut defs = builtin_options();
+        defs.sort_by_key(|def| def.name);
+
+        let global = defs.iter()
+            .map(|def| (def.name, def.default.clone()))
+            .collect();
+
+        Self {
+            defs,
+            global,
+            local: HashMap::new(),
+            changes: Vec::new(),
+        }
+    }
+
+    /// Create a registry with the global values taken from the configuration
+    pub fn from_config(general: &GeneralConfig) -> Self {
+        let mut registry = Self::new();
+        registry.load_config(general);
+        registry.changes.clear();
+        registry
+    }
+
+    /// Set the global values from the configuration
+    pub fn load_config(&mut self, general: &GeneralConfig) {
+        let path = |path: &Option<PathBuf>| {
+            OptionValue::String(path.as_ref().map(|path| path.display().to_string()).unwrap_or_default())
+        };
+        let clipboard = if general.use_system_clipboard { vec!["unnamedplus".to_string()] } else { Vec::new() };
+
+        let values = [
+            ("autoindent", OptionValue::Bool(general.auto_indent)),
+            ("autoread", OptionValue::Bool(general.autoread)),
+            ("backup", OptionValue::Bool(general.backup)),
+            ("backupdir", path(&general.backupdir)),
+            ("backupext", OptionValue::String(general.backupext.clone())),
+            ("clipboard", OptionValue::List(clipboard)),
+            ("directory", path(&general.directory)),
+            ("expandtab", OptionValue::Bool(general.expand_tabs)),
+            ("number", OptionValue::Bool(general.show_line_numbers)),
+            ("swapfile", OptionValue::Bool(general.swapfile)),
+            ("tabstop", OptionValue::Number(i64::from(general.tab_width))),
+            ("shiftwidth", OptionValue::Number(i64::from(general.tab_width))),
+            ("undodir", path(&general.undodir)),
+            ("undofile", OptionValue::Bool(general.undofile)),
+            ("updatetime", OptionValue::Number(general.updatetime.try_into().unwrap_or(i64::MAX))),
+            ("writebackup", OptionValue::Bool(general.writebackup)),
+        ];
+
+        for (name, value) in values {
+            // A bad value in the configuration keeps the default
+            if let Err(err) = self.set_global(name, value) {
+                log::warn!("Ignoring configured option: {}", err);
+            }
+        }
+    }
+
+    /// Get the definition of an option by its full or short name
+    pub fn def(&self, name: &str) -> Option<&OptionDef> {
+        self.defs.iter().find(|def| def.matches(name))
+    }
+
+    /// Get the definitions of all options, sorted by name
+    pub fn defs(&self) -> &[OptionDef] {
+        &self.defs
+    }
+
+    /// Get the value of an option, using the local value if there is one
+    pub fn get(&self, name: &str, context: OptionContext) -> Option<&OptionValue> {
+        let def = self.def(name)?;
+        self.local_value(def, context).or_else(|| self.global.get(def.name))
+    }
+
+    /// Get the global value of an option
+    pub fn get_global(&self, name: &str) -> Option<&OptionValue> {
+        let def = self.def(name)?;
+        self.global.get(def.name)
+    }
+
+    /// Get the local value of an option, `None` if it only has the global value
+    pub fn get_local(&self, name: &str, context: OptionContext) -> Option<&OptionValue> {
+        let def = self.def(name)?;
+        self.local_value(def, context)
+    }
+
+    /// Get the value of a bool option, false if there is no such option
+    pub fn get_bool(&self, name: &str, context: OptionContext) -> bool {
+        self.get(name, context).and_then(OptionValue::as_bool).unwrap_or(false)
+    }
+
+    /// Get the value of a number option, 0 if there is no such option
+    pub fn get_number(&self, name: &str, context: OptionContext) -> i64 {
+        self.get(name, context).and_then(OptionValue::as_number).unwrap_or(0)
+    }
+
+    /// Get the value of a string option, empty if there is no such option
+    pub fn get_string(&self, name: &str, context: OptionContext) -> &str {
+        self.get(name, context).and_then(OptionValue::as_str).unwrap_or("")
+    }
+
+    /// Get the value of a list option, empty if there is no such option
+    pub fn get_list(&self, name: &str, context: OptionContext) -> &[String] {
+        self.get(name, context).and_then(OptionValue::as_list).unwrap_or(&[])
+    }
+
+    /// Set the global value of an option
+    pub fn set_global(&mut self, name: &str, value: OptionValue) -> OptionResult<()> {
+        self.set(name, value, SetCommand::SetGlobal, OptionContext::default())
+    }
+
+    /// Set an option the way the given set command does
+    ///
+    /// `:setlocal` on a global option sets the global value, as there is no other.
+    pub fn set(&mut self, name: &str, value: OptionValue, command: SetCommand, context: OptionContext) -> OptionResult<()> {
+        let def = self.def(name).ok_or_else(|| OptionError::Unknown(name.to_string()))?;
+        if !def.is_valid(&value) {
+            return Err(OptionError::InvalidArgument(format!("{}={}", name, value)));
+        }
+        let (name, scope) = (def.name, def.scope);
+
+        let local_id = context.id(scope);
+        let set_global = command != SetCommand::SetLocal || scope == OptionScope::Global || local_id.is_none();
+        let set_local = command != SetCommand::SetGlobal && local_id.is_some();
+
+        if set_local {
+            let id = local_id.unwrap_or_default();
+            self.local.entry((scope, id)).or_default().insert(name, value.clone());
+            self.changes.push(OptionChange { name, scope, id: Some(id) });
+        }
+        if set_global {
+            self.global.insert(name, value);
+            self.changes.push(OptionChange { name, scope: OptionScope::Global, id: None });
+        }
+
+        Ok(())
+    }
+
+    /// Record the local value of an option without reporting a change
+    ///
+    /// Used for values that are kept elsewhere, such as the file format of a
+    /// buffer, so `:set` shows them as they are.
+    pub fn store_local(&mut self, name: &str, value: OptionValue, context: OptionContext) {
+        if let Some(def) = self.def(name) {
+            if let Some(id) = context.id(def.scope) {
+                let name = def.name;
+                self.local.entry((def.scope, id)).or_default().insert(name, value);
+            }
+        }
+    }
+
+    /// Forget the local values of a window or buffer that was closed
+    pub fn remove_local(&mut self, scope: OptionScope, id: usize) {
+        self.local.remove(&(scope, id));
+    }
+
+    /// Take the changes made since this was last called
+    pub fn take_changes(&mut self) -> Vec<OptionChange> {
+        std::mem::take(&mut self.changes)
+    }
+
+    /// Run a set command, returning the lines it shows
+    ///
+    /// Arguments are separated by spaces, a space in a value is written as
+    /// `\ `. Each argument is one of `all`, `all&`, `{option}`, `{option}?`,
+    /// `no{option}`, `inv{option}`, `{option}!`, `{option}&` or
+    /// `{option}={value}`, where `=` can also be `:`, `+=`, `-=` or `^=`.
+    /// Without arguments the options that differ from their default are shown.
+    pub fn execute(&mut self, command: SetCommand, args: &str, context: OptionContext) -> OptionResult<Vec<String>> {
+        let args = split_args(args);
+        if args.is_empty() {
+            return Ok(self.list(command, context, false));
+        }
+
+        let mut output = Vec::new();
+        for arg in args {
+            match arg.as_str() {
+                "all" => output.extend(self.list(command, context, true)),
+                "all&" => {
+                    for def in self.defs.clone() {
+                        self.set(def.name, def.default, command, context)?;
+                    }
+                }
+                _ => {
+                    if let Some(line) = self.execute_arg(command, &arg, context)? {
+                        output.push(line);
+                    }
+                }
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Run a single set command argument
+    fn execute_arg(&mut self, command: SetCommand, arg: &str, context: OptionContext) -> OptionResult<Option<String>> {
+        let invalid = || OptionError::InvalidArgument(arg.to_string());
+
+        // {option}={value} and friends
+        if let Some(pos) = arg.find(['=', ':']) {
+            let (name, assign) = match &arg[..pos] {
+                name if name.ends_with('+') => (&name[..name.len() - 1], Assign::Add),
+                name if name.ends_with('-') => (&name[..name.len() - 1], Assign::Remove),
+                name if name.ends_with('^') => (&name[..name.len() - 1], Assign::Prepend),
+                name => (name, Assign::Set),
+            };
+            let def = self.def(name).ok_or_else(|| OptionError::Unknown(arg.to_string()))?;
+            let current = self.value_for(def, command, context);
+            let value = assign_value(&current, assign, &arg[pos + 1..])
+                .ok_or_else(|| match current {
+                    OptionValue::Number(_) => OptionError::NumberRequired(arg.to_string()),
+                    _ => OptionError::InvalidArgument(arg.to_string()),
+                })?;
+            let name = def.name;
+            self.set(name, value, command, context).map_err(|_| invalid())?;
+            return Ok(None);
+        }
+
+        // {option}? shows any option
+        if let Some(name) = arg.strip_suffix('?') {
+            let def = self.def(name).ok_or_else(|| OptionError::Unknown(arg.to_string()))?;
+            return Ok(Some(self.show(def, command, context)));
+        }
+
+        // {option}& resets to the default
+        if let Some(name) = arg.strip_suffix('&') {
+            let def = self.def(name).ok_or_else(|| OptionError::Unknown(arg.to_string()))?;
+            let (name, default) = (def.name, def.default.clone());
+            self.set(name, default, command, context)?;
+            return Ok(None);
+        }
+
+        // {option}! and inv{option} toggle a bool option
+        let toggle = arg.strip_suffix('!').or_else(|| arg.strip_prefix("inv").filter(|name| self.def(name).is_some()));
+        if let Some(name) = toggle {
+            let def = self.def(name).ok_or_else(|| OptionError::Unknown(arg.to_string()))?;
+            let value = self.value_for(def, command, context).as_bool().ok_or_else(invalid)?;
+            let name = def.name;
+            self.set(name, OptionValue::Bool(!value), command, context)?;
+            return Ok(None);
+        }
+
+        if let Some(def) = self.def(arg) {
+            // {option} turns a bool option on and shows any other option
+            if def.option_type() != OptionType::Bool {
+                return Ok(Some(self.show(def, command, context)));
+            }
+            let name = def.name;
+            self.set(name, OptionValue::Bool(true), command, context)?;
+            return Ok(None);
+        }
+
+        // no{option} turns a bool option off
+        if let Some(def) = arg.strip_prefix("no").and_then(|name| self.def(name)) {
+            if def.option_type() != OptionType::Bool {
+                return Err(invalid());
+            }
+            let name = def.name;
+            self.set(name, OptionValue::Bool(false), command, context)?;
+            return Ok(None);
+        }
+
+        Err(OptionError::Unknown(arg.to_string()))
+    }
+
+    /// Get the value a set command works with
+    fn value_for(&self, def: &OptionDef, command: SetCommand, context: OptionContext) -> OptionValue {
+        let value = match command {
+            SetCommand::SetGlobal => self.global.get(def.name),
+            _ => self.local_value(def, context).or_else(|| self.global.get(def.name)),
+        };
+        value.cloned().unwrap_or_else(|| def.default.clone())
+    }
+
+    /// Get the local value of an option
+    fn local_value(&self, def: &OptionDef, context: OptionContext) -> Option<&OptionValue> {
+        let id = context.id(def.scope)?;
+        self.local.get(&(def.scope, id))?.get(def.name)
+    }
+
+    /// Show an option the way `:set {option}?` does
+    fn show(&self, def: &OptionDef, command: SetCommand, context: OptionContext) -> String {
+        match self.value_for(def, command, context) {
+            OptionValue::Bool(true) => def.name.to_string(),
+            OptionValue::Bool(false) => format!("no{}", def.name),
+            value => format!("{}={}", def.name, value),
+        }
+    }
+
+    /// List the options, or only those that differ from their default
+    fn list(&self, command: SetCommand, context: OptionContext, all: bool) -> Vec<String> {
+        self.defs.iter()
+            .filter(|def| command != SetCommand::SetLocal || def.scope != OptionScope::Global)
+            .filter(|def| all || self.value_for(def, command, context) != def.default)
+            .map(|def| self.show(def, command, context))
+            .collect()
+    }
+}
+
+/// Split set command arguments on spaces, keeping spaces escaped with `\`
+fn split_args(args: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut chars = args.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(next @ (' ' | '\\')) => current.push(next),
+                Some(next) => {
+                    current.push('\\');
+                    current.push(next);
+                }
+                None => current.push('\\'),
+            },
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    parts.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+
+    parts
+}
+
+/// Work out the new value for `{option}={value}` and friends
+fn assign_value(current: &OptionValue, assign: Assign, text: &str) -> Option<OptionValue> {
+    match current {
+        OptionValue::Bool(_) => None,
+        OptionValue::Number(number) => {
+            let value: i64 = text.parse().ok()?;
+            Some(OptionValue::Number(match assign {
+                Assign::Set => value,
+                Assign::Add => number.checked_add(value)?,
+                Assign::Remove => number.checked_sub(value)?,
+                Assign::Prepend => number.checked_mul(value)?,
+            }))
+        }
+        OptionValue::String(string) => Some(OptionValue::String(match assign {
+            Assign::Set => text.to_string(),
+            Assign::Add => format!("{}{}", string, text),
+            Assign::Remove => string.replacen(text, "", 1),
+            Assign::Prepend => format!("{}{}", text, string),
+        })),
+        OptionValue::List(values) => {
+            let items: Vec<String> = text.split(',')
+                .filter(|item| !item.is_empty())
+                .map(|item| item.to_string())
+                .collect();
+            let mut values = values.clone();
+            match assign {
+                Assign::Set => values = items,
+                Assign::Add => values.extend(items.into_iter().filter(|item| !values.contains(item)).collect::<Vec<_>>()),
+                Assign::Remove => values.retain(|value| !items.contains(value)),
+                Assign::Prepend => {
+                    values.retain(|value| !items.contains(value));
+                    values.splice(0..0, items);
+                }
+            }
+            Some(OptionValue::List(values))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW_1: OptionContext = OptionContext { window: Some(1), buffer: Some(1) };
+    const WINDOW_2: OptionContext = OptionContext { window: Some(2), buffer: Some(2) };
+
+    #[test]
+    fn test_set_arguments() {
+        let mut options = OptionRegistry::new();
+        let context = WINDOW_1;
+
+        options.execute(SetCommand::Set, "noai ts=8 ic", context).unwrap();
+        assert!(!options.get_bool("autoindent", context));
+        assert_eq!(options.get_number("tabstop", context), 8);
+        assert!(options.get_bool("ignorecase", context));
+
+        options.execute(SetCommand::Set, "ic! invnu ts+=2 ts^=3", context).unwrap();
+        assert!(!options.get_bool("ignorecase", context));
+        assert!(!options.get_bool("number", context));
+        assert_eq!(options.get_number("tabstop", context), 30);
+
+        options.execute(SetCommand::Set, "ts& bex=.bak backupdir=/tmp/a\\ b", context).unwrap();
+        assert_eq!(options.get_number("tabstop", context), 4);
+        assert_eq!(options.get_string("backupext", context), ".bak");
+        assert_eq!(options.get_string("backupdir", context), "/tmp/a b");
+
+        assert_eq!(options.execute(SetCommand::Set, "ts? nu ff", context).unwrap(), vec![
+            "tabstop=4".to_string(),
+            "fileformat=unix".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_set_list_options() {
+        let mut options = OptionRegistry::new();
+        let context = WINDOW_1;
+
+        options.execute(SetCommand::Set, "mps+=<:>", context).unwrap();
+        assert_eq!(options.get_list("matchpairs", context), ["(:)", "{:}", "[:]", "<:>"]);
+        options.execute(SetCommand::Set, "mps-=(:) mps^=(:)", context).unwrap();
+        assert_eq!(options.get_list("matchpairs", context), ["(:)", "{:}", "[:]", "<:>"]);
+        options.execute(SetCommand::Set, "cb=", context).unwrap();
+        assert!(options.get_list("clipboard", context).is_empty());
+        options.execute(SetCommand::Set, "wim=longest,list:full", context).unwrap();
+        assert_eq!(options.get_list("wildmode", context), ["longest", "list:full"]);
+    }
+
+    #[test]
+    fn test_set_errors() {
+        let mut options = OptionRegistry::new();
+        let context = WINDOW_1;
+
+        let err = |args: &str, options: &mut OptionRegistry| options.execute(SetCommand::Set, args, context).unwrap_err();
+        assert_eq!(err("bogus", &mut options), OptionError::Unknown("bogus".to_string()));
+        assert_eq!(err("ts=x", &mut options), OptionError::NumberRequired("ts=x".to_string()));
+        assert_eq!(err("ts=0", &mut options), OptionError::InvalidArgument("ts=0".to_string()));
+        assert_eq!(err("ff=amiga", &mut options), OptionError::InvalidArgument("ff=amiga".to_string()));
+        assert_eq!(err("nots", &mut options), OptionError::InvalidArgument("nots".to_string()));
+        assert_eq!(err("ai=1", &mut options), OptionError::InvalidArgument("ai=1".to_string()));
+        assert_eq!(err("mps=x", &mut options), OptionError::InvalidArgument("mps=x".to_string()));
+        assert_eq!(err("wim=full:list", &mut options), OptionError::InvalidArgument("wim=full:list".to_string()));
+        assert_eq!(options.get_number("tabstop", context), 4);
+    }
+
+    #[test]
+    fn test_option_scopes() {
+        let mut options = OptionRegistry::new();
+
+        // :setlocal only affects the current window and buffer
+        options.execute(SetCommand::SetLocal, "nonu ts=2", WINDOW_1).unwrap();
+        assert!(!options.get_bool("number", WINDOW_1));
+        assert!(options.get_bool("number", WINDOW_2));
+        assert_eq!(options.get_number("tabstop", WINDOW_1), 2);
+        assert_eq!(options.get_number("tabstop", WINDOW_2), 4);
+
+        // :setglobal leaves local values alone
+        options.execute(SetCommand::SetGlobal, "ts=8", WINDOW_1).unwrap();
+        assert_eq!(options.get_number("tabstop", WINDOW_1), 2);
+        assert_eq!(options.get_number("tabstop", WINDOW_2), 8);
+        assert_eq!(options.execute(SetCommand::SetGlobal, "ts?", WINDOW_1).unwrap(), vec!["tabstop=8".to_string()]);
+
+        // :set changes both
+        options.execute(SetCommand::Set, "ts=6", WINDOW_1).unwrap();
+        assert_eq!(options.get_global("tabstop"), Some(&OptionValue::Number(6)));
+        assert_eq!(options.get_local("tabstop", WINDOW_1), Some(&OptionValue::Number(6)));
+
+        // :setlocal on a global option sets the global value
+        options.execute(SetCommand::SetLocal, "ic", WINDOW_1).unwrap();
+        assert!(options.get_bool("ignorecase", WINDOW_2));
+
+        options.remove_local(OptionScope::Window, 1);
+        assert!(options.get_bool("number", WINDOW_1));
+    }
+
+    #[test]
+    fn test_option_changes() {
+        let mut options = OptionRegistry::new();
+        options.execute(SetCommand::SetLocal, "ts=2", WINDOW_1).unwrap();
+        options.execute(SetCommand::Set, "ic", WINDOW_1).unwrap();
+
+        assert_eq!(options.take_changes(), vec![
+            OptionChange { name: "tabstop", scope: OptionScope::Buffer, id: Some(1) },
+            OptionChange { name: "ignorecase", scope: OptionScope::Global, id: None },
+        ]);
+        assert!(options.take_changes().is_empty());
+
+        // Values kept elsewhere aren't reported back
+        options.store_local("ff", OptionValue::String("dos".to_string()), WINDOW_1);
+        assert_eq!(options.get_string("fileformat", WINDOW_1), "dos");
+        assert!(options.take_changes().is_empty());
+    }
+
+    #[test]
+    fn test_option_listing() {
+        let mut options = OptionRegistry::from_config(&GeneralConfig::default());
+        assert!(options.take_changes().is_empty());
+        assert!(options.execute(SetCommand::Set, "", WINDOW_1).unwrap().is_empty());
+
+        options.execute(SetCommand::Set, "nowb", WINDOW_1).unwrap();
+        assert_eq!(options.execute(SetCommand::Set, "", WINDOW_1).unwrap(), vec!["nowritebackup".to_string()]);
+
+        let all = options.execute(SetCommand::Set, "all", WINDOW_1).unwrap();
+        assert_eq!(all.len(), options.defs().len());
+        assert!(all.contains(&"tabstop=4".to_string()));
+
+        // :setlocal only lists local options
+        let local = options.execute(SetCommand::SetLocal, "all", WINDOW_1).unwrap();
+        assert!(!local.contains(&"nowritebackup".to_string()));
+    }
+}
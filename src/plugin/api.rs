@@ -7,13 +7,37 @@ use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use crate::buffer::BufferManager;
 use crate::command::ExCommandRegistry;
+use crate::cursor::CursorManager;
 use crate::editor::Editor;
 use crate::mode::ModeManager;
+use crate::selection::SelectionManager;
+
+/// A command registered by a plugin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginCommand {
+    /// Name of the plugin that registered the command
+    pub plugin: String,
+    /// Command name
+    pub name: String,
+    /// Command description
+    pub description: String,
+}
+
+/// A completer registered by a plugin for the arguments of a command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginCompleter {
+    /// Name of the plugin that registered the completer
+    pub plugin: String,
+    /// Name of the command whose arguments are completed
+    pub command: String,
+}
 
 /// Plugin context
 ///
 /// This struct provides access to editor functionality for plugins.
-/// It is shared between the editor and all plugins.
+/// It is shared between the editor and all plugins. The editor lends its
+/// managers to the context while plugin code runs and takes them back
+/// afterwards, so whatever plugins ask of the editor itself is queued.
 pub struct PluginContext {
     /// Buffer manager reference
     buffer_manager: Option<Arc<Mutex<BufferManager>>>,
@@ -21,8 +45,22 @@ pub struct PluginContext {
     mode_manager: Option<Arc<Mutex<ModeManager>>>,
     /// Command registry reference
     command_registry: Option<Arc<Mutex<ExCommandRegistry>>>,
+    /// Cursor manager reference
+    cursor_manager: Option<Arc<Mutex<CursorManager>>>,
+    /// Selection manager reference
+    selection_manager: Option<Arc<Mutex<SelectionManager>>>,
     /// Terminal UI reference
     terminal_ui: Option<Arc<Mutex<crate::ui::TerminalUi>>>,
+    /// Command lines issued by plugins that the editor has not run yet
+    pending_command_lines: Vec<String>,
+    /// Messages plugins gave that the editor has not shown yet
+    pending_messages: Vec<String>,
+    /// Commands registered by plugins that the editor has not picked up yet
+    pending_commands: Vec<PluginCommand>,
+    /// Completers registered by plugins that the editor has not picked up yet
+    pending_completers: Vec<PluginCompleter>,
+    /// Whether a plugin changed editor state since the last synchronization
+    changed: bool,
     /// Custom data storage for plugins
     custom_data: HashMap<String, Vec<u8>>,
 }
@@ -34,7 +72,14 @@ impl PluginContext {
             buffer_manager: None,
             mode_manager: None,
             command_registry: None,
+            cursor_manager: None,
+            selection_manager: None,
             terminal_ui: None,
+            pending_command_lines: Vec::new(),
+            pending_messages: Vec::new(),
+            pending_commands: Vec::new(),
+            pending_completers: Vec::new(),
+            changed: false,
             custom_data: HashMap::new(),
         }
     }
@@ -54,6 +99,16 @@ impl PluginContext {
         self.command_registry = Some(command_registry);
     }
     
+    /// Set the cursor manager reference
+    pub fn set_cursor_manager(&mut self, cursor_manager: Arc<Mutex<CursorManager>>) {
+        self.cursor_manager = Some(cursor_manager);
+    }
+    
+    /// Set the selection manager reference
+    pub fn set_selection_manager(&mut self, selection_manager: Arc<Mutex<SelectionManager>>) {
+        self.selection_manager = Some(selection_manager);
+    }
+    
     /// Set the terminal UI reference
     pub fn set_terminal_ui(&mut self, terminal_ui: Arc<Mutex<crate::ui::TerminalUi>>) {
         self.terminal_ui = Some(terminal_ui);
@@ -74,11 +129,91 @@ impl PluginContext {
         self.command_registry.clone()
     }
     
+    /// Get the cursor manager reference
+    pub fn cursor_manager(&self) -> Option<Arc<Mutex<CursorManager>>> {
+        self.cursor_manager.clone()
+    }
+    
+    /// Get the selection manager reference
+    pub fn selection_manager(&self) -> Option<Arc<Mutex<SelectionManager>>> {
+        self.selection_manager.clone()
+    }
+    
     /// Get the terminal UI reference
     pub fn terminal_ui(&self) -> Option<Arc<Mutex<crate::ui::TerminalUi>>> {
         self.terminal_ui.clone()
     }
     
+    /// Take back the buffer manager
+    pub fn take_buffer_manager(&mut self) -> Option<Arc<Mutex<BufferManager>>> {
+        self.buffer_manager.take()
+    }
+    
+    /// Take back the mode manager
+    pub fn take_mode_manager(&mut self) -> Option<Arc<Mutex<ModeManager>>> {
+        self.mode_manager.take()
+    }
+    
+    /// Take back the cursor manager
+    pub fn take_cursor_manager(&mut self) -> Option<Arc<Mutex<CursorManager>>> {
+        self.cursor_manager.take()
+    }
+    
+    /// Take back the selection manager
+    pub fn take_selection_manager(&mut self) -> Option<Arc<Mutex<SelectionManager>>> {
+        self.selection_manager.take()
+    }
+    
+    /// Record that a plugin changed editor state
+    pub fn mark_changed(&mut self) {
+        self.changed = true;
+    }
+    
+    /// Check whether a plugin changed editor state, clearing the flag
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.changed, false)
+    }
+    
+    /// Queue an Ex command line issued by a plugin
+    pub fn add_command_line(&mut self, command_line: &str) {
+        self.pending_command_lines.push(command_line.to_string());
+    }
+    
+    /// Take the command lines issued by plugins since the last call
+    pub fn take_command_lines(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.pending_command_lines)
+    }
+    
+    /// Queue a message for the editor to show
+    pub fn add_message(&mut self, message: &str) {
+        self.pending_messages.push(message.to_string());
+    }
+    
+    /// Take the messages given by plugins since the last call
+    pub fn take_messages(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.pending_messages)
+    }
+    
+    /// Queue a command registered by a plugin
+    pub fn add_plugin_command(&mut self, command: PluginCommand) {
+        self.pending_commands.push(command);
+    }
+    
+    /// Take the commands registered by plugins since the last call
+    pub fn take_plugin_commands(&mut self) -> Vec<PluginCommand> {
+        std::mem::take(&mut self.pending_commands)
+    }
+    
+    /// Queue a completer registered by a plugin
+    pub fn add_plugin_completer(&mut self, completer: PluginCompleter) {
+        self.pending_completers.push(completer);
+    }
+    
+    /// Take the completers registered by plugins since the last call
+    pub fn take_plugin_completers(&mut self) -> Vec<PluginCompleter> {
+        std::mem::take(&mut self.pending_completers)
+    }
+    
     /// Set custom data
     pub fn set_custom_data(&mut self, key: &str, data: Vec<u8>) {
         self.custom_data.insert(key.to_string(), data);
@@ -107,6 +242,7 @@ impl Default for PluginContext {
 /// They provide a safe way for plugins to interact with the editor.
 pub mod functions {
     use super::*;
+    use crate::cursor::CursorPosition;
     
     /// Get the current buffer ID
     pub fn get_current_buffer_id(context: &PluginContext) -> Option<usize> {
@@ -141,18 +277,97 @@ pub mod functions {
     }
     
     /// Set buffer content
-    pub fn set_buffer_content(context: &PluginContext, buffer_id: usize, content: &str) -> bool {
+    pub fn set_buffer_content(context: &mut PluginContext, buffer_id: usize, content: &str) -> bool {
         if let Some(buffer_manager) = &context.buffer_manager() {
             if let Ok(mut buffer_manager) = buffer_manager.lock() {
                 if let Ok(buffer) = buffer_manager.get_buffer_mut(buffer_id) {
                     // Clear the buffer and insert the new content
-                    let buffer_len = buffer.content().len();
+                    let buffer_len = buffer.content().chars().count();
                     if buffer_len > 0 {
                         if let Err(_) = buffer.delete(0, buffer_len) {
                             return false;
                         }
                     }
                     if let Ok(_) = buffer.insert(0, content) {
+                        context.mark_changed();
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+    
+    /// Get the cursor position
+    pub fn get_cursor_position(context: &PluginContext) -> Option<CursorPosition> {
+        if let Some(cursor_manager) = &context.cursor_manager() {
+            if let Ok(cursor_manager) = cursor_manager.lock() {
+                return Some(cursor_manager.position());
+            }
+        }
+        None
+    }
+    
+    /// Set the cursor position in the current buffer
+    pub fn set_cursor_position(context: &mut PluginContext, line: usize, column: usize) -> bool {
+        // Validate the position against the current buffer
+        if let Some(buffer_manager) = &context.buffer_manager() {
+            if let Ok(buffer_manager) = buffer_manager.lock() {
+                match buffer_manager.current_buffer() {
+                    Ok(buffer) => {
+                        if buffer.position_to_char_idx(line, column).is_err() {
+                            return false;
+                        }
+                    }
+                    Err(_) => return false,
+                }
+            }
+        }
+        
+        if let Some(cursor_manager) = &context.cursor_manager() {
+            if let Ok(mut cursor_manager) = cursor_manager.lock() {
+                cursor_manager.set_position(CursorPosition::new(line, column));
+                context.mark_changed();
+                return true;
+            }
+        }
+        false
+    }
+    
+    /// Convert a line and column position to a character index
+    pub fn position_to_char_idx(context: &PluginContext, buffer_id: usize, line: usize, column: usize) -> Option<usize> {
+        if let Some(buffer_manager) = &context.buffer_manager() {
+            if let Ok(buffer_manager) = buffer_manager.lock() {
+                if let Ok(buffer) = buffer_manager.get_buffer(buffer_id) {
+                    return buffer.position_to_char_idx(line, column).ok();
+                }
+            }
+        }
+        None
+    }
+    
+    /// Insert text into a buffer at a character index
+    pub fn insert_text(context: &mut PluginContext, buffer_id: usize, position: usize, text: &str) -> bool {
+        if let Some(buffer_manager) = &context.buffer_manager() {
+            if let Ok(mut buffer_manager) = buffer_manager.lock() {
+                if let Ok(buffer) = buffer_manager.get_buffer_mut(buffer_id) {
+                    if buffer.insert(position, text).is_ok() {
+                        context.mark_changed();
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+    
+    /// Delete the characters in `start..end` from a buffer
+    pub fn delete_text(context: &mut PluginContext, buffer_id: usize, start: usize, end: usize) -> bool {
+        if let Some(buffer_manager) = &context.buffer_manager() {
+            if let Ok(mut buffer_manager) = buffer_manager.lock() {
+                if let Ok(buffer) = buffer_manager.get_buffer_mut(buffer_id) {
+                    if buffer.delete(start, end).is_ok() {
+                        context.mark_changed();
                         return true;
                     }
                 }
@@ -161,6 +376,64 @@ pub mod functions {
         false
     }
     
+    /// Create a new buffer
+    ///
+    /// Scratch buffers are not associated with a file. Buffers that are not
+    /// hidden become the current buffer.
+    pub fn create_buffer(context: &mut PluginContext, name: &str, scratch: bool, hidden: bool) -> Option<usize> {
+        if let Some(buffer_manager) = &context.buffer_manager() {
+            if let Ok(mut buffer_manager) = buffer_manager.lock() {
+                let buffer_id = buffer_manager.create_buffer().ok()?;
+                
+                if let Ok(buffer) = buffer_manager.get_buffer_mut(buffer_id) {
+                    buffer.set_name(name.to_string());
+                    if scratch {
+                        buffer.set_buffer_type(crate::buffer::BufferType::NoFile);
+                    }
+                }
+                
+                if !hidden {
+                    buffer_manager.set_current_buffer(buffer_id).ok()?;
+                }
+                
+                context.mark_changed();
+                return Some(buffer_id);
+            }
+        }
+        None
+    }
+    
+    /// Get the current selection as a character range in the current buffer
+    pub fn get_selection(context: &PluginContext) -> Option<(usize, usize)> {
+        let selection = {
+            let selection_manager = context.selection_manager()?;
+            let selection_manager = selection_manager.lock().ok()?;
+            selection_manager.current_selection()?.clone()
+        };
+        
+        let buffer_manager = context.buffer_manager()?;
+        let buffer_manager = buffer_manager.lock().ok()?;
+        let buffer = buffer_manager.current_buffer().ok()?;
+        
+        // Clamp both ends to their lines; the end of a selection is inclusive
+        let (start_line, start_col) = selection.normalized_start();
+        let (end_line, end_col) = selection.normalized_end();
+        let start_len = buffer.line_length(start_line).ok()?;
+        let end_len = buffer.line_length(end_line).ok()?;
+        let start = buffer.position_to_char_idx(start_line, start_col.min(start_len)).ok()?;
+        let end = buffer.position_to_char_idx(end_line, end_col.saturating_add(1).min(end_len)).ok()?;
+        
+        Some((start, end.max(start)))
+    }
+    
+    /// Execute an Ex command line
+    ///
+    /// The command line is queued, and the editor runs it once the plugin
+    /// call returns and the editor has its state back.
+    pub fn execute_command(context: &mut PluginContext, command_line: &str) {
+        context.add_command_line(command_line.trim_start_matches(':'));
+    }
+    
     /// Register a command
     pub fn register_command(context: &PluginContext, name: &str, handler: Box<dyn Fn(&[&str]) -> Result<(), String> + Send + Sync + 'static>) -> bool {
         if let Some(command_registry) = &context.command_registry() {
@@ -0,0 +1,512 @@
+//! Host functions for WASM plugins
+//!
+//! This module registers the `xvim` import module in the wasmtime linker. The
+//! functions route plugin calls to the editor through the shared `PluginContext`.
+//!
+//! # ABI
+//!
+//! - Strings are passed as `(ptr: i32, len: i32)` pairs pointing at UTF-8 bytes in
+//!   the plugin's linear memory.
+//! - Structured values (plugin info, cursor positions, modes, selections) are
+//!   passed as JSON strings using the serde representation of the types in the
+//!   `xvim_plugin_api` crate.
+//! - Buffer IDs, positions and character indices are passed as `i64`.
+//! - Functions that return data call the plugin's `alloc` export, copy the data
+//!   into the new buffer and return `(ptr << 32) | len` as an `i64`. The plugin
+//!   owns the returned buffer.
+//! - A negative return value is one of the `ERR_*` error codes. Other `i32`
+//!   results are `0` on success.
+
+use std::path::Path;
+use anyhow::Result;
+use wasmtime::{Caller, Linker, Memory};
+
+use super::api::{functions, PluginCommand, PluginCompleter};
+use super::sandbox::SandboxError;
+use super::wasm::PluginState;
+
+/// Name of the host import module
+pub const HOST_MODULE: &str = "xvim";
+
+/// An argument could not be read or decoded
+pub const ERR_INVALID_ARGUMENT: i32 = -1;
+/// The requested buffer or value does not exist
+pub const ERR_NOT_FOUND: i32 = -2;
+/// The editor component needed for the call is not available
+pub const ERR_UNAVAILABLE: i32 = -3;
+/// The operation failed
+pub const ERR_FAILED: i32 = -4;
+/// The plugin lacks the capability for the operation
+pub const ERR_PERMISSION_DENIED: i32 = -5;
+
+/// Get the plugin's exported memory
+fn memory(caller: &mut Caller<'_, PluginState>) -> Option<Memory> {
+    caller.get_export("memory").and_then(|export| export.into_memory())
+}
+
+/// Read a UTF-8 string from the plugin's memory
+fn read_string(caller: &mut Caller<'_, PluginState>, ptr: i32, len: i32) -> Result<String, i32> {
+    if len < 0 {
+        return Err(ERR_INVALID_ARGUMENT);
+    }
+    if len == 0 {
+        return Ok(String::new());
+    }
+
+    let memory = memory(caller).ok_or(ERR_INVALID_ARGUMENT)?;
+    let mut bytes = vec![0u8; len as usize];
+    memory.read(&caller, ptr as u32 as usize, &mut bytes)
+        .map_err(|_| ERR_INVALID_ARGUMENT)?;
+
+    String::from_utf8(bytes).map_err(|_| ERR_INVALID_ARGUMENT)
+}
+
+/// Read and decode a JSON value from the plugin's memory
+fn read_json<T: serde::de::DeserializeOwned>(caller: &mut Caller<'_, PluginState>, ptr: i32, len: i32) -> Result<T, i32> {
+    let json = read_string(caller, ptr, len)?;
+    serde_json::from_str(&json).map_err(|_| ERR_INVALID_ARGUMENT)
+}
+
+/// Copy bytes into a buffer allocated by the plugin, returning the packed pointer
+fn write_bytes(caller: &mut Caller<'_, PluginState>, bytes: &[u8]) -> i64 {
+    if bytes.is_empty() {
+        return 0;
+    }
+
+    let memory = match memory(caller) {
+        Some(memory) => memory,
+        None => return ERR_UNAVAILABLE as i64,
+    };
+    let alloc = match caller.get_export("alloc")
+        .and_then(|export| export.into_func())
+        .and_then(|func| func.typed::<i32, i32>(&caller).ok())
+    {
+        Some(alloc) => alloc,
+        None => return ERR_UNAVAILABLE as i64,
+    };
+
+    let len = match i32::try_from(bytes.len()) {
+        Ok(len) => len,
+        Err(_) => return ERR_FAILED as i64,
+    };
+    let ptr = match alloc.call(&mut *caller, len) {
+        Ok(ptr) => ptr,
+        Err(_) => return ERR_FAILED as i64,
+    };
+    if memory.write(&mut *caller, ptr as u32 as usize, bytes).is_err() {
+        return ERR_FAILED as i64;
+    }
+
+    ((ptr as u32 as i64) << 32) | len as u32 as i64
+}
+
+/// Serialize a value as JSON into the plugin's memory
+fn write_json<T: serde::Serialize>(caller: &mut Caller<'_, PluginState>, value: &T) -> i64 {
+    match serde_json::to_vec(value) {
+        Ok(bytes) => write_bytes(caller, &bytes),
+        Err(_) => ERR_FAILED as i64,
+    }
+}
+
+/// Convert a plugin-supplied integer to an index
+fn to_index(value: i64) -> Result<usize, i32> {
+    usize::try_from(value).map_err(|_| ERR_INVALID_ARGUMENT)
+}
+
+/// Convert a boolean outcome to a status code
+fn status(ok: bool, error: i32) -> i32 {
+    if ok { 0 } else { error }
+}
+
+/// Convert a sandbox error to an error code, logging denied operations
+fn sandbox_error(caller: &Caller<'_, PluginState>, err: &SandboxError) -> i32 {
+    match err {
+        SandboxError::PermissionDenied(_) => {
+            log::warn!("[{}] {}", caller.data().name, err);
+            ERR_PERMISSION_DENIED
+        }
+        SandboxError::Io(_) => ERR_FAILED,
+    }
+}
+
+/// Plugin metadata sent with `register_plugin`
+#[derive(Debug, Clone, serde::Deserialize)]
+struct HostPluginInfo {
+    name: String,
+    version: String,
+    description: String,
+    author: String,
+}
+
+/// Cursor position exchanged with plugins
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+struct HostCursorPosition {
+    line: usize,
+    column: usize,
+}
+
+/// Convert an editor mode to the `EditorMode` representation used by the SDK
+fn mode_to_json(mode: crate::mode::Mode) -> serde_json::Value {
+    use crate::mode::Mode;
+
+    match mode {
+        Mode::Normal => serde_json::json!("Normal"),
+        Mode::Insert => serde_json::json!("Insert"),
+        Mode::Visual => serde_json::json!("Visual"),
+        Mode::VisualLine => serde_json::json!("VisualLine"),
+        Mode::VisualBlock => serde_json::json!("VisualBlock"),
+        Mode::Command => serde_json::json!("Command"),
+        Mode::Replace => serde_json::json!("Replace"),
+        Mode::Terminal => serde_json::json!("Terminal"),
+        Mode::OperatorPending => serde_json::json!({ "Custom": "OperatorPending" }),
+    }
+}
+
+/// Register the `xvim` host functions in a linker
+pub fn add_to_linker(linker: &mut Linker<PluginState>) -> Result<()> {
+    // Plugin registration
+    linker.func_wrap(HOST_MODULE, "register_plugin",
+        |mut caller: Caller<'_, PluginState>, ptr: i32, len: i32| -> i32 {
+            let info: HostPluginInfo = match read_json(&mut caller, ptr, len) {
+                Ok(info) => info,
+                Err(code) => return code,
+            };
+
+            let state = caller.data_mut();
+            state.metadata = Some(super::wasm::PluginMetadata {
+                name: info.name,
+                version: info.version,
+                description: info.description,
+                author: info.author,
+            });
+            0
+        })?;
+
+    linker.func_wrap(HOST_MODULE, "register_command",
+        |mut caller: Caller<'_, PluginState>, name_ptr: i32, name_len: i32, desc_ptr: i32, desc_len: i32| -> i32 {
+            let name = match read_string(&mut caller, name_ptr, name_len) {
+                Ok(name) if !name.is_empty() => name,
+                Ok(_) => return ERR_INVALID_ARGUMENT,
+                Err(code) => return code,
+            };
+            let description = match read_string(&mut caller, desc_ptr, desc_len) {
+                Ok(description) => description,
+                Err(code) => return code,
+            };
+
+            let state = caller.data();
+            let command = PluginCommand {
+                plugin: state.name.clone(),
+                name,
+                description,
+            };
+            match state.context.lock() {
+                Ok(mut context) => {
+                    context.add_plugin_command(command);
+                    0
+                }
+                Err(_) => ERR_UNAVAILABLE,
+            }
+        })?;
+
+    linker.func_wrap(HOST_MODULE, "register_completer",
+        |mut caller: Caller<'_, PluginState>, ptr: i32, len: i32| -> i32 {
+            let command = match read_string(&mut caller, ptr, len) {
+                Ok(command) if !command.is_empty() => command,
+                Ok(_) => return ERR_INVALID_ARGUMENT,
+                Err(code) => return code,
+            };
+
+            let state = caller.data();
+            let completer = PluginCompleter {
+                plugin: state.name.clone(),
+                command,
+            };
+            match state.context.lock() {
+                Ok(mut context) => {
+                    context.add_plugin_completer(completer);
+                    0
+                }
+                Err(_) => ERR_UNAVAILABLE,
+            }
+        })?;
+
+    linker.func_wrap(HOST_MODULE, "register_event_handler",
+        |mut caller: Caller<'_, PluginState>, ptr: i32, len: i32| -> i32 {
+            let event = match read_string(&mut caller, ptr, len) {
+                Ok(event) => event,
+                Err(code) => return code,
+            };
+
+            let state = caller.data_mut();
+            if !state.subscriptions.contains(&event) {
+                state.subscriptions.push(event);
+            }
+            0
+        })?;
+
+    // Messages
+    linker.func_wrap(HOST_MODULE, "editor_message",
+        |mut caller: Caller<'_, PluginState>, ptr: i32, len: i32| -> i32 {
+            let message = match read_string(&mut caller, ptr, len) {
+                Ok(message) => message,
+                Err(code) => return code,
+            };
+
+            // The editor shows the message on its last line
+            match caller.data().context.lock() {
+                Ok(mut context) => {
+                    context.add_message(&message);
+                    0
+                }
+                Err(_) => ERR_UNAVAILABLE,
+            }
+        })?;
+
+    linker.func_wrap(HOST_MODULE, "log_message",
+        |mut caller: Caller<'_, PluginState>, ptr: i32, len: i32| -> i32 {
+            match read_string(&mut caller, ptr, len) {
+                Ok(message) => {
+                    log::info!("[{}] {}", caller.data().name, message);
+                    0
+                }
+                Err(code) => code,
+            }
+        })?;
+
+    // Editor state
+    linker.func_wrap(HOST_MODULE, "get_current_buffer_id",
+        |caller: Caller<'_, PluginState>| -> i64 {
+            let context = match caller.data().context.lock() {
+                Ok(context) => context,
+                Err(_) => return ERR_UNAVAILABLE as i64,
+            };
+            match functions::get_current_buffer_id(&context) {
+                Some(id) => id as i64,
+                None => ERR_NOT_FOUND as i64,
+            }
+        })?;
+
+    linker.func_wrap(HOST_MODULE, "get_current_mode",
+        |mut caller: Caller<'_, PluginState>| -> i64 {
+            let mode = {
+                let context = match caller.data().context.lock() {
+                    Ok(context) => context,
+                    Err(_) => return ERR_UNAVAILABLE as i64,
+                };
+                let mode_manager = match context.mode_manager() {
+                    Some(mode_manager) => mode_manager,
+                    None => return ERR_UNAVAILABLE as i64,
+                };
+                let guard = match mode_manager.lock() {
+                    Ok(mode_manager) => mode_manager,
+                    Err(_) => return ERR_UNAVAILABLE as i64,
+                };
+                guard.current_mode()
+            };
+            write_json(&mut caller, &mode_to_json(mode))
+        })?;
+
+    // Buffer access
+    linker.func_wrap(HOST_MODULE, "get_buffer_content",
+        |mut caller: Caller<'_, PluginState>, buffer_id: i64| -> i64 {
+            let buffer_id = match to_index(buffer_id) {
+                Ok(id) => id,
+                Err(code) => return code as i64,
+            };
+            let content = match caller.data().context.lock() {
+                Ok(context) => functions::get_buffer_content(&context, buffer_id),
+                Err(_) => return ERR_UNAVAILABLE as i64,
+            };
+            match content {
+                Some(content) => write_bytes(&mut caller, content.as_bytes()),
+                None => ERR_NOT_FOUND as i64,
+            }
+        })?;
+
+    linker.func_wrap(HOST_MODULE, "set_buffer_content",
+        |mut caller: Caller<'_, PluginState>, buffer_id: i64, ptr: i32, len: i32| -> i32 {
+            let buffer_id = match to_index(buffer_id) {
+                Ok(id) => id,
+                Err(code) => return code,
+            };
+            let content = match read_string(&mut caller, ptr, len) {
+                Ok(content) => content,
+                Err(code) => return code,
+            };
+            match caller.data().context.lock() {
+                Ok(mut context) => status(functions::set_buffer_content(&mut context, buffer_id, &content), ERR_NOT_FOUND),
+                Err(_) => ERR_UNAVAILABLE,
+            }
+        })?;
+
+    linker.func_wrap(HOST_MODULE, "position_to_char_idx",
+        |caller: Caller<'_, PluginState>, buffer_id: i64, line: i64, column: i64| -> i64 {
+            let (buffer_id, line, column) = match (to_index(buffer_id), to_index(line), to_index(column)) {
+                (Ok(buffer_id), Ok(line), Ok(column)) => (buffer_id, line, column),
+                _ => return ERR_INVALID_ARGUMENT as i64,
+            };
+            let context = match caller.data().context.lock() {
+                Ok(context) => context,
+                Err(_) => return ERR_UNAVAILABLE as i64,
+            };
+            match functions::position_to_char_idx(&context, buffer_id, line, column) {
+                Some(idx) => idx as i64,
+                None => ERR_NOT_FOUND as i64,
+            }
+        })?;
+
+    linker.func_wrap(HOST_MODULE, "insert_text",
+        |mut caller: Caller<'_, PluginState>, buffer_id: i64, position: i64, ptr: i32, len: i32| -> i32 {
+            let (buffer_id, position) = match (to_index(buffer_id), to_index(position)) {
+                (Ok(buffer_id), Ok(position)) => (buffer_id, position),
+                _ => return ERR_INVALID_ARGUMENT,
+            };
+            let text = match read_string(&mut caller, ptr, len) {
+                Ok(text) => text,
+                Err(code) => return code,
+            };
+            match caller.data().context.lock() {
+                Ok(mut context) => status(functions::insert_text(&mut context, buffer_id, position, &text), ERR_FAILED),
+                Err(_) => ERR_UNAVAILABLE,
+            }
+        })?;
+
+    linker.func_wrap(HOST_MODULE, "delete_text",
+        |caller: Caller<'_, PluginState>, buffer_id: i64, start: i64, end: i64| -> i32 {
+            let (buffer_id, start, end) = match (to_index(buffer_id), to_index(start), to_index(end)) {
+                (Ok(buffer_id), Ok(start), Ok(end)) => (buffer_id, start, end),
+                _ => return ERR_INVALID_ARGUMENT,
+            };
+            match caller.data().context.lock() {
+                Ok(mut context) => status(functions::delete_text(&mut context, buffer_id, start, end), ERR_FAILED),
+                Err(_) => ERR_UNAVAILABLE,
+            }
+        })?;
+
+    linker.func_wrap(HOST_MODULE, "create_buffer",
+        |mut caller: Caller<'_, PluginState>, ptr: i32, len: i32, scratch: i32, hidden: i32| -> i64 {
+            let name = match read_string(&mut caller, ptr, len) {
+                Ok(name) => name,
+                Err(code) => return code as i64,
+            };
+            let buffer_id = match caller.data().context.lock() {
+                Ok(mut context) => functions::create_buffer(&mut context, &name, scratch != 0, hidden != 0),
+                Err(_) => return ERR_UNAVAILABLE as i64,
+            };
+            match buffer_id {
+                Some(id) => id as i64,
+                None => ERR_FAILED as i64,
+            }
+        })?;
+
+    // Cursor and selection
+    linker.func_wrap(HOST_MODULE, "get_cursor_position",
+        |mut caller: Caller<'_, PluginState>| -> i64 {
+            let position = match caller.data().context.lock() {
+                Ok(context) => functions::get_cursor_position(&context),
+                Err(_) => return ERR_UNAVAILABLE as i64,
+            };
+            match position {
+                Some(position) => write_json(&mut caller, &HostCursorPosition {
+                    line: position.line,
+                    column: position.column,
+                }),
+                None => ERR_UNAVAILABLE as i64,
+            }
+        })?;
+
+    linker.func_wrap(HOST_MODULE, "set_cursor_position",
+        |mut caller: Caller<'_, PluginState>, ptr: i32, len: i32| -> i32 {
+            let position: HostCursorPosition = match read_json(&mut caller, ptr, len) {
+                Ok(position) => position,
+                Err(code) => return code,
+            };
+            match caller.data().context.lock() {
+                Ok(mut context) => status(functions::set_cursor_position(&mut context, position.line, position.column), ERR_INVALID_ARGUMENT),
+                Err(_) => ERR_UNAVAILABLE,
+            }
+        })?;
+
+    linker.func_wrap(HOST_MODULE, "get_selection",
+        |mut caller: Caller<'_, PluginState>| -> i64 {
+            let selection = match caller.data().context.lock() {
+                Ok(context) => functions::get_selection(&context),
+                Err(_) => return ERR_UNAVAILABLE as i64,
+            };
+            write_json(&mut caller, &selection)
+        })?;
+
+    // Commands
+    linker.func_wrap(HOST_MODULE, "execute_command",
+        |mut caller: Caller<'_, PluginState>, ptr: i32, len: i32| -> i32 {
+            let command_line = match read_string(&mut caller, ptr, len) {
+                Ok(command_line) => command_line,
+                Err(code) => return code,
+            };
+
+            // The editor runs the command line once the plugin returns
+            match caller.data().context.lock() {
+                Ok(mut context) => {
+                    functions::execute_command(&mut context, &command_line);
+                    0
+                }
+                Err(_) => ERR_UNAVAILABLE,
+            }
+        })?;
+
+    // Filesystem and processes, checked against the plugin's capabilities
+    linker.func_wrap(HOST_MODULE, "read_file",
+        |mut caller: Caller<'_, PluginState>, ptr: i32, len: i32| -> i64 {
+            let path = match read_string(&mut caller, ptr, len) {
+                Ok(path) => path,
+                Err(code) => return code as i64,
+            };
+            match caller.data().capabilities.read_file(Path::new(&path)) {
+                Ok(content) => write_bytes(&mut caller, content.as_bytes()),
+                Err(err) => sandbox_error(&caller, &err) as i64,
+            }
+        })?;
+
+    linker.func_wrap(HOST_MODULE, "write_file",
+        |mut caller: Caller<'_, PluginState>, path_ptr: i32, path_len: i32, content_ptr: i32, content_len: i32| -> i32 {
+            let path = match read_string(&mut caller, path_ptr, path_len) {
+                Ok(path) => path,
+                Err(code) => return code,
+            };
+            let content = match read_string(&mut caller, content_ptr, content_len) {
+                Ok(content) => content,
+                Err(code) => return code,
+            };
+            match caller.data().capabilities.write_file(Path::new(&path), &content) {
+                Ok(()) => 0,
+                Err(err) => sandbox_error(&caller, &err),
+            }
+        })?;
+
+    linker.func_wrap(HOST_MODULE, "list_files",
+        |mut caller: Caller<'_, PluginState>, ptr: i32, len: i32| -> i64 {
+            let root = match read_string(&mut caller, ptr, len) {
+                Ok(root) => root,
+                Err(code) => return code as i64,
+            };
+            match caller.data().capabilities.list_files(Path::new(&root)) {
+                Ok(files) => write_json(&mut caller, &files),
+                Err(err) => sandbox_error(&caller, &err) as i64,
+            }
+        })?;
+
+    linker.func_wrap(HOST_MODULE, "execute_shell_command",
+        |mut caller: Caller<'_, PluginState>, ptr: i32, len: i32| -> i64 {
+            let command = match read_string(&mut caller, ptr, len) {
+                Ok(command) => command,
+                Err(code) => return code as i64,
+            };
+            match caller.data().capabilities.execute_command(&command) {
+                Ok(output) => write_bytes(&mut caller, output.as_bytes()),
+                Err(err) => sandbox_error(&caller, &err) as i64,
+            }
+        })?;
+
+    Ok(())
+}
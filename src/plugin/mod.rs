@@ -18,7 +18,9 @@ pub mod commands;
 pub mod debug;
 pub mod dependency;
 pub mod events;
+pub mod host;
 pub mod network;
+pub mod sandbox;
 pub mod ui;
 pub mod wasm;
 
@@ -29,7 +31,7 @@ use dependency::DependencyManager;
 use events::EventManager;
 use network::NetworkManager;
 use ui::UiManager;
-use wasm::WasmRuntime;
+use wasm::{PluginLimits, WasmRuntime};
 
 /// Plugin information
 #[derive(Debug, Clone)]
@@ -75,9 +77,6 @@ pub struct PluginManager {
 impl PluginManager {
     /// Create a new plugin manager
     pub fn new() -> Self {
-        // Create a new WASM runtime
-        let runtime = WasmRuntime::new();
-        
         // Create a new event manager
         let event_manager = EventManager::new();
         
@@ -96,6 +95,9 @@ impl PluginManager {
         // Create a shared plugin context
         let context = Arc::new(Mutex::new(PluginContext::new()));
         
+        // Create a new WASM runtime sharing the plugin context
+        let runtime = WasmRuntime::with_context(context.clone());
+        
         // Default plugin directory
         let plugin_dir = PathBuf::from("plugins");
         
@@ -174,6 +176,17 @@ impl PluginManager {
             self.dependency_manager.check_dependencies()?;
         }
         
+        // Apply the capabilities declared in the plugin manifest, if any
+        let manifest_path = path.with_extension("manifest.json");
+        if manifest_path.exists() {
+            let manifest = sandbox::PluginManifest::load_from_file(&manifest_path)?;
+            
+            if let Some(permission) = manifest.capabilities.network.clone() {
+                self.network_manager.set_permission(name, permission);
+            }
+            self.runtime.set_plugin_capabilities(name, manifest.capabilities);
+        }
+        
         // Log plugin loading
         self.debug_manager.log(
             "plugin_manager",
@@ -185,13 +198,22 @@ impl PluginManager {
         // Load the plugin into the WASM runtime
         self.runtime.load_plugin(&path, name)?;
         
-        // Create plugin info
-        let info = PluginInfo {
-            name: name.to_string(),
-            version: "0.1.0".to_string(), // TODO: Get from plugin metadata
-            description: "".to_string(),  // TODO: Get from plugin metadata
-            author: "".to_string(),       // TODO: Get from plugin metadata
-            path,
+        // Create plugin info from the metadata the plugin reported, if any
+        let info = match self.runtime.plugin_metadata(name) {
+            Some(metadata) => PluginInfo {
+                name: name.to_string(),
+                version: metadata.version,
+                description: metadata.description,
+                author: metadata.author,
+                path,
+            },
+            None => PluginInfo {
+                name: name.to_string(),
+                version: "0.1.0".to_string(),
+                description: "".to_string(),
+                author: "".to_string(),
+                path,
+            },
         };
         
         // Add the plugin to the map
@@ -293,6 +315,61 @@ impl PluginManager {
         self.ai_conversation_manager = Some(manager);
     }
     
+    /// Set the default resource limits for plugins loaded afterwards
+    pub fn set_limits(&mut self, limits: PluginLimits) {
+        self.runtime.set_limits(limits);
+    }
+    
+    /// Set the resource limits for a specific plugin, applied when it is loaded
+    pub fn set_plugin_limits(&mut self, name: &str, limits: PluginLimits) {
+        self.runtime.set_plugin_limits(name, limits);
+    }
+    
+    /// Check whether a plugin was disabled after repeated faults
+    pub fn is_plugin_disabled(&self, name: &str) -> bool {
+        self.runtime.is_disabled(name)
+    }
+    
+    /// Re-enable a plugin that was disabled after repeated faults
+    pub fn enable_plugin(&mut self, name: &str) -> Result<()> {
+        self.runtime.enable_plugin(name)?;
+        
+        // Log the plugin being re-enabled
+        self.debug_manager.log(
+            "plugin_manager",
+            debug::LogLevel::Info,
+            &format!("Re-enabled plugin: {}", name),
+            None,
+        )
+    }
+    
+    /// Get the names of plugins that are currently disabled
+    fn disabled_plugins(&self) -> Vec<String> {
+        self.plugins.keys()
+            .filter(|name| self.runtime.is_disabled(name))
+            .cloned()
+            .collect()
+    }
+    
+    /// Log plugins that were disabled since `previously_disabled` was taken
+    fn log_disabled_plugins(&mut self, previously_disabled: &[String]) -> Result<()> {
+        for name in self.disabled_plugins() {
+            if previously_disabled.contains(&name) {
+                continue;
+            }
+            
+            let faults = self.runtime.plugin_faults(&name);
+            self.debug_manager.log(
+                &name,
+                debug::LogLevel::Error,
+                &format!("Plugin disabled after repeated faults: {}", name),
+                Some(&format!("faults: {}", faults)),
+            )?;
+        }
+        
+        Ok(())
+    }
+    
     /// Call a function in a plugin
     pub fn call_function(&mut self, plugin_name: &str, function_name: &str, args: &[u8]) -> Result<Vec<u8>> {
         // Start a trace for the function call
@@ -303,10 +380,12 @@ impl PluginManager {
         )?;
         
         // Call the function
+        let disabled = self.disabled_plugins();
         let result = self.runtime.call_function(plugin_name, function_name, args);
         
         // End the trace
         self.debug_manager.end_trace(&trace_id)?;
+        self.log_disabled_plugins(&disabled)?;
         
         // Log the result
         match &result {
@@ -331,6 +410,57 @@ impl PluginManager {
         result
     }
     
+    /// Call a command handler in a specific plugin
+    ///
+    /// Returns whether the plugin handled the command.
+    pub fn call_command(&mut self, plugin_name: &str, command: &str, args: &[&str]) -> Result<bool> {
+        // Start a trace for the command call
+        let trace_id = self.debug_manager.start_trace(
+            plugin_name,
+            &format!("call_command: {}", command),
+            Some(&format!("args: {:?}", args)),
+        )?;
+        
+        // Call the command handler
+        let disabled = self.disabled_plugins();
+        let result = self.runtime.call_command(plugin_name, command, args);
+        
+        // End the trace
+        self.debug_manager.end_trace(&trace_id)?;
+        self.log_disabled_plugins(&disabled)?;
+        
+        // Log failures
+        if let Err(err) = &result {
+            self.debug_manager.log(
+                plugin_name,
+                debug::LogLevel::Error,
+                &format!("Command failed: {}", command),
+                Some(&format!("error: {}", err)),
+            )?;
+        }
+        
+        result
+    }
+    
+    /// Ask a plugin for the matches of the argument typed for one of its commands
+    pub fn complete(&mut self, plugin_name: &str, command: &str, arg_lead: &str, line: &str) -> Result<Vec<String>> {
+        let disabled = self.disabled_plugins();
+        let result = self.runtime.complete(plugin_name, command, arg_lead, line);
+        self.log_disabled_plugins(&disabled)?;
+        
+        // Log failures
+        if let Err(err) = &result {
+            self.debug_manager.log(
+                plugin_name,
+                debug::LogLevel::Error,
+                &format!("Completion failed: {}", command),
+                Some(&format!("error: {}", err)),
+            )?;
+        }
+        
+        result
+    }
+    
     /// Execute a command in a plugin
     pub fn execute_command(&mut self, command: &str, args: &[&str]) -> Result<()> {
         // Log the command execution
@@ -352,20 +482,38 @@ impl PluginManager {
         // For now, we'll just try to call the command handler in each plugin
         // In a real implementation, we would have a registry of commands
         let mut success = false;
+        let disabled = self.disabled_plugins();
+        let plugin_names: Vec<String> = self.plugins.keys().cloned().collect();
         
-        for plugin_name in self.plugins.keys() {
-            // Try to call the command handler in this plugin
-            let result = self.runtime.call_command(plugin_name, command, args);
+        for plugin_name in &plugin_names {
+            // Skip plugins that were disabled after repeated faults
+            if self.runtime.is_disabled(plugin_name) {
+                continue;
+            }
             
-            if let Ok(true) = result {
-                // The plugin handled the command
-                success = true;
-                break;
+            // Try to call the command handler in this plugin
+            match self.runtime.call_command(plugin_name, command, args) {
+                Ok(true) => {
+                    // The plugin handled the command
+                    success = true;
+                    break;
+                }
+                Ok(false) => {}
+                Err(err) => {
+                    // A failing plugin does not keep the others from handling the command
+                    self.debug_manager.log(
+                        plugin_name,
+                        debug::LogLevel::Error,
+                        &format!("Command failed: {}", command),
+                        Some(&format!("error: {}", err)),
+                    )?;
+                }
             }
         }
         
         // End the trace
         self.debug_manager.end_trace(&trace_id)?;
+        self.log_disabled_plugins(&disabled)?;
         
         if success {
             Ok(())
@@ -406,12 +554,24 @@ impl PluginManager {
         self.event_manager.dispatch_event(event_type, event);
         
         // Send the event to all plugins
-        self.runtime.send_event(event)?;
+        let disabled = self.disabled_plugins();
+        let result = self.runtime.send_event(event);
         
         // End the trace
         self.debug_manager.end_trace(&trace_id)?;
+        self.log_disabled_plugins(&disabled)?;
+        
+        // Log failures
+        if let Err(err) = &result {
+            self.debug_manager.log(
+                "plugin_manager",
+                debug::LogLevel::Error,
+                &format!("Event dispatch failed: {}", event_type),
+                Some(&format!("error: {}", err)),
+            )?;
+        }
         
-        Ok(())
+        result
     }
 }
 
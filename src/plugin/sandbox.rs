@@ -0,0 +1,500 @@
+//! Filesystem and process sandbox for plugins
+//!
+//! This module implements the capability model that controls which files a
+//! plugin may read or write and which programs it may run. Like the network
+//! permissions, capabilities are granted per plugin and default to no access.
+//! They are declared in the plugin's manifest file (`my_plugin.manifest.json`).
+
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use super::network::NetworkPermission;
+
+/// Access granted to a directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FilesystemAccess {
+    /// Files may be read and listed
+    ReadOnly,
+    /// Files may be read, listed, created and modified
+    ReadWrite,
+}
+
+/// A directory a plugin has access to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryPermission {
+    /// Directory path, relative paths are resolved against the working directory
+    pub path: PathBuf,
+    /// Access granted to the directory and everything below it
+    pub access: FilesystemAccess,
+}
+
+/// Filesystem permission
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilesystemPermission {
+    /// Directories the plugin has access to
+    #[serde(default)]
+    directories: Vec<DirectoryPermission>,
+}
+
+impl FilesystemPermission {
+    /// Create a new filesystem permission with no access
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Create a new filesystem permission with read-only access to some directories
+    pub fn read_only(paths: &[&str]) -> Self {
+        let mut permission = Self::none();
+        for path in paths {
+            permission.add_directory(path, FilesystemAccess::ReadOnly);
+        }
+        permission
+    }
+
+    /// Create a new filesystem permission with read-write access to some directories
+    pub fn read_write(paths: &[&str]) -> Self {
+        let mut permission = Self::none();
+        for path in paths {
+            permission.add_directory(path, FilesystemAccess::ReadWrite);
+        }
+        permission
+    }
+
+    /// Grant access to a directory
+    pub fn add_directory<P: AsRef<Path>>(&mut self, path: P, access: FilesystemAccess) {
+        self.directories.push(DirectoryPermission {
+            path: path.as_ref().to_path_buf(),
+            access,
+        });
+    }
+
+    /// Revoke access to a directory
+    pub fn remove_directory<P: AsRef<Path>>(&mut self, path: P) {
+        self.directories.retain(|directory| directory.path != path.as_ref());
+    }
+
+    /// Get the directories the plugin has access to
+    pub fn directories(&self) -> &[DirectoryPermission] {
+        &self.directories
+    }
+
+    /// Get the access granted to a path, if any
+    ///
+    /// When several directories contain the path, the most permissive access wins.
+    pub fn access(&self, path: &Path) -> Option<FilesystemAccess> {
+        let path = resolve_path(path);
+        let mut access = None;
+
+        for directory in &self.directories {
+            if !path.starts_with(resolve_path(&directory.path)) {
+                continue;
+            }
+
+            match directory.access {
+                FilesystemAccess::ReadWrite => return Some(FilesystemAccess::ReadWrite),
+                FilesystemAccess::ReadOnly => access = Some(FilesystemAccess::ReadOnly),
+            }
+        }
+
+        access
+    }
+
+    /// Check if a path may be read
+    pub fn is_read_allowed(&self, path: &Path) -> bool {
+        self.access(path).is_some()
+    }
+
+    /// Check if a path may be written
+    pub fn is_write_allowed(&self, path: &Path) -> bool {
+        self.access(path) == Some(FilesystemAccess::ReadWrite)
+    }
+}
+
+/// Process permission level
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProcessPermissionLevel {
+    /// No processes may be spawned
+    #[default]
+    None,
+    /// Only specific programs may be spawned, without a shell
+    Restricted,
+    /// Any shell command may be run
+    All,
+}
+
+/// Process permission
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessPermission {
+    /// Permission level
+    #[serde(default)]
+    level: ProcessPermissionLevel,
+    /// Programs that may be spawned at the restricted level
+    #[serde(default)]
+    allowed_commands: HashSet<String>,
+}
+
+impl ProcessPermission {
+    /// Create a new process permission with no access
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Create a new process permission allowing specific programs
+    pub fn restricted(commands: &[&str]) -> Self {
+        Self {
+            level: ProcessPermissionLevel::Restricted,
+            allowed_commands: commands.iter().map(|command| command.to_string()).collect(),
+        }
+    }
+
+    /// Create a new process permission allowing any shell command
+    pub fn all() -> Self {
+        Self {
+            level: ProcessPermissionLevel::All,
+            allowed_commands: HashSet::new(),
+        }
+    }
+
+    /// Get the permission level
+    pub fn level(&self) -> ProcessPermissionLevel {
+        self.level
+    }
+
+    /// Check if a program may be spawned
+    pub fn is_command_allowed(&self, program: &str) -> bool {
+        match self.level {
+            ProcessPermissionLevel::None => false,
+            ProcessPermissionLevel::Restricted => self.allowed_commands.contains(program),
+            ProcessPermissionLevel::All => true,
+        }
+    }
+
+    /// Add an allowed program
+    pub fn add_allowed_command(&mut self, command: &str) {
+        self.allowed_commands.insert(command.to_string());
+    }
+
+    /// Remove an allowed program
+    pub fn remove_allowed_command(&mut self, command: &str) {
+        self.allowed_commands.remove(command);
+    }
+}
+
+/// Sandbox error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SandboxError {
+    /// The plugin lacks the capability for the operation
+    PermissionDenied(String),
+    /// The operation was allowed but failed
+    Io(String),
+}
+
+impl fmt::Display for SandboxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SandboxError::PermissionDenied(msg) => write!(f, "Permission denied: {}", msg),
+            SandboxError::Io(msg) => write!(f, "I/O error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SandboxError {}
+
+/// Result type for sandboxed operations
+pub type SandboxResult<T> = std::result::Result<T, SandboxError>;
+
+/// Capabilities granted to a plugin
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PluginCapabilities {
+    /// Filesystem access
+    #[serde(default)]
+    pub filesystem: FilesystemPermission,
+    /// Process spawning
+    #[serde(default)]
+    pub process: ProcessPermission,
+    /// Network access, handed to the network manager
+    #[serde(default)]
+    pub network: Option<NetworkPermission>,
+}
+
+impl PluginCapabilities {
+    /// Create capabilities granting no access
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Read a file
+    pub fn read_file(&self, path: &Path) -> SandboxResult<String> {
+        if !self.filesystem.is_read_allowed(path) {
+            return Err(SandboxError::PermissionDenied(format!("cannot read '{}'", path.display())));
+        }
+
+        std::fs::read_to_string(resolve_path(path)).map_err(|e| SandboxError::Io(e.to_string()))
+    }
+
+    /// Write a file
+    pub fn write_file(&self, path: &Path, content: &str) -> SandboxResult<()> {
+        if !self.filesystem.is_write_allowed(path) {
+            return Err(SandboxError::PermissionDenied(format!("cannot write '{}'", path.display())));
+        }
+
+        std::fs::write(resolve_path(path), content).map_err(|e| SandboxError::Io(e.to_string()))
+    }
+
+    /// List the files below a directory
+    ///
+    /// Symbolic links are not followed into directories, and are only listed
+    /// when they point at a file the plugin may read.
+    pub fn list_files(&self, root: &Path) -> SandboxResult<Vec<String>> {
+        if !self.filesystem.is_read_allowed(root) {
+            return Err(SandboxError::PermissionDenied(format!("cannot list '{}'", root.display())));
+        }
+
+        fn visit_dirs(filesystem: &FilesystemPermission, dir: &Path, files: &mut Vec<String>) -> std::io::Result<()> {
+            for entry in std::fs::read_dir(dir)? {
+                let entry = entry?;
+                let path = entry.path();
+
+                // The type of the entry itself, not of what a link points at
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    visit_dirs(filesystem, &path, files)?;
+                } else if file_type.is_symlink() && !(path.is_file() && filesystem.is_read_allowed(&path)) {
+                    continue;
+                } else if let Some(path_str) = path.to_str() {
+                    files.push(path_str.to_string());
+                }
+            }
+            Ok(())
+        }
+
+        let mut files = Vec::new();
+        visit_dirs(&self.filesystem, root, &mut files).map_err(|e| SandboxError::Io(e.to_string()))?;
+
+        Ok(files)
+    }
+
+    /// Run a command and return its standard output
+    ///
+    /// At the restricted level the command is split on whitespace and the
+    /// program is spawned directly, so shell syntax cannot reach other programs.
+    pub fn execute_command(&self, command: &str) -> SandboxResult<String> {
+        let mut words = command.split_whitespace();
+        let program = words.next()
+            .ok_or_else(|| SandboxError::Io("empty command".to_string()))?;
+
+        if !self.process.is_command_allowed(program) {
+            return Err(SandboxError::PermissionDenied(format!("cannot run '{}'", program)));
+        }
+
+        let output = match self.process.level() {
+            ProcessPermissionLevel::All if cfg!(target_os = "windows") => {
+                std::process::Command::new("cmd").args(["/C", command]).output()
+            }
+            ProcessPermissionLevel::All => {
+                std::process::Command::new("sh").args(["-c", command]).output()
+            }
+            _ => std::process::Command::new(program).args(words).output(),
+        }.map_err(|e| SandboxError::Io(e.to_string()))?;
+
+        if !output.status.success() {
+            return Err(SandboxError::Io(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+}
+
+/// Plugin manifest
+///
+/// The manifest is read from a JSON file next to the plugin module, for
+/// example `my_plugin.manifest.json` for `my_plugin.wasm`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PluginManifest {
+    /// Plugin name
+    #[serde(default)]
+    pub name: String,
+    /// Plugin version
+    #[serde(default)]
+    pub version: String,
+    /// Capabilities requested by the plugin
+    #[serde(default)]
+    pub capabilities: PluginCapabilities,
+}
+
+impl PluginManifest {
+    /// Load a manifest from a file
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let content = std::fs::read_to_string(path.as_ref())?;
+
+        serde_json::from_str(&content)
+            .map_err(|e| anyhow!("Invalid plugin manifest '{}': {}", path.as_ref().display(), e))
+    }
+}
+
+/// Make a path absolute and resolve symlinks and `..` components
+///
+/// The longest existing prefix is canonicalized as it is, so `..` after a
+/// symlink leads where the filesystem would take it. Components after it
+/// do not exist yet and are resolved without touching the filesystem, so a
+/// new file cannot be used to escape a directory.
+fn resolve_path(path: &Path) -> PathBuf {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir().unwrap_or_default().join(path)
+    };
+
+    // Canonicalize the longest existing prefix
+    let mut existing = absolute.as_path();
+    let mut rest = Vec::new();
+    let mut resolved = loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            break canonical;
+        }
+        match existing.parent() {
+            Some(parent) => {
+                rest.extend(existing.components().next_back());
+                existing = parent;
+            }
+            None => break PathBuf::new(),
+        }
+    };
+
+    for component in rest.into_iter().rev() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            component => resolved.push(component),
+        }
+    }
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filesystem_access() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = dir.path().join("notes");
+        std::fs::create_dir(&notes).unwrap();
+
+        let mut permission = FilesystemPermission::none();
+        permission.add_directory(dir.path(), FilesystemAccess::ReadOnly);
+        permission.add_directory(&notes, FilesystemAccess::ReadWrite);
+
+        assert!(permission.is_read_allowed(&dir.path().join("README")));
+        assert!(!permission.is_write_allowed(&dir.path().join("README")));
+        assert!(permission.is_write_allowed(&notes.join("new.txt")));
+        assert!(!permission.is_read_allowed(Path::new("/etc/passwd")));
+
+        // `..` cannot be used to leave a directory
+        assert!(!permission.is_write_allowed(&notes.join("../README")));
+        assert!(!permission.is_read_allowed(&dir.path().join("../outside")));
+    }
+
+    #[test]
+    fn test_denied_operations_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.txt");
+        std::fs::write(&file, "content").unwrap();
+
+        let mut capabilities = PluginCapabilities::none();
+        assert!(matches!(capabilities.read_file(&file), Err(SandboxError::PermissionDenied(_))));
+        assert!(matches!(capabilities.execute_command("echo hi"), Err(SandboxError::PermissionDenied(_))));
+
+        capabilities.filesystem = FilesystemPermission::read_only(&[dir.path().to_str().unwrap()]);
+        assert_eq!(capabilities.read_file(&file).unwrap(), "content");
+        assert_eq!(capabilities.list_files(dir.path()).unwrap(), vec![file.to_str().unwrap().to_string()]);
+        assert!(matches!(capabilities.write_file(&file, "changed"), Err(SandboxError::PermissionDenied(_))));
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "content");
+
+        capabilities.process = ProcessPermission::restricted(&["echo"]);
+        assert_eq!(capabilities.execute_command("echo hi").unwrap(), "hi\n");
+        assert!(matches!(capabilities.execute_command("sh -c true"), Err(SandboxError::PermissionDenied(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_list_files_stays_in_grants() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let granted = dir.path().join("granted");
+        let outside = dir.path().join("outside");
+        std::fs::create_dir(&granted).unwrap();
+        std::fs::create_dir(&outside).unwrap();
+        std::fs::write(granted.join("file.txt"), "").unwrap();
+        std::fs::write(outside.join("secret.txt"), "").unwrap();
+        symlink(&outside, granted.join("dir-link")).unwrap();
+        symlink(outside.join("secret.txt"), granted.join("secret-link")).unwrap();
+        symlink(granted.join("file.txt"), granted.join("file-link")).unwrap();
+
+        let mut capabilities = PluginCapabilities::none();
+        capabilities.filesystem = FilesystemPermission::read_only(&[granted.to_str().unwrap()]);
+        let mut files = capabilities.list_files(&granted).unwrap();
+        files.sort();
+        assert_eq!(files, vec![
+            granted.join("file-link").to_str().unwrap().to_string(),
+            granted.join("file.txt").to_str().unwrap().to_string(),
+        ]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_parent_of_symlink_stays_in_grants() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let granted = dir.path().join("granted");
+        let outside = dir.path().join("outside");
+        std::fs::create_dir(&granted).unwrap();
+        std::fs::create_dir_all(outside.join("sub")).unwrap();
+        std::fs::write(outside.join("secret.txt"), "SECRET").unwrap();
+        symlink(outside.join("sub"), granted.join("link")).unwrap();
+
+        // `..` after the link is the parent of where it points, not the grant
+        let mut capabilities = PluginCapabilities::none();
+        capabilities.filesystem = FilesystemPermission::read_write(&[granted.to_str().unwrap()]);
+        let secret = granted.join("link/../secret.txt");
+        assert!(matches!(capabilities.read_file(&secret), Err(SandboxError::PermissionDenied(_))));
+        assert!(matches!(capabilities.write_file(&secret, "changed"), Err(SandboxError::PermissionDenied(_))));
+        let new_file = granted.join("link/../new.txt");
+        assert!(matches!(capabilities.write_file(&new_file, "new"), Err(SandboxError::PermissionDenied(_))));
+        assert_eq!(std::fs::read_to_string(outside.join("secret.txt")).unwrap(), "SECRET");
+        assert!(!outside.join("new.txt").exists());
+
+        // Paths that lead back into the grant are fine
+        capabilities.write_file(&granted.join("link/../../granted/kept.txt"), "kept").unwrap();
+        assert_eq!(capabilities.read_file(&granted.join("kept.txt")).unwrap(), "kept");
+    }
+
+    #[test]
+    fn test_manifest_capabilities() {
+        let manifest: PluginManifest = serde_json::from_str(r#"{
+            "name": "notes",
+            "capabilities": {
+                "filesystem": { "directories": [{ "path": "notes", "access": "ReadWrite" }] },
+                "process": { "level": "Restricted", "allowed_commands": ["git"] }
+            }
+        }"#).unwrap();
+
+        assert_eq!(manifest.name, "notes");
+        assert!(manifest.capabilities.filesystem.is_write_allowed(Path::new("notes/todo.md")));
+        assert!(manifest.capabilities.process.is_command_allowed("git"));
+        assert!(!manifest.capabilities.process.is_command_allowed("rm"));
+        assert!(manifest.capabilities.network.is_none());
+
+        // Capabilities default to no access
+        let manifest: PluginManifest = serde_json::from_str("{}").unwrap();
+        assert!(!manifest.capabilities.filesystem.is_read_allowed(Path::new("notes")));
+        assert!(!manifest.capabilities.process.is_command_allowed("git"));
+    }
+}
@@ -1,120 +1,749 @@
 //! WASM runtime for the plugin system
 //!
-//! This module implements a placeholder for the WebAssembly runtime for the xvim plugin system.
-//! In the future, it will use wasmtime to execute WASM modules and provide a sandboxed environment
-//! for plugins to run in.
+//! This module implements the WebAssembly runtime for the xvim plugin system.
+//! It uses wasmtime to compile and instantiate plugin modules, and exchanges data
+//! with plugins by copying byte buffers in and out of their linear memory.
+//!
+//! # Plugin ABI
+//!
+//! A plugin module exports the following items:
+//!
+//! - `memory`: the plugin's linear memory
+//! - `init() -> i32`: called once after instantiation, `0` means success
+//! - `alloc(len: i32) -> i32`: reserves `len` bytes for data written by the host
+//! - `dealloc(ptr: i32, len: i32)`: releases a buffer returned by `alloc` (optional)
+//! - `handle_command(ptr: i32, len: i32) -> i32`: receives a JSON object
+//!   `{"command": ..., "args": [...]}` and returns `1` if the command was handled,
+//!   `0` if it was not and a negative value on failure (optional)
+//! - `handle_event(ptr: i32, len: i32) -> i32`: receives a JSON object
+//!   `{"event": ..., "data": ...}` (optional)
+//! - `handle_complete(ptr: i32, len: i32) -> i64`: receives a JSON object
+//!   `{"command": ..., "arg_lead": ..., "line": ...}` and returns a JSON array
+//!   of completion matches, packed as for [`WasmRuntime::call_function`] (optional)
+//!
+//! Functions called through [`WasmRuntime::call_function`] take the argument buffer
+//! as `(ptr: i32, len: i32)` and return an `i64` packing the result buffer as
+//! `(ptr << 32) | len`. A return value of `0` means an empty result.
+//!
+//! Plugins call back into the editor through the `xvim` import module, see the
+//! `host` module for its functions.
+//!
+//! # Execution limits
+//!
+//! Every call into a plugin runs with a fuel budget, so a plugin stuck in a loop
+//! traps instead of freezing the editor, and each plugin's memory is capped through
+//! the store's resource limiter. Failed calls are reported as [`PluginError`]s and
+//! counted; a plugin that faults too often is disabled until it is re-enabled.
+//!
+//! # Capabilities
+//!
+//! Plugins only get the filesystem and process access granted by their
+//! [`PluginCapabilities`]. Granted directories are preopened in the plugin's WASI
+//! context, read-only ones through a wrapper that refuses every change, and the
+//! `read_file`, `write_file` and `list_files` host functions check the same grants.
 
 use anyhow::{anyhow, Result};
 use std::collections::HashMap;
-use std::path::Path;
+use std::fmt;
+use std::any::Any;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use wasi_common::dir::{OpenResult, ReaddirCursor, ReaddirEntity};
+use wasi_common::file::{FdFlags, Filestat, OFlags};
+use wasi_common::{ErrorExt, SystemTimeSpec, WasiCtx, WasiDir};
+use wasmtime::{Config, Engine, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, Trap};
+use wasmtime_wasi::sync::{ambient_authority, Dir, WasiCtxBuilder};
+
+use super::api::PluginContext;
+use super::events::EventType;
+use super::sandbox::{FilesystemAccess, PluginCapabilities};
+
+/// Name of the plugin initialization export
+const INIT_EXPORT: &str = "init";
+/// Name of the plugin allocation export
+const ALLOC_EXPORT: &str = "alloc";
+/// Name of the plugin deallocation export
+const DEALLOC_EXPORT: &str = "dealloc";
+/// Name of the plugin command dispatch export
+const COMMAND_EXPORT: &str = "handle_command";
+/// Name of the plugin event dispatch export
+const EVENT_EXPORT: &str = "handle_event";
+/// Name of the plugin completion export
+const COMPLETE_EXPORT: &str = "handle_complete";
+/// Name of the plugin memory export
+const MEMORY_EXPORT: &str = "memory";
+
+/// Default fuel available to a single plugin call
+pub const DEFAULT_FUEL: u64 = 1_000_000_000;
+/// Default memory limit for a plugin in bytes
+pub const DEFAULT_MAX_MEMORY: usize = 64 * 1024 * 1024;
+/// Default number of faults after which a plugin is disabled
+pub const DEFAULT_MAX_FAULTS: u32 = 3;
+
+/// Plugin execution error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PluginError {
+    /// The plugin used up the fuel available to a call
+    FuelExhausted(String),
+    /// The plugin trapped or failed while executing
+    Trap(String, String),
+    /// The plugin was disabled after repeated faults
+    Disabled(String),
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PluginError::FuelExhausted(name) => write!(f, "Plugin '{}' exceeded its execution budget", name),
+            PluginError::Trap(name, message) => write!(f, "Plugin '{}' trapped: {}", name, message),
+            PluginError::Disabled(name) => write!(f, "Plugin '{}' is disabled after repeated faults", name),
+        }
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+/// Resource limits applied to a plugin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PluginLimits {
+    /// Fuel available to each call into the plugin
+    pub fuel: u64,
+    /// Maximum size of the plugin's linear memory in bytes
+    pub max_memory: usize,
+    /// Number of faults after which the plugin is disabled
+    pub max_faults: u32,
+}
+
+impl Default for PluginLimits {
+    fn default() -> Self {
+        Self {
+            fuel: DEFAULT_FUEL,
+            max_memory: DEFAULT_MAX_MEMORY,
+            max_faults: DEFAULT_MAX_FAULTS,
+        }
+    }
+}
+
+/// Plugin metadata reported through `register_plugin`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginMetadata {
+    /// Plugin name
+    pub name: String,
+    /// Plugin version
+    pub version: String,
+    /// Plugin description
+    pub description: String,
+    /// Plugin author
+    pub author: String,
+}
 
-/// WASM plugin instance (placeholder)
+/// Per-plugin state stored in the wasmtime store
+pub struct PluginState {
+    /// Plugin name
+    pub name: String,
+    /// Plugin context shared with the editor
+    pub context: Arc<Mutex<PluginContext>>,
+    /// WASI context
+    pub wasi: WasiCtx,
+    /// Metadata reported by the plugin
+    pub metadata: Option<PluginMetadata>,
+    /// Events the plugin registered handlers for
+    pub subscriptions: Vec<String>,
+    /// Memory limits enforced on the plugin's store
+    pub limits: StoreLimits,
+    /// Filesystem and process capabilities granted to the plugin
+    pub capabilities: PluginCapabilities,
+}
+
+/// WASM plugin instance
 pub struct WasmPlugin {
     /// Plugin name
     name: String,
     /// Plugin path
     path: std::path::PathBuf,
+    /// Store holding the plugin's instance state
+    store: Store<PluginState>,
+    /// Instantiated module
+    instance: Instance,
+    /// Exported linear memory
+    memory: Option<Memory>,
+    /// Whether the plugin is currently executing
+    busy: bool,
+    /// Resource limits applied to the plugin
+    limits: PluginLimits,
+    /// Number of faults since the plugin was loaded or re-enabled
+    faults: u32,
+    /// Whether the plugin was disabled after repeated faults
+    disabled: bool,
+}
+
+impl WasmPlugin {
+    /// Get the plugin name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the plugin path
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the metadata reported by the plugin
+    pub fn metadata(&self) -> Option<&PluginMetadata> {
+        self.store.data().metadata.as_ref()
+    }
+
+    /// Check whether the plugin registered a handler for an event
+    pub fn is_subscribed(&self, event: &str) -> bool {
+        self.store.data().subscriptions.iter().any(|e| e == event)
+    }
+
+    /// Check whether the plugin was disabled after repeated faults
+    pub fn is_disabled(&self) -> bool {
+        self.disabled
+    }
+
+    /// Get the number of faults since the plugin was loaded or re-enabled
+    pub fn faults(&self) -> u32 {
+        self.faults
+    }
+
+    /// Re-enable the plugin and reset its fault count
+    pub fn enable(&mut self) {
+        self.disabled = false;
+        self.faults = 0;
+    }
+
+    /// Mark the plugin as executing and refill its fuel, refusing re-entrant calls
+    fn enter(&mut self) -> Result<()> {
+        if self.disabled {
+            return Err(PluginError::Disabled(self.name.clone()).into());
+        }
+        if self.busy {
+            return Err(anyhow!("Plugin '{}' is already running", self.name));
+        }
+
+        refuel(&mut self.store, self.limits.fuel)?;
+        self.busy = true;
+        Ok(())
+    }
+
+    /// Mark the plugin as idle, recording a fault if the call failed
+    fn leave<T>(&mut self, result: Result<T>) -> Result<T> {
+        self.busy = false;
+
+        result.map_err(|err| {
+            let error = fault_error(&self.name, err);
+
+            self.faults += 1;
+            if self.faults >= self.limits.max_faults && !self.disabled {
+                self.disabled = true;
+                log::warn!("Disabling plugin '{}' after {} faults", self.name, self.faults);
+            }
+
+            error.into()
+        })
+    }
+
+    /// Check whether the plugin exports a function
+    pub fn has_export(&mut self, name: &str) -> bool {
+        self.instance.get_func(&mut self.store, name).is_some()
+    }
+
+    /// Copy bytes into the plugin's memory, returning the guest pointer and length
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<(i32, i32)> {
+        if bytes.is_empty() {
+            return Ok((0, 0));
+        }
+
+        let memory = self.memory
+            .ok_or_else(|| anyhow!("Plugin '{}' does not export memory", self.name))?;
+        let alloc = self.instance
+            .get_typed_func::<i32, i32>(&mut self.store, ALLOC_EXPORT)
+            .map_err(|_| anyhow!("Plugin '{}' does not export '{}'", self.name, ALLOC_EXPORT))?;
+
+        let len = i32::try_from(bytes.len())
+            .map_err(|_| anyhow!("Argument buffer too large ({} bytes)", bytes.len()))?;
+        let ptr = alloc.call(&mut self.store, len)?;
+
+        memory.write(&mut self.store, ptr as u32 as usize, bytes)
+            .map_err(|e| anyhow!("Failed to write plugin memory: {}", e))?;
+
+        Ok((ptr, len))
+    }
+
+    /// Copy bytes out of the plugin's memory
+    fn read_bytes(&mut self, ptr: i32, len: i32) -> Result<Vec<u8>> {
+        if len <= 0 {
+            return Ok(Vec::new());
+        }
+
+        let memory = self.memory
+            .ok_or_else(|| anyhow!("Plugin '{}' does not export memory", self.name))?;
+
+        let mut buffer = vec![0u8; len as u32 as usize];
+        memory.read(&self.store, ptr as u32 as usize, &mut buffer)
+            .map_err(|e| anyhow!("Failed to read plugin memory: {}", e))?;
+
+        Ok(buffer)
+    }
+
+    /// Release a buffer previously handed to the plugin
+    fn free_bytes(&mut self, ptr: i32, len: i32) -> Result<()> {
+        if len <= 0 {
+            return Ok(());
+        }
+
+        // Deallocation is optional, plugins without it simply leak the buffer
+        if let Ok(dealloc) = self.instance.get_typed_func::<(i32, i32), ()>(&mut self.store, DEALLOC_EXPORT) {
+            dealloc.call(&mut self.store, (ptr, len))?;
+        }
+
+        Ok(())
+    }
+
+    /// Call a `(ptr, len) -> i32` export with a byte buffer
+    fn call_with_buffer(&mut self, function_name: &str, args: &[u8]) -> Result<i32> {
+        let func = self.instance
+            .get_typed_func::<(i32, i32), i32>(&mut self.store, function_name)
+            .map_err(|_| anyhow!("Plugin '{}' does not export '{}'", self.name, function_name))?;
+
+        self.enter()?;
+        let result = self.write_bytes(args).and_then(|(ptr, len)| {
+            let result = func.call(&mut self.store, (ptr, len));
+            self.free_bytes(ptr, len)?;
+            result
+        });
+
+        self.leave(result)
+    }
+}
+
+/// Top up a store's fuel to the given budget
+fn refuel(store: &mut Store<PluginState>, fuel: u64) -> Result<()> {
+    let remaining = store.consume_fuel(0)?;
+    if remaining < fuel {
+        store.add_fuel(fuel - remaining)?;
+    }
+    Ok(())
 }
 
-/// WASM runtime for executing plugins (placeholder)
+/// A preopened directory plugins may only read
+///
+/// Calls that would change the directory fail with `EPERM`, files are
+/// opened without write access, and directories opened through it are
+/// read-only as well.
+struct ReadOnlyDir(Box<dyn WasiDir>);
+
+#[async_trait::async_trait]
+impl WasiDir for ReadOnlyDir {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn open_file(
+        &self,
+        symlink_follow: bool,
+        path: &str,
+        oflags: OFlags,
+        read: bool,
+        write: bool,
+        fdflags: FdFlags,
+    ) -> Result<OpenResult, wasi_common::Error> {
+        let modifying = OFlags::CREATE | OFlags::EXCLUSIVE | OFlags::TRUNCATE;
+        if write || oflags.intersects(modifying) || fdflags.contains(FdFlags::APPEND) {
+            return Err(wasi_common::Error::perm());
+        }
+
+        match self.0.open_file(symlink_follow, path, oflags, read, false, fdflags).await? {
+            OpenResult::Dir(dir) => Ok(OpenResult::Dir(Box::new(ReadOnlyDir(dir)))),
+            file => Ok(file),
+        }
+    }
+
+    async fn create_dir(&self, _path: &str) -> Result<(), wasi_common::Error> {
+        Err(wasi_common::Error::perm())
+    }
+
+    async fn readdir(
+        &self,
+        cursor: ReaddirCursor,
+    ) -> Result<Box<dyn Iterator<Item = Result<ReaddirEntity, wasi_common::Error>> + Send>, wasi_common::Error> {
+        self.0.readdir(cursor).await
+    }
+
+    async fn symlink(&self, _old_path: &str, _new_path: &str) -> Result<(), wasi_common::Error> {
+        Err(wasi_common::Error::perm())
+    }
+
+    async fn remove_dir(&self, _path: &str) -> Result<(), wasi_common::Error> {
+        Err(wasi_common::Error::perm())
+    }
+
+    async fn unlink_file(&self, _path: &str) -> Result<(), wasi_common::Error> {
+        Err(wasi_common::Error::perm())
+    }
+
+    async fn read_link(&self, path: &str) -> Result<PathBuf, wasi_common::Error> {
+        self.0.read_link(path).await
+    }
+
+    async fn get_filestat(&self) -> Result<Filestat, wasi_common::Error> {
+        self.0.get_filestat().await
+    }
+
+    async fn get_path_filestat(&self, path: &str, follow_symlinks: bool) -> Result<Filestat, wasi_common::Error> {
+        self.0.get_path_filestat(path, follow_symlinks).await
+    }
+
+    async fn rename(&self, _path: &str, _dest_dir: &dyn WasiDir, _dest_path: &str) -> Result<(), wasi_common::Error> {
+        Err(wasi_common::Error::perm())
+    }
+
+    async fn hard_link(&self, _path: &str, _target_dir: &dyn WasiDir, _target_path: &str) -> Result<(), wasi_common::Error> {
+        Err(wasi_common::Error::perm())
+    }
+
+    async fn set_times(
+        &self,
+        _path: &str,
+        _atime: Option<SystemTimeSpec>,
+        _mtime: Option<SystemTimeSpec>,
+        _follow_symlinks: bool,
+    ) -> Result<(), wasi_common::Error> {
+        Err(wasi_common::Error::perm())
+    }
+}
+
+/// Build the WASI context for a plugin, preopening the directories it may access
+///
+/// Read-only directories are wrapped in a [`ReadOnlyDir`].
+fn build_wasi_ctx(capabilities: &PluginCapabilities) -> Result<WasiCtx> {
+    let wasi = WasiCtxBuilder::new().inherit_stdio().build();
+
+    for directory in capabilities.filesystem.directories() {
+        let dir = Dir::open_ambient_dir(&directory.path, ambient_authority())
+            .map_err(|e| anyhow!("Failed to open directory '{}': {}", directory.path.display(), e))?;
+        let dir: Box<dyn WasiDir> = Box::new(wasmtime_wasi::sync::dir::Dir::from_cap_std(dir));
+        let dir = match directory.access {
+            FilesystemAccess::ReadWrite => dir,
+            FilesystemAccess::ReadOnly => Box::new(ReadOnlyDir(dir)),
+        };
+        wasi.push_preopened_dir(dir, &directory.path)
+            .map_err(|e| anyhow!("Failed to preopen directory '{}': {}", directory.path.display(), e))?;
+    }
+
+    Ok(wasi)
+}
+
+/// Convert a failed plugin call into a `PluginError`
+fn fault_error(plugin_name: &str, err: anyhow::Error) -> PluginError {
+    if let Some(error) = err.downcast_ref::<PluginError>() {
+        return error.clone();
+    }
+
+    match err.downcast_ref::<Trap>() {
+        Some(Trap::OutOfFuel) => PluginError::FuelExhausted(plugin_name.to_string()),
+        Some(trap) => PluginError::Trap(plugin_name.to_string(), trap.to_string()),
+        None => PluginError::Trap(plugin_name.to_string(), err.to_string()),
+    }
+}
+
+/// WASM runtime for executing plugins
 pub struct WasmRuntime {
+    /// Compilation engine shared by all plugins
+    engine: Engine,
     /// Loaded plugins
     plugins: HashMap<String, WasmPlugin>,
     /// Plugin context
-    context: Arc<Mutex<super::api::PluginContext>>,
+    context: Arc<Mutex<PluginContext>>,
+    /// Default limits for newly loaded plugins
+    limits: PluginLimits,
+    /// Limits for specific plugins, overriding the defaults
+    plugin_limits: HashMap<String, PluginLimits>,
+    /// Capabilities granted to specific plugins, plugins without an entry get none
+    plugin_capabilities: HashMap<String, PluginCapabilities>,
 }
 
 impl WasmRuntime {
     /// Create a new WASM runtime
     pub fn new() -> Self {
         // Create a shared plugin context
-        let context = Arc::new(Mutex::new(super::api::PluginContext::new()));
-        
+        let context = Arc::new(Mutex::new(PluginContext::new()));
+
+        Self::with_context(context)
+    }
+
+    /// Create a new WASM runtime that uses an existing plugin context
+    pub fn with_context(context: Arc<Mutex<PluginContext>>) -> Self {
+        // Meter plugin execution so runaway plugins can be interrupted
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).expect("failed to create the WASM engine");
+
         Self {
+            engine,
             plugins: HashMap::new(),
             context,
+            limits: PluginLimits::default(),
+            plugin_limits: HashMap::new(),
+            plugin_capabilities: HashMap::new(),
         }
     }
-    
+
     /// Initialize the runtime
     pub fn init(&mut self) -> Result<()> {
         // Nothing to do here yet
         Ok(())
     }
-    
+
+    /// Get the plugin context
+    pub fn context(&self) -> Arc<Mutex<PluginContext>> {
+        self.context.clone()
+    }
+
+    /// Set the default limits for plugins loaded afterwards
+    pub fn set_limits(&mut self, limits: PluginLimits) {
+        self.limits = limits;
+    }
+
+    /// Set the limits for a specific plugin, applied when it is loaded
+    pub fn set_plugin_limits(&mut self, name: &str, limits: PluginLimits) {
+        self.plugin_limits.insert(name.to_string(), limits);
+    }
+
+    /// Get the limits that apply to a plugin
+    pub fn plugin_limits(&self, name: &str) -> PluginLimits {
+        self.plugin_limits.get(name).copied().unwrap_or(self.limits)
+    }
+
+    /// Set the capabilities granted to a plugin, applied when it is loaded
+    pub fn set_plugin_capabilities(&mut self, name: &str, capabilities: PluginCapabilities) {
+        self.plugin_capabilities.insert(name.to_string(), capabilities);
+    }
+
+    /// Get the capabilities granted to a plugin
+    pub fn plugin_capabilities(&self, name: &str) -> PluginCapabilities {
+        self.plugin_capabilities.get(name).cloned().unwrap_or_default()
+    }
+
+    /// Check whether a plugin was disabled after repeated faults
+    pub fn is_disabled(&self, name: &str) -> bool {
+        self.plugins.get(name).is_some_and(|plugin| plugin.is_disabled())
+    }
+
+    /// Get the number of faults a plugin has had
+    pub fn plugin_faults(&self, name: &str) -> u32 {
+        self.plugins.get(name).map_or(0, |plugin| plugin.faults())
+    }
+
+    /// Re-enable a plugin that was disabled after repeated faults
+    pub fn enable_plugin(&mut self, name: &str) -> Result<()> {
+        let plugin = self.plugins.get_mut(name)
+            .ok_or_else(|| anyhow!("Plugin '{}' is not loaded", name))?;
+
+        plugin.enable();
+
+        Ok(())
+    }
+
     /// Load a plugin from a WASM file
     pub fn load_plugin(&mut self, path: &Path, name: &str) -> Result<()> {
         // Check if the plugin is already loaded
         if self.plugins.contains_key(name) {
             return Err(anyhow!("Plugin '{}' is already loaded", name));
         }
-        
+
         // Check if the file exists
         if !path.exists() {
             return Err(anyhow!("Plugin file '{}' does not exist", path.display()));
         }
-        
-        // Create the plugin (placeholder)
-        let plugin = WasmPlugin {
+
+        // Compile the module
+        let module = Module::from_file(&self.engine, path)
+            .map_err(|e| anyhow!("Failed to compile plugin '{}': {}", name, e))?;
+
+        // Create the store with the plugin's state, limits and capabilities
+        let limits = self.plugin_limits(name);
+        let capabilities = self.plugin_capabilities(name);
+        let state = PluginState {
+            name: name.to_string(),
+            context: self.context.clone(),
+            wasi: build_wasi_ctx(&capabilities)?,
+            metadata: None,
+            subscriptions: Vec::new(),
+            limits: StoreLimitsBuilder::new().memory_size(limits.max_memory).build(),
+            capabilities,
+        };
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+
+        // Instantiation runs the start function, so it needs fuel too
+        refuel(&mut store, limits.fuel)?;
+
+        // Link the WASI and editor imports and instantiate the module
+        let mut linker: Linker<PluginState> = Linker::new(&self.engine);
+        wasmtime_wasi::sync::add_to_linker(&mut linker, |state: &mut PluginState| &mut state.wasi)?;
+        super::host::add_to_linker(&mut linker)?;
+        let instance = linker.instantiate(&mut store, &module)
+            .map_err(|e| anyhow!("Failed to instantiate plugin '{}': {}", name, e))?;
+
+        let memory = instance.get_memory(&mut store, MEMORY_EXPORT);
+
+        let mut plugin = WasmPlugin {
             name: name.to_string(),
             path: path.to_path_buf(),
+            store,
+            instance,
+            memory,
+            busy: false,
+            limits,
+            faults: 0,
+            disabled: false,
         };
-        
+
+        // Run the plugin's entry point
+        if plugin.has_export(INIT_EXPORT) {
+            let init = plugin.instance
+                .get_typed_func::<(), i32>(&mut plugin.store, INIT_EXPORT)
+                .map_err(|e| anyhow!("Plugin '{}' has an invalid '{}' export: {}", name, INIT_EXPORT, e))?;
+            plugin.enter()?;
+            let result = init.call(&mut plugin.store, ());
+            let status = plugin.leave(result)
+                .map_err(|e| anyhow!("Plugin '{}' failed to initialize: {}", name, e))?;
+
+            if status != 0 {
+                return Err(anyhow!("Plugin '{}' initialization returned {}", name, status));
+            }
+        }
+
         // Add the plugin to the map
         self.plugins.insert(name.to_string(), plugin);
-        
+
         Ok(())
     }
-    
+
     /// Unload a plugin
     pub fn unload_plugin(&mut self, name: &str) -> Result<()> {
         // Check if the plugin is loaded
         if !self.plugins.contains_key(name) {
             return Err(anyhow!("Plugin '{}' is not loaded", name));
         }
-        
-        // Remove the plugin from the map
+
+        // Remove the plugin from the map, dropping its store and instance
         self.plugins.remove(name);
-        
+
         Ok(())
     }
-    
+
     /// List loaded plugins
     pub fn list_plugins(&self) -> Vec<String> {
         self.plugins.keys().cloned().collect()
     }
-    
+
+    /// Get the metadata a plugin reported during initialization
+    pub fn plugin_metadata(&self, name: &str) -> Option<PluginMetadata> {
+        self.plugins.get(name).and_then(|plugin| plugin.metadata().cloned())
+    }
+
     /// Call a function in a plugin
     pub fn call_function(&mut self, plugin_name: &str, function_name: &str, args: &[u8]) -> Result<Vec<u8>> {
         // Check if the plugin is loaded
-        let _plugin = self.plugins.get(plugin_name)
+        let plugin = self.plugins.get_mut(plugin_name)
             .ok_or_else(|| anyhow!("Plugin '{}' is not loaded", plugin_name))?;
-        
-        // This is a placeholder implementation
-        // Return empty result
-        Ok(Vec::new())
+
+        let func = plugin.instance
+            .get_typed_func::<(i32, i32), i64>(&mut plugin.store, function_name)
+            .map_err(|_| anyhow!("Plugin '{}' does not export '{}'", plugin_name, function_name))?;
+
+        plugin.enter()?;
+        let result = (|| {
+            // Copy the arguments into the plugin's memory and call the function
+            let (ptr, len) = plugin.write_bytes(args)?;
+            let packed = func.call(&mut plugin.store, (ptr, len))?;
+            plugin.free_bytes(ptr, len)?;
+
+            // Unpack and copy out the result buffer
+            let result_ptr = (packed as u64 >> 32) as u32 as i32;
+            let result_len = (packed as u64 & 0xffff_ffff) as u32 as i32;
+            let result = plugin.read_bytes(result_ptr, result_len)?;
+            plugin.free_bytes(result_ptr, result_len)?;
+
+            Ok(result)
+        })();
+
+        plugin.leave(result)
     }
-    
+
     /// Call a command in a plugin
     pub fn call_command(&mut self, plugin_name: &str, command_name: &str, args: &[&str]) -> Result<bool> {
         // Check if the plugin is loaded
-        let _plugin = self.plugins.get(plugin_name)
+        let plugin = self.plugins.get_mut(plugin_name)
             .ok_or_else(|| anyhow!("Plugin '{}' is not loaded", plugin_name))?;
-        
-        // For the noxvim plugin, we'll simulate handling the NoxChat command
-        if plugin_name == "noxvim" && command_name == "NoxChat" {
-            return Ok(true);
-        }
-        
-        // Return false to indicate the command was not handled
-        Ok(false)
-    }
-    
-    /// Send an event to all plugins
-    pub fn send_event(&mut self, event: &super::events::EventType) -> Result<()> {
-        // This is a placeholder implementation
-        Ok(())
+
+        // Plugins without a command dispatcher cannot handle commands
+        if !plugin.has_export(COMMAND_EXPORT) {
+            return Ok(false);
+        }
+
+        let payload = serde_json::json!({
+            "command": command_name,
+            "args": args,
+        });
+        match plugin.call_with_buffer(COMMAND_EXPORT, payload.to_string().as_bytes())? {
+            0 => Ok(false),
+            1 => Ok(true),
+            status => Err(anyhow!("Command '{}' failed in plugin '{}' (status {})", command_name, plugin_name, status)),
+        }
+    }
+
+    /// Ask a plugin for the matches of the argument typed for one of its commands
+    ///
+    /// A plugin without a completion export has no matches.
+    pub fn complete(&mut self, plugin_name: &str, command_name: &str, arg_lead: &str, line: &str) -> Result<Vec<String>> {
+        let plugin = self.plugins.get_mut(plugin_name)
+            .ok_or_else(|| anyhow!("Plugin '{}' is not loaded", plugin_name))?;
+        if !plugin.has_export(COMPLETE_EXPORT) {
+            return Ok(Vec::new());
+        }
+
+        let payload = serde_json::json!({
+            "command": command_name,
+            "arg_lead": arg_lead,
+            "line": line,
+        });
+        let result = self.call_function(plugin_name, COMPLETE_EXPORT, payload.to_string().as_bytes())?;
+        if result.is_empty() {
+            return Ok(Vec::new());
+        }
+        serde_json::from_slice(&result)
+            .map_err(|e| anyhow!("Plugin '{}' returned invalid completions: {}", plugin_name, e))
+    }
+
+    /// Send an event to all plugins that registered a handler for it
+    ///
+    /// A failing plugin does not keep the event from reaching the others, the
+    /// first failure is returned once every plugin has been called.
+    pub fn send_event(&mut self, event: &EventType) -> Result<()> {
+        let payload = event_to_json(event);
+        let event_name = payload["event"].as_str().unwrap_or_default().to_string();
+        let payload = payload.to_string();
+        let mut first_error = None;
+
+        for plugin in self.plugins.values_mut() {
+            if plugin.is_disabled() || !plugin.is_subscribed(&event_name) || !plugin.has_export(EVENT_EXPORT) {
+                continue;
+            }
+
+            if let Err(err) = plugin.call_with_buffer(EVENT_EXPORT, payload.as_bytes()) {
+                first_error.get_or_insert(err);
+            }
+        }
+
+        match first_error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
     }
 }
 
@@ -122,4 +751,258 @@ impl Default for WasmRuntime {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+/// Convert an event into the JSON payload passed to plugins
+fn event_to_json(event: &EventType) -> serde_json::Value {
+    match event {
+        EventType::BufferCreated(id) => serde_json::json!({ "event": "buffer_created", "data": id }),
+        EventType::BufferDeleted(id) => serde_json::json!({ "event": "buffer_deleted", "data": id }),
+        EventType::BufferChanged(id) => serde_json::json!({ "event": "buffer_changed", "data": id }),
+        EventType::ModeChanged(mode) => serde_json::json!({ "event": "mode_changed", "data": mode }),
+        EventType::CursorMoved(buffer_id, line, column) => serde_json::json!({
+            "event": "cursor_moved",
+            "data": { "buffer_id": buffer_id, "line": line, "column": column },
+        }),
+        EventType::CommandExecuted(command) => serde_json::json!({ "event": "command_executed", "data": command }),
+        EventType::Custom(name, data) => serde_json::json!({
+            "event": name,
+            "data": String::from_utf8_lossy(data),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// A plugin that echoes its input and counts `init` calls
+    const ECHO_PLUGIN: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 1024))
+          (global $inits (mut i32) (i32.const 0))
+          (func (export "init") (result i32)
+            (global.set $inits (i32.add (global.get $inits) (i32.const 1)))
+            (i32.const 0))
+          (func (export "alloc") (param $len i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get $len)))
+            (local.get $ptr))
+          (func (export "echo") (param $ptr i32) (param $len i32) (result i64)
+            (i64.or
+              (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+              (i64.extend_i32_u (local.get $len))))
+          (func (export "handle_command") (param $ptr i32) (param $len i32) (result i32)
+            (i32.const 1)))
+    "#;
+
+    fn write_plugin(source: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::Builder::new().suffix(".wat").tempfile().unwrap();
+        file.write_all(source.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_load_and_call_function() {
+        let file = write_plugin(ECHO_PLUGIN);
+        let mut runtime = WasmRuntime::new();
+        runtime.load_plugin(file.path(), "echo").unwrap();
+
+        assert_eq!(runtime.list_plugins(), vec!["echo".to_string()]);
+        assert_eq!(runtime.call_function("echo", "echo", b"hello").unwrap(), b"hello".to_vec());
+        assert!(runtime.call_function("echo", "missing", b"").is_err());
+        assert!(runtime.call_command("echo", "Anything", &["a"]).unwrap());
+
+        runtime.unload_plugin("echo").unwrap();
+        assert!(runtime.call_function("echo", "echo", b"hello").is_err());
+    }
+
+    #[test]
+    fn test_host_functions_edit_buffers() {
+        // A plugin that registers a command and inserts text during init
+        let file = write_plugin(r#"
+            (module
+              (import "xvim" "register_command" (func $register_command (param i32 i32 i32 i32) (result i32)))
+              (import "xvim" "insert_text" (func $insert_text (param i64 i64 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "Greet")
+              (data (i32.const 16) "Hello, ")
+              (func (export "init") (result i32)
+                (drop (call $register_command (i32.const 0) (i32.const 5) (i32.const 0) (i32.const 0)))
+                (call $insert_text (i64.const 1) (i64.const 0) (i32.const 16) (i32.const 7))))
+        "#);
+
+        let mut buffer_manager = crate::buffer::BufferManager::new();
+        let buffer_id = buffer_manager.create_buffer().unwrap();
+        buffer_manager.get_buffer_mut(buffer_id).unwrap().insert(0, "world").unwrap();
+
+        let context = Arc::new(Mutex::new(PluginContext::new()));
+        context.lock().unwrap().set_buffer_manager(Arc::new(Mutex::new(buffer_manager)));
+
+        let mut runtime = WasmRuntime::with_context(context.clone());
+        runtime.load_plugin(file.path(), "greeter").unwrap();
+
+        let mut context = context.lock().unwrap();
+        assert!(context.take_changed());
+        let buffer_manager = context.buffer_manager().unwrap();
+        assert_eq!(buffer_manager.lock().unwrap().get_buffer(buffer_id).unwrap().content(), "Hello, world");
+
+        let commands = context.take_plugin_commands();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].plugin, "greeter");
+        assert_eq!(commands[0].name, "Greet");
+    }
+
+    #[test]
+    fn test_plugin_completion() {
+        // A plugin that completes the arguments of a command with a fixed list
+        let matches = r#"["hello","help me"]"#;
+        let file = write_plugin(&format!(r#"
+            (module
+              (import "xvim" "register_completer" (func $register_completer (param i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "Greet")
+              (data (i32.const 64) "{}")
+              (func (export "init") (result i32)
+                (call $register_completer (i32.const 0) (i32.const 5)))
+              (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+              (func (export "handle_complete") (param i32 i32) (result i64)
+                (i64.or (i64.shl (i64.const 64) (i64.const 32)) (i64.const {}))))
+        "#, matches.replace('"', "\\22"), matches.len()));
+
+        let context = Arc::new(Mutex::new(PluginContext::new()));
+        let mut runtime = WasmRuntime::with_context(context.clone());
+        runtime.load_plugin(file.path(), "greeter").unwrap();
+
+        let completers = context.lock().unwrap().take_plugin_completers();
+        assert_eq!(completers.len(), 1);
+        assert_eq!(completers[0].plugin, "greeter");
+        assert_eq!(completers[0].command, "Greet");
+        assert_eq!(runtime.complete("greeter", "Greet", "he", "Greet he").unwrap(), vec!["hello", "help me"]);
+
+        // Plugins without a completion export have no matches
+        let file = write_plugin(ECHO_PLUGIN);
+        runtime.load_plugin(file.path(), "echo").unwrap();
+        assert!(runtime.complete("echo", "Anything", "", "Anything ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_runaway_plugin_is_disabled() {
+        // A plugin that loops forever and one that grows its memory past the cap
+        let file = write_plugin(r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+              (func (export "spin") (param i32 i32) (result i64)
+                (loop $forever (br $forever))
+                (i64.const 0))
+              (func (export "grow") (param i32 i32) (result i64)
+                (if (i32.lt_s (memory.grow (i32.const 16)) (i32.const 0))
+                  (then (unreachable)))
+                (i64.const 0)))
+        "#);
+
+        let mut runtime = WasmRuntime::new();
+        runtime.set_plugin_limits("runaway", PluginLimits {
+            fuel: 100_000,
+            max_memory: 4 * 64 * 1024,
+            max_faults: 2,
+        });
+        runtime.load_plugin(file.path(), "runaway").unwrap();
+
+        let err = runtime.call_function("runaway", "spin", b"").unwrap_err();
+        assert_eq!(err.downcast_ref::<PluginError>(), Some(&PluginError::FuelExhausted("runaway".to_string())));
+        assert!(!runtime.is_disabled("runaway"));
+
+        let err = runtime.call_function("runaway", "grow", b"").unwrap_err();
+        assert!(matches!(err.downcast_ref::<PluginError>(), Some(PluginError::Trap(name, _)) if name == "runaway"));
+        assert!(runtime.is_disabled("runaway"));
+        assert_eq!(runtime.plugin_faults("runaway"), 2);
+
+        let err = runtime.call_function("runaway", "spin", b"").unwrap_err();
+        assert_eq!(err.downcast_ref::<PluginError>(), Some(&PluginError::Disabled("runaway".to_string())));
+
+        runtime.enable_plugin("runaway").unwrap();
+        assert!(!runtime.is_disabled("runaway"));
+        assert_eq!(runtime.plugin_faults("runaway"), 0);
+    }
+
+    #[test]
+    fn test_file_access_follows_capabilities() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+        let path = path.to_str().unwrap();
+
+        // A plugin whose command handler writes a file and returns the host status
+        let file = write_plugin(&format!(r#"
+            (module
+              (import "xvim" "write_file" (func $write_file (param i32 i32 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "hello")
+              (data (i32.const 16) "{}")
+              (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+              (func (export "handle_command") (param i32 i32) (result i32)
+                (call $write_file (i32.const 16) (i32.const {}) (i32.const 0) (i32.const 5))))
+        "#, path, path.len()));
+
+        let mut runtime = WasmRuntime::new();
+        runtime.load_plugin(file.path(), "denied").unwrap();
+        assert!(runtime.call_command("denied", "Write", &[]).is_err());
+        assert!(!dir.path().join("out.txt").exists());
+
+        runtime.set_plugin_capabilities("writer", PluginCapabilities {
+            filesystem: super::super::sandbox::FilesystemPermission::read_write(&[dir.path().to_str().unwrap()]),
+            ..PluginCapabilities::none()
+        });
+        runtime.load_plugin(file.path(), "writer").unwrap();
+        assert!(!runtime.call_command("writer", "Write", &[]).unwrap());
+        assert_eq!(std::fs::read_to_string(dir.path().join("out.txt")).unwrap(), "hello");
+    }
+
+    /// Run a WASI directory call, which completes without waiting
+    fn ready<F: std::future::Future>(future: F) -> F::Output {
+        let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+        match std::pin::pin!(future).poll(&mut context) {
+            std::task::Poll::Ready(output) => output,
+            std::task::Poll::Pending => panic!("WASI call did not complete"),
+        }
+    }
+
+    #[test]
+    fn test_read_only_dir_refuses_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "notes").unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        let opened = Dir::open_ambient_dir(dir.path(), ambient_authority()).unwrap();
+        let read_only = ReadOnlyDir(Box::new(wasmtime_wasi::sync::dir::Dir::from_cap_std(opened)));
+
+        assert!(ready(read_only.open_file(false, "notes.txt", OFlags::empty(), true, false, FdFlags::empty())).is_ok());
+        assert!(ready(read_only.open_file(false, "notes.txt", OFlags::empty(), true, true, FdFlags::empty())).is_err());
+        assert!(ready(read_only.open_file(false, "new.txt", OFlags::CREATE, true, false, FdFlags::empty())).is_err());
+        assert!(ready(read_only.create_dir("made")).is_err());
+        assert!(ready(read_only.unlink_file("notes.txt")).is_err());
+
+        // Directories opened through it stay read-only
+        match ready(read_only.open_file(false, "sub", OFlags::DIRECTORY, true, false, FdFlags::empty())).unwrap() {
+            OpenResult::Dir(sub) => assert!(ready(sub.create_dir("made")).is_err()),
+            OpenResult::File(_) => panic!("expected a directory"),
+        }
+
+        assert!(!dir.path().join("made").exists());
+        assert!(!dir.path().join("sub/made").exists());
+        assert!(!dir.path().join("new.txt").exists());
+        assert_eq!(std::fs::read_to_string(dir.path().join("notes.txt")).unwrap(), "notes");
+    }
+
+    #[test]
+    fn test_failed_init_is_reported() {
+        let file = write_plugin(r#"(module (func (export "init") (result i32) (i32.const 1)))"#);
+        let mut runtime = WasmRuntime::new();
+
+        assert!(runtime.load_plugin(file.path(), "broken").is_err());
+        assert!(runtime.list_plugins().is_empty());
+    }
+}
@@ -0,0 +1,194 @@
+//! Repeat module for xvim
+//!
+//! This module implements repeating the last change with `.`. Commands that
+//! change the text record what they did as a `RepeatableChange`: the command,
+//! its count and register, and for commands that go on in insert mode, like
+//! `c` and `o`, the text typed before insert mode was left.
+
+use crate::operator::{Operator, OperatorTarget};
+use crate::visual::VisualMode;
+
+/// A command that starts insert mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertCommand {
+    /// Insert before the cursor (`i`)
+    Insert,
+    /// Open a line below the cursor (`o`)
+    OpenBelow,
+    /// Open a line above the cursor (`O`)
+    OpenAbove,
+}
+
+/// The command of a repeatable change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepeatCommand {
+    /// An operator applied to a motion, text object or lines
+    Operator {
+        /// The operator
+        operator: Operator,
+        /// What it was applied to
+        target: OperatorTarget,
+    },
+    /// An operator applied to a visual selection
+    ///
+    /// It is repeated on an area of the same size at the cursor: the same
+    /// number of lines, and on the last line the same number of characters
+    /// for a selection within one line or the same end column otherwise.
+    Visual {
+        /// The operator
+        operator: Operator,
+        /// The visual mode of the selection
+        mode: VisualMode,
+        /// Number of lines after the first one
+        lines: usize,
+        /// Characters selected on a single line, or the column of the end
+        columns: usize,
+    },
+    /// A command that starts insert mode
+    Insert(InsertCommand),
+    /// Putting text from a register (`p` and `P`)
+    Put {
+        /// Whether the text was put before the cursor
+        before: bool,
+    },
+}
+
+/// A change that can be repeated with `.`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepeatableChange {
+    /// The command that made the change
+    pub command: RepeatCommand,
+    /// The count of the command
+    pub count: usize,
+    /// The register the command used, if one was given
+    pub register: Option<char>,
+    /// The text typed in insert mode, for commands that start it
+    pub inserted_text: String,
+}
+
+impl RepeatableChange {
+    /// Create a new repeatable change with a count of 1
+    pub fn new(command: RepeatCommand) -> Self {
+        Self {
+            command,
+            count: 1,
+            register: None,
+            inserted_text: String::new(),
+        }
+    }
+
+    /// Set the count of the change
+    pub fn with_count(mut self, count: usize) -> Self {
+        self.count = count;
+        self
+    }
+
+    /// Set the register of the change
+    pub fn with_register(mut self, register: Option<char>) -> Self {
+        self.register = register;
+        self
+    }
+
+    /// Check whether the change goes on in insert mode
+    pub fn starts_insert_mode(&self) -> bool {
+        match &self.command {
+            RepeatCommand::Operator { operator, .. } | RepeatCommand::Visual { operator, .. } => {
+                *operator == Operator::Change
+            },
+            RepeatCommand::Insert(_) => true,
+            RepeatCommand::Put { .. } => false,
+        }
+    }
+
+    /// Get the register to use when repeating the change
+    ///
+    /// Like in Vim, repeating a put from a numbered register uses the next
+    /// one, so `"1p...` puts the last four deletes.
+    pub fn next_register(&self) -> Option<char> {
+        match (&self.command, self.register) {
+            (RepeatCommand::Put { .. }, Some(register @ '1'..='8')) => Some((register as u8 + 1) as char),
+            (_, register) => register,
+        }
+    }
+}
+
+/// The last change, and the one being made in insert mode
+#[derive(Debug, Clone, Default)]
+pub struct RepeatState {
+    /// The last complete change
+    last: Option<RepeatableChange>,
+    /// A change waiting for insert mode to end
+    pending: Option<RepeatableChange>,
+}
+
+impl RepeatState {
+    /// Create a new repeat state
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a change
+    ///
+    /// A change that goes on in insert mode only becomes the last change
+    /// once insert mode ends, see `finish_insert`.
+    pub fn record(&mut self, change: RepeatableChange) {
+        if change.starts_insert_mode() {
+            self.pending = Some(change);
+        } else {
+            self.pending = None;
+            self.last = Some(change);
+        }
+    }
+
+    /// Complete the change waiting for insert mode with the text typed
+    pub fn finish_insert(&mut self, inserted_text: &str) {
+        if let Some(mut change) = self.pending.take() {
+            change.inserted_text = inserted_text.to_string();
+            self.last = Some(change);
+        }
+    }
+
+    /// Get the last complete change
+    pub fn last_change(&self) -> Option<&RepeatableChange> {
+        self.last.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cursor::Direction;
+
+    #[test]
+    fn test_repeat_state() {
+        let mut state = RepeatState::new();
+        assert!(state.last_change().is_none());
+
+        let delete = RepeatableChange::new(RepeatCommand::Operator {
+            operator: Operator::Delete,
+            target: OperatorTarget::Motion(Direction::WordNext),
+        }).with_count(3);
+        state.record(delete.clone());
+        assert_eq!(state.last_change(), Some(&delete));
+
+        // A change only replaces the last one once insert mode ends
+        state.record(RepeatableChange::new(RepeatCommand::Insert(InsertCommand::OpenBelow)));
+        assert_eq!(state.last_change(), Some(&delete));
+        state.finish_insert("text");
+        let change = state.last_change().unwrap();
+        assert_eq!(change.command, RepeatCommand::Insert(InsertCommand::OpenBelow));
+        assert_eq!(change.inserted_text, "text");
+
+        // Leaving insert mode without a pending change keeps the last one
+        state.finish_insert("other");
+        assert_eq!(state.last_change().unwrap().inserted_text, "text");
+    }
+
+    #[test]
+    fn test_next_register() {
+        let put = RepeatableChange::new(RepeatCommand::Put { before: false });
+        assert_eq!(put.clone().with_register(Some('1')).next_register(), Some('2'));
+        assert_eq!(put.clone().with_register(Some('9')).next_register(), Some('9'));
+        assert_eq!(put.with_register(Some('a')).next_register(), Some('a'));
+    }
+}
@@ -2,14 +2,18 @@
 //!
 //! This module implements search functionality for xvim, including
 //! forward and backward search, search history, and search highlighting.
+//! Patterns use Vim's syntax, which the `pattern` module translates.
 
 use crate::buffer::Buffer;
+use crate::command::History;
 use crate::editor::Editor;
 use crate::cursor::CursorPosition;
-use std::collections::VecDeque;
 
-/// Maximum number of search patterns to store in history
-const MAX_SEARCH_HISTORY: usize = 50;
+mod pattern;
+mod substitute;
+
+pub use pattern::{MatchContext, Pattern, PatternError, PatternMatch, PatternOptions, PatternResult};
+pub use substitute::{Replacement, Substitute, SubstituteFlags, expand_previous};
 
 /// Search direction
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -27,12 +31,12 @@ pub struct SearchState {
     pattern: Option<String>,
     /// Current search direction
     direction: SearchDirection,
-    /// Search history (most recent first)
-    history: VecDeque<String>,
-    /// Current position in history when navigating
-    history_index: Option<usize>,
+    /// Search history, shared with the command line
+    history: History,
     /// Whether search is case-sensitive
     case_sensitive: bool,
+    /// Whether a pattern with upper case letters is case-sensitive ('smartcase')
+    smart_case: bool,
     /// Last search results
     last_results: Vec<(usize, usize, String)>,
     /// Current result index
@@ -45,9 +49,9 @@ impl SearchState {
         Self {
             pattern: None,
             direction: SearchDirection::Forward,
-            history: VecDeque::new(),
-            history_index: None,
+            history: History::new(),
             case_sensitive: false,
+            smart_case: false,
             last_results: Vec::new(),
             current_result_index: None,
         }
@@ -55,22 +59,8 @@ impl SearchState {
 
     /// Set the search pattern
     pub fn set_pattern(&mut self, pattern: String) {
-        // Add to history if it's a new pattern
-        if Some(&pattern) != self.pattern.as_ref() {
-            // Remove any existing instances of this pattern
-            self.history.retain(|p| p != &pattern);
-            
-            // Add to the front of the history
-            self.history.push_front(pattern.clone());
-            
-            // Trim history if it's too long
-            if self.history.len() > MAX_SEARCH_HISTORY {
-                self.history.pop_back();
-            }
-        }
-        
+        self.history.add(&pattern);
         self.pattern = Some(pattern);
-        self.history_index = None;
         self.current_result_index = None;
     }
 
@@ -99,35 +89,28 @@ impl SearchState {
         self.case_sensitive
     }
 
-    /// Get the next item in search history
-    pub fn history_next(&mut self) -> Option<&str> {
-        if self.history.is_empty() {
-            return None;
-        }
-
-        let index = match self.history_index {
-            Some(i) if i < self.history.len() - 1 => i + 1,
-            _ => 0,
-        };
+    /// Set whether a pattern with upper case letters is case-sensitive
+    pub fn set_smart_case(&mut self, smart_case: bool) {
+        self.smart_case = smart_case;
+    }
 
-        self.history_index = Some(index);
-        self.history.get(index).map(|s| s.as_str())
+    /// Get whether a pattern with upper case letters is case-sensitive
+    pub fn smart_case(&self) -> bool {
+        self.smart_case
     }
 
-    /// Get the previous item in search history
-    pub fn history_prev(&mut self) -> Option<&str> {
-        if self.history.is_empty() {
-            return None;
+    /// Get the options to compile patterns with, for 'ignorecase' and 'smartcase'
+    pub fn pattern_options(&self) -> PatternOptions<'static> {
+        PatternOptions {
+            ignore_case: !self.case_sensitive,
+            smart_case: self.smart_case,
+            previous_replacement: None,
         }
+    }
 
-        let index = match self.history_index {
-            Some(i) if i > 0 => i - 1,
-            Some(_) => self.history.len() - 1,
-            None => 0,
-        };
-
-        self.history_index = Some(index);
-        self.history.get(index).map(|s| s.as_str())
+    /// Get the search history
+    pub fn history(&self) -> &History {
+        &self.history
     }
 
     /// Set the search results
@@ -246,21 +229,31 @@ mod tests {
         state.set_pattern("pattern2".to_string());
         state.set_pattern("pattern3".to_string());
         
-        // The history should now contain: pattern3, pattern2, pattern1, test (in that order)
-        // Let's verify the current pattern first
+        // The history has the most recent pattern first
         assert_eq!(state.pattern(), Some("pattern3"));
+        let history: Vec<&str> = state.history().iter().collect();
+        assert_eq!(history, vec!["pattern3", "pattern2", "pattern1", "test"]);
         
-        // Now let's check the history navigation
-        // The first call to history_next() should return the current pattern
-        assert_eq!(state.history_next(), Some("pattern3"));
-        assert_eq!(state.history_next(), Some("pattern2"));
-        assert_eq!(state.history_next(), Some("pattern1"));
-        assert_eq!(state.history_next(), Some("test"));
-        assert_eq!(state.history_next(), Some("pattern3")); // Wrap around
+        // Setting a pattern again moves it to the front
+        state.set_pattern("pattern1".to_string());
+        assert_eq!(state.history().latest(), Some("pattern1"));
+        assert_eq!(state.history().len(), 4);
+    }
+    
+    #[test]
+    fn test_search_smart_case() {
+        let mut state = SearchState::new();
+        let context = MatchContext::default();
+        let matches = |state: &SearchState, pattern: &str| {
+            Pattern::new(pattern, &state.pattern_options()).unwrap().is_match("FOO", &context)
+        };
+        assert!(matches(&state, "Foo"));
+        
+        state.set_smart_case(true);
+        assert!(matches(&state, "foo"));
+        assert!(!matches(&state, "Foo"));
         
-        assert_eq!(state.history_prev(), Some("test"));
-        assert_eq!(state.history_prev(), Some("pattern1"));
-        assert_eq!(state.history_prev(), Some("pattern2"));
-        assert_eq!(state.history_prev(), Some("pattern3")); // Wrap around
+        state.set_case_sensitive(true);
+        assert!(!matches(&state, "foo"));
     }
-}
\ No newline at end of file
+}
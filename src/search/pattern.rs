@@ -0,0 +1,1041 @@
+//! Pattern - Vim regular expressions
+//!
+//! This module translates Vim's pattern syntax to the `regex` crate. A
+//! pattern is parsed into nodes first, whatever its magic (`\v`, `\m`,
+//! `\M`, `\V`), and the nodes are written out as a regular expression.
+//! What the `regex` crate can't express, the start and end of the match
+//! (`\zs`, `\ze`) and the atoms that check where the match is (`\<`, `\>`,
+//! `\%V`, `\%23l`, ...), becomes an empty group. The position each of
+//! those groups matched at is checked once the regular expression has
+//! matched, and a match that fails a check is dropped.
+
+use crate::cursor::CursorPosition;
+use crate::visual::{VisualArea, VisualMode};
+use regex::{CaptureLocations, Regex, RegexBuilder};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Error in a pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternError(String);
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+/// Result type for patterns
+pub type PatternResult<T> = Result<T, PatternError>;
+
+/// How a pattern is compiled
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PatternOptions<'a> {
+    /// Ignore case ('ignorecase')
+    pub ignore_case: bool,
+    /// Match case anyway when the pattern has upper case letters ('smartcase')
+    pub smart_case: bool,
+    /// Replacement string of the last substitute, which `~` matches
+    pub previous_replacement: Option<&'a str>,
+}
+
+/// Where the line being matched is, for the atoms that check positions
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MatchContext<'a> {
+    /// Index of the line
+    pub line: usize,
+    /// Index of the last line in the buffer
+    pub last_line: usize,
+    /// Cursor position (`\%#`)
+    pub cursor: Option<CursorPosition>,
+    /// Visual area, the one `gv` reselects (`\%V`)
+    pub visual: Option<&'a VisualArea>,
+}
+
+/// A match of a pattern in a line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternMatch {
+    /// Byte index the match starts at
+    pub start: usize,
+    /// Byte index the match ends at (exclusive)
+    pub end: usize,
+    /// Byte ranges of the groups `\1` to `\9`
+    groups: Vec<Option<(usize, usize)>>,
+}
+
+impl PatternMatch {
+    /// Get the text of the whole match (0) or of a group in the line matched
+    pub fn group<'t>(&self, text: &'t str, group: usize) -> Option<&'t str> {
+        let (start, end) = match group {
+            0 => (self.start, self.end),
+            n => (*self.groups.get(n - 1)?)?,
+        };
+        text.get(start..end)
+    }
+}
+
+/// How much of the syntax needs a backslash (\V, \M, \m, \v)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum MagicLevel {
+    VeryNomagic,
+    Nomagic,
+    Magic,
+    VeryMagic,
+}
+
+/// A character of the pattern, with the meaning the magic gives it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    /// A character that matches itself
+    Char(char),
+    /// A character with a special meaning, as it is written after `\v`
+    Special(char),
+    /// A letter, digit or `_` after a backslash
+    Escape(char),
+}
+
+/// Comparison of a position atom (`\%<23l`, `\%23l`, `\%>23l`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compare {
+    Before,
+    At,
+    After,
+}
+
+impl Compare {
+    fn holds(self, value: usize, target: usize) -> bool {
+        match self {
+            Compare::Before => value < target,
+            Compare::At => value == target,
+            Compare::After => value > target,
+        }
+    }
+}
+
+/// A zero-width atom checked after matching
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Check {
+    /// Start of a word (\<)
+    WordStart,
+    /// End of a word (\>)
+    WordEnd,
+    /// Inside the visual area (\%V)
+    Visual,
+    /// Line number (\%23l)
+    Line(Compare, usize),
+    /// Byte column (\%23c)
+    Column(Compare, usize),
+    /// Cursor position (\%#)
+    Cursor,
+    /// Start of the file (\%^)
+    FileStart,
+    /// End of the file (\%$)
+    FileEnd,
+}
+
+/// A parsed pattern
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    /// A character that matches itself
+    Char(char),
+    /// Any character, and a line break too with `\_.`
+    AnyChar { newline: bool },
+    /// A character class, in `regex` syntax
+    Class(String),
+    /// Start of the line (^)
+    LineStart,
+    /// End of the line ($)
+    LineEnd,
+    /// Start of the match (\zs)
+    MatchStart,
+    /// End of the match (\ze)
+    MatchEnd,
+    /// A position checked after matching
+    Check(Check),
+    /// Branches in a group, capturing (\( \)) or not (\%( \))
+    Group { capture: bool, branches: Vec<Vec<Node>> },
+    /// An atom repeated (*, \+, \=, \?, \{n,m})
+    Repeat { node: Box<Node>, min: u32, max: Option<u32>, greedy: bool },
+}
+
+/// Regular expression of a class escape (\s, \d, \w, ...)
+fn class_escape(c: char) -> Option<&'static str> {
+    Some(match c {
+        's' => "[ \\t]",
+        'S' => "[^ \\t]",
+        'd' => "[0-9]",
+        'D' => "[^0-9]",
+        'w' => "[0-9A-Za-z_]",
+        'W' => "[^0-9A-Za-z_]",
+        'a' => "[A-Za-z]",
+        'A' => "[^A-Za-z]",
+        'l' => "(?-i:[a-z])",
+        'L' => "(?-i:[^a-z])",
+        'u' => "(?-i:[A-Z])",
+        'U' => "(?-i:[^A-Z])",
+        'x' => "[0-9A-Fa-f]",
+        'X' => "[^0-9A-Fa-f]",
+        'o' => "[0-7]",
+        'O' => "[^0-7]",
+        'h' => "[A-Za-z_]",
+        'H' => "[^A-Za-z_]",
+        'i' | 'k' => "\\w",
+        'I' | 'K' => "[\\w&&[^0-9]]",
+        'f' => "[0-9A-Za-z_/.\\-+,#$%~=]",
+        'F' => "[A-Za-z_/.\\-+,#$%~=]",
+        'p' => "[^\\x00-\\x1f\\x7f]",
+        'P' => "[^\\x00-\\x1f\\x7f0-9]",
+        _ => return None,
+    })
+}
+
+/// Character of a character escape (\e, \t, \r, \b, \n)
+fn char_escape(c: char) -> Option<char> {
+    Some(match c {
+        'e' => '\x1b',
+        't' => '\t',
+        'r' => '\r',
+        'b' => '\x08',
+        'n' => '\n',
+        _ => return None,
+    })
+}
+
+/// Write a character so that it matches itself in a character class
+fn push_class_char(c: char, out: &mut String) {
+    if c.is_ascii_punctuation() {
+        out.push('\\');
+        out.push(c);
+    } else if c.is_control() {
+        out.push_str(&format!("\\x{{{:x}}}", c as u32));
+    } else {
+        out.push(c);
+    }
+}
+
+/// Whether a character is part of a word
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Whether a pattern has upper case letters, for 'smartcase'
+///
+/// What follows a backslash doesn't count, so `\S` doesn't make a pattern
+/// match case.
+fn has_upper_case(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some('_' | '%') = chars.next() {
+                chars.next();
+            }
+        } else if c.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
+
+fn unsupported(item: &str) -> PatternError {
+    PatternError(format!("Unsupported pattern item: {}", item))
+}
+
+/// Parser of Vim patterns
+struct Parser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    magic: MagicLevel,
+    /// Case set with \c (true) or \C (false)
+    ignore_case: Option<bool>,
+    /// Number of capturing groups so far
+    groups: usize,
+    previous_replacement: Option<&'a str>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(pattern: &str, previous_replacement: Option<&'a str>) -> Self {
+        Self {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            magic: MagicLevel::Magic,
+            ignore_case: None,
+            groups: 0,
+            previous_replacement,
+        }
+    }
+
+    /// Take the next raw character
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.chars.get(self.pos).copied();
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
+    }
+
+    /// Take the next raw character if it is the one given
+    fn eat(&mut self, c: char) -> bool {
+        if self.chars.get(self.pos) == Some(&c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether a character is special, written with a backslash or not
+    fn is_special(&self, c: char, escaped: bool) -> bool {
+        match c {
+            '^' | '$' => !escaped,
+            '.' | '*' | '[' | '~' => (self.magic >= MagicLevel::Magic) != escaped,
+            '+' | '?' | '=' | '{' | '(' | ')' | '|' | '<' | '>' | '@' | '%' | '&' => {
+                (self.magic == MagicLevel::VeryMagic) != escaped
+            },
+            _ => false,
+        }
+    }
+
+    /// Take the next token, applying the magic and case items on the way
+    fn next_token(&mut self) -> Option<Token> {
+        loop {
+            let c = self.next_char()?;
+            if c != '\\' {
+                return Some(if self.is_special(c, false) { Token::Special(c) } else { Token::Char(c) });
+            }
+            let c = match self.next_char() {
+                Some(c) => c,
+                None => return Some(Token::Char('\\')),
+            };
+            match c {
+                'v' => self.magic = MagicLevel::VeryMagic,
+                'm' => self.magic = MagicLevel::Magic,
+                'M' => self.magic = MagicLevel::Nomagic,
+                'V' => self.magic = MagicLevel::VeryNomagic,
+                'c' => self.ignore_case = Some(true),
+                'C' => self.ignore_case = Some(false),
+                c if self.is_special(c, true) => return Some(Token::Special(c)),
+                c if c.is_ascii_alphanumeric() || c == '_' => return Some(Token::Escape(c)),
+                c => return Some(Token::Char(c)),
+            }
+        }
+    }
+
+    /// Look at the next token without taking it
+    fn peek_token(&mut self) -> Option<Token> {
+        let (pos, magic, ignore_case) = (self.pos, self.magic, self.ignore_case);
+        let token = self.next_token();
+        self.pos = pos;
+        self.magic = magic;
+        self.ignore_case = ignore_case;
+        token
+    }
+
+    /// Parse branches up to the end of the pattern, or of a group
+    fn parse_branches(&mut self, in_group: bool) -> PatternResult<Vec<Vec<Node>>> {
+        let mut branches = vec![Vec::new()];
+
+        loop {
+            let token = match self.next_token() {
+                Some(token) => token,
+                None if in_group => return Err(PatternError("E54: Unmatched \\(".to_string())),
+                None => return Ok(branches),
+            };
+            let branch = branches.last_mut().expect("there is always a branch");
+            let node = match token {
+                Token::Special('|') => {
+                    branches.push(Vec::new());
+                    continue;
+                },
+                Token::Special(')') if in_group => return Ok(branches),
+                Token::Special(')') => return Err(PatternError("E55: Unmatched \\)".to_string())),
+                Token::Special('^') if branch.is_empty() => Node::LineStart,
+                Token::Special('$') if self.at_branch_end() => Node::LineEnd,
+                Token::Special('*') if branch.is_empty() => Node::Char('*'),
+                token => self.parse_atom(token)?,
+            };
+            let node = self.parse_multi(node)?;
+            branch.push(node);
+        }
+    }
+
+    /// Whether the pattern, a branch or a group ends after the current token
+    fn at_branch_end(&mut self) -> bool {
+        matches!(self.peek_token(), None | Some(Token::Special('|' | ')')))
+    }
+
+    /// Parse an atom starting with a token
+    fn parse_atom(&mut self, token: Token) -> PatternResult<Node> {
+        Ok(match token {
+            Token::Char(c) => Node::Char(c),
+            Token::Special('^') => Node::Char('^'),
+            Token::Special('$') => Node::Char('$'),
+            Token::Special('.') => Node::AnyChar { newline: false },
+            Token::Special('[') => self.parse_collection(false).unwrap_or(Node::Char('[')),
+            Token::Special('~') => match self.previous_replacement {
+                Some(text) => Node::Group { capture: false, branches: vec![text.chars().map(Node::Char).collect()] },
+                None => return Err(PatternError("E33: No previous substitute regular expression".to_string())),
+            },
+            Token::Special('<') => Node::Check(Check::WordStart),
+            Token::Special('>') => Node::Check(Check::WordEnd),
+            Token::Special('(') => {
+                self.groups += 1;
+                if self.groups > 9 {
+                    return Err(PatternError("E51: Too many \\(".to_string()));
+                }
+                Node::Group { capture: true, branches: self.parse_branches(true)? }
+            },
+            Token::Special('%') => self.parse_percent()?,
+            Token::Special(c @ ('*' | '+' | '=' | '?' | '{')) => {
+                return Err(PatternError(format!("E64: {} follows nothing", c)));
+            },
+            Token::Special('@') => return Err(unsupported("\\@")),
+            Token::Special('&') => return Err(unsupported("\\&")),
+            Token::Special(c) => Node::Char(c),
+            Token::Escape('z') => match self.next_char() {
+                Some('s') => Node::MatchStart,
+                Some('e') => Node::MatchEnd,
+                other => return Err(unsupported(&format!("\\z{}", other.map_or(String::new(), String::from)))),
+            },
+            Token::Escape('_') => match self.next_char() {
+                Some('^') => Node::LineStart,
+                Some('$') => Node::LineEnd,
+                Some('.') => Node::AnyChar { newline: true },
+                Some('[') => match self.parse_collection(true) {
+                    Some(node) => node,
+                    None => return Err(PatternError("E769: Missing ] after \\_[".to_string())),
+                },
+                Some(c) => match class_escape(c) {
+                    Some(class) => Node::Class(format!("(?:{}|\\n)", class)),
+                    None => return Err(PatternError("E63: Invalid use of \\_".to_string())),
+                },
+                None => return Err(PatternError("E63: Invalid use of \\_".to_string())),
+            },
+            Token::Escape(c @ '1'..='9') => return Err(unsupported(&format!("\\{}", c))),
+            Token::Escape(c) => match class_escape(c) {
+                Some(class) => Node::Class(class.to_string()),
+                None => Node::Char(char_escape(c).unwrap_or(c)),
+            },
+        })
+    }
+
+    /// Parse what follows `\%`
+    fn parse_percent(&mut self) -> PatternResult<Node> {
+        let c = self.next_char();
+        Ok(match c {
+            Some('(') => Node::Group { capture: false, branches: self.parse_branches(true)? },
+            Some('V') => Node::Check(Check::Visual),
+            Some('#') => Node::Check(Check::Cursor),
+            Some('^') => Node::Check(Check::FileStart),
+            Some('$') => Node::Check(Check::FileEnd),
+            Some(base @ ('d' | 'o' | 'x' | 'u' | 'U')) => {
+                let code = self.parse_code(base).ok_or_else(|| PatternError(format!("E678: Invalid character after \\%{}", base)))?;
+                Node::Char(code)
+            },
+            Some('<' | '>' | '0'..='9') => {
+                let compare = match c {
+                    Some('<') => Compare::Before,
+                    Some('>') => Compare::After,
+                    _ => {
+                        self.pos -= 1;
+                        Compare::At
+                    },
+                };
+                let number = self.parse_number();
+                match (number, self.next_char()) {
+                    (Some(number), Some('l')) => Node::Check(Check::Line(compare, number)),
+                    (Some(number), Some('c')) => Node::Check(Check::Column(compare, number)),
+                    (_, Some('v')) => return Err(unsupported("\\%v")),
+                    (None, Some('\'')) => return Err(unsupported("\\%'m")),
+                    _ => return Err(PatternError("E71: Invalid character after \\%".to_string())),
+                }
+            },
+            Some('[') => return Err(unsupported("\\%[]")),
+            _ => return Err(PatternError("E71: Invalid character after \\%".to_string())),
+        })
+    }
+
+    /// Parse a decimal number
+    fn parse_number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.chars.get(self.pos).is_some_and(char::is_ascii_digit) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect::<String>().parse().ok()
+    }
+
+    /// Parse a character code after \%d, \%o, \%x, \%u or \%U
+    fn parse_code(&mut self, base: char) -> Option<char> {
+        let (radix, max_digits) = match base {
+            'd' => (10, usize::MAX),
+            'o' => (8, 4),
+            'x' => (16, 2),
+            'u' => (16, 4),
+            _ => (16, 8),
+        };
+        let start = self.pos;
+        while self.pos - start < max_digits && self.chars.get(self.pos).is_some_and(|c| c.is_digit(radix)) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        u32::from_str_radix(&digits, radix).ok().and_then(char::from_u32)
+    }
+
+    /// Parse a collection after its `[`, as a class that also matches a
+    /// line break with `\_[`
+    ///
+    /// Returns `None`, with nothing taken, when there is no `]` to end it.
+    fn parse_collection(&mut self, newline: bool) -> Option<Node> {
+        let start = self.pos;
+        let mut class = String::from("[");
+        if self.eat('^') {
+            class.push('^');
+        }
+        if newline {
+            class.push_str("\\n");
+        }
+
+        let mut first = true;
+        loop {
+            let c = match self.next_char() {
+                Some(']') if !first => break,
+                Some(c) => c,
+                None => {
+                    self.pos = start;
+                    return None;
+                },
+            };
+            first = false;
+
+            if c == '[' {
+                if let Some(item) = self.parse_bracket_item() {
+                    class.push_str(&item);
+                    continue;
+                }
+            }
+            let c = if c == '\\' { self.collection_escape() } else { c };
+            push_class_char(c, &mut class);
+
+            // A range, unless the '-' is the last character
+            if self.chars.get(self.pos) == Some(&'-') && self.chars.get(self.pos + 1).is_some_and(|&next| next != ']') {
+                self.pos += 1;
+                let end = match self.next_char() {
+                    Some('\\') => self.collection_escape(),
+                    Some(end) => end,
+                    None => continue,
+                };
+                class.push('-');
+                push_class_char(end, &mut class);
+            }
+        }
+
+        class.push(']');
+        Some(Node::Class(class))
+    }
+
+    /// Parse `[:name:]`, `[=c=]` or `[.c.]` after its `[` in a collection
+    fn parse_bracket_item(&mut self) -> Option<String> {
+        let rest: String = self.chars[self.pos..].iter().collect();
+        if let Some(name) = rest.strip_prefix(':').and_then(|rest| rest.split_once(":]")).map(|(name, _)| name) {
+            let item = match name {
+                "alnum" | "alpha" | "blank" | "cntrl" | "digit" | "graph" | "lower" | "print" | "punct" | "space"
+                | "upper" | "xdigit" => format!("[:{}:]", name),
+                "return" => "\\r".to_string(),
+                "tab" => "\\t".to_string(),
+                "escape" => "\\x1b".to_string(),
+                "backspace" => "\\x08".to_string(),
+                "ident" | "keyword" => "\\w".to_string(),
+                "fname" => "0-9A-Za-z_/.\\-+,#$%~=".to_string(),
+                _ => return None,
+            };
+            self.pos += name.chars().count() + 3;
+            return Some(item);
+        }
+        for delimiter in ['=', '.'] {
+            let mut chars = rest.chars();
+            if let (Some(open), Some(c), Some(close), Some(']')) = (chars.next(), chars.next(), chars.next(), chars.next()) {
+                if open == delimiter && close == delimiter {
+                    self.pos += 4;
+                    let mut item = String::new();
+                    push_class_char(c, &mut item);
+                    return Some(item);
+                }
+            }
+        }
+        None
+    }
+
+    /// Character of a backslash item in a collection, after the backslash
+    fn collection_escape(&mut self) -> char {
+        match self.chars.get(self.pos).copied() {
+            Some(c @ ('\\' | ']' | '^' | '-')) => {
+                self.pos += 1;
+                c
+            },
+            Some(c @ ('d' | 'o' | 'x' | 'u' | 'U')) => {
+                self.pos += 1;
+                match self.parse_code(c) {
+                    Some(code) => code,
+                    None => {
+                        self.pos -= 1;
+                        '\\'
+                    },
+                }
+            },
+            Some(c) => match char_escape(c) {
+                Some(escaped) => {
+                    self.pos += 1;
+                    escaped
+                },
+                None => '\\',
+            },
+            None => '\\',
+        }
+    }
+
+    /// Parse the multis after an atom
+    fn parse_multi(&mut self, mut node: Node) -> PatternResult<Node> {
+        loop {
+            let (min, max, greedy) = match self.peek_token() {
+                Some(Token::Special('*')) => (0, None, true),
+                Some(Token::Special('+')) => (1, None, true),
+                Some(Token::Special('=' | '?')) => (0, Some(1), true),
+                Some(Token::Special('{')) => {
+                    self.next_token();
+                    let (min, max, greedy) = self.parse_count()?;
+                    node = Node::Repeat { node: Box::new(node), min, max, greedy };
+                    continue;
+                },
+                Some(Token::Special('@')) => return Err(unsupported("\\@")),
+                _ => return Ok(node),
+            };
+            self.next_token();
+            node = Node::Repeat { node: Box::new(node), min, max, greedy };
+        }
+    }
+
+    /// Parse a count after its `\{`: `n,m`, `n`, `n,`, `,m`, or none, with
+    /// a `-` in front to match as few as possible
+    fn parse_count(&mut self) -> PatternResult<(u32, Option<u32>, bool)> {
+        let error = || PatternError("E554: Syntax error in \\{...}".to_string());
+        let greedy = !self.eat('-');
+        let min = self.parse_number();
+        let max = if self.eat(',') { self.parse_number() } else { min };
+        self.eat('\\');
+        if !self.eat('}') {
+            return Err(error());
+        }
+        let to_u32 = |n: usize| u32::try_from(n).map_err(|_| error());
+        let min = to_u32(min.unwrap_or(0))?;
+        let max = max.map(to_u32).transpose()?;
+        // Vim takes the bounds either way round
+        Ok(match max {
+            Some(max) if max < min => (max, Some(min), greedy),
+            max => (min, max, greedy),
+        })
+    }
+}
+
+/// Writes parsed nodes out as a regular expression
+#[derive(Default)]
+struct Writer {
+    regex: String,
+    /// Number of groups in the regular expression so far
+    groups: usize,
+    /// Regular expression groups of the pattern's groups
+    captures: Vec<usize>,
+    /// Groups marking \zs and \ze
+    match_start: Vec<usize>,
+    match_end: Vec<usize>,
+    /// Groups marking positions to check
+    checks: Vec<(usize, Check)>,
+}
+
+impl Writer {
+    /// Add an empty group to mark a position, returning its index
+    fn marker(&mut self) -> usize {
+        self.groups += 1;
+        self.regex.push_str("()");
+        self.groups
+    }
+
+    fn write_branches(&mut self, branches: &[Vec<Node>]) {
+        for (i, branch) in branches.iter().enumerate() {
+            if i > 0 {
+                self.regex.push('|');
+            }
+            for node in branch {
+                self.write(node);
+            }
+        }
+    }
+
+    fn write(&mut self, node: &Node) {
+        match node {
+            Node::Char(c) => self.regex.push_str(&regex::escape(&c.to_string())),
+            Node::AnyChar { newline: false } => self.regex.push('.'),
+            Node::AnyChar { newline: true } => self.regex.push_str("(?s:.)"),
+            Node::Class(class) => self.regex.push_str(class),
+            Node::LineStart => self.regex.push('^'),
+            Node::LineEnd => self.regex.push('$'),
+            Node::MatchStart => {
+                let group = self.marker();
+                self.match_start.push(group);
+            },
+            Node::MatchEnd => {
+                let group = self.marker();
+                self.match_end.push(group);
+            },
+            Node::Check(check) => {
+                // The regular expression can at least tell where words change
+                if let Check::WordStart | Check::WordEnd = check {
+                    self.regex.push_str("\\b");
+                }
+                let group = self.marker();
+                self.checks.push((group, *check));
+            },
+            Node::Group { capture, branches } => {
+                if *capture {
+                    self.groups += 1;
+                    self.captures.push(self.groups);
+                    self.regex.push('(');
+                } else {
+                    self.regex.push_str("(?:");
+                }
+                self.write_branches(branches);
+                self.regex.push(')');
+            },
+            Node::Repeat { node, min, max, greedy } => {
+                let atomic = matches!(**node, Node::Char(_) | Node::AnyChar { .. } | Node::Class(_) | Node::Group { .. });
+                if !atomic {
+                    self.regex.push_str("(?:");
+                }
+                self.write(node);
+                if !atomic {
+                    self.regex.push(')');
+                }
+                match (min, max) {
+                    (0, None) => self.regex.push('*'),
+                    (1, None) => self.regex.push('+'),
+                    (0, Some(1)) => self.regex.push('?'),
+                    (min, None) => self.regex.push_str(&format!("{{{},}}", min)),
+                    (min, Some(max)) if min == max => self.regex.push_str(&format!("{{{}}}", min)),
+                    (min, Some(max)) => self.regex.push_str(&format!("{{{},{}}}", min, max)),
+                }
+                if !greedy {
+                    self.regex.push('?');
+                }
+            },
+        }
+    }
+}
+
+/// A compiled Vim pattern
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    regex: Regex,
+    /// Regular expression groups of `\1` to `\9`
+    captures: Vec<usize>,
+    /// Groups marking \zs and \ze
+    match_start: Vec<usize>,
+    match_end: Vec<usize>,
+    /// Groups marking positions to check
+    checks: Vec<(usize, Check)>,
+}
+
+impl Pattern {
+    /// Compile a pattern
+    ///
+    /// The pattern ignores case when `\c` is in it, or when the options say
+    /// so and there is no `\C`.
+    pub fn new(pattern: &str, options: &PatternOptions) -> PatternResult<Self> {
+        let mut parser = Parser::new(pattern, options.previous_replacement);
+        let branches = parser.parse_branches(false)?;
+        let ignore_case = parser
+            .ignore_case
+            .unwrap_or(options.ignore_case && !(options.smart_case && has_upper_case(pattern)));
+
+        let mut writer = Writer::default();
+        if ignore_case {
+            writer.regex.push_str("(?i)");
+        }
+        writer.write_branches(&branches);
+
+        let regex = RegexBuilder::new(&writer.regex)
+            .build()
+            .map_err(|_| PatternError(format!("E383: Invalid search string: {}", pattern)))?;
+        Ok(Self {
+            regex,
+            captures: writer.captures,
+            match_start: writer.match_start,
+            match_end: writer.match_end,
+            checks: writer.checks,
+        })
+    }
+
+    /// Get the regular expression the pattern was translated to
+    pub fn as_regex(&self) -> &Regex {
+        &self.regex
+    }
+
+    /// Whether the pattern matches somewhere in a line
+    pub fn is_match(&self, text: &str, context: &MatchContext) -> bool {
+        self.find_at(text, 0, context).is_some()
+    }
+
+    /// Find the first match in a line
+    pub fn find(&self, text: &str, context: &MatchContext) -> Option<PatternMatch> {
+        self.find_at(text, 0, context).map(|(found, _)| found)
+    }
+
+    /// Find all the matches in a line
+    ///
+    /// An empty match right where the previous match ended is skipped.
+    pub fn find_all(&self, text: &str, context: &MatchContext) -> Vec<PatternMatch> {
+        let mut matches: Vec<PatternMatch> = Vec::new();
+        let mut pos = 0;
+
+        while pos <= text.len() {
+            let (found, end) = match self.find_at(text, pos, context) {
+                Some(found) => found,
+                None => break,
+            };
+            let previous_end = matches.last().map(|previous| previous.end);
+            pos = if end > pos { end } else { next_boundary(text, pos) };
+            if found.start == found.end && previous_end == Some(found.start) {
+                continue;
+            }
+            matches.push(found);
+        }
+
+        matches
+    }
+
+    /// Find the first match from a byte index on, returning it and where
+    /// the regular expression's match ended
+    fn find_at(&self, text: &str, mut pos: usize, context: &MatchContext) -> Option<(PatternMatch, usize)> {
+        let mut locations = self.regex.capture_locations();
+
+        while pos <= text.len() {
+            let found = self.regex.captures_read_at(&mut locations, text, pos)?;
+            if self.checks_hold(&locations, text, context) {
+                return Some((self.make_match(&locations, found.start(), found.end()), found.end()));
+            }
+            // Try again from the next character
+            pos = next_boundary(text, found.start());
+        }
+
+        None
+    }
+
+    fn make_match(&self, locations: &CaptureLocations, start: usize, end: usize) -> PatternMatch {
+        let marker = |groups: &[usize]| groups.iter().rev().find_map(|&group| locations.get(group)).map(|(at, _)| at);
+        let match_start = marker(&self.match_start).unwrap_or(start);
+        let match_end = marker(&self.match_end).unwrap_or(end).max(match_start);
+        PatternMatch {
+            start: match_start,
+            end: match_end,
+            groups: self.captures.iter().map(|&group| locations.get(group)).collect(),
+        }
+    }
+
+    fn checks_hold(&self, locations: &CaptureLocations, text: &str, context: &MatchContext) -> bool {
+        self.checks.iter().all(|&(group, check)| match locations.get(group) {
+            Some((at, _)) => check_holds(check, text, at, context),
+            None => true,
+        })
+    }
+}
+
+/// Byte index of the character after the one at an index, or past the end
+fn next_boundary(text: &str, at: usize) -> usize {
+    text[at..].chars().next().map_or(at + 1, |c| at + c.len_utf8())
+}
+
+/// Whether a position check holds at a byte index of a line
+fn check_holds(check: Check, text: &str, at: usize, context: &MatchContext) -> bool {
+    let before = text[..at].chars().next_back();
+    let after = text[at..].chars().next();
+    let column = || text[..at].chars().count();
+    match check {
+        Check::WordStart => after.is_some_and(is_word_char) && !before.is_some_and(is_word_char),
+        Check::WordEnd => before.is_some_and(is_word_char) && !after.is_some_and(is_word_char),
+        Check::Visual => context.visual.is_some_and(|area| in_visual_area(area, context.line, column())),
+        Check::Line(compare, line) => compare.holds(context.line + 1, line),
+        Check::Column(compare, column) => compare.holds(at + 1, column),
+        Check::Cursor => context.cursor == Some(CursorPosition::new(context.line, column())),
+        Check::FileStart => context.line == 0 && at == 0,
+        Check::FileEnd => context.line == context.last_line && at == text.len(),
+    }
+}
+
+/// Whether a position is in a visual area
+fn in_visual_area(area: &VisualArea, line: usize, column: usize) -> bool {
+    let (start, end) = (area.start, area.end);
+    let (first, last) = if start.line <= end.line { (start.line, end.line) } else { (end.line, start.line) };
+    if line < first || line > last {
+        return false;
+    }
+    match area.mode {
+        VisualMode::Line => true,
+        VisualMode::Block => {
+            let (left, right) = match start.column.cmp(&end.column) {
+                Ordering::Greater => (end.column, start.column),
+                _ => (start.column, end.column),
+            };
+            (left..=right).contains(&column)
+        },
+        VisualMode::Char => {
+            let (from, to) = if (start.line, start.column) <= (end.line, end.column) { (start, end) } else { (end, start) };
+            (from.line, from.column) <= (line, column) && (line, column) <= (to.line, to.column)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(pattern: &str) -> Pattern {
+        Pattern::new(pattern, &PatternOptions::default()).unwrap()
+    }
+
+    fn matches(pattern: &str, text: &str) -> Vec<String> {
+        compile(pattern)
+            .find_all(text, &MatchContext::default())
+            .iter()
+            .map(|found| text[found.start..found.end].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_magic() {
+        assert_eq!(matches("\\<the\\>", "the other then the"), ["the", "the"]);
+        assert_eq!(matches("a\\{2,3}", "a aa aaaa"), ["aa", "aaa"]);
+        assert_eq!(matches("a\\{-1,}", "aaa"), ["a", "a", "a"]);
+        assert_eq!(matches("ab\\+c\\=", "ab abbc"), ["ab", "abbc"]);
+        assert_eq!(matches("foo\\|bar", "bar foo"), ["bar", "foo"]);
+        assert_eq!(matches("x.*y", "x(1+2)? y"), ["x(1+2)? y"]);
+        assert_eq!(matches("a+b?", "aa+b? a+b"), ["a+b?"]);
+        assert_eq!(matches("\\d\\+\\s\\w", "x 12 y"), ["12 y"]);
+        assert_eq!(matches("^a", "aa"), ["a"]);
+        assert_eq!(matches("a^b$c$", "a^b$c"), ["a^b$c"]);
+        assert_eq!(matches("*a", "**a"), ["*a"]);
+        assert_eq!(matches("[a-c]\\+[^0-9x]", "abcd x0"), ["abcd"]);
+        assert_eq!(matches("[]x-]\\+", "a]-x"), ["]-x"]);
+        assert_eq!(matches("[[:digit:][:upper:]]\\+", "abC3d"), ["C3"]);
+        assert_eq!(matches("[abc", "x[abc"), ["[abc"]);
+        assert_eq!(matches("\\%d97\\%x62", "cab"), ["ab"]);
+    }
+
+    #[test]
+    fn test_magic_levels() {
+        assert_eq!(matches("\\v(ab)+c?", "ababc"), ["ababc"]);
+        assert_eq!(matches("\\v<a{2}>", "aa aaa"), ["aa"]);
+        assert_eq!(matches("\\Ma.b*", "a.b* axbb"), ["a.b*"]);
+        assert_eq!(matches("\\Ma\\.b\\*", "axbb"), ["axbb"]);
+        assert_eq!(matches("\\V$a.*", "$a.*"), ["$a.*"]);
+        assert_eq!(matches("\\Va\\.\\*\\(b\\)", "axxb"), ["axxb"]);
+        assert_eq!(matches("a.\\vb+", "a.bb"), ["a.bb"]);
+    }
+
+    #[test]
+    fn test_match_start_and_end() {
+        assert_eq!(matches("foo\\zsbar", "foobar bar"), ["bar"]);
+        assert_eq!(matches("a\\zsb\\zec", "abc abd abc"), ["b", "b"]);
+        assert_eq!(matches("\\v(x|y)\\zsz", "xz yz zz"), ["z", "z"]);
+
+        let pattern = compile("\\(\\w\\+\\)\\zs=\\(\\d\\)");
+        let text = "key=1";
+        let found = pattern.find(text, &MatchContext::default()).unwrap();
+        assert_eq!(found.group(text, 0), Some("=1"));
+        assert_eq!(found.group(text, 1), Some("key"));
+        assert_eq!(found.group(text, 2), Some("1"));
+        assert_eq!(found.group(text, 3), None);
+    }
+
+    #[test]
+    fn test_empty_matches() {
+        assert_eq!(matches("x*", "xab").len(), 3);
+        assert_eq!(matches("x*", "abc").len(), 4);
+    }
+
+    #[test]
+    fn test_case() {
+        let ignore = PatternOptions { ignore_case: true, ..Default::default() };
+        let smart = PatternOptions { ignore_case: true, smart_case: true, ..Default::default() };
+        let context = MatchContext::default();
+        let is_match = |pattern: &str, options: &PatternOptions, text: &str| {
+            Pattern::new(pattern, options).unwrap().is_match(text, &context)
+        };
+
+        assert!(!is_match("foo", &PatternOptions::default(), "FOO"));
+        assert!(is_match("foo", &ignore, "FOO"));
+        assert!(is_match("foo\\c", &PatternOptions::default(), "FOO"));
+        assert!(!is_match("\\Cfoo", &ignore, "FOO"));
+        assert!(is_match("foo", &smart, "FOO"));
+        assert!(!is_match("Foo", &smart, "FOO"));
+        assert!(is_match("\\Sfoo", &smart, "XFOO"));
+        assert!(!is_match("\\u", &ignore, "a"));
+    }
+
+    #[test]
+    fn test_line_breaks() {
+        assert_eq!(matches("a\\_sb", "a b"), ["a b"]);
+        assert_eq!(matches("a\\_[xy]", "ay"), ["ay"]);
+        assert_eq!(matches("a\\_.b\\_$", "acb"), ["acb"]);
+    }
+
+    #[test]
+    fn test_positions() {
+        let area = VisualArea {
+            buffer_id: 1,
+            mode: VisualMode::Char,
+            start: CursorPosition::new(1, 4),
+            end: CursorPosition::new(0, 2),
+            curswant: 2,
+        };
+        let context = |line| MatchContext {
+            line,
+            last_line: 1,
+            cursor: Some(CursorPosition::new(1, 2)),
+            visual: Some(&area),
+        };
+        let starts = |pattern: &str, line| -> Vec<usize> {
+            compile(pattern).find_all("a a a a", &context(line)).iter().map(|found| found.start).collect()
+        };
+
+        assert_eq!(starts("\\%Va", 0), [2, 4, 6]);
+        assert_eq!(starts("\\%Va", 1), [0, 2, 4]);
+        assert_eq!(starts("\\%#a", 1), [2]);
+        assert_eq!(starts("\\%>3ca", 0), [4, 6]);
+        assert_eq!(starts("\\%<5ca", 0), [0, 2]);
+        assert_eq!(starts("\\%2la", 0), Vec::<usize>::new());
+        assert_eq!(starts("\\%2la", 1), [0, 2, 4, 6]);
+        assert_eq!(starts("\\%^a", 0), [0]);
+        assert_eq!(starts("a\\%$", 0), Vec::<usize>::new());
+        assert_eq!(starts("a\\%$", 1), [6]);
+    }
+
+    #[test]
+    fn test_previous_replacement() {
+        let options = PatternOptions { previous_replacement: Some("a.b"), ..Default::default() };
+        let pattern = Pattern::new("x~\\+", &options).unwrap();
+        let found = pattern.find("axbab xa.ba.b", &MatchContext::default()).unwrap();
+        assert_eq!(found.start, 6);
+        assert_eq!(found.end, 13);
+        assert!(Pattern::new("~", &PatternOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |pattern: &str| Pattern::new(pattern, &PatternOptions::default()).unwrap_err().to_string();
+        assert_eq!(error("\\(a"), "E54: Unmatched \\(");
+        assert_eq!(error("a\\)"), "E55: Unmatched \\)");
+        assert_eq!(error("\\+a"), "E64: + follows nothing");
+        assert_eq!(error("a\\{1"), "E554: Syntax error in \\{...}");
+        assert_eq!(error("\\(a\\)\\1"), "Unsupported pattern item: \\1");
+        assert_eq!(error("a\\@!"), "Unsupported pattern item: \\@");
+    }
+}
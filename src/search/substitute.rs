@@ -0,0 +1,269 @@
+//! Substitute - Flags and replacement strings of `:substitute`
+//!
+//! This module parses the flags and the replacement string of a substitute
+//! and expands the replacement for each match. Finding the matches and
+//! changing the buffer is left to the editor.
+
+use super::PatternMatch;
+
+/// Flags of a substitute
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SubstituteFlags {
+    /// Replace every match in a line rather than the first one (g)
+    pub global: bool,
+    /// Ask before each replacement (c)
+    pub confirm: bool,
+    /// Whether case is ignored (i, I), or `None` to follow 'ignorecase' and 'smartcase'
+    pub ignore_case: Option<bool>,
+    /// Only count the matches (n)
+    pub count_only: bool,
+    /// Don't fail when nothing matches (e)
+    pub no_error: bool,
+    /// Use the last search pattern rather than the last substitute pattern (r)
+    pub search_pattern: bool,
+}
+
+impl SubstituteFlags {
+    /// Parse the flags at the start of the input, returning the rest
+    ///
+    /// A leading `&` keeps the flags of the previous substitute, and each
+    /// `g` toggles replacing every match.
+    pub fn parse(input: &str, previous: SubstituteFlags) -> (Self, &str) {
+        let (mut flags, input) = match input.strip_prefix('&') {
+            Some(rest) => (previous, rest),
+            None => (Self::default(), input),
+        };
+
+        let end = input.find(|c| !"gciIner".contains(c)).unwrap_or(input.len());
+        for c in input[..end].chars() {
+            match c {
+                'g' => flags.global = !flags.global,
+                'c' => flags.confirm = true,
+                'i' => flags.ignore_case = Some(true),
+                'I' => flags.ignore_case = Some(false),
+                'n' => flags.count_only = true,
+                'e' => flags.no_error = true,
+                _ => flags.search_pattern = true,
+            }
+        }
+
+        (flags, &input[end..])
+    }
+}
+
+/// A substitute, as it is kept for repeating it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Substitute {
+    /// Pattern to replace
+    pub pattern: String,
+    /// Replacement string, with `~` expanded
+    pub replacement: String,
+    /// Flags
+    pub flags: SubstituteFlags,
+}
+
+/// Case a replacement changes text to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Case {
+    Upper,
+    Lower,
+}
+
+impl Case {
+    fn apply(self, c: char, out: &mut String) {
+        match self {
+            Case::Upper => out.extend(c.to_uppercase()),
+            Case::Lower => out.extend(c.to_lowercase()),
+        }
+    }
+}
+
+/// Part of a replacement string
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    /// Text taken as it is
+    Text(String),
+    /// The whole match (0) or a group
+    Group(usize),
+    /// Change the case of the next character (\u, \l)
+    NextChar(Case),
+    /// Change the case of what follows up to \e or \E (\U, \L)
+    Until(Case),
+    /// End \U or \L (\e, \E)
+    End,
+}
+
+/// A parsed replacement string
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replacement {
+    parts: Vec<Part>,
+}
+
+impl Replacement {
+    /// Parse a replacement string
+    ///
+    /// `&` and `\0` stand for the whole match and `\1` to `\9` for groups.
+    /// `\u` and `\l` change the case of the next character, and `\U` and
+    /// `\L` the case of what follows up to `\e` or `\E`. `\r` and `\n`
+    /// break the line and `\t` is a tab. Any other character after a
+    /// backslash is taken as it is.
+    pub fn parse(text: &str) -> Self {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars();
+
+        while let Some(c) = chars.next() {
+            let part = match c {
+                '&' => Part::Group(0),
+                '\\' => match chars.next() {
+                    Some(digit @ '0'..='9') => Part::Group(digit as usize - '0' as usize),
+                    Some('u') => Part::NextChar(Case::Upper),
+                    Some('l') => Part::NextChar(Case::Lower),
+                    Some('U') => Part::Until(Case::Upper),
+                    Some('L') => Part::Until(Case::Lower),
+                    Some('e' | 'E') => Part::End,
+                    Some('r' | 'n') => {
+                        literal.push('\n');
+                        continue;
+                    },
+                    Some('t') => {
+                        literal.push('\t');
+                        continue;
+                    },
+                    Some(other) => {
+                        literal.push(other);
+                        continue;
+                    },
+                    None => {
+                        literal.push('\\');
+                        continue;
+                    },
+                },
+                c => {
+                    literal.push(c);
+                    continue;
+                },
+            };
+
+            if !literal.is_empty() {
+                parts.push(Part::Text(std::mem::take(&mut literal)));
+            }
+            parts.push(part);
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Text(literal));
+        }
+        Self { parts }
+    }
+
+    /// Expand the replacement for a match in a line
+    pub fn expand(&self, text: &str, found: &PatternMatch) -> String {
+        let mut out = String::new();
+        let mut next_char = None;
+        let mut until = None;
+
+        for part in &self.parts {
+            let text = match part {
+                Part::Text(text) => text.as_str(),
+                Part::Group(group) => found.group(text, *group).unwrap_or(""),
+                Part::NextChar(case) => {
+                    next_char = Some(*case);
+                    continue;
+                },
+                Part::Until(case) => {
+                    until = Some(*case);
+                    continue;
+                },
+                Part::End => {
+                    until = None;
+                    continue;
+                },
+            };
+
+            for c in text.chars() {
+                match next_char.take().or(until) {
+                    Some(case) => case.apply(c, &mut out),
+                    None => out.push(c),
+                }
+            }
+        }
+
+        out
+    }
+}
+
+/// Put the previous replacement string in place of each `~` in a replacement
+///
+/// `\~` stays, for the replacement to take as a literal `~`.
+pub fn expand_previous(text: &str, previous: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '~' => out.push_str(previous),
+            '\\' => {
+                out.push('\\');
+                if let Some(next) = chars.next() {
+                    out.push(next);
+                }
+            },
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::{MatchContext, Pattern, PatternOptions};
+
+    fn expand(pattern: &str, replacement: &str, text: &str) -> String {
+        let pattern = Pattern::new(pattern, &PatternOptions::default()).unwrap();
+        let found = pattern.find(text, &MatchContext::default()).unwrap();
+        Replacement::parse(replacement).expand(text, &found)
+    }
+
+    #[test]
+    fn test_parse_flags() {
+        let (flags, rest) = SubstituteFlags::parse("gi 3", SubstituteFlags::default());
+        assert!(flags.global);
+        assert_eq!(flags.ignore_case, Some(true));
+        assert_eq!(rest, " 3");
+
+        let previous = SubstituteFlags { global: true, confirm: true, ..Default::default() };
+        let (flags, rest) = SubstituteFlags::parse("&gn", previous);
+        assert!(!flags.global);
+        assert!(flags.confirm);
+        assert!(flags.count_only);
+        assert_eq!(rest, "");
+
+        let (flags, _) = SubstituteFlags::parse("Ie", previous);
+        assert!(!flags.global);
+        assert!(flags.no_error);
+        assert_eq!(flags.ignore_case, Some(false));
+    }
+
+    #[test]
+    fn test_replacement() {
+        assert_eq!(expand("b\\+", "[&]", "abbc"), "[bb]");
+        assert_eq!(expand("b\\+", "\\&\\0", "abbc"), "&bb");
+        assert_eq!(expand("\\(\\w\\+\\) \\(\\w\\+\\)", "\\2 \\1", "hello world"), "world hello");
+        assert_eq!(expand("\\v(\\w+) (\\w+)", "\\u\\1 \\U\\2\\E!", "hello world"), "Hello WORLD!");
+        assert_eq!(expand("\\w\\+", "\\L\\u&", "hELLO"), "Hello");
+        assert_eq!(expand("\\w\\+", "\\l&", "HELLO"), "hELLO");
+        assert_eq!(expand("x\\zsy\\(z\\)", "<&\\1>", "xyz"), "<yzz>");
+        assert_eq!(expand(",", "\\r\\t", ","), "\n\t");
+        assert_eq!(expand("x", "a\\\\b\\/", "x"), "a\\b/");
+    }
+
+    #[test]
+    fn test_expand_previous() {
+        assert_eq!(expand_previous("<~>", "old"), "<old>");
+        assert_eq!(expand_previous("\\~~", "old"), "\\~old");
+        assert_eq!(expand_previous("\\\\~", "old"), "\\\\old");
+    }
+}
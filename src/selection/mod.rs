@@ -218,6 +218,7 @@ impl Selection {
 }
 
 /// Manager for handling selections
+#[derive(Clone)]
 pub struct SelectionManager {
     /// The current selection, if any
     current_selection: Option<Selection>,
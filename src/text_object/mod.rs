@@ -35,6 +35,27 @@ pub enum TextObjectType {
     TagBlock,
 }
 
+impl TextObjectType {
+    /// Get the text object typed after `i` or `a`, like the `w` of `iw`
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            'w' => Some(TextObjectType::Word),
+            'W' => Some(TextObjectType::BigWord),
+            's' => Some(TextObjectType::Sentence),
+            'p' => Some(TextObjectType::Paragraph),
+            '(' | ')' | 'b' => Some(TextObjectType::ParenBlock),
+            '{' | '}' | 'B' => Some(TextObjectType::BraceBlock),
+            '[' | ']' => Some(TextObjectType::BracketBlock),
+            '<' | '>' => Some(TextObjectType::AngleBlock),
+            '\'' => Some(TextObjectType::SingleQuoteBlock),
+            '"' => Some(TextObjectType::DoubleQuoteBlock),
+            '`' => Some(TextObjectType::BacktickBlock),
+            't' => Some(TextObjectType::TagBlock),
+            _ => None,
+        }
+    }
+}
+
 /// A text object represents a range of text in a buffer
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct TextObject {
@@ -549,6 +570,14 @@ mod tests {
     use super::*;
     use crate::buffer::Buffer;
     
+    #[test]
+    fn test_text_object_type_from_char() {
+        assert_eq!(TextObjectType::from_char('w'), Some(TextObjectType::Word));
+        assert_eq!(TextObjectType::from_char('b'), Some(TextObjectType::ParenBlock));
+        assert_eq!(TextObjectType::from_char('}'), Some(TextObjectType::BraceBlock));
+        assert_eq!(TextObjectType::from_char('x'), None);
+    }
+    
     #[test]
     fn test_find_word() {
         let mut buffer = Buffer::new(1);
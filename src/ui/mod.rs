@@ -7,6 +7,7 @@ pub mod status_line;
 pub mod tab;
 pub mod window;
 
+use std::cell::Cell;
 use std::io::{self, Write};
 use std::error::Error;
 use crossterm::{
@@ -28,6 +29,9 @@ use self::status_line::StatusLine;
 use self::tab::{TabManager, Tab, TabId};
 use self::window::{WindowManager, WindowRect, SplitDirection, Window};
 
+/// Shown under a message of several lines, which stays until a key is typed
+const HIT_ENTER_PROMPT: &str = "Press ENTER or type command to continue";
+
 /// UI-related errors
 #[derive(Debug)]
 pub enum UiError {
@@ -75,6 +79,8 @@ pub struct TerminalUi {
     status_line: StatusLine,
     /// Tab manager
     tab_manager: TabManager,
+    /// Whether the terminal regained focus since this was last checked
+    focus_gained: Cell<bool>,
 }
 
 impl TerminalUi {
@@ -89,6 +95,7 @@ impl TerminalUi {
             syntax_renderer: SyntaxRenderer::new(),
             status_line: StatusLine::new(),
             tab_manager: TabManager::new(),
+            focus_gained: Cell::new(false),
         })
     }
     
@@ -102,7 +109,8 @@ impl TerminalUi {
         execute!(
             io::stdout(),
             terminal::EnterAlternateScreen,
-            cursor::Hide
+            cursor::Hide,
+            event::EnableFocusChange
         )?;
         self.alternate_screen = true;
         
@@ -121,6 +129,7 @@ impl TerminalUi {
         if self.alternate_screen {
             execute!(
                 io::stdout(),
+                event::DisableFocusChange,
                 terminal::LeaveAlternateScreen,
                 cursor::Show
             )?;
@@ -158,14 +167,21 @@ impl TerminalUi {
     /// Check if a key event is available
     pub fn poll_key(&self, timeout_ms: u64) -> UiResult<Option<KeyEvent>> {
         if event::poll(std::time::Duration::from_millis(timeout_ms))? {
-            if let Event::Key(key) = event::read()? {
-                return Ok(Some(key));
+            match event::read()? {
+                Event::Key(key) => return Ok(Some(key)),
+                Event::FocusGained => self.focus_gained.set(true),
+                _ => {}
             }
         }
         
         Ok(None)
     }
     
+    /// Check whether the terminal regained focus since the last call
+    pub fn take_focus_gained(&self) -> bool {
+        self.focus_gained.replace(false)
+    }
+    
     /// Render a buffer to the terminal
     pub fn render_buffer(
         &self,
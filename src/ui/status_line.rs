@@ -59,8 +59,12 @@ impl StatusLine {
         // Get file information
         let file_name = buffer.name();
         let file_type = self.get_file_type(buffer);
-        let encoding = "UTF-8"; // Assuming UTF-8 for now
-        let line_ending = "LF";  // Assuming LF for now
+        let encoding = format!(
+            "{}{}",
+            buffer.fileencoding().name().to_uppercase(),
+            if buffer.bomb() { " BOM" } else { "" }
+        );
+        let line_ending = buffer.fileformat().line_ending_name();
         
         // Get buffer state indicators
         let modified_indicator = if buffer.is_modified() { "[+]" } else { "" };
@@ -85,12 +89,13 @@ impl StatusLine {
         
         // Format file information
         let file_info = format!(
-            "{}{}{} | {} | {}",
+            "{}{}{} | {} | {} | {}",
             file_name,
             modified_indicator,
             readonly_indicator,
             file_type,
-            encoding
+            encoding,
+            line_ending
         );
         
         // Calculate available space
@@ -4,6 +4,8 @@
 //! Each tab can contain multiple windows, and the user can switch between tabs.
 
 use std::collections::HashMap;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
 use crate::ui::window::{WindowManager, Window};
 
 /// Tab ID type
@@ -72,6 +74,8 @@ pub struct TabManager {
     current_tab_id: Option<TabId>,
     /// Next tab ID
     next_tab_id: TabId,
+    /// Next window ID, shared by the tabs so window IDs are unique
+    next_window_id: Arc<AtomicUsize>,
 }
 
 impl TabManager {
@@ -81,6 +85,7 @@ impl TabManager {
             tabs: HashMap::new(),
             current_tab_id: None,
             next_tab_id: 1,
+            next_window_id: Arc::new(AtomicUsize::new(1)),
         }
     }
 
@@ -89,7 +94,8 @@ impl TabManager {
         let id = self.next_tab_id;
         self.next_tab_id += 1;
 
-        let tab = Tab::new(id, name);
+        let mut tab = Tab::new(id, name);
+        tab.window_manager = WindowManager::with_id_counter(self.next_window_id.clone());
         self.tabs.insert(id, tab);
 
         // If this is the first tab, make it the current tab
@@ -222,6 +228,11 @@ impl TabManager {
         &self.tabs
     }
 
+    /// Get mutable references to all tabs
+    pub fn tabs_mut(&mut self) -> impl Iterator<Item = &mut Tab> {
+        self.tabs.values_mut()
+    }
+
     /// Get the number of tabs
     pub fn tab_count(&self) -> usize {
         self.tabs.len()
@@ -95,6 +95,11 @@ impl TerminalUi {
         self.tab_manager.current_tab_mut().and_then(|tab| tab.current_window_mut())
     }
     
+    /// Get mutable references to the windows of all tabs
+    pub fn windows_mut(&mut self) -> impl Iterator<Item = &mut Window> {
+        self.tab_manager.tabs_mut().flat_map(|tab| tab.window_manager.windows_mut().iter_mut())
+    }
+    
     /// Split the current window
     pub fn split_window(&mut self, direction: SplitDirection, buffer_id: usize) -> UiResult<Option<usize>> {
         if let Some(tab) = self.tab_manager.current_tab_mut() {
@@ -181,7 +186,19 @@ impl TerminalUi {
     }
     
     /// Render the current tab
-    pub fn render_current_tab(&self, buffers: &[&Buffer], mode: Mode, command_buffer: &str) -> UiResult<()> {
+    ///
+    /// The command line comes with the column of its cursor, and the
+    /// wildmenu with the matches of a completion and the one selected. A
+    /// message takes the place of the command line when none is typed, and
+    /// goes above it otherwise.
+    pub fn render_current_tab(
+        &self,
+        buffers: &[&Buffer],
+        mode: Mode,
+        command_line: Option<(&str, usize)>,
+        wildmenu: Option<(&[String], Option<usize>)>,
+        message: Option<&str>,
+    ) -> UiResult<()> {
         // Clear the screen
         execute!(
             io::stdout(),
@@ -207,8 +224,18 @@ impl TerminalUi {
             self.render_windows(&tab.window_manager, &tab_buffers, mode)?;
         }
         
-        // Render command line if in command mode
-        if mode == Mode::Command {
+        // Render the wildmenu above the command line
+        if let Some((matches, selected)) = wildmenu {
+            self.render_wildmenu(matches, selected)?;
+        }
+        
+        // Render the message, above the command line when there is one
+        if let Some(message) = message {
+            self.render_message(message, command_line.is_none())?;
+        }
+        
+        // Render the command line, if there is one
+        if let Some((command_line, cursor)) = command_line {
             let (width, height) = self.size;
             let mut stdout = io::stdout();
             
@@ -224,13 +251,13 @@ impl TerminalUi {
                 terminal::Clear(ClearType::CurrentLine)
             )?;
             
-            // Write the command prompt and buffer
-            write!(stdout, ":{}", command_buffer)?;
+            // Write the command line
+            write!(stdout, "{}", command_line)?;
             
-            // Position the cursor after the command text
+            // Position the cursor in the command text
             execute!(
                 stdout,
-                cursor::MoveTo(1 + command_buffer.len() as u16, height - 1)
+                cursor::MoveTo(cursor.min(width.saturating_sub(1) as usize) as u16, height - 1)
             )?;
         }
         
@@ -240,6 +267,86 @@ impl TerminalUi {
         Ok(())
     }
     
+    /// Render a message on the last lines of the screen, or above the command line
+    ///
+    /// A message of several lines covers the windows from the bottom up, its
+    /// last lines when it doesn't fit. On the last line it asks for a key.
+    fn render_message(&self, message: &str, last_line: bool) -> UiResult<()> {
+        let (width, height) = self.size;
+        let mut stdout = io::stdout();
+        
+        let mut lines: Vec<&str> = message.lines().collect();
+        if last_line && lines.len() > 1 {
+            lines.push(HIT_ENTER_PROMPT);
+        }
+        let bottom = if last_line { height } else { height.saturating_sub(1) };
+        let shown = &lines[lines.len().saturating_sub(bottom as usize)..];
+        
+        // Leave the cursor in the window it was put in
+        execute!(stdout, cursor::SavePosition)?;
+        for (row, line) in (bottom - shown.len() as u16..).zip(shown) {
+            execute!(
+                stdout,
+                cursor::MoveTo(0, row),
+                terminal::Clear(ClearType::CurrentLine)
+            )?;
+            let line: String = line.chars().take(width as usize).collect();
+            write!(stdout, "{}", line)?;
+        }
+        execute!(stdout, cursor::RestorePosition)?;
+        
+        Ok(())
+    }
+    
+    /// Render the matches of a completion on the line above the command line
+    ///
+    /// The matches start from the selected one when it would not fit
+    /// otherwise, and `>` shows there are more than fit.
+    fn render_wildmenu(&self, matches: &[String], selected: Option<usize>) -> UiResult<()> {
+        let mut stdout = io::stdout();
+        let (width, height) = self.size;
+        let width = width as usize;
+        
+        // Start from the selected match when it is past the edge
+        let end_of = |first: usize, last: usize| -> usize {
+            matches[first..=last].iter().map(|name| name.chars().count() + 2).sum()
+        };
+        let first = match selected {
+            Some(index) if end_of(0, index) > width.saturating_sub(1) => index,
+            _ => 0,
+        };
+        
+        execute!(
+            stdout,
+            cursor::MoveTo(0, height.saturating_sub(2)),
+            style::SetBackgroundColor(Color::DarkGrey),
+            style::SetForegroundColor(Color::White)
+        )?;
+        
+        let mut used = 0;
+        for (index, name) in matches.iter().enumerate().skip(first) {
+            let len = name.chars().count() + 2;
+            if used + len > width.saturating_sub(1) {
+                write!(stdout, ">")?;
+                used += 1;
+                break;
+            }
+            if Some(index) == selected {
+                execute!(stdout, style::SetBackgroundColor(Color::Yellow), style::SetForegroundColor(Color::Black))?;
+                write!(stdout, "{}", name)?;
+                execute!(stdout, style::SetBackgroundColor(Color::DarkGrey), style::SetForegroundColor(Color::White))?;
+                write!(stdout, "  ")?;
+            } else {
+                write!(stdout, "{}  ", name)?;
+            }
+            used += len;
+        }
+        write!(stdout, "{}", " ".repeat(width.saturating_sub(used)))?;
+        
+        execute!(stdout, style::ResetColor)?;
+        Ok(())
+    }
+    
     /// Render the tab bar
     fn render_tab_bar(&self) -> UiResult<()> {
         let mut stdout = io::stdout();
@@ -358,7 +465,9 @@ impl TerminalUi {
                 execute!(stdout, cursor::MoveTo(content_area.x, content_area.y + i))?;
                 
                 // Render the line number
-                write!(stdout, "{:4} ", line_idx + 1)?;
+                if window.number {
+                    write!(stdout, "{:4} ", line_idx + 1)?;
+                }
                 
                 // Render the line content with syntax highlighting
                 self.syntax_renderer.render_line(
@@ -366,7 +475,7 @@ impl TerminalUi {
                     buffer,
                     line_idx,
                     window.left_col,
-                    content_area.width as usize - 5, // Subtract 5 for line number and space
+                    window.visible_width(),
                 )?;
             } else {
                 // Position the cursor at the start of the line
@@ -380,8 +489,31 @@ impl TerminalUi {
         // Render the status line
         self.render_window_status_line(window, buffer, mode)?;
         
+        // Highlight text, like the match a substitute asks about
+        if let Some((line_idx, start, end)) = window.highlight {
+            if line_idx >= window.top_line && line_idx < window.top_line + content_area.height as usize {
+                let line = buffer.line(line_idx).unwrap_or_default();
+                let start_col = line.get(..start).map_or(0, |text| text.chars().count());
+                
+                // Only the part of the text right of the left edge of the window shows
+                let column = start_col.max(window.left_col) - window.left_col;
+                let text: String = line.get(start..end).unwrap_or_default().chars()
+                    .skip(window.left_col.saturating_sub(start_col))
+                    .take(window.visible_width().saturating_sub(column))
+                    .collect();
+                
+                execute!(
+                    stdout,
+                    cursor::MoveTo(content_area.x + (window.number_width() + column) as u16, content_area.y + (line_idx - window.top_line) as u16),
+                    style::SetAttribute(style::Attribute::Reverse)
+                )?;
+                write!(stdout, "{}", text)?;
+                execute!(stdout, style::SetAttribute(style::Attribute::Reset))?;
+            }
+        }
+        
         // Highlight the cursor position
-        let cursor_x = content_area.x + 5 + (window.cursor.column - window.left_col) as u16;
+        let cursor_x = content_area.x + window.number_width() as u16 + (window.cursor.column - window.left_col) as u16;
         let cursor_y = content_area.y + (window.cursor.line - window.top_line) as u16;
         
         if cursor_x < content_area.x + content_area.width && cursor_y < content_area.y + content_area.height {
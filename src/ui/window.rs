@@ -4,9 +4,12 @@
 //! Windows can be split horizontally or vertically, resized, and navigated between.
 
 use std::cmp::{max, min};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use crate::buffer::Buffer;
 use crate::cursor::CursorPosition;
+use crate::mark::JumpList;
 
 /// Window split direction
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -101,6 +104,12 @@ pub struct Window {
     pub top_line: usize,
     /// Left column of the visible portion of the buffer
     pub left_col: usize,
+    /// Whether line numbers are shown ('number')
+    pub number: bool,
+    /// Positions jumped from, for Ctrl-O and Ctrl-I
+    pub jumps: JumpList,
+    /// Text to highlight, as its line and its start and end in the line
+    pub highlight: Option<(usize, usize, usize)>,
 }
 
 impl Window {
@@ -113,6 +122,9 @@ impl Window {
             cursor: CursorPosition::new(0, 0),
             top_line: 0,
             left_col: 0,
+            number: true,
+            jumps: JumpList::new(),
+            highlight: None,
         }
     }
 
@@ -123,7 +135,12 @@ impl Window {
 
     /// Get the visible width (number of columns that can be displayed)
     pub fn visible_width(&self) -> usize {
-        self.rect.content_area().width as usize
+        (self.rect.content_area().width as usize).saturating_sub(self.number_width())
+    }
+
+    /// Get the width of the line number column, including the space after it
+    pub fn number_width(&self) -> usize {
+        if self.number { 5 } else { 0 }
     }
 
     /// Ensure the cursor is visible by scrolling if necessary
@@ -207,17 +224,22 @@ pub struct WindowManager {
     windows: Vec<Window>,
     /// Current window ID
     current_window_id: usize,
-    /// Next window ID
-    next_window_id: usize,
+    /// Next window ID, shared with the other tabs so IDs are unique
+    next_window_id: Arc<AtomicUsize>,
 }
 
 impl WindowManager {
     /// Create a new window manager
     pub fn new() -> Self {
+        Self::with_id_counter(Arc::new(AtomicUsize::new(1)))
+    }
+
+    /// Create a window manager that takes window IDs from a shared counter
+    pub fn with_id_counter(next_window_id: Arc<AtomicUsize>) -> Self {
         Self {
             windows: Vec::new(),
             current_window_id: 0,
-            next_window_id: 1,
+            next_window_id,
         }
     }
 
@@ -225,8 +247,7 @@ impl WindowManager {
     pub fn create_initial_window(&mut self, buffer_id: usize, width: u16, height: u16) -> usize {
         // Start at y=1 to leave room for the tab bar at y=0
         let rect = WindowRect::new(0, 1, width, height - 1);
-        let window = Window::new(self.next_window_id, buffer_id, rect);
-        self.next_window_id += 1;
+        let window = Window::new(self.next_window_id.fetch_add(1, Ordering::Relaxed), buffer_id, rect);
         let id = window.id;
         self.windows.push(window);
         self.current_window_id = id;
@@ -251,8 +272,7 @@ impl WindowManager {
         self.windows[current_window_idx].rect = rect1;
 
         // Create a new window
-        let new_window = Window::new(self.next_window_id, buffer_id, rect2);
-        self.next_window_id += 1;
+        let new_window = Window::new(self.next_window_id.fetch_add(1, Ordering::Relaxed), buffer_id, rect2);
         let new_id = new_window.id;
         self.windows.push(new_window);
 
@@ -248,16 +248,151 @@ fn test_substitute_command() {
     // Create a command parser
     let parser = ExCommandParser::new();
     
-    // Execute the :substitute command
-    let cmd = parser.parse("substitute/Line/NewLine/g").unwrap();
+    // Execute the :substitute command on every line
+    let cmd = parser.parse("%substitute/Line/NewLine/g").unwrap();
     let result = registry.execute(&cmd);
     
     // Check the result
     assert!(result.is_ok(), "Failed to execute :substitute command: {:?}", result);
     
+    // Verify the buffer content
+    let buffer = editor.get_buffer_manager().get_buffer(buffer_id).unwrap();
+    assert_eq!(buffer.content(), "NewLine 1\nNewLine 2\nNewLine 3\n");
+    
     println!("  :substitute command test passed");
 }
 
+/// Test :substitute flags, special replacements and repeating a substitute
+fn test_substitute_flags() {
+    println!("Testing :substitute flags...");
+    
+    // Create an editor instance
+    let mut editor = Editor::new().unwrap();
+    
+    // Set the editor reference in the handlers
+    handlers::set_editor(&mut editor);
+    
+    // Create a buffer with some content
+    let buffer_id = editor.get_buffer_manager_mut().create_buffer().unwrap();
+    editor.get_buffer_manager_mut().set_current_buffer(buffer_id).unwrap();
+    editor.insert_text("foo bar foo\nFoo baz\nqux").unwrap();
+    editor.get_cursor_manager_mut().set_position(xvim::cursor::CursorPosition::new(0, 0));
+    
+    // Create a command registry
+    let mut registry = ExCommandRegistry::new();
+    handlers::register_handlers(&mut registry, None);
+    
+    // Create a command parser
+    let parser = ExCommandParser::new();
+    let run = |command: &str| registry.execute(&parser.parse(command).unwrap());
+    let content = |editor: &Editor| editor.get_buffer_manager().get_buffer(buffer_id).unwrap().content();
+    
+    // Only the first match in a line without 'g', and case matters without 'i'
+    assert!(run("%s/foo/x/").is_ok());
+    assert_eq!(content(&editor), "x bar foo\nFoo baz\nqux");
+    assert!(run("%s/foo/y/gi").is_ok());
+    assert_eq!(content(&editor), "x bar y\ny baz\nqux");
+    assert_eq!(editor.message(), Some("2 substitutions on 2 lines"));
+    
+    // 'n' only counts, and 'e' hides a pattern that doesn't match
+    assert!(run("%s/y//gn").is_ok());
+    assert_eq!(content(&editor), "x bar y\ny baz\nqux");
+    assert_eq!(editor.message(), Some("2 matches on 2 lines"));
+    assert!(run("%s/nothing/z/").is_err());
+    assert!(run("%s/nothing/z/e").is_ok());
+    
+    // Case changes, and '~' for the previous replacement
+    assert!(run("1s/bar/\\u&/").is_ok());
+    assert!(run("2s/baz/\\U&\\E!/").is_ok());
+    assert!(run("3s/qux/<&>/").is_ok());
+    assert!(run("3s/q/~~/").is_ok());
+    assert_eq!(content(&editor), "x Bar y\ny BAZ!\n<<q><q>ux>");
+    
+    // ':&&' repeats with the flags, ':&' without them, and ':~' with the last search pattern
+    assert!(run("1,2s/y/[&]/g").is_ok());
+    assert!(run("%&&").is_ok());
+    assert_eq!(content(&editor), "x Bar [[y]]\n[[y]] BAZ!\n<<q><q>ux>");
+    assert!(run("2s/\\[/</g").is_ok());
+    assert!(run("1&&").is_ok());
+    assert!(run("2s/]/>/g").is_ok());
+    assert!(run("1&").is_ok());
+    assert_eq!(content(&editor), "x Bar <<y>]\n<<y>> BAZ!\n<<q><q>ux>");
+    editor.process_command("/Bar").unwrap();
+    assert!(run("1~").is_ok());
+    assert_eq!(content(&editor), "x > <<y>]\n<<y>> BAZ!\n<<q><q>ux>");
+    
+    // A count after the flags
+    assert!(run("1s/</(/g 2").is_ok());
+    assert_eq!(content(&editor), "x > ((y>]\n((y>> BAZ!\n<<q><q>ux>");
+    assert!(run("1s/x/X/ z").is_err());
+    
+    println!("  :substitute flags test passed");
+}
+
+/// Test commands on ranges of lines
+fn test_range_commands() {
+    println!("Testing command ranges...");
+    
+    // Create an editor instance
+    let mut editor = Editor::new().unwrap();
+    
+    // Set the editor reference in the handlers
+    handlers::set_editor(&mut editor);
+    
+    // Create a buffer with some content
+    let buffer_id = editor.get_buffer_manager_mut().create_buffer().unwrap();
+    editor.get_buffer_manager_mut().set_current_buffer(buffer_id).unwrap();
+    editor.insert_text("one\ntwo\nfoo three\nfour\nfoo five\nsix").unwrap();
+    editor.get_cursor_manager_mut().set_position(xvim::cursor::CursorPosition::new(0, 0));
+    
+    // Create a command registry
+    let mut registry = ExCommandRegistry::new();
+    handlers::register_handlers(&mut registry, None);
+    
+    // Create a command parser
+    let parser = ExCommandParser::new();
+    let run = |command: &str| registry.execute(&parser.parse(command).unwrap());
+    let content = |editor: &Editor| editor.get_buffer_manager().get_buffer(buffer_id).unwrap().content();
+    
+    // A pattern address with an offset, and ';' resolving the end from the start
+    assert!(run("/foo/+1;+1s/^/-/").is_ok());
+    assert_eq!(content(&editor), "one\ntwo\nfoo three\n-four\n-foo five\nsix");
+    
+    // Copy and move to addresses
+    assert!(run("1,2t$").is_ok());
+    assert_eq!(content(&editor), "one\ntwo\nfoo three\n-four\n-foo five\nsix\none\ntwo");
+    assert!(run("7,8m0").is_ok());
+    assert_eq!(content(&editor), "one\ntwo\none\ntwo\nfoo three\n-four\n-foo five\nsix");
+    assert!(run("1m$").is_ok());
+    assert_eq!(content(&editor), "two\none\ntwo\nfoo three\n-four\n-foo five\nsix\none");
+    assert!(run("2,4m3").is_err());
+    
+    // Sort, yank and delete a range
+    assert!(run("1,3sort").is_ok());
+    assert_eq!(content(&editor), "one\ntwo\ntwo\nfoo three\n-four\n-foo five\nsix\none");
+    assert!(run("?three?,$-1d").is_ok());
+    assert_eq!(content(&editor), "one\ntwo\ntwo\none");
+    assert!(run("2d 2").is_ok());
+    assert_eq!(content(&editor), "one\none");
+    
+    // Run normal mode commands on each line
+    assert!(run("%normal 0x").is_ok());
+    assert_eq!(content(&editor), "ne\nne");
+    
+    // A command line with a command after the pattern is not a search
+    editor.get_cursor_manager_mut().set_position(xvim::cursor::CursorPosition::new(0, 0));
+    editor.process_command("/ne/s/n/N/").unwrap();
+    assert_eq!(content(&editor), "ne\nNe");
+    editor.process_command("?ne?").unwrap();
+    assert_eq!(content(&editor), "ne\nNe");
+    
+    // Addresses past the end are an error
+    assert!(run("5d").is_err());
+    assert!(run("/nothing/d").is_err());
+    
+    println!("  command ranges test passed");
+}
+
 /// Test the :global command
 fn test_global_command() {
     println!("Testing :global command...");
@@ -336,6 +471,141 @@ fn test_vglobal_command() {
     println!("  :vglobal command test passed");
 }
 
+/// Test :global running commands on the lines it marked
+fn test_global_marks() {
+    println!("Testing :global marks and commands...");
+    
+    // Create an editor instance
+    let mut editor = Editor::new().unwrap();
+    
+    // Set the editor reference in the handlers
+    handlers::set_editor(&mut editor);
+    
+    // Create a buffer with some content
+    let buffer_id = editor.get_buffer_manager_mut().create_buffer().unwrap();
+    editor.get_buffer_manager_mut().set_current_buffer(buffer_id).unwrap();
+    editor.insert_text("a1\nb2\na3\nb4\na5\nb6").unwrap();
+    
+    // Create a command registry
+    let mut registry = ExCommandRegistry::new();
+    handlers::register_handlers(&mut registry, None);
+    
+    // Create a command parser
+    let parser = ExCommandParser::new();
+    let run = |command: &str| registry.execute(&parser.parse(command).unwrap());
+    let content = |editor: &Editor| editor.get_buffer_manager().get_buffer(buffer_id).unwrap().content();
+    let original = "a1\nb2\na3\nb4\na5\nb6";
+    
+    // Each command is a single undo step
+    assert!(run("g/a/s/\\d/#/").is_ok());
+    assert_eq!(content(&editor), "a#\nb2\na#\nb4\na#\nb6");
+    assert!(run("undo").is_ok());
+    assert_eq!(content(&editor), original);
+    
+    // Lines are marked before the command runs, and moved lines keep their turn
+    assert!(run("g/^b/m0").is_ok());
+    assert_eq!(content(&editor), "b6\nb4\nb2\na1\na3\na5");
+    assert!(run("undo").is_ok());
+    assert!(run("g!/a/t$").is_ok());
+    assert_eq!(content(&editor), "a1\nb2\na3\nb4\na5\nb6\nb2\nb4\nb6");
+    assert!(run("undo").is_ok());
+    
+    // Ranges are relative to each line, and lines deleted on the way are skipped
+    assert!(run("g/\\d/.,+1d").is_ok());
+    assert_eq!(content(&editor), "");
+    assert!(run("undo").is_ok());
+    assert_eq!(content(&editor), original);
+    assert!(run("2,$v/a/-1d").is_ok());
+    assert_eq!(content(&editor), "b2\nb4\nb6");
+    assert!(run("undo").is_ok());
+    
+    // :normal, and :p when there is no command
+    assert!(run("g/b/normal 0x").is_ok());
+    assert_eq!(content(&editor), "a1\n2\na3\n4\na5\n6");
+    assert!(run("g/a").is_ok());
+    assert_eq!(editor.cursor_position().line, 4);
+    assert!(run("g/a/g/b/d").is_err());
+    assert!(run("gx").is_err());
+    assert!(run("g/nothing/d").is_ok());
+    assert_eq!(editor.message(), Some("Pattern not found: nothing"));
+    assert!(run("v/./d").is_ok());
+    assert_eq!(editor.message(), Some("Pattern found in every line: ."));
+    
+    // A substitute can't ask for confirmation under :global
+    let message = run("g/a/s//x/gc").unwrap_err().to_string();
+    assert!(message.contains("Cannot confirm substitutions under :global"), "{}", message);
+    assert_eq!(content(&editor), "a1\n2\na3\n4\na5\n6");
+    assert_eq!(editor.current_mode(), xvim::mode::Mode::Normal);
+    
+    println!("  :global marks and commands test passed");
+}
+
+/// Test :!, :r !, :w ! and filtering lines through a command
+#[cfg(unix)]
+fn test_shell_commands() {
+    println!("Testing shell commands...");
+    
+    // Create an editor instance
+    let mut editor = Editor::new().unwrap();
+    
+    // Set the editor reference in the handlers
+    handlers::set_editor(&mut editor);
+    
+    // Create a buffer with some content
+    let buffer_id = editor.get_buffer_manager_mut().create_buffer().unwrap();
+    editor.get_buffer_manager_mut().set_current_buffer(buffer_id).unwrap();
+    editor.insert_text("pear\napple\nfig").unwrap();
+    
+    // Create a command registry
+    let mut registry = ExCommandRegistry::new();
+    handlers::register_handlers(&mut registry, None);
+    
+    // Create a command parser
+    let parser = ExCommandParser::new();
+    let run = |command: &str| registry.execute(&parser.parse(command).unwrap());
+    let content = |editor: &Editor| editor.get_buffer_manager().get_buffer(buffer_id).unwrap().content();
+    
+    // A range is filtered through the command
+    assert!(run("%!sort").is_ok());
+    assert_eq!(content(&editor), "apple\nfig\npear");
+    assert!(run("2,3!tr a-z A-Z").is_ok());
+    assert_eq!(content(&editor), "apple\nFIG\nPEAR");
+    assert!(run("undo").is_ok());
+    
+    // The output of :r ! goes below the line, or above the first one
+    assert!(run("1r !echo one; echo two").is_ok());
+    assert_eq!(content(&editor), "apple\none\ntwo\nfig\npear");
+    assert!(run("0r!echo top").is_ok());
+    assert_eq!(content(&editor), "top\napple\none\ntwo\nfig\npear");
+    
+    // :w ! passes lines to the command without changing the buffer
+    assert!(run("w !grep -q top").is_ok());
+    assert!(run("3,$w !grep -q top").is_err());
+    assert_eq!(content(&editor), "top\napple\none\ntwo\nfig\npear");
+    
+    // A failing command leaves the lines alone, and ! repeats the last command
+    assert!(run("!exit 4").is_err());
+    assert!(run("%!!").is_err());
+    assert_eq!(content(&editor), "top\napple\none\ntwo\nfig\npear");
+    assert!(run("!").is_err());
+    assert!(run("!echo %").is_err());
+    assert!(run("!echo \\%").is_ok());
+    
+    // The line break at the end of a file stays where it is
+    assert!(run("%d").is_ok());
+    editor.insert_text("3\n1\n2\n").unwrap();
+    assert!(run("%!sort").is_ok());
+    assert_eq!(content(&editor), "1\n2\n3\n");
+    
+    // What a command writes is shown as a message, its errors included
+    assert!(run("!echo out; echo err >&2").is_ok());
+    assert_eq!(editor.message(), Some("out\nerr"));
+    assert!(run("w !cat").is_ok());
+    assert_eq!(editor.message(), Some("1\n2\n3"));
+    
+    println!("  shell commands test passed");
+}
+
 /// Test the :set command
 fn test_set_command() {
     println!("Testing :set command...");
@@ -488,6 +758,129 @@ fn test_normal_command() {
     println!("  :normal command test passed");
 }
 
+/// Test the :command and :delcommand commands
+fn test_user_commands() {
+    println!("Testing user commands...");
+    
+    // Create an editor instance
+    let mut editor = Editor::new().unwrap();
+    
+    // Set the editor reference in the handlers
+    handlers::set_editor(&mut editor);
+    
+    // Create a buffer with some content
+    let buffer_id = editor.get_buffer_manager_mut().create_buffer().unwrap();
+    editor.get_buffer_manager_mut().set_current_buffer(buffer_id).unwrap();
+    editor.insert_text("pear\napple\nfig").unwrap();
+    
+    // User commands are registered with the editor's commands
+    let run = |editor: &mut Editor, command: &str| editor.execute_ex_command(command);
+    let content = |editor: &Editor| editor.get_buffer_manager().get_buffer(buffer_id).unwrap().content();
+    
+    // The escapes expand to the range, bang and arguments
+    assert!(run(&mut editor, "command -range=% -nargs=? Sort <line1>,<line2>sort <args>").is_ok());
+    assert!(run(&mut editor, "Sort").is_ok());
+    assert_eq!(content(&editor), "apple\nfig\npear");
+    assert!(run(&mut editor, "Sort r").is_ok());
+    assert_eq!(content(&editor), "pear\nfig\napple");
+    assert!(run(&mut editor, "command -bang -nargs=+ Norm normal<bang> <args>").is_ok());
+    editor.get_cursor_manager_mut().set_position(xvim::cursor::CursorPosition::new(0, 0));
+    assert!(run(&mut editor, "Norm! dd").is_ok());
+    assert_eq!(content(&editor), "fig\napple");
+    assert!(run(&mut editor, "com -nargs=1 -range Sub <line1>,<line2>s/<args>/X/").is_ok());
+    assert!(run(&mut editor, "1,2Sub [ai]").is_ok());
+    assert_eq!(content(&editor), "fXg\nXpple");
+    
+    // Definitions are checked
+    assert!(run(&mut editor, "command sort echo").is_err());
+    assert!(run(&mut editor, "command -nargs=2 Two echo").is_err());
+    assert!(run(&mut editor, "command Sort echo").is_err());
+    assert!(run(&mut editor, "command! -nargs=? Sort sort <args>").is_ok());
+    assert!(run(&mut editor, "Norm").is_err());
+    assert!(run(&mut editor, "Sort!").is_err());
+    assert!(run(&mut editor, "2Sort").is_err());
+    assert!(run(&mut editor, "command").is_ok());
+    assert!(run(&mut editor, "command S").is_ok());
+    
+    // Deleted commands are unknown
+    assert!(run(&mut editor, "delcommand Sort").is_ok());
+    assert!(run(&mut editor, "delc Sort").is_err());
+    assert!(run(&mut editor, "Sort").is_err());
+    assert!(run(&mut editor, "delcommand").is_err());
+    let names: Vec<String> = editor.user_commands().map(|command| command.name.clone()).collect();
+    assert_eq!(names, ["Norm", "Sub"]);
+    
+    // A command that runs itself is stopped, and commands still run afterwards
+    assert!(run(&mut editor, "command Loop Loop").is_ok());
+    let message = run(&mut editor, "Loop").unwrap_err().to_string();
+    assert!(message.contains("E169: Command too recursive"), "{}", message);
+    assert!(run(&mut editor, "delcommand Loop").is_ok());
+    assert!(run(&mut editor, "1Sub f").is_ok());
+    assert_eq!(content(&editor), "XXg\nXpple");
+    
+    println!("  User commands test passed");
+}
+
+/// Test the :source command
+fn test_source_command() {
+    println!("Testing :source command...");
+    
+    // Create an editor instance
+    let mut editor = Editor::new().unwrap();
+    
+    // Set the editor reference in the handlers
+    handlers::set_editor(&mut editor);
+    
+    // Create a buffer with some content
+    let buffer_id = editor.get_buffer_manager_mut().create_buffer().unwrap();
+    editor.get_buffer_manager_mut().set_current_buffer(buffer_id).unwrap();
+    editor.insert_text("pear\napple\nfig").unwrap();
+    
+    // Write a script that sources another one
+    let dir = std::env::temp_dir().join(format!("xvim_source_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let inner = dir.join("inner.vim");
+    let script = dir.join("xvimrc");
+    fs::write(&inner, "command Inner sort\n").unwrap();
+    fs::write(&script, format!(
+        "\" Sorting helpers\n\
+         command -range=% -nargs=?\n\
+         \x20     \\ Sort\n\
+         \x20     \\ <line1>,<line2>sort <args>\n\
+         \n\
+         :Sort r\n\
+         NoSuchCommand\n\
+         source {}\n\
+         /^a/s/$/!/\n",
+        inner.display(),
+    )).unwrap();
+    
+    // The commands after a failing one still run, and the error says where it was.
+    // A line starting with a pattern is a command with a pattern address.
+    let result = editor.execute_ex_command(&format!("source {}", script.display()));
+    let message = result.unwrap_err().to_string();
+    assert!(message.contains(&format!("{} line 7", script.display())), "{}", message);
+    assert_eq!(editor.get_buffer_manager().get_buffer(buffer_id).unwrap().content(), "pear\nfig\napple!");
+    
+    // User commands remember where they were defined
+    let sources: Vec<(String, Option<String>)> = editor.user_commands()
+        .map(|command| (command.name.clone(), command.source.clone()))
+        .collect();
+    assert_eq!(sources, [
+        ("Inner".to_string(), Some(format!("{} line 1", inner.display()))),
+        ("Sort".to_string(), Some(format!("{} line 2", script.display()))),
+    ]);
+    
+    // The file must exist
+    assert!(editor.execute_ex_command("source").is_err());
+    assert!(editor.execute_ex_command(&format!("so {}", dir.join("missing").display())).is_err());
+    
+    // Clean up
+    fs::remove_dir_all(&dir).unwrap();
+    
+    println!("  :source command test passed");
+}
+
 /// Test the :sort command
 fn test_sort_command() {
     println!("Testing :sort command...");
@@ -633,6 +1026,15 @@ fn test_undo_command() {
     let buffer = editor.get_buffer_manager().get_buffer(buffer_id).unwrap();
     assert_eq!(buffer.content(), "Initial text", "Expected 'Initial text', but got '{}'", buffer.content());
     
+    // Times past either end stop there, and ones too large are rejected
+    let run = |command: &str| registry.execute(&parser.parse(command).unwrap());
+    assert!(run("earlier 99999999999d").is_ok());
+    assert_eq!(editor.get_buffer_manager().get_buffer(buffer_id).unwrap().content(), "");
+    assert!(run("later 99999999999d").is_ok());
+    assert_eq!(editor.get_buffer_manager().get_buffer(buffer_id).unwrap().content(), "Initial text with more content");
+    let message = run("later 999999999999999999d").unwrap_err().to_string();
+    assert!(message.contains("E475"), "{}", message);
+    
     println!("  :undo command test passed");
 }
 
@@ -699,8 +1101,13 @@ fn main() {
     test_copy_command();
     test_move_command();
     test_substitute_command();
+    test_substitute_flags();
+    test_range_commands();
     test_global_command();
     test_vglobal_command();
+    test_global_marks();
+    #[cfg(unix)]
+    test_shell_commands();
     
     // Test undo/redo operations
     test_undo_command();
@@ -715,6 +1122,8 @@ fn main() {
     
     // Test our new commands
     test_normal_command();
+    test_user_commands();
+    test_source_command();
     test_sort_command();
     test_cd_command();
     
@@ -1,4 +1,5 @@
 use xvim::buffer::Buffer;
+use xvim::command::CommandLine;
 use xvim::search::{SearchState, SearchDirection};
 
 #[test]
@@ -64,15 +65,21 @@ fn test_search_state() {
     state.set_pattern("pattern2".to_string());
     state.set_pattern("pattern3".to_string());
     
-    // Test history navigation
-    assert_eq!(state.history_next(), Some("pattern3"));
-    assert_eq!(state.history_next(), Some("pattern2"));
-    assert_eq!(state.history_next(), Some("pattern1"));
-    assert_eq!(state.history_next(), Some("test")); // Wrap around
-    
-    assert_eq!(state.history_prev(), Some("pattern1"));
-    assert_eq!(state.history_prev(), Some("pattern2"));
-    assert_eq!(state.history_prev(), Some("pattern3")); // Wrap around
+    // Test history navigation, which the command line does
+    let mut line = CommandLine::new();
+    assert!(line.history_older(state.history()));
+    assert_eq!(line.text(), "pattern3");
+    assert!(line.history_older(state.history()));
+    assert!(line.history_older(state.history()));
+    assert!(line.history_older(state.history()));
+    assert_eq!(line.text(), "test");
+    assert!(!line.history_older(state.history()));
+    
+    assert!(line.history_newer(state.history()));
+    assert_eq!(line.text(), "pattern1");
+    assert!(line.history_newer(state.history()));
+    assert!(line.history_newer(state.history()));
+    assert_eq!(line.text(), "pattern3");
     
     // Test search results
     let results = vec![
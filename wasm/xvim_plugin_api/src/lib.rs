@@ -6,6 +6,97 @@
 
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// Raw imports from the editor's `xvim` host module
+///
+/// Strings are passed as pointer/length pairs and structured values as JSON.
+/// Functions returning data hand back a buffer allocated through the `alloc`
+/// export, packed as `(ptr << 32) | len`. Negative results are error codes.
+mod host {
+    #[link(wasm_import_module = "xvim")]
+    extern "C" {
+        pub fn register_plugin(ptr: *const u8, len: usize) -> i32;
+        pub fn register_command(name_ptr: *const u8, name_len: usize, desc_ptr: *const u8, desc_len: usize) -> i32;
+        pub fn register_event_handler(ptr: *const u8, len: usize) -> i32;
+        pub fn register_completer(ptr: *const u8, len: usize) -> i32;
+        pub fn editor_message(ptr: *const u8, len: usize) -> i32;
+        pub fn log_message(ptr: *const u8, len: usize) -> i32;
+        pub fn get_current_buffer_id() -> i64;
+        pub fn get_current_mode() -> i64;
+        pub fn get_buffer_content(buffer_id: i64) -> i64;
+        pub fn set_buffer_content(buffer_id: i64, ptr: *const u8, len: usize) -> i32;
+        pub fn get_cursor_position() -> i64;
+        pub fn set_cursor_position(ptr: *const u8, len: usize) -> i32;
+        pub fn execute_command(ptr: *const u8, len: usize) -> i32;
+        pub fn create_buffer(ptr: *const u8, len: usize, scratch: i32, hidden: i32) -> i64;
+        pub fn get_selection() -> i64;
+        pub fn position_to_char_idx(buffer_id: i64, line: i64, column: i64) -> i64;
+        pub fn insert_text(buffer_id: i64, position: i64, ptr: *const u8, len: usize) -> i32;
+        pub fn delete_text(buffer_id: i64, start: i64, end: i64) -> i32;
+        pub fn read_file(ptr: *const u8, len: usize) -> i64;
+        pub fn write_file(path_ptr: *const u8, path_len: usize, content_ptr: *const u8, content_len: usize) -> i32;
+        pub fn list_files(ptr: *const u8, len: usize) -> i64;
+        pub fn execute_shell_command(ptr: *const u8, len: usize) -> i64;
+    }
+}
+
+/// Error code returned when the plugin lacks the capability for a call
+const ERR_PERMISSION_DENIED: i64 = -5;
+
+/// Convert a host error code into an error
+fn host_error(code: i64) -> anyhow::Error {
+    if code == ERR_PERMISSION_DENIED {
+        anyhow!("Permission denied")
+    } else {
+        anyhow!("Host call failed with error code {}", code)
+    }
+}
+
+/// Convert a host status code into a result
+fn check(code: i32) -> Result<()> {
+    if code < 0 {
+        Err(host_error(code as i64))
+    } else {
+        Ok(())
+    }
+}
+
+/// Convert a host integer result into a value
+fn check_value(value: i64) -> Result<usize> {
+    if value < 0 {
+        Err(host_error(value))
+    } else {
+        Ok(value as usize)
+    }
+}
+
+/// Take ownership of a buffer returned by the host
+fn take_bytes(packed: i64) -> Result<Vec<u8>> {
+    if packed < 0 {
+        return Err(host_error(packed));
+    }
+    if packed == 0 {
+        return Ok(Vec::new());
+    }
+    
+    let ptr = (packed as u64 >> 32) as usize;
+    let len = (packed as u64 & 0xffff_ffff) as usize;
+    
+    // The host allocated the buffer through `alloc` with a capacity of `len`
+    Ok(unsafe { Vec::from_raw_parts(ptr as *mut u8, len, len) })
+}
+
+/// Take ownership of a string returned by the host
+fn take_string(packed: i64) -> Result<String> {
+    String::from_utf8(take_bytes(packed)?).map_err(|e| anyhow!("Invalid UTF-8 from host: {}", e))
+}
+
+/// Take ownership of a JSON value returned by the host
+fn take_json<T: serde::de::DeserializeOwned>(packed: i64) -> Result<T> {
+    serde_json::from_slice(&take_bytes(packed)?).map_err(|e| anyhow!("Invalid JSON from host: {}", e))
+}
 
 /// Plugin information
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -121,9 +212,8 @@ pub enum TaskResult {
 /// This function registers a plugin with the editor, providing information about
 /// the plugin and its capabilities.
 pub fn register_plugin(info: PluginInfo) -> Result<()> {
-    // This is a placeholder implementation
-    println!("Registered plugin: {}", info.name);
-    Ok(())
+    let json = serde_json::to_string(&info)?;
+    check(unsafe { host::register_plugin(json.as_ptr(), json.len()) })
 }
 
 /// Command handler type
@@ -132,14 +222,28 @@ pub type CommandHandler = fn(&[&str]) -> Result<(), String>;
 /// Event handler type
 pub type EventHandler = fn(&Event) -> Result<(), String>;
 
+/// Completer type, called with the argument typed and the whole command line
+pub type CompleteHandler = fn(&str, &str) -> Vec<String>;
+
+thread_local! {
+    /// Command handlers registered by this plugin
+    static COMMAND_HANDLERS: RefCell<HashMap<String, CommandHandler>> = RefCell::new(HashMap::new());
+    /// Event handlers registered by this plugin
+    static EVENT_HANDLERS: RefCell<HashMap<String, Vec<EventHandler>>> = RefCell::new(HashMap::new());
+    /// Completers registered by this plugin, by command name
+    static COMPLETERS: RefCell<HashMap<String, CompleteHandler>> = RefCell::new(HashMap::new());
+}
+
 /// Register a command with the editor
 ///
 /// This function registers a command with the editor, allowing the plugin to
 /// provide custom commands that can be executed by the user.
 pub fn register_command(name: &str, description: &str, handler: CommandHandler) -> Result<()> {
-    // This is a placeholder implementation
-    println!("Registered command: {}", name);
-    Ok(())
+    COMMAND_HANDLERS.with(|handlers| {
+        handlers.borrow_mut().insert(name.to_string(), handler);
+    });
+    
+    check(unsafe { host::register_command(name.as_ptr(), name.len(), description.as_ptr(), description.len()) })
 }
 
 /// Register an event handler with the editor
@@ -147,85 +251,88 @@ pub fn register_command(name: &str, description: &str, handler: CommandHandler)
 /// This function registers an event handler with the editor, allowing the plugin
 /// to receive notifications when certain events occur.
 pub fn register_event_handler(event_type: &str, handler: EventHandler) -> Result<()> {
-    // This is a placeholder implementation
-    println!("Registered event handler for: {}", event_type);
-    Ok(())
+    EVENT_HANDLERS.with(|handlers| {
+        handlers.borrow_mut().entry(event_type.to_string()).or_insert_with(Vec::new).push(handler);
+    });
+    
+    check(unsafe { host::register_event_handler(event_type.as_ptr(), event_type.len()) })
+}
+
+/// Register a completer for the arguments of a command
+///
+/// When Tab is pressed on the command line after the command, the matches
+/// the completer returns are offered instead of the editor's own.
+pub fn register_completer(command: &str, handler: CompleteHandler) -> Result<()> {
+    COMPLETERS.with(|completers| {
+        completers.borrow_mut().insert(command.to_string(), handler);
+    });
+    
+    check(unsafe { host::register_completer(command.as_ptr(), command.len()) })
 }
 
 /// Display a message in the editor
 ///
 /// This function displays a message in the editor's status line.
 pub fn editor_message(message: &str) -> Result<()> {
-    // This is a placeholder implementation
-    println!("Editor message: {}", message);
-    Ok(())
+    check(unsafe { host::editor_message(message.as_ptr(), message.len()) })
 }
 
 /// Log a message to the editor's log
 ///
 /// This function logs a message to the editor's log file.
 pub fn log_message(message: &str) -> Result<()> {
-    // This is a placeholder implementation
-    println!("Log message: {}", message);
-    Ok(())
+    check(unsafe { host::log_message(message.as_ptr(), message.len()) })
 }
 
 /// Get the current buffer ID
 ///
 /// This function returns the ID of the current buffer.
 pub fn get_current_buffer_id() -> Result<usize> {
-    // This is a placeholder implementation
-    Ok(0)
+    check_value(unsafe { host::get_current_buffer_id() })
 }
 
 /// Get the current editor mode
 ///
 /// This function returns the current editor mode.
 pub fn get_current_mode() -> Result<EditorMode> {
-    // This is a placeholder implementation
-    Ok(EditorMode::Normal)
+    take_json(unsafe { host::get_current_mode() })
 }
 
 /// Get buffer content
 ///
 /// This function returns the content of the specified buffer.
 pub fn get_buffer_content(buffer_id: usize) -> Result<String> {
-    // This is a placeholder implementation
-    Ok(String::new())
+    take_string(unsafe { host::get_buffer_content(buffer_id as i64) })
 }
 
 /// Set buffer content
 ///
 /// This function sets the content of the specified buffer.
 pub fn set_buffer_content(buffer_id: usize, content: &str) -> Result<()> {
-    // This is a placeholder implementation
-    println!("Set buffer {} content to {} bytes", buffer_id, content.len());
-    Ok(())
+    check(unsafe { host::set_buffer_content(buffer_id as i64, content.as_ptr(), content.len()) })
 }
 
 /// Get cursor position
 ///
 /// This function returns the current cursor position.
 pub fn get_cursor_position() -> Result<CursorPosition> {
-    // This is a placeholder implementation
-    Ok(CursorPosition { line: 0, column: 0 })
+    take_json(unsafe { host::get_cursor_position() })
 }
 
 /// Set cursor position
 ///
 /// This function sets the cursor position.
 pub fn set_cursor_position(position: CursorPosition) -> Result<()> {
-    // This is a placeholder implementation
-    println!("Set cursor position to line {}, column {}", position.line, position.column);
-    Ok(())
+    let json = serde_json::to_string(&position)?;
+    check(unsafe { host::set_cursor_position(json.as_ptr(), json.len()) })
 }
 
 /// Execute a command
 ///
-/// This function executes a command in the editor.
+/// This function queues an Ex command line, which the editor runs once the
+/// plugin function that issued it returns.
 pub fn execute_command(command: &str) -> Result<String> {
-    // This is a placeholder implementation
-    println!("Executed command: {}", command);
+    check(unsafe { host::execute_command(command.as_ptr(), command.len()) })?;
     Ok(String::new())
 }
 
@@ -252,67 +359,43 @@ pub fn file_exists(path: &str) -> bool {
 
 /// Read a file
 ///
-/// This function reads the content of a file at the specified path.
+/// This function reads the content of a file at the specified path. The plugin
+/// needs read access to the file's directory.
 pub fn read_file(path: &str) -> Result<String> {
-    // This is a placeholder implementation
-    std::fs::read_to_string(path).map_err(|e| anyhow!("Failed to read file: {}", e))
+    take_string(unsafe { host::read_file(path.as_ptr(), path.len()) })
+        .map_err(|e| anyhow!("Failed to read file: {}", e))
 }
 
 /// Write a file
 ///
-/// This function writes content to a file at the specified path.
+/// This function writes content to a file at the specified path. The plugin
+/// needs read-write access to the file's directory.
 pub fn write_file(path: &str, content: &str) -> Result<()> {
-    // This is a placeholder implementation
-    std::fs::write(path, content).map_err(|e| anyhow!("Failed to write file: {}", e))
+    check(unsafe { host::write_file(path.as_ptr(), path.len(), content.as_ptr(), content.len()) })
+        .map_err(|e| anyhow!("Failed to write file: {}", e))
 }
 
 /// Execute a shell command
 ///
-/// This function executes a shell command and returns the output.
+/// This function executes a shell command and returns the output. The plugin
+/// needs permission to run the command's program.
 pub fn execute_shell_command(command: &str) -> Result<String> {
-    // This is a placeholder implementation
-    use std::process::Command;
-    
-    let output = if cfg!(target_os = "windows") {
-        Command::new("cmd")
-            .args(&["/C", command])
-            .output()
-    } else {
-        Command::new("sh")
-            .args(&["-c", command])
-            .output()
-    };
-    
-    match output {
-        Ok(output) => {
-            let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-            let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-            
-            if !stderr.is_empty() {
-                return Err(anyhow!("Command error: {}", stderr));
-            }
-            
-            Ok(stdout)
-        }
-        Err(e) => Err(anyhow!("Failed to execute command: {}", e)),
-    }
+    take_string(unsafe { host::execute_shell_command(command.as_ptr(), command.len()) })
+        .map_err(|e| anyhow!("Failed to execute command: {}", e))
 }
 
 /// Create a buffer
 ///
 /// This function creates a new buffer with the specified name.
 pub fn create_buffer(name: &str, scratch: bool, hidden: bool) -> Result<usize> {
-    // This is a placeholder implementation
-    println!("Created buffer: {}", name);
-    Ok(0)
+    check_value(unsafe { host::create_buffer(name.as_ptr(), name.len(), scratch as i32, hidden as i32) })
 }
 
 /// Get the current selection
 ///
 /// This function returns the current selection in the current buffer.
 pub fn get_selection() -> Option<(usize, usize)> {
-    // This is a placeholder implementation
-    None
+    take_json(unsafe { host::get_selection() }).ok().flatten()
 }
 
 /// Create a task
@@ -328,55 +411,203 @@ pub fn create_task(name: &str, description: &str, callback: Option<Box<dyn FnOnc
 ///
 /// This function converts a line and column position to a character index.
 pub fn position_to_char_idx(buffer_id: usize, line: usize, column: usize) -> Result<usize> {
-    // This is a placeholder implementation
-    Ok(0)
+    check_value(unsafe { host::position_to_char_idx(buffer_id as i64, line as i64, column as i64) })
 }
 
 /// Insert text at a position
 ///
 /// This function inserts text at the specified position in the buffer.
 pub fn insert_text(buffer_id: usize, position: usize, text: &str) -> Result<()> {
-    // This is a placeholder implementation
-    println!("Inserted text at position {} in buffer {}", position, buffer_id);
-    Ok(())
+    check(unsafe { host::insert_text(buffer_id as i64, position as i64, text.as_ptr(), text.len()) })
 }
 
 /// Delete text from a buffer
 ///
 /// This function deletes text from the specified range in the buffer.
 pub fn delete_text(buffer_id: usize, start: usize, end: usize) -> Result<()> {
-    // This is a placeholder implementation
-    println!("Deleted text from position {} to {} in buffer {}", start, end, buffer_id);
-    Ok(())
+    check(unsafe { host::delete_text(buffer_id as i64, start as i64, end as i64) })
 }
 
 /// List project files
 ///
-/// This function lists all files in the project.
+/// This function lists all files in the project. The plugin needs read access
+/// to the root directory.
 pub fn list_project_files(root_dir: &str) -> Result<Vec<String>> {
-    // This is a placeholder implementation
-    let mut files = Vec::new();
+    take_json(unsafe { host::list_files(root_dir.as_ptr(), root_dir.len()) })
+        .map_err(|e| anyhow!("Failed to list project files: {}", e))
+}
+
+/// Allocate a buffer in the plugin's linear memory
+///
+/// The editor calls this export to reserve space for arguments it passes to the
+/// plugin. The buffer must be released with `dealloc`.
+#[no_mangle]
+pub extern "C" fn alloc(len: i32) -> i32 {
+    let mut buffer = Vec::<u8>::with_capacity(len.max(0) as usize);
+    let ptr = buffer.as_mut_ptr();
+    std::mem::forget(buffer);
+    ptr as usize as i32
+}
+
+/// Release a buffer allocated with `alloc`
+#[no_mangle]
+pub extern "C" fn dealloc(ptr: i32, len: i32) {
+    if ptr == 0 || len <= 0 {
+        return;
+    }
+    
+    unsafe {
+        drop(Vec::from_raw_parts(ptr as usize as *mut u8, 0, len as usize));
+    }
+}
+
+/// Read a buffer passed in by the editor
+fn read_input(ptr: i32, len: i32) -> Vec<u8> {
+    if ptr == 0 || len <= 0 {
+        return Vec::new();
+    }
     
-    fn visit_dirs(dir: &std::path::Path, files: &mut Vec<String>) -> std::io::Result<()> {
-        if dir.is_dir() {
-            for entry in std::fs::read_dir(dir)? {
-                let entry = entry?;
-                let path = entry.path();
-                
-                if path.is_dir() {
-                    visit_dirs(&path, files)?;
-                } else {
-                    if let Some(path_str) = path.to_str() {
-                        files.push(path_str.to_string());
-                    }
-                }
-            }
+    // The editor keeps ownership of the buffer and releases it with `dealloc`
+    unsafe { std::slice::from_raw_parts(ptr as usize as *const u8, len as usize).to_vec() }
+}
+
+/// Command invocation sent by the editor
+#[derive(Debug, Deserialize)]
+struct CommandCall {
+    command: String,
+    args: Vec<String>,
+}
+
+/// Completion request sent by the editor
+#[derive(Debug, Deserialize)]
+struct CompleteCall {
+    command: String,
+    arg_lead: String,
+    line: String,
+}
+
+/// Event notification sent by the editor
+#[derive(Debug, Deserialize)]
+struct EventCall {
+    event: String,
+    data: serde_json::Value,
+}
+
+/// Convert a mode name reported by the editor into an `EditorMode`
+fn mode_from_name(name: &str) -> EditorMode {
+    match name.to_lowercase().replace(['-', '_', ' '], "").as_str() {
+        "normal" => EditorMode::Normal,
+        "insert" => EditorMode::Insert,
+        "visual" => EditorMode::Visual,
+        "visualline" => EditorMode::VisualLine,
+        "visualblock" => EditorMode::VisualBlock,
+        "command" => EditorMode::Command,
+        "replace" => EditorMode::Replace,
+        "terminal" => EditorMode::Terminal,
+        _ => EditorMode::Custom(name.to_string()),
+    }
+}
+
+/// Convert an event notification into an `Event`
+fn event_from_call(call: &EventCall) -> Event {
+    let index = |value: &serde_json::Value| value.as_u64().unwrap_or(0) as usize;
+    
+    match call.event.as_str() {
+        "buffer_created" => Event::BufferCreated(index(&call.data)),
+        "buffer_deleted" => Event::BufferDeleted(index(&call.data)),
+        "buffer_changed" => Event::BufferChanged(index(&call.data)),
+        "mode_changed" => Event::ModeChanged(mode_from_name(call.data.as_str().unwrap_or_default())),
+        "cursor_moved" => Event::CursorMoved(
+            index(&call.data["buffer_id"]),
+            index(&call.data["line"]),
+            index(&call.data["column"]),
+        ),
+        "command_executed" => Event::CommandExecuted(call.data.as_str().unwrap_or_default().to_string()),
+        name => Event::Custom(name.to_string(), call.data.as_str().unwrap_or_default().to_string()),
+    }
+}
+
+/// Dispatch a command invocation to the registered handler
+///
+/// Returns `1` if the command was handled, `0` if no handler is registered for
+/// it, and `-1` if the handler failed.
+#[no_mangle]
+pub extern "C" fn handle_command(ptr: i32, len: i32) -> i32 {
+    let call: CommandCall = match serde_json::from_slice(&read_input(ptr, len)) {
+        Ok(call) => call,
+        Err(_) => return -1,
+    };
+    
+    let handler = COMMAND_HANDLERS.with(|handlers| handlers.borrow().get(&call.command).copied());
+    let handler = match handler {
+        Some(handler) => handler,
+        None => return 0,
+    };
+    
+    let args: Vec<&str> = call.args.iter().map(|s| s.as_str()).collect();
+    match handler(&args) {
+        Ok(_) => 1,
+        Err(err) => {
+            let _ = editor_message(&format!("{}: {}", call.command, err));
+            -1
         }
-        Ok(())
     }
+}
+
+/// Dispatch an event notification to the registered handlers
+#[no_mangle]
+pub extern "C" fn handle_event(ptr: i32, len: i32) -> i32 {
+    let call: EventCall = match serde_json::from_slice(&read_input(ptr, len)) {
+        Ok(call) => call,
+        Err(_) => return -1,
+    };
     
-    visit_dirs(&std::path::Path::new(root_dir), &mut files)
-        .map_err(|e| anyhow!("Failed to list project files: {}", e))?;
+    let handlers = EVENT_HANDLERS.with(|handlers| handlers.borrow().get(&call.event).cloned().unwrap_or_default());
+    let event = event_from_call(&call);
     
-    Ok(files)
-}
\ No newline at end of file
+    let mut status = 0;
+    for handler in handlers {
+        if let Err(err) = handler(&event) {
+            let _ = log_message(&format!("{} handler failed: {}", call.event, err));
+            status = -1;
+        }
+    }
+    
+    status
+}
+
+/// Dispatch a completion request to the registered completer
+///
+/// Returns the matches as a JSON array, packed as by `return_bytes`.
+#[no_mangle]
+pub extern "C" fn handle_complete(ptr: i32, len: i32) -> i64 {
+    let call: CompleteCall = match serde_json::from_slice(&read_input(ptr, len)) {
+        Ok(call) => call,
+        Err(_) => return -1,
+    };
+    
+    let matches = COMPLETERS.with(|completers| completers.borrow().get(&call.command).copied())
+        .map(|completer| completer(&call.arg_lead, &call.line))
+        .unwrap_or_default();
+    match serde_json::to_vec(&matches) {
+        Ok(bytes) => return_bytes(bytes),
+        Err(_) => -1,
+    }
+}
+
+/// Hand a result buffer back to the editor
+///
+/// Returns the packed `(ptr << 32) | len` value expected by the editor for
+/// functions called through the plugin runtime. Ownership of the buffer passes
+/// to the editor, which releases it with `dealloc`.
+pub fn return_bytes(bytes: Vec<u8>) -> i64 {
+    if bytes.is_empty() {
+        return 0;
+    }
+    
+    let mut bytes = bytes.into_boxed_slice();
+    let len = bytes.len();
+    let ptr = bytes.as_mut_ptr();
+    std::mem::forget(bytes);
+    ((ptr as usize as u64) << 32 | len as u64) as i64
+}